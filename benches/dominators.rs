@@ -0,0 +1,44 @@
+// Compares the bundled dominator implementation with petgraph's on the test
+// heap: `cargo bench --bench dominators [path/to/heap.json]`.
+use petgraph::algo::dominators::simple_fast;
+use reap::csr::Csr;
+use reap::dominator::immediate_dominators;
+use reap::parse;
+use std::env;
+use std::fs::File;
+use std::io::BufReader;
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 10;
+
+fn time<F: FnMut()>(mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        f();
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    let path = env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .unwrap_or_else(|| "test/heap.json".to_string());
+    let mut reader = BufReader::new(File::open(&path).unwrap());
+    let (root, graph) = parse::parse(&mut reader, false).unwrap();
+    println!(
+        "{}: {} nodes, {} edges",
+        path,
+        graph.node_count(),
+        graph.edge_count()
+    );
+
+    let simple = time(|| {
+        simple_fast(&graph, root);
+    });
+    let lengauer_tarjan = time(|| {
+        immediate_dominators(&Csr::new(&graph), root);
+    });
+    println!("simple_fast:     {:?}", simple);
+    println!("Lengauer-Tarjan: {:?}", lengauer_tarjan);
+}
@@ -0,0 +1,48 @@
+/* C interface to reap, built with
+ * `cargo rustc --release --lib --features reap-capi --crate-type cdylib`.
+ *
+ * Every pointer returned is owned by the caller and must be released with the
+ * matching reap_*_free function. Addresses are the object addresses from the
+ * heap dump; the root is 0. A call that panics fails as it would on an error:
+ * with NULL, 0 or zeroed stats, and a message in *error where it takes one. */
+#ifndef REAP_H
+#define REAP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct ReapAnalysis ReapAnalysis;
+
+typedef struct {
+    uint64_t count;
+    uint64_t bytes;
+} ReapStats;
+
+/* Return NULL on failure, setting *error (unless error is NULL) to a message
+ * to free with reap_string_free. */
+ReapAnalysis *reap_analyze_file(const char *path, int class_name_only, char **error);
+ReapAnalysis *reap_analyze_buffer(const uint8_t *data, size_t len, int class_name_only,
+                                  char **error);
+void reap_analysis_free(ReapAnalysis *analysis);
+
+/* Memory used by all objects reachable from the root. */
+ReapStats reap_totals(const ReapAnalysis *analysis);
+
+/* Returns 0 if there's no reachable object at address. */
+int reap_retained_stats(const ReapAnalysis *analysis, uint64_t address, ReapStats *out);
+
+/* JSON strings, to free with reap_string_free, or NULL on failure. */
+char *reap_summary_json(const ReapAnalysis *analysis, size_t count);
+char *reap_query_json(const ReapAnalysis *analysis, const char *query, size_t count,
+                      char **error);
+void reap_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,16 +1,29 @@
+use crate::csr::Csr;
+use crate::dominator;
+use crate::hooks::{Cancelled, Hooks, Progress, CHECK_INTERVAL};
+use crate::intern::Symbol;
 use crate::object::*;
-use petgraph::algo::dominators;
+use crate::storage::Heap;
+use crate::timing;
+use log::debug;
 use petgraph::graph::NodeIndex;
 use petgraph::visit::Dfs;
-use petgraph::Graph;
+use petgraph::{Direction, Graph};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::Deserialize;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::Write;
 use std::iter::Iterator;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use timed_function::timed;
 
 type Index = NodeIndex<usize>;
 
+// Label of the nodes standing in for the parts of the dominator tree deeper
+// than graphs show
+const DEEPER: &str = "(deeper)";
+
 #[derive(Debug)]
 pub struct Analysis {
     // Root (of full graph, or of subgraph).
@@ -28,21 +41,272 @@ pub struct Analysis {
     rest: Vec<Object>,
 
     // Dominator index for each node in the dominated subgraph.
-    dominators: HashMap<Index, Index>,
+    dominators: FxHashMap<Index, Index>,
+
+    // The same, as the children of each node.
+    tree: DominatorTree,
+
+    // Index of each object in the dominated subgraph, by address.
+    indices: FxHashMap<usize, Index>,
 
     // Size of each dominator subtree.
-    subtree_sizes: HashMap<Index, Stats>,
+    subtree_sizes: FxHashMap<Index, Stats>,
 
     // output only class names in flamegraph
     class_name_only: bool,
+
+    // Kinds left out of per-kind reports and flamegraph frames
+    excluded_kinds: FxHashSet<String>,
+
+    // Rows smaller than this are folded into the remainder of reports
+    min_bytes: usize,
+
+    // How to order rows of reports
+    sort: SortKey,
+
+    // How objects become flamegraph frames
+    frame_format: FrameFormat,
+
+    // Subtrees retaining less than this are folded into a "[small]" frame of
+    // flamegraphs
+    flame_min_bytes: usize,
+
+    // Merge siblings with the same class into one flamegraph frame
+    merge_siblings: bool,
+
+    // Objects deeper than this below the root are left out of flamegraph
+    // stacks, and their bytes counted as their ancestors'
+    flame_max_depth: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    #[default]
+    Bytes,
+    Count,
+    Retained,
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bytes" => Ok(SortKey::Bytes),
+            "count" => Ok(SortKey::Count),
+            "retained" => Ok(SortKey::Retained),
+            _ => Err(format!("Unknown sort key {:?}", s)),
+        }
+    }
+}
+
+// How each object in a stack becomes flamegraph frames: one for the object
+// itself, or one for its class, or two grouping it by class and then
+// internal type, or by the gem or file allocating it and then class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FrameFormat {
+    #[default]
+    Object,
+    Class,
+    ClassType,
+    GemClass,
+    FileClass,
+}
+
+impl std::str::FromStr for FrameFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "object" => Ok(FrameFormat::Object),
+            "class" => Ok(FrameFormat::Class),
+            "class-type" => Ok(FrameFormat::ClassType),
+            "gem-class" => Ok(FrameFormat::GemClass),
+            "file-class" => Ok(FrameFormat::FileClass),
+            _ => Err(format!("Unknown frame format {:?}", s)),
+        }
+    }
+}
+
+// A number with its thousands separated by commas.
+fn grouped(n: usize) -> String {
+    let digits = n.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// The gem a file is part of, from its directory under a gems directory,
+// less the version (or git revision) in its name.
+fn gem(file: &str) -> Option<&str> {
+    let (_, rest) = file.rsplit_once("/gems/")?;
+    let dir = rest.split('/').next().filter(|dir| !dir.is_empty())?;
+    let version = dir
+        .match_indices('-')
+        .map(|(i, _)| i)
+        .find(|&i| dir[i + 1..].starts_with(|c: char| c.is_ascii_digit()));
+    Some(match (version, dir.rsplit_once('-')) {
+        (Some(i), _) => &dir[..i],
+        (None, Some((name, revision)))
+            if revision.len() >= 7 && revision.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            name
+        }
+        _ => dir,
+    })
+}
+
+// Whose code allocated an object: the app's, a gem's, or Ruby's own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Origin {
+    App,
+    Gem(Symbol),
+    Stdlib,
+    Unknown,
+}
+
+impl Origin {
+    // The origin of code in `file`.
+    pub fn of_file(file: &str) -> Origin {
+        if file.starts_with("<internal:") {
+            return Origin::Stdlib;
+        }
+        match gem(file) {
+            Some(name) => Origin::Gem(name.into()),
+            None if file.contains("/lib/ruby/") => Origin::Stdlib,
+            None => Origin::App,
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::App => write!(f, "(app)"),
+            Origin::Gem(name) => write!(f, "{}", name),
+            Origin::Stdlib => write!(f, "(stdlib)"),
+            Origin::Unknown => write!(f, "(unknown)"),
+        }
+    }
+}
+
+// A dominator subtree which is only dominated by the root, because it is
+// referenced from several places which don't themselves share a dominator.
+#[derive(Debug)]
+pub struct SharedRegion<'a> {
+    pub head: &'a Object,
+    pub stats: Stats,
+
+    // Objects referencing the head of the region, excluding any which are
+    // retained by another referrer; dropping all of these would free the region.
+    pub referrers: Vec<&'a Object>,
+}
+
+// A strongly connected component of the reference graph: objects which all
+// reach each other, so that none of them is freed before all of them are.
+#[derive(Debug)]
+pub struct Cycle<'a> {
+    // The member retaining the most
+    pub head: &'a Object,
+
+    // The members' own sizes
+    pub stats: Stats,
+
+    // A shortest chain of references from the head back to itself
+    pub path: Vec<&'a Object>,
+
+    // The nearest object outside the cycle dominating all its members
+    pub retainer: &'a Object,
+
+    // What would be freed if nothing outside the cycle referenced it,
+    // including the members themselves
+    pub pinned: Stats,
+}
+
+// Strings with the same value.
+#[derive(Debug)]
+pub struct DuplicateString<'a> {
+    // The first 40 characters of the value, ending in … if it's longer, as
+    // labels show it
+    pub value: &'a str,
+    pub stats: Stats,
+
+    // What would be saved by sharing one copy, i.e. all but the largest
+    pub wasted: usize,
+
+    // Classes of the nearest non-container dominators of the copies, with
+    // how many copies each holds, most first
+    pub holders: Vec<(Symbol, usize)>,
+}
+
+// Percentiles of a distribution, e.g. of objects' numbers of referrers.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Percentiles {
+    pub median: usize,
+    pub p90: usize,
+    pub p99: usize,
+    pub max: usize,
+    pub mean: f64,
+}
+
+impl Percentiles {
+    fn of(mut values: Vec<usize>) -> Percentiles {
+        if values.is_empty() {
+            return Percentiles::default();
+        }
+        values.sort_unstable();
+        let at = |p: usize| values[(values.len() - 1) * p / 100];
+        Percentiles {
+            median: at(50),
+            p90: at(90),
+            p99: at(99),
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<usize>() as f64 / values.len() as f64,
+        }
+    }
+}
+
+// The shape of the heap's graph, as a quick fingerprint of a dump.
+#[derive(Debug, Clone)]
+pub struct GraphStats {
+    pub live: Stats,
+    pub unreachable: Stats,
+    pub references: usize,
+    pub referrers_per_object: Percentiles,
+    pub references_per_object: Percentiles,
+
+    // Strongly connected components of more than one object, and their
+    // members
+    pub cycles: usize,
+    pub cyclic: usize,
+    pub dominator_depth: Percentiles,
 }
 
-type AnalysisResultType = (Index, ReferenceGraph, Vec<Object>, HashMap<Index, Index>);
+// The objects at depths from `min` to `max` in the dominator tree.
+#[derive(Debug, Clone)]
+pub struct DepthBand<'a> {
+    pub min: usize,
+    pub max: usize,
+    pub stats: Stats,
+
+    // The kind of object using the most memory at these depths
+    pub kind: &'a Symbol,
+}
+
+type AnalysisResultType = (Index, ReferenceGraph, Vec<Object>, FxHashMap<Index, Index>);
 
 #[derive(Debug)]
 pub enum AnalysisError {
     NodeCountMismatch,
     DominatorAddrLengthExceeded,
+    Cancelled,
     // Other potential error types can be added here
 }
 
@@ -52,157 +316,230 @@ impl fmt::Display for AnalysisError {
             AnalysisError::NodeCountMismatch => write!(f, "Node count mismatch"),
             AnalysisError::DominatorAddrLengthExceeded => {
                 write!(f, "Dominator addr length exceeded")
-            } // Add other variants as needed
+            }
+            AnalysisError::Cancelled => write!(f, "Analysis cancelled"),
+            // Add other variants as needed
         }
     }
 }
 impl std::error::Error for AnalysisError {}
 
-#[timed]
+impl From<Cancelled> for AnalysisError {
+    fn from(_: Cancelled) -> Self {
+        AnalysisError::Cancelled
+    }
+}
+
 pub fn analyze(
     orig_root: Index,
     subgraph_root: Index,
     graph: ReferenceGraph,
     class_name_only: bool,
 ) -> Result<Analysis, AnalysisError> {
-    let dominators = find_dominators(orig_root, &graph);
+    analyze_with_hooks(
+        orig_root,
+        subgraph_root,
+        graph,
+        class_name_only,
+        &Hooks::default(),
+    )
+}
 
+#[timed]
+pub fn analyze_with_hooks(
+    orig_root: Index,
+    subgraph_root: Index,
+    graph: ReferenceGraph,
+    class_name_only: bool,
+    hooks: &Hooks,
+) -> Result<Analysis, AnalysisError> {
+    hooks.check()?;
+    hooks.report(Progress::Analyzing {
+        phase: "dominators",
+    });
+    let csr = Csr::new(&graph);
+    let dominators = find_dominators(orig_root, &csr);
+
+    hooks.check()?;
+    hooks.report(Progress::Analyzing { phase: "subgraph" });
     let (root, dominated_subgraph, rest, dominators) = if subgraph_root == orig_root {
-        remove_unreachable(orig_root, &graph, &dominators)?
+        remove_unreachable(orig_root, graph, &dominators)?
     } else {
-        extract_dominated_subgraph(subgraph_root, &graph, &dominators)?
+        extract_dominated_subgraph(subgraph_root, graph, &csr, &dominators)?
     };
-
-    let subtree_sizes = dominator_subtree_sizes(&dominated_subgraph, &dominators);
+    drop(csr);
+
+    hooks.check()?;
+    hooks.report(Progress::Analyzing {
+        phase: "subtree sizes",
+    });
+    let tree = DominatorTree::new(dominated_subgraph.node_count(), &dominators);
+    let subtree_sizes =
+        dominator_subtree_sizes_with_hooks(&dominated_subgraph, &dominators, &tree, hooks)?;
+    debug!(
+        "Analyzed {} dominated objects, {} others",
+        dominated_subgraph.node_count(),
+        rest.len()
+    );
 
     Ok(Analysis {
         root,
+        indices: address_indices(&dominated_subgraph),
         dominated_subgraph,
         rest,
         dominators,
+        tree,
         subtree_sizes,
         class_name_only,
+        excluded_kinds: FxHashSet::default(),
+        min_bytes: 0,
+        sort: SortKey::default(),
+        frame_format: FrameFormat::default(),
+        flame_min_bytes: 0,
+        merge_siblings: false,
+        flame_max_depth: None,
     })
 }
 
 #[timed]
-fn find_dominators(root: Index, graph: &ReferenceGraph) -> HashMap<Index, Index> {
-    let dominators = dominators::simple_fast(&graph, root);
-
-    // Convert dominators to map because we need a more flexible data structure;
-    // this would be unnecessary if the Dominators struct exposed its internals.
-    let mut map = HashMap::new();
-    for i in graph.node_indices() {
-        if let Some(d) = dominators.immediate_dominator(i) {
-            map.insert(i, d);
+fn find_dominators(root: Index, graph: &Csr) -> FxHashMap<Index, Index> {
+    timing::nodes(graph.node_count());
+    let dominators = dominator::immediate_dominators(graph, root);
+
+    // Convert dominators to map because we need a more flexible data structure
+    let mut map = FxHashMap::default();
+    for (i, d) in dominators.into_iter().enumerate() {
+        if let Some(d) = d {
+            map.insert(Index::new(i), d);
         }
     }
     map
 }
 
+// Moves the nodes `keep` selects, and the references between them, into a
+// new graph, returning the others with their indices. Nothing is cloned, and
+// the original graph is freed once it has been split.
+fn split_graph(graph: ReferenceGraph, keep: &[bool]) -> (ReferenceGraph, Vec<(Index, Object)>) {
+    let (nodes, edges) = graph.into_nodes_edges();
+    let node_count = keep.iter().filter(|k| **k).count();
+    let edge_count = edges
+        .iter()
+        .filter(|e| keep[e.source().index()] && keep[e.target().index()])
+        .count();
+
+    let mut subgraph = Graph::with_capacity(node_count, edge_count);
+    let mut removed = Vec::with_capacity(nodes.len() - node_count);
+    let mut new_indices = vec![None; nodes.len()];
+    for (i, node) in nodes.into_iter().enumerate() {
+        if keep[i] {
+            new_indices[i] = Some(subgraph.add_node(node.weight));
+        } else {
+            removed.push((Index::new(i), node.weight));
+        }
+    }
+    for edge in edges {
+        if let (Some(a), Some(b)) = (
+            new_indices[edge.source().index()],
+            new_indices[edge.target().index()],
+        ) {
+            subgraph.add_edge(a, b, edge.weight);
+        }
+    }
+    (subgraph, removed)
+}
+
 #[timed]
 fn remove_unreachable(
     root: Index,
-    graph: &ReferenceGraph,
-    dominators: &HashMap<Index, Index>,
+    graph: ReferenceGraph,
+    dominators: &FxHashMap<Index, Index>,
 ) -> Result<AnalysisResultType, AnalysisError> {
     // We take advantage of the fact that all reachable nodes have a dominator
     // to traverse the graph just once while both sorting reachable/unreachable
     // and translating domination edges into address terms
-    let (reachable, unreachable, dominator_addrs) = {
-        let mut unreachable: Vec<Object> = Vec::new();
-        let mut dominator_addrs: HashMap<usize, usize> = HashMap::new();
-
-        let reachable = graph.filter_map(
-            |i, w| {
-                if i == root {
-                    Some(w.clone())
-                } else if let Some(&d) = dominators.get(&i) {
-                    dominator_addrs.insert(w.address, graph[d].address);
-                    Some(w.clone())
-                } else {
-                    unreachable.push(w.clone());
-                    None
-                }
-            },
-            |_, e| Some(*e),
-        );
+    let mut keep = vec![false; graph.node_count()];
+    let mut dominator_addrs: FxHashMap<usize, usize> = FxHashMap::default();
+    for (i, w) in graph.node_indices().zip(graph.node_weights()) {
+        if i == root {
+            keep[i.index()] = true;
+        } else if let Some(&d) = dominators.get(&i) {
+            dominator_addrs.insert(w.address, graph[d].address);
+            keep[i.index()] = true;
+        }
+    }
 
-        (reachable, unreachable, dominator_addrs)
-    };
+    let node_count = graph.node_count();
+    let root_addr = graph[root].address;
+    let (reachable, unreachable) = split_graph(graph, &keep);
+    let unreachable: Vec<Object> = unreachable.into_iter().map(|(_, w)| w).collect();
 
     // Prove that our optimization above does not change results vs checking reachability
     // separately
-    if reachable.node_count() + unreachable.len() != graph.node_count() {
+    if reachable.node_count() + unreachable.len() != node_count {
         return Err(AnalysisError::NodeCountMismatch);
     }
     if dominator_addrs.len() > reachable.node_count() {
         return Err(AnalysisError::DominatorAddrLengthExceeded);
     }
 
-    let (root, dominators) = map_indices(&reachable, &dominator_addrs, graph[root].address);
+    let (root, dominators) = map_indices(&reachable, &dominator_addrs, root_addr);
     Ok((root, reachable, unreachable, dominators))
 }
 
 #[timed]
 fn extract_dominated_subgraph(
     root: Index,
-    graph: &ReferenceGraph,
-    dominators: &HashMap<Index, Index>,
+    graph: ReferenceGraph,
+    csr: &Csr,
+    dominators: &FxHashMap<Index, Index>,
 ) -> Result<AnalysisResultType, AnalysisError> {
-    let reachable = find_reachable_indices(root, graph);
-    let dominator_addrs = find_addrs_of_filtered_edges(root, &reachable, dominators, graph);
-
-    let (dominated, rest) = {
-        let mut not_dominated: Vec<Object> = Vec::new();
-
-        let dominated = graph.filter_map(
-            |i, w| {
-                if i == root || dominator_addrs.contains_key(&w.address) {
-                    Some(w.clone())
-                } else if reachable.contains(&i) {
-                    not_dominated.push(w.clone());
-                    None
-                } else {
-                    None
-                }
-            },
-            |_, e| Some(*e),
-        );
-
-        (dominated, not_dominated)
-    };
-
-    if reachable.len() > graph.node_count()
-        || dominator_addrs.len() > graph.node_count()
-        || dominator_addrs.len() > dominators.len()
-        || dominator_addrs.len() > reachable.len()
-        || dominated.node_count() + rest.len() != reachable.len()
-        || dominated.node_count() > dominator_addrs.len() + 1
-    {
-        return Err(AnalysisError::NodeCountMismatch);
-    }
+    let reachable = csr.reachable(root);
+    let reachable_count = reachable.iter().filter(|r| **r).count();
+    let dominator_addrs = find_addrs_of_filtered_edges(root, &reachable, dominators, &graph);
 
     // Prove that the optimization of passing the reachable set to `find_addrs_of_filtered_edges`
     // does not change results
     debug_assert_eq!(
         dominator_addrs.len(),
-        find_addrs_of_filtered_edges(root, &graph.node_indices().collect(), dominators, graph)
+        find_addrs_of_filtered_edges(root, &vec![true; graph.node_count()], dominators, &graph)
             .len()
     );
 
-    let (root, dominators) = map_indices(&dominated, &dominator_addrs, graph[root].address);
+    let keep: Vec<bool> = graph
+        .node_indices()
+        .map(|i| i == root || dominator_addrs.contains_key(&graph[i].address))
+        .collect();
+    let node_count = graph.node_count();
+    let root_addr = graph[root].address;
+    let (dominated, not_dominated) = split_graph(graph, &keep);
+    let rest: Vec<Object> = not_dominated
+        .into_iter()
+        .filter(|(i, _)| reachable[i.index()])
+        .map(|(_, w)| w)
+        .collect();
+
+    if reachable_count > node_count
+        || dominator_addrs.len() > node_count
+        || dominator_addrs.len() > dominators.len()
+        || dominator_addrs.len() > reachable_count
+        || dominated.node_count() + rest.len() != reachable_count
+        || dominated.node_count() > dominator_addrs.len() + 1
+    {
+        return Err(AnalysisError::NodeCountMismatch);
+    }
+
+    let (root, dominators) = map_indices(&dominated, &dominator_addrs, root_addr);
     Ok((root, dominated, rest, dominators))
 }
 
 #[timed]
 fn find_addrs_of_filtered_edges(
     root: Index,
-    reachable: &HashSet<Index>,
-    tree_edges: &HashMap<Index, Index>,
+    reachable: &[bool],
+    tree_edges: &FxHashMap<Index, Index>,
     graph: &ReferenceGraph,
-) -> HashMap<usize, usize> {
-    let mut result: HashMap<usize, usize> = HashMap::new();
+) -> FxHashMap<usize, usize> {
+    let mut result: FxHashMap<usize, usize> = FxHashMap::default();
 
     // Re-usable buffer
     let mut descendents: Vec<Index> = Vec::new();
@@ -212,7 +549,7 @@ fn find_addrs_of_filtered_edges(
         let mut parent = *p;
 
         loop {
-            if !reachable.contains(&parent) {
+            if !reachable[parent.index()] {
                 // We've proved this subtree is _not_ rooted at this root
                 // (this an optimization; we'll get the same results if we
                 // never hit this case)
@@ -244,8 +581,8 @@ fn find_addrs_of_filtered_edges(
 }
 
 #[timed]
-fn find_reachable_indices(root: Index, graph: &ReferenceGraph) -> HashSet<Index> {
-    let mut reachable: HashSet<Index> = HashSet::new();
+fn find_reachable_indices(root: Index, graph: &ReferenceGraph) -> FxHashSet<Index> {
+    let mut reachable: FxHashSet<Index> = FxHashSet::default();
     reachable.insert(root);
 
     let mut dfs = Dfs::new(&graph, root);
@@ -256,13 +593,66 @@ fn find_reachable_indices(root: Index, graph: &ReferenceGraph) -> HashSet<Index>
     reachable
 }
 
+// Objects that would become unreachable if every object in `set` were
+// dropped, including those in the set.
+pub fn freed_by_set(root: Index, graph: &ReferenceGraph, set: &HashSet<Index>) -> Vec<Index> {
+    let reachable = find_reachable_indices(root, graph);
+
+    let mut reachable_without_set: FxHashSet<Index> = FxHashSet::default();
+    let mut stack: Vec<Index> = Vec::new();
+    if !set.contains(&root) {
+        reachable_without_set.insert(root);
+        stack.push(root);
+    }
+    while let Some(i) = stack.pop() {
+        for n in graph.neighbors(i) {
+            if !set.contains(&n) && reachable_without_set.insert(n) {
+                stack.push(n);
+            }
+        }
+    }
+
+    reachable
+        .into_iter()
+        .filter(|i| !reachable_without_set.contains(i))
+        .collect()
+}
+
+// Memory that would become unreachable if every object in `set` were dropped.
+//
+// This can be more than the sum of the objects' dominator subtrees, since it
+// includes anything which is only kept alive by the set as a whole.
+#[timed]
+pub fn retained_by_set(root: Index, graph: &ReferenceGraph, set: &HashSet<Index>) -> Stats {
+    freed_by_set(root, graph, set)
+        .iter()
+        .fold(Stats::default(), |mut acc, i| acc.add(graph[*i].stats()))
+}
+
+// The memory retained_by_set counts, by kind: the `top_n` kinds with the
+// most, largest first, and the total of the rest.
+#[timed]
+pub fn retained_by_set_by_kind(
+    root: Index,
+    graph: &ReferenceGraph,
+    set: &HashSet<Index>,
+    top_n: usize,
+) -> (Vec<(Symbol, Stats)>, Stats) {
+    let freed = freed_by_set(root, graph, set);
+    let stats = by_kind(freed.iter().map(|i| (&graph[*i], graph[*i].stats())));
+    largest_and_rest(stats.into_iter().map(|(k, v)| (*k, v)), top_n, 0, |_, c| {
+        c.bytes
+    })
+}
+
 fn map_indices(
     graph: &ReferenceGraph,
-    addr_edges: &HashMap<usize, usize>,
+    addr_edges: &FxHashMap<usize, usize>,
     root: usize,
-) -> (Index, HashMap<Index, Index>) {
+) -> (Index, FxHashMap<Index, Index>) {
     let index_by_addr = {
-        let mut index_by_addr: HashMap<usize, Index> = HashMap::with_capacity(graph.node_count());
+        let mut index_by_addr: FxHashMap<usize, Index> =
+            FxHashMap::with_capacity_and_hasher(graph.node_count(), Default::default());
         for i in graph.node_indices() {
             index_by_addr.insert(graph[i].address, i);
         }
@@ -270,7 +660,7 @@ fn map_indices(
     };
 
     let mapped_edges = {
-        let mut mapped_edges: HashMap<Index, Index> = HashMap::new();
+        let mut mapped_edges: FxHashMap<Index, Index> = FxHashMap::default();
         for (a, d) in addr_edges {
             if let (Some(i), Some(j)) = (index_by_addr.get(a), index_by_addr.get(d)) {
                 mapped_edges.insert(*i, *j);
@@ -284,29 +674,124 @@ fn map_indices(
 
 fn dominator_subtree_sizes(
     graph: &ReferenceGraph,
-    dominators: &HashMap<Index, Index>,
-) -> HashMap<Index, Stats> {
-    let mut subtree_sizes: HashMap<Index, Stats> = HashMap::new();
+    dominators: &FxHashMap<Index, Index>,
+    tree: &DominatorTree,
+) -> FxHashMap<Index, Stats> {
+    dominator_subtree_sizes_with_hooks(graph, dominators, tree, &Hooks::default())
+        .expect("default hooks never cancel")
+}
+
+fn address_indices(graph: &ReferenceGraph) -> FxHashMap<usize, Index> {
+    graph
+        .node_indices()
+        .map(|i| (graph[i].address, i))
+        .collect()
+}
+
+// The dominator tree, as the children of each node.
+#[derive(Debug)]
+struct DominatorTree {
+    // Where each node's children start in `children`
+    offsets: Vec<usize>,
+    children: Vec<Index>,
+}
+
+impl DominatorTree {
+    fn new(node_count: usize, dominators: &FxHashMap<Index, Index>) -> DominatorTree {
+        let mut offsets = vec![0; node_count + 1];
+        for d in dominators.values() {
+            offsets[d.index() + 1] += 1;
+        }
+        for i in 0..node_count {
+            offsets[i + 1] += offsets[i];
+        }
+        let mut next = offsets.clone();
+        let mut children = vec![Index::new(0); dominators.len()];
+        for (child, d) in dominators {
+            children[next[d.index()]] = *child;
+            next[d.index()] += 1;
+        }
+        DominatorTree { offsets, children }
+    }
+
+    fn children(&self, i: Index) -> &[Index] {
+        &self.children[self.offsets[i.index()]..self.offsets[i.index() + 1]]
+    }
+}
+
+// Sizes of the subtree rooted at `top` and all the subtrees within it, adding
+// each node's to its parent's after visiting them breadth first, so that
+// every child is added before its parent.
+fn accumulate_subtree(
+    top: Index,
+    graph: &ReferenceGraph,
+    tree: &DominatorTree,
+    hooks: &Hooks,
+    done: &AtomicUsize,
+) -> Result<Vec<(Index, Stats)>, Cancelled> {
+    // Each node with the position of its parent
+    let mut order: Vec<(Index, usize)> = vec![(top, 0)];
+    let mut k = 0;
+    while k < order.len() {
+        let i = order[k].0;
+        order.extend(tree.children(i).iter().map(|c| (*c, k)));
+        k += 1;
+    }
 
-    // Assign each node's stats to itself
-    for i in graph.node_indices() {
-        subtree_sizes.insert(i, graph[i].stats());
+    let mut stats: Vec<Stats> = order.iter().map(|(i, _)| graph[*i].stats()).collect();
+    for k in (1..order.len()).rev() {
+        if k % CHECK_INTERVAL == 0 {
+            hooks.check()?;
+            let done = done.fetch_add(CHECK_INTERVAL, Ordering::Relaxed) + CHECK_INTERVAL;
+            hooks.report(Progress::Nodes {
+                done,
+                total: graph.node_count(),
+            });
+        }
+        let (parent, child) = (order[k].1, stats[k]);
+        stats[parent] = stats[parent].add(child);
     }
+    Ok(order.into_iter().map(|(i, _)| i).zip(stats).collect())
+}
 
-    // Assign each node's stats to all of its dominators
-    for mut i in graph.node_indices() {
-        let stats = graph[i].stats();
-        while let Some(&d) = dominators.get(&i) {
-            subtree_sizes.entry(d).and_modify(|e| *e = (*e).add(stats));
-            i = d;
+#[timed]
+fn dominator_subtree_sizes_with_hooks(
+    graph: &ReferenceGraph,
+    dominators: &FxHashMap<Index, Index>,
+    tree: &DominatorTree,
+    hooks: &Hooks,
+) -> Result<FxHashMap<Index, Stats>, Cancelled> {
+    timing::nodes(graph.node_count());
+    let done = AtomicUsize::new(0);
+    let mut subtree_sizes: FxHashMap<Index, Stats> =
+        FxHashMap::with_capacity_and_hasher(graph.node_count(), Default::default());
+
+    // Each subtree below the top of the tree can be summed up independently
+    for top in graph.node_indices().filter(|i| !dominators.contains_key(i)) {
+        let children = tree.children(top);
+        let accumulate = |child: &Index| accumulate_subtree(*child, graph, tree, hooks, &done);
+        #[cfg(feature = "parallel")]
+        let subtrees: Vec<_> = {
+            use rayon::prelude::*;
+            children.par_iter().map(accumulate).collect()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let subtrees: Vec<_> = children.iter().map(accumulate).collect();
+
+        let mut stats = graph[top].stats();
+        for subtree in subtrees {
+            let subtree = subtree?;
+            stats = stats.add(subtree[0].1);
+            subtree_sizes.extend(subtree);
         }
+        subtree_sizes.insert(top, stats);
     }
 
-    subtree_sizes
+    Ok(subtree_sizes)
 }
 
-fn by_kind<'a, I: Iterator<Item = (&'a Object, Stats)>>(objs: I) -> HashMap<&'a String, Stats> {
-    objs.fold(HashMap::new(), |mut by_kind, (obj, stats)| {
+fn by_kind<'a, I: Iterator<Item = (&'a Object, Stats)>>(objs: I) -> FxHashMap<&'a Symbol, Stats> {
+    objs.fold(FxHashMap::default(), |mut by_kind, (obj, stats)| {
         by_kind
             .entry(&obj.kind)
             .and_modify(|c| *c = (*c).add(stats))
@@ -315,127 +800,1880 @@ fn by_kind<'a, I: Iterator<Item = (&'a Object, Stats)>>(objs: I) -> HashMap<&'a
     })
 }
 
-fn largest_and_rest<'a, K, I: Iterator<Item = (&'a K, Stats)>>(
+// The `count` items largest by `key`, ties broken by the items themselves so
+// the order doesn't depend on how they were collected, and the total of the rest.
+fn largest_and_rest<K, I, F>(
     iter: I,
     count: usize,
-) -> (Vec<(&'a K, Stats)>, Stats) {
-    let sorted = {
-        let mut vec: Vec<(&'a K, Stats)> = iter.collect();
-        vec.sort_unstable_by_key(|(_, c)| usize::MAX - c.bytes);
-        vec
-    };
-
-    if count >= sorted.len() {
-        (sorted, Stats::default())
-    } else {
-        (
-            sorted[0..count].to_vec(),
-            sorted[count..]
-                .iter()
-                .fold(Stats::default(), |mut acc, (_, c)| acc.add(*c)),
-        )
-    }
+    min_bytes: usize,
+    key: F,
+) -> (Vec<(K, Stats)>, Stats)
+where
+    K: Ord,
+    I: Iterator<Item = (K, Stats)>,
+    F: Fn(&K, Stats) -> usize,
+{
+    let (mut sorted, small): (Vec<_>, Vec<_>) = iter.partition(|(_, c)| c.bytes >= min_bytes);
+    sorted.sort_unstable_by(|(a, x), (b, y)| key(b, *y).cmp(&key(a, *x)).then_with(|| a.cmp(b)));
+
+    let count = count.min(sorted.len());
+    let rest = sorted[count..]
+        .iter()
+        .chain(small.iter())
+        .fold(Stats::default(), |mut acc, (_, c)| acc.add(*c));
+    sorted.truncate(count);
+    (sorted, rest)
 }
 
 impl Analysis {
-    pub fn live_stats_by_kind(&self, top_n: usize) -> (Vec<(&String, Stats)>, Stats) {
-        let stats = by_kind(self.dominated_subgraph.node_indices().map(|i| {
-            let obj = &self.dominated_subgraph[i];
-            (obj, obj.stats())
-        }));
-        largest_and_rest(stats.iter().map(|(k, v)| (*k, *v)), top_n)
-    }
+    // Restrict the analysis to objects matching the filter, plus everything
+    // they dominate. Matching objects which aren't dominated by another match
+    // become direct children of the root.
+    pub fn restrict_to<F: Fn(&Object) -> bool>(self, filter: F) -> Analysis {
+        let graph = &self.dominated_subgraph;
+
+        let mut kept: FxHashSet<Index> = FxHashSet::default();
+        let mut stack: Vec<Index> = graph
+            .node_indices()
+            .filter(|i| *i != self.root && filter(&graph[*i]))
+            .collect();
+        while let Some(i) = stack.pop() {
+            if kept.insert(i) {
+                stack.extend(self.tree.children(i));
+            }
+        }
 
-    pub fn retained_stats_by_kind(&self, top_n: usize) -> (Vec<(&String, Stats)>, Stats) {
-        let stats = by_kind(self.dominated_subgraph.node_indices().map(|i| {
-            let obj = &self.dominated_subgraph[i];
-            (obj, self.subtree_sizes[&i])
-        }));
-        largest_and_rest(stats.iter().map(|(k, v)| (*k, *v)), top_n)
-    }
+        let root_addr = graph[self.root].address;
+        let mut dominator_addrs: FxHashMap<usize, usize> = FxHashMap::default();
+        for i in kept.iter() {
+            let d = self.dominators[i];
+            let d_addr = if kept.contains(&d) {
+                graph[d].address
+            } else {
+                root_addr
+            };
+            dominator_addrs.insert(graph[*i].address, d_addr);
+        }
 
-    pub fn unreachable_stats_by_kind(&self, top_n: usize) -> (Vec<(&String, Stats)>, Stats) {
-        let stats = by_kind(self.rest.iter().map(|o| (o, o.stats())));
-        largest_and_rest(stats.iter().map(|(k, v)| (*k, *v)), top_n)
-    }
+        let restricted = graph.filter_map(
+            |i, w| {
+                if i == self.root || kept.contains(&i) {
+                    Some(w.clone())
+                } else {
+                    None
+                }
+            },
+            |_, e| Some(*e),
+        );
 
-    pub fn dominator_subtree_stats(&self, top_n: usize) -> (Vec<(&Object, Stats)>, Stats) {
-        let (largest, rest) =
-            largest_and_rest(self.subtree_sizes.iter().map(|(k, v)| (k, *v)), top_n);
-        (
-            largest
-                .into_iter()
-                .map(|(i, stats)| (&self.dominated_subgraph[*i], stats))
-                .collect(),
-            rest,
-        )
+        let (root, dominators) = map_indices(&restricted, &dominator_addrs, root_addr);
+        let tree = DominatorTree::new(restricted.node_count(), &dominators);
+        let subtree_sizes = dominator_subtree_sizes(&restricted, &dominators, &tree);
+
+        Analysis {
+            root,
+            indices: address_indices(&restricted),
+            dominated_subgraph: restricted,
+            rest: self.rest.into_iter().filter(|o| filter(o)).collect(),
+            dominators,
+            tree,
+            subtree_sizes,
+            class_name_only: self.class_name_only,
+            excluded_kinds: self.excluded_kinds,
+            min_bytes: self.min_bytes,
+            sort: self.sort,
+            frame_format: self.frame_format,
+            flame_min_bytes: self.flame_min_bytes,
+            merge_siblings: self.merge_siblings,
+            flame_max_depth: self.flame_max_depth,
+        }
     }
 
-    pub fn relevant_dominator_subgraph(&self, relevance_threshold: f64) -> ReferenceGraph {
-        let threshold_bytes =
-            (self.dominated_totals().bytes as f64 * relevance_threshold).floor() as usize;
-
-        let mut subgraph: ReferenceGraph = Graph::default();
-        let mut old_to_new: HashMap<Index, Index> = HashMap::new();
+    // Derive the analysis of the subtree rooted at the given address. This is
+    // only meaningful for an analysis of the whole heap, since the objects
+    // reachable from the new root are found via the references we kept.
+    pub fn rooted_at(&self, address: usize) -> Option<Analysis> {
+        self.rooted_at_all(&[address])
+    }
 
-        for (i, stats) in self
-            .subtree_sizes
+    // Derive the analysis of the union of the subtrees rooted at the given
+    // addresses. If there is more than one, they are placed under a synthetic
+    // root, except where one of them dominates another.
+    pub fn rooted_at_all(&self, addresses: &[usize]) -> Option<Analysis> {
+        let graph = &self.dominated_subgraph;
+        let roots = addresses
             .iter()
-            .filter(|(_, stats)| stats.bytes >= threshold_bytes)
-        {
-            let obj = &self.dominated_subgraph[*i];
-            let added = subgraph.add_node(obj.with_dominator_stats(*stats));
-            old_to_new.insert(*i, added);
+            .map(|address| self.find(*address))
+            .collect::<Option<Vec<Index>>>()?;
+        if roots.contains(&self.root) {
+            return self.rooted_at_all(&[graph[self.root].address]);
         }
 
-        for (old, new) in old_to_new.iter() {
-            if let Some(d) = self.dominators.get(old) {
-                subgraph.add_edge(old_to_new[d], *new, EDGE_WEIGHT);
+        let mut dominator_addrs: FxHashMap<usize, usize> = FxHashMap::default();
+        let mut stack = roots.clone();
+        while let Some(i) = stack.pop() {
+            for child in self.tree.children(i) {
+                dominator_addrs.insert(graph[*child].address, graph[i].address);
+                stack.push(*child);
             }
         }
 
-        subgraph
+        let mut reachable: FxHashSet<Index> = FxHashSet::default();
+        for root in roots.iter() {
+            if !reachable.contains(root) {
+                reachable.extend(find_reachable_indices(*root, graph));
+            }
+        }
+
+        let mut rest: Vec<Object> = Vec::new();
+        let mut dominated = graph.filter_map(
+            |i, w| {
+                if roots.contains(&i) || dominator_addrs.contains_key(&w.address) {
+                    Some(w.clone())
+                } else {
+                    if reachable.contains(&i) {
+                        rest.push(w.clone());
+                    }
+                    None
+                }
+            },
+            |_, e| Some(*e),
+        );
+
+        let top_level: FxHashSet<usize> = roots
+            .iter()
+            .map(|i| graph[*i].address)
+            .filter(|address| !dominator_addrs.contains_key(address))
+            .collect();
+
+        let root_addr = if top_level.len() == 1 {
+            *top_level.iter().next().unwrap()
+        } else {
+            let synthetic = Object::root();
+            let synthetic_addr = synthetic.address;
+            let synthetic_index = dominated.add_node(synthetic);
+            for i in dominated.node_indices() {
+                let address = dominated[i].address;
+                if top_level.contains(&address) {
+                    dominated.add_edge(synthetic_index, i, EDGE_WEIGHT);
+                    dominator_addrs.insert(address, synthetic_addr);
+                }
+            }
+            synthetic_addr
+        };
+
+        let (root, dominators) = map_indices(&dominated, &dominator_addrs, root_addr);
+        let tree = DominatorTree::new(dominated.node_count(), &dominators);
+        let subtree_sizes = dominator_subtree_sizes(&dominated, &dominators, &tree);
+
+        Some(Analysis {
+            root,
+            indices: address_indices(&dominated),
+            dominated_subgraph: dominated,
+            rest,
+            dominators,
+            tree,
+            subtree_sizes,
+            class_name_only: self.class_name_only,
+            excluded_kinds: self.excluded_kinds.clone(),
+            min_bytes: self.min_bytes,
+            sort: self.sort,
+            frame_format: self.frame_format,
+            flame_min_bytes: self.flame_min_bytes,
+            merge_siblings: self.merge_siblings,
+            flame_max_depth: self.flame_max_depth,
+        })
     }
 
-    pub fn dominated_totals(&self) -> Stats {
-        self.subtree_sizes[&self.root]
+    pub fn exclude_kinds<I: IntoIterator<Item = String>>(&mut self, kinds: I) {
+        self.excluded_kinds.extend(kinds);
     }
 
-    // Produces valid input for inferno::flamegraph::from_lines
-    //
-    // The basic idea is that we treat every reachable byte as a sample.
-    pub fn flamegraph_lines(&self) -> Result<Vec<String>, std::fmt::Error> {
-        let mut lines = Vec::with_capacity(self.dominated_subgraph.node_count());
+    pub fn set_min_bytes(&mut self, min_bytes: usize) {
+        self.min_bytes = min_bytes;
+    }
 
-        // Re-usable buffer
-        let mut ancestors: Vec<Index> = Vec::new();
+    pub fn set_sort(&mut self, sort: SortKey) {
+        self.sort = sort;
+    }
 
-        for mut i in self.dominators.keys() {
-            let node = &self.dominated_subgraph[*i];
+    pub fn set_frame_format(&mut self, frame_format: FrameFormat) {
+        self.frame_format = frame_format;
+    }
 
-            while let Some(d) = self.dominators.get(i) {
-                ancestors.push(*d);
-                i = d;
-            }
+    pub fn set_flame_min_bytes(&mut self, flame_min_bytes: usize) {
+        self.flame_min_bytes = flame_min_bytes;
+    }
 
-            let mut line = String::new();
-            for d in ancestors.iter().rev() {
-                write!(
-                    line,
-                    "{}",
-                    self.dominated_subgraph[*d].format(self.class_name_only)
-                )?;
-                line.push(';');
-            }
-            ancestors.clear();
+    pub fn set_merge_siblings(&mut self, merge_siblings: bool) {
+        self.merge_siblings = merge_siblings;
+    }
 
-            write!(line, "{}", node.format(self.class_name_only))?;
-            line.push(' ');
-            write!(line, "{}", node.bytes)?;
+    pub fn set_flame_max_depth(&mut self, flame_max_depth: Option<usize>) {
+        self.flame_max_depth = flame_max_depth;
+    }
 
-            lines.push(line);
+    // Rows which are already retained stats sort the same by bytes or retained bytes
+    fn sort_key(&self, stats: Stats) -> usize {
+        match self.sort {
+            SortKey::Bytes | SortKey::Retained => stats.bytes,
+            SortKey::Count => stats.count,
         }
+    }
 
-        Ok(lines)
+    fn is_excluded(&self, obj: &Object) -> bool {
+        self.excluded_kinds.contains(obj.kind.as_str())
+    }
+
+    pub fn live_stats_by_kind(&self, top_n: usize) -> (Vec<(&Symbol, Stats)>, Stats) {
+        self.live_stats_by_kind_where(top_n, |_| true)
+    }
+
+    pub fn live_stats_by_kind_where<F: Fn(&Object) -> bool>(
+        &self,
+        top_n: usize,
+        filter: F,
+    ) -> (Vec<(&Symbol, Stats)>, Stats) {
+        let stats = by_kind(
+            self.dominated_subgraph
+                .node_weights()
+                .filter(|obj| !self.is_excluded(obj) && filter(obj))
+                .map(|obj| (obj, obj.stats())),
+        );
+
+        if self.sort == SortKey::Retained {
+            let retained = self.retained_by_kind(&filter);
+            largest_and_rest(
+                stats.iter().map(|(k, v)| (*k, *v)),
+                top_n,
+                self.min_bytes,
+                |k, _| retained[k].bytes,
+            )
+        } else {
+            largest_and_rest(
+                stats.iter().map(|(k, v)| (*k, *v)),
+                top_n,
+                self.min_bytes,
+                |_, c| self.sort_key(c),
+            )
+        }
+    }
+
+    pub fn retained_stats_by_kind(&self, top_n: usize) -> (Vec<(&Symbol, Stats)>, Stats) {
+        self.retained_stats_by_kind_where(top_n, |_| true)
+    }
+
+    pub fn retained_stats_by_kind_where<F: Fn(&Object) -> bool>(
+        &self,
+        top_n: usize,
+        filter: F,
+    ) -> (Vec<(&Symbol, Stats)>, Stats) {
+        let stats = self.retained_by_kind(&filter);
+        largest_and_rest(
+            stats.iter().map(|(k, v)| (*k, *v)),
+            top_n,
+            self.min_bytes,
+            |_, c| self.sort_key(c),
+        )
+    }
+
+    fn retained_by_kind<F: Fn(&Object) -> bool>(&self, filter: &F) -> FxHashMap<&Symbol, Stats> {
+        by_kind(self.dominated_subgraph.node_indices().filter_map(|i| {
+            let obj = &self.dominated_subgraph[i];
+            if !self.is_excluded(obj) && filter(obj) {
+                Some((obj, self.subtree_sizes[&i]))
+            } else {
+                None
+            }
+        }))
+    }
+
+    // Total size of the reachable objects matching the filter.
+    pub fn live_where<F: Fn(&Object) -> bool>(&self, filter: F) -> Stats {
+        self.dominated_subgraph
+            .node_weights()
+            .filter(|obj| !obj.is_root() && filter(obj))
+            .fold(Stats::default(), |mut total, obj| total.add(obj.stats()))
+    }
+
+    // Total memory retained by objects matching the filter, counting objects
+    // dominated by several matches only once.
+    pub fn retained_where<F: Fn(&Object) -> bool>(&self, filter: F) -> Stats {
+        let mut total = Stats::default();
+        let mut stack = vec![self.root];
+        while let Some(i) = stack.pop() {
+            if i != self.root && filter(&self.dominated_subgraph[i]) {
+                total = total.add(self.subtree_sizes[&i]);
+            } else {
+                stack.extend(self.tree.children(i));
+            }
+        }
+        total
+    }
+
+    // Memory retained by objects matching the filter within each subtree
+    // headed by an object matching `under` (but not within another such
+    // subtree), counting objects dominated by several matches only once.
+    pub fn retained_under<U, F>(&self, under: U, filter: F) -> Vec<(&Object, Stats)>
+    where
+        U: Fn(&Object) -> bool,
+        F: Fn(&Object) -> bool,
+    {
+        let tree = &self.tree;
+        let mut found = vec![];
+        let mut heads = vec![self.root];
+        while let Some(i) = heads.pop() {
+            let head = &self.dominated_subgraph[i];
+            if !under(head) {
+                heads.extend(tree.children(i));
+                continue;
+            }
+
+            let mut total = Stats::default();
+            let mut stack = tree.children(i).to_vec();
+            while let Some(j) = stack.pop() {
+                if filter(&self.dominated_subgraph[j]) {
+                    total = total.add(self.subtree_sizes[&j]);
+                } else {
+                    stack.extend(tree.children(j));
+                }
+            }
+            found.push((head, total));
+        }
+        found
+    }
+
+    // The objects holding the most instances matching the filter, by their
+    // instances' own sizes: each instance counts towards its nearest
+    // dominator which doesn't match, so nested instances count towards the
+    // holder of the outermost.
+    pub fn holders_of<F: Fn(&Object) -> bool>(
+        &self,
+        top_n: usize,
+        filter: F,
+    ) -> (Vec<(&Object, Stats)>, Stats) {
+        let graph = &self.dominated_subgraph;
+        let tree = &self.tree;
+        let mut held: FxHashMap<Index, Stats> = FxHashMap::default();
+        let mut stack: Vec<(Index, Index)> = tree
+            .children(self.root)
+            .iter()
+            .map(|&i| (i, self.root))
+            .collect();
+        while let Some((i, holder)) = stack.pop() {
+            let obj = &graph[i];
+            let holder = if filter(obj) {
+                let stats = held.entry(holder).or_default();
+                *stats = stats.add(obj.stats());
+                holder
+            } else {
+                i
+            };
+            stack.extend(tree.children(i).iter().map(|&j| (j, holder)));
+        }
+
+        let (largest, rest) = largest_and_rest(
+            held.into_iter()
+                .map(|(i, stats)| (self.by_address(i), stats)),
+            top_n,
+            self.min_bytes,
+            |_, c| self.sort_key(c),
+        );
+        (self.objects_of(largest), rest)
+    }
+
+    pub fn unreachable_stats_by_kind(&self, top_n: usize) -> (Vec<(&Symbol, Stats)>, Stats) {
+        let stats = by_kind(
+            self.rest
+                .iter()
+                .filter(|o| !self.is_excluded(o))
+                .map(|o| (o, o.stats())),
+        );
+        largest_and_rest(
+            stats.iter().map(|(k, v)| (*k, *v)),
+            top_n,
+            self.min_bytes,
+            |_, c| self.sort_key(c),
+        )
+    }
+
+    pub fn dominator_subtree_stats(&self, top_n: usize) -> (Vec<(&Object, Stats)>, Stats) {
+        self.dominator_subtree_stats_where(top_n, |_| true)
+    }
+
+    pub fn dominator_subtree_stats_where<F: Fn(&Object) -> bool>(
+        &self,
+        top_n: usize,
+        filter: F,
+    ) -> (Vec<(&Object, Stats)>, Stats) {
+        self.objects_where(top_n, |obj, _| filter(obj))
+    }
+
+    // Like dominator_subtree_stats_where, but the filter also sees the
+    // memory retained by each object.
+    pub fn objects_where<F: Fn(&Object, Stats) -> bool>(
+        &self,
+        top_n: usize,
+        filter: F,
+    ) -> (Vec<(&Object, Stats)>, Stats) {
+        let (largest, rest) = largest_and_rest(
+            self.subtree_sizes
+                .iter()
+                .filter(|(i, stats)| filter(&self.dominated_subgraph[**i], **stats))
+                .map(|(i, stats)| (self.by_address(*i), *stats)),
+            top_n,
+            self.min_bytes,
+            |_, c| self.sort_key(c),
+        );
+        (self.objects_of(largest), rest)
+    }
+
+    // The largest instances matching the filter by their own sizes, with
+    // what each retains, and the total own size of the rest.
+    pub fn largest_instances<F: Fn(&Object) -> bool>(
+        &self,
+        top_n: usize,
+        filter: F,
+    ) -> (Vec<(&Object, Stats)>, Stats) {
+        let (largest, rest) = largest_and_rest(
+            self.subtree_sizes
+                .keys()
+                .filter(|i| {
+                    let obj = &self.dominated_subgraph[**i];
+                    !obj.is_root() && filter(obj)
+                })
+                .map(|i| (self.by_address(*i), self.dominated_subgraph[*i].stats())),
+            top_n,
+            0,
+            |_, c| c.bytes,
+        );
+        let largest = largest
+            .into_iter()
+            .map(|((_, i), _)| (&self.dominated_subgraph[i], self.subtree_sizes[&i]))
+            .collect();
+        (largest, rest)
+    }
+
+    // Immediate children of the given object in the dominator tree, largest first.
+    pub fn dominator_children(
+        &self,
+        address: usize,
+        top_n: usize,
+    ) -> Option<(Vec<(&Object, Stats)>, Stats)> {
+        let parent = self.find(address)?;
+        let children = self
+            .tree
+            .children(parent)
+            .iter()
+            .map(|c| (self.by_address(*c), self.subtree_sizes[c]));
+
+        let (largest, rest) =
+            largest_and_rest(children, top_n, self.min_bytes, |_, c| self.sort_key(c));
+        Some((self.objects_of(largest), rest))
+    }
+
+    // Sorts objects by address, then position, in largest_and_rest.
+    fn by_address(&self, i: Index) -> (usize, Index) {
+        (self.dominated_subgraph[i].address, i)
+    }
+
+    fn objects_of(&self, rows: Vec<((usize, Index), Stats)>) -> Vec<(&Object, Stats)> {
+        rows.into_iter()
+            .map(|((_, i), stats)| (&self.dominated_subgraph[i], stats))
+            .collect()
+    }
+
+    // Fills in labels of objects parsed without them, by address.
+    pub fn set_labels(&mut self, mut labels: HashMap<usize, Box<str>>) {
+        for obj in self.dominated_subgraph.node_weights_mut() {
+            if let Some(label) = labels.remove(&obj.address) {
+                obj.label = Some(label);
+            }
+        }
+    }
+
+    pub fn root(&self) -> &Object {
+        &self.dominated_subgraph[self.root]
+    }
+
+    // Every object dominated by the root, with the memory it retains.
+    pub fn objects(&self) -> impl Iterator<Item = (&Object, Stats)> {
+        self.dominated_subgraph
+            .node_indices()
+            .map(|i| (&self.dominated_subgraph[i], self.subtree_sizes[&i]))
+    }
+
+    // References between objects dominated by the root, as (from, to).
+    pub fn references(&self) -> impl Iterator<Item = (&Object, &Object)> {
+        self.dominated_subgraph.raw_edges().iter().map(|e| {
+            (
+                &self.dominated_subgraph[e.source()],
+                &self.dominated_subgraph[e.target()],
+            )
+        })
+    }
+
+    // Edges of the dominator tree, as (dominator, dominated).
+    pub fn dominator_edges(&self) -> impl Iterator<Item = (&Object, &Object)> {
+        self.dominated_subgraph.node_indices().filter_map(|i| {
+            let d = self.dominators.get(&i)?;
+            Some((&self.dominated_subgraph[*d], &self.dominated_subgraph[i]))
+        })
+    }
+
+    pub fn retained_stats(&self, address: usize) -> Option<(&Object, Stats)> {
+        let i = self.find(address)?;
+        Some((&self.dominated_subgraph[i], self.subtree_sizes[&i]))
+    }
+
+    // Objects directly referencing the given object.
+    pub fn referrers(&self, address: usize) -> Option<Vec<&Object>> {
+        let i = self.find(address)?;
+        Some(
+            self.dominated_subgraph
+                .neighbors_directed(i, Direction::Incoming)
+                .map(|r| &self.dominated_subgraph[r])
+                .collect(),
+        )
+    }
+
+    // The object's dominators, from the root down to the object itself.
+    pub fn dominator_chain(&self, address: usize) -> Option<Vec<&Object>> {
+        let mut i = self.find(address)?;
+        let mut chain = vec![&self.dominated_subgraph[i]];
+        while let Some(&d) = self.dominators.get(&i) {
+            chain.push(&self.dominated_subgraph[d]);
+            i = d;
+        }
+        chain.reverse();
+        Some(chain)
+    }
+
+    // A shortest chain of references from the root to the given object.
+    pub fn shortest_path(&self, address: usize) -> Option<Vec<&Object>> {
+        let target = self.find(address)?;
+        let graph = &self.dominated_subgraph;
+
+        let mut parents: FxHashMap<Index, Index> = FxHashMap::default();
+        let mut queue = std::collections::VecDeque::from([self.root]);
+        while let Some(i) = queue.pop_front() {
+            if i == target {
+                break;
+            }
+            for n in graph.neighbors(i) {
+                if n != self.root && !parents.contains_key(&n) {
+                    parents.insert(n, i);
+                    queue.push_back(n);
+                }
+            }
+        }
+
+        let mut path = vec![&graph[target]];
+        let mut i = target;
+        while let Some(&p) = parents.get(&i) {
+            path.push(&graph[p]);
+            i = p;
+        }
+        if i != self.root {
+            return None;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    fn find(&self, address: usize) -> Option<Index> {
+        self.indices.get(&address).copied()
+    }
+
+    pub fn shared_regions(&self, top_n: usize) -> (Vec<SharedRegion<'_>>, Stats) {
+        let graph = &self.dominated_subgraph;
+        let root_refs: FxHashSet<Index> = graph.neighbors(self.root).collect();
+
+        let heads = self
+            .tree
+            .children(self.root)
+            .iter()
+            .filter(|i| !root_refs.contains(i))
+            .map(|i| (self.by_address(*i), self.subtree_sizes[i]));
+
+        let (largest, rest) =
+            largest_and_rest(heads, top_n, self.min_bytes, |_, c| self.sort_key(c));
+        let regions = largest
+            .into_iter()
+            .map(|((_, head), stats)| {
+                let external: FxHashSet<Index> = graph
+                    .neighbors_directed(head, Direction::Incoming)
+                    .filter(|r| !self.is_dominated_by(*r, head))
+                    .collect();
+
+                let mut referrers: Vec<&Object> = external
+                    .iter()
+                    .filter(|r| {
+                        !external
+                            .iter()
+                            .any(|other| other != *r && self.is_dominated_by(**r, *other))
+                    })
+                    .map(|r| &graph[*r])
+                    .collect();
+                referrers.sort_unstable_by_key(|obj| obj.address);
+
+                SharedRegion {
+                    head: &graph[head],
+                    stats,
+                    referrers,
+                }
+            })
+            .collect();
+
+        (regions, rest)
+    }
+
+    // The largest strongly connected components of more than one object.
+    // The dominator tree doesn't show cycles, as each member is dominated by
+    // the one the cycle is entered through.
+    pub fn cycles(&self, top_n: usize) -> (Vec<Cycle<'_>>, Stats) {
+        let graph = &self.dominated_subgraph;
+        let components = petgraph::algo::kosaraju_scc(graph)
+            .into_iter()
+            .filter(|members| members.len() > 1)
+            .map(|members| {
+                let stats = members.iter().fold(Stats::default(), |mut stats, i| {
+                    stats.add(graph[*i].stats())
+                });
+                let head = *members
+                    .iter()
+                    .max_by_key(|i| {
+                        (
+                            self.subtree_sizes[i].bytes,
+                            std::cmp::Reverse(graph[**i].address),
+                        )
+                    })
+                    .unwrap();
+                ((graph[head].address, members, head), stats)
+            });
+        let (largest, rest) =
+            largest_and_rest(components, top_n, self.min_bytes, |_, c| self.sort_key(c));
+
+        let cycles = largest
+            .into_iter()
+            .map(|((_, members, head), stats)| {
+                let set: HashSet<Index> = members.iter().copied().collect();
+                Cycle {
+                    head: &graph[head],
+                    stats,
+                    path: self.cycle_through(head, &members),
+                    retainer: &graph[self.common_dominator(&members, &set)],
+                    pinned: retained_by_set(self.root, graph, &set),
+                }
+            })
+            .collect();
+        (cycles, rest)
+    }
+
+    // The objects referenced by the most others (not counting the root), with
+    // how many, most first. Shared objects like these are why objects' own
+    // retained sizes can look too small: what they retain is left to the root.
+    pub fn most_referenced(&self, top_n: usize) -> Vec<(&Object, usize)> {
+        let graph = &self.dominated_subgraph;
+        let mut referrers = vec![0; graph.node_count()];
+        for edge in graph.raw_edges() {
+            if edge.source() != self.root {
+                referrers[edge.target().index()] += 1;
+            }
+        }
+        let mut most: Vec<(&Object, usize)> = graph
+            .node_indices()
+            .filter(|i| referrers[i.index()] > 0 && !self.is_excluded(&graph[*i]))
+            .map(|i| (&graph[i], referrers[i.index()]))
+            .collect();
+        most.sort_unstable_by(|(a, x), (b, y)| y.cmp(x).then(a.address.cmp(&b.address)));
+        most.truncate(top_n);
+        most
+    }
+
+    // The nearest common dominator of `members` not in `set`.
+    fn common_dominator(&self, members: &[Index], set: &HashSet<Index>) -> Index {
+        let mut chain = vec![members[0]];
+        while let Some(&d) = self.dominators.get(chain.last().unwrap()) {
+            chain.push(d);
+        }
+        let position: FxHashMap<Index, usize> =
+            chain.iter().enumerate().map(|(p, i)| (*i, p)).collect();
+
+        let mut top = 0;
+        for &member in &members[1..] {
+            let mut i = member;
+            while !position.contains_key(&i) {
+                i = self.dominators[&i];
+            }
+            top = top.max(position[&i]);
+        }
+        while set.contains(&chain[top]) {
+            top += 1;
+        }
+        chain[top]
+    }
+
+    // A shortest path from `head` back to itself through `members`.
+    fn cycle_through(&self, head: Index, members: &[Index]) -> Vec<&Object> {
+        let graph = &self.dominated_subgraph;
+        let members: FxHashSet<Index> = members.iter().copied().collect();
+        let mut parents: FxHashMap<Index, Index> = FxHashMap::default();
+        let mut queue = std::collections::VecDeque::from([head]);
+        'search: while let Some(i) = queue.pop_front() {
+            for n in graph.neighbors(i) {
+                if n == head {
+                    parents.insert(head, i);
+                    break 'search;
+                }
+                if members.contains(&n) && !parents.contains_key(&n) {
+                    parents.insert(n, i);
+                    queue.push_back(n);
+                }
+            }
+        }
+
+        let mut path = vec![&graph[head]];
+        let mut i = parents[&head];
+        while i != head {
+            path.push(&graph[i]);
+            i = parents[&i];
+        }
+        path.push(&graph[head]);
+        path.reverse();
+        path
+    }
+
+    // What each class retains as a whole: the graph is contracted to one
+    // node per class, referencing the classes its instances reference, and
+    // each class retains the instances of the classes it dominates in that
+    // graph. This finds classes which retain a lot between their instances
+    // even when no one instance dominates much.
+    pub fn class_dominator_stats(&self, top_n: usize) -> (Vec<(Symbol, Stats)>, Stats) {
+        let graph = &self.dominated_subgraph;
+
+        // The root is node 0, and the classes follow
+        let mut classes: Vec<Symbol> = vec![graph[self.root].kind];
+        let mut totals = vec![graph[self.root].stats()];
+        let mut class_nodes: FxHashMap<Symbol, usize> = FxHashMap::default();
+        let node_class: Vec<usize> = graph
+            .node_indices()
+            .map(|i| {
+                let obj = &graph[i];
+                if i == self.root {
+                    return 0;
+                }
+                let class = *class_nodes.entry(obj.kind).or_insert_with(|| {
+                    classes.push(obj.kind);
+                    totals.push(Stats::default());
+                    classes.len() - 1
+                });
+                totals[class] = totals[class].add(obj.stats());
+                class
+            })
+            .collect();
+
+        let mut edges: Vec<(usize, usize)> = graph
+            .raw_edges()
+            .iter()
+            .map(|e| {
+                (
+                    node_class[e.source().index()],
+                    node_class[e.target().index()],
+                )
+            })
+            .filter(|(a, b)| a != b)
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+        let Ok(csr) = Csr::build(&Heap, classes.len(), edges.len(), || edges.iter().copied());
+        let dominators = dominator::immediate_dominators(&csr, NodeIndex::new(0));
+
+        // Add each class's retained stats to its dominator's, deepest first
+        let mut children: Vec<Vec<usize>> = vec![vec![]; classes.len()];
+        for (i, d) in dominators.iter().enumerate() {
+            if let Some(d) = d.filter(|_| i != 0) {
+                children[d.index()].push(i);
+            }
+        }
+        let mut order = vec![0];
+        let mut next = 0;
+        while next < order.len() {
+            order.extend_from_slice(&children[order[next]]);
+            next += 1;
+        }
+        let mut retained = totals;
+        for &i in order.iter().skip(1).rev() {
+            if let Some(d) = dominators[i] {
+                let child = retained[i];
+                retained[d.index()] = retained[d.index()].add(child);
+            }
+        }
+
+        let rows = order
+            .into_iter()
+            .skip(1)
+            .filter(|&i| !self.excluded_kinds.contains(classes[i].as_str()))
+            .map(|i| (classes[i], retained[i]));
+        largest_and_rest(rows, top_n, self.min_bytes, |_, c| self.sort_key(c))
+    }
+
+    // Live memory by the origin of the code allocating each object or, where
+    // the dump doesn't say, defining its class or allocating its nearest
+    // dominator of known origin; largest first.
+    pub fn origin_stats(&self) -> Vec<(Origin, Stats)> {
+        let graph = &self.dominated_subgraph;
+        // Where classes were allocated, i.e. defined, by their names
+        let class_files: FxHashMap<&str, &str> = graph
+            .node_weights()
+            .filter(|obj| matches!(obj.internal_type.as_str(), "CLASS" | "MODULE"))
+            .filter_map(|obj| {
+                let name = obj.label.as_deref()?.split('[').next()?;
+                Some((name, obj.file?.as_str()))
+            })
+            .collect();
+        let mut origins: FxHashMap<&str, Origin> = FxHashMap::default();
+        let mut origin_of = |obj: &Object| {
+            let file = obj
+                .file
+                .map(|file| file.as_str())
+                .or_else(|| class_files.get(obj.kind.as_str()).copied())?;
+            Some(*origins.entry(file).or_insert_with(|| Origin::of_file(file)))
+        };
+
+        let mut stats: FxHashMap<Origin, Stats> = FxHashMap::default();
+        let tree = &self.tree;
+        let mut stack = vec![(self.root, Origin::Unknown)];
+        while let Some((i, inherited)) = stack.pop() {
+            let obj = &graph[i];
+            let origin = origin_of(obj).unwrap_or(inherited);
+            if i != self.root {
+                let total = stats.entry(origin).or_default();
+                *total = total.add(obj.stats());
+            }
+            stack.extend(tree.children(i).iter().map(|&c| (c, origin)));
+        }
+
+        let mut stats: Vec<(Origin, Stats)> = stats.into_iter().collect();
+        stats.sort_by_key(|(origin, stats)| (std::cmp::Reverse(stats.bytes), origin.to_string()));
+        stats
+    }
+
+    fn is_dominated_by(&self, mut i: Index, dominator: Index) -> bool {
+        while let Some(&d) = self.dominators.get(&i) {
+            if d == dominator {
+                return true;
+            }
+            i = d;
+        }
+        false
+    }
+
+    pub fn relevant_dominator_subgraph(&self, relevance_threshold: f64) -> DominatorGraph {
+        let threshold_bytes =
+            (self.dominated_totals().bytes as f64 * relevance_threshold).floor() as usize;
+        self.dominator_subgraph(threshold_bytes, None)
+    }
+
+    // The part of the dominator tree retaining at least `min_bytes`, and at
+    // most `max_depth` levels below the root, with a "(deeper)" leaf under
+    // each object at that depth standing in for everything below it.
+    pub fn dominator_subgraph(&self, min_bytes: usize, max_depth: Option<usize>) -> DominatorGraph {
+        let depths = max_depth.map(|_| self.depths());
+        let max_depth = max_depth.unwrap_or(usize::MAX);
+
+        let mut subgraph: DominatorGraph = Graph::default();
+        let mut old_to_new: FxHashMap<Index, Index> = FxHashMap::default();
+
+        let mut kept = vec![];
+        let mut deepest = vec![];
+        for i in self.dominated_subgraph.node_indices() {
+            let stats = self.subtree_sizes[&i];
+            let depth = depths.as_ref().map_or(0, |depths| depths[&i]);
+            if stats.bytes >= min_bytes && depth <= max_depth {
+                let dominator = Dominator {
+                    object: self.dominated_subgraph[i].clone(),
+                    retained: stats,
+                };
+                old_to_new.insert(i, subgraph.add_node(dominator));
+                kept.push(i);
+                if depth == max_depth && stats.count > 1 {
+                    deepest.push(i);
+                }
+            }
+        }
+
+        for old in kept {
+            if let Some(d) = self.dominators.get(&old) {
+                subgraph.add_edge(old_to_new[d], old_to_new[&old], EDGE_WEIGHT);
+            }
+        }
+
+        for old in deepest {
+            let obj = &self.dominated_subgraph[old];
+            let stats = self.subtree_sizes[&old];
+            let deeper = Dominator {
+                object: Object {
+                    bytes: 0,
+                    kind: DEEPER.into(),
+                    class: DEEPER.into(),
+                    internal_type: DEEPER.into(),
+                    label: Some(DEEPER.into()),
+                    ..obj.clone()
+                },
+                retained: Stats {
+                    count: stats.count - 1,
+                    bytes: stats.bytes - obj.bytes,
+                },
+            };
+            let new = subgraph.add_node(deeper);
+            subgraph.add_edge(old_to_new[&old], new, EDGE_WEIGHT);
+        }
+
+        subgraph
+    }
+
+    // The depth of each object in the dominator tree, the root's being 0.
+    fn depths(&self) -> FxHashMap<Index, usize> {
+        let mut depths: FxHashMap<Index, usize> = FxHashMap::default();
+        depths.insert(self.root, 0);
+
+        // Objects whose depths aren't known yet, each dominated by the next
+        let mut chain = vec![];
+        for i in self.dominated_subgraph.node_indices() {
+            let mut j = i;
+            while !depths.contains_key(&j) {
+                chain.push(j);
+                match self.dominators.get(&j) {
+                    Some(&d) => j = d,
+                    None => break,
+                }
+            }
+            let mut depth = depths.get(&j).copied().unwrap_or(0);
+            for k in chain.drain(..).rev() {
+                if k != j {
+                    depth += 1;
+                }
+                depths.insert(k, depth);
+            }
+        }
+        depths
+    }
+
+    pub fn graph_stats(&self) -> GraphStats {
+        let graph = &self.dominated_subgraph;
+        let mut referrers = vec![0; graph.node_count()];
+        let mut references = vec![0; graph.node_count()];
+        for edge in graph.raw_edges() {
+            if edge.source() != self.root {
+                referrers[edge.target().index()] += 1;
+                references[edge.source().index()] += 1;
+            }
+        }
+        let without_root = |mut values: Vec<usize>| {
+            values.remove(self.root.index());
+            values
+        };
+        let components: Vec<usize> = petgraph::algo::kosaraju_scc(graph)
+            .iter()
+            .map(|members| members.len())
+            .filter(|n| *n > 1)
+            .collect();
+        let depths = self.depths();
+
+        GraphStats {
+            live: self.dominated_totals(),
+            unreachable: self
+                .rest
+                .iter()
+                .fold(Stats::default(), |mut stats, obj| stats.add(obj.stats())),
+            references: references.iter().sum(),
+            referrers_per_object: Percentiles::of(without_root(referrers)),
+            references_per_object: Percentiles::of(without_root(references)),
+            cycles: components.len(),
+            cyclic: components.iter().sum(),
+            dominator_depth: Percentiles::of(
+                depths
+                    .iter()
+                    .filter(|(i, _)| **i != self.root)
+                    .map(|(_, depth)| *depth)
+                    .collect(),
+            ),
+        }
+    }
+
+    // The string values with the most bytes in copies beyond the first,
+    // compared by `value_hash`, a hash of each string's whole value, and the
+    // total count and wasted bytes of the other duplicated values.
+    pub fn duplicate_strings<F>(
+        &self,
+        top_n: usize,
+        value_hash: F,
+    ) -> (Vec<DuplicateString<'_>>, Stats)
+    where
+        F: Fn(&Object) -> Option<u64>,
+    {
+        let graph = &self.dominated_subgraph;
+        let mut values: FxHashMap<u64, Vec<Index>> = FxHashMap::default();
+        for i in graph.node_indices() {
+            let obj = &graph[i];
+            if obj.internal_type.as_str() != "STRING" {
+                continue;
+            }
+            if let Some(hash) = value_hash(obj) {
+                values.entry(hash).or_default().push(i);
+            }
+        }
+
+        let mut duplicates: Vec<DuplicateString> = values
+            .into_values()
+            .filter(|copies| copies.len() > 1)
+            .map(|copies| {
+                let value = copies
+                    .iter()
+                    .find_map(|&i| graph[i].label.as_deref()?.split_once("]["))
+                    .and_then(|(_, value)| value.strip_suffix(']'))
+                    .unwrap_or("");
+                let mut stats = Stats::default();
+                let mut holders: FxHashMap<Symbol, usize> = FxHashMap::default();
+                for &i in &copies {
+                    stats = stats.add(graph[i].stats());
+                    let mut holder = i;
+                    while let Some(&d) = self.dominators.get(&holder) {
+                        holder = d;
+                        let obj = &graph[d];
+                        if d == self.root || !CONTAINERS.contains(&obj.internal_type.as_str()) {
+                            break;
+                        }
+                    }
+                    *holders.entry(graph[holder].kind).or_default() += 1;
+                }
+                let largest = copies.iter().map(|&i| graph[i].bytes).max().unwrap_or(0);
+                let mut holders: Vec<(Symbol, usize)> = holders.into_iter().collect();
+                holders.sort_unstable_by(|(a, x), (b, y)| y.cmp(x).then_with(|| a.cmp(b)));
+                DuplicateString {
+                    value,
+                    stats,
+                    wasted: stats.bytes - largest,
+                    holders,
+                }
+            })
+            .collect();
+        duplicates
+            .sort_unstable_by(|a, b| b.wasted.cmp(&a.wasted).then_with(|| a.value.cmp(b.value)));
+
+        let count = top_n.min(duplicates.len());
+        let rest = duplicates[count..]
+            .iter()
+            .fold(Stats::default(), |mut rest, d| {
+                rest.add(Stats {
+                    count: d.stats.count,
+                    bytes: d.wasted,
+                })
+            });
+        duplicates.truncate(count);
+        (duplicates, rest)
+    }
+
+    // Objects' own sizes by their depth in the dominator tree, in bands
+    // doubling in width: 1, 2-3, 4-7 and so on. Much memory in deep bands
+    // means long chains, like linked lists, each retaining the rest.
+    pub fn depth_histogram(&self) -> Vec<DepthBand<'_>> {
+        let mut bands: Vec<FxHashMap<&Symbol, Stats>> = vec![];
+        for (i, depth) in self.depths() {
+            if i == self.root {
+                continue;
+            }
+            let band = depth.ilog2() as usize;
+            if bands.len() <= band {
+                bands.resize_with(band + 1, FxHashMap::default);
+            }
+            let obj = &self.dominated_subgraph[i];
+            let stats = bands[band].entry(&obj.kind).or_default();
+            *stats = stats.add(obj.stats());
+        }
+
+        bands
+            .into_iter()
+            .enumerate()
+            .filter(|(_, kinds)| !kinds.is_empty())
+            .map(|(band, kinds)| {
+                let stats = kinds
+                    .values()
+                    .fold(Stats::default(), |mut total, stats| total.add(*stats));
+                let (kind, _) = kinds
+                    .iter()
+                    .max_by(|(a, x), (b, y)| x.bytes.cmp(&y.bytes).then_with(|| b.cmp(a)))
+                    .unwrap();
+                DepthBand {
+                    min: 1 << band,
+                    max: (1 << (band + 1)) - 1,
+                    stats,
+                    kind,
+                }
+            })
+            .collect()
+    }
+
+    pub fn dominated_totals(&self) -> Stats {
+        self.subtree_sizes[&self.root]
+    }
+
+    // Label of an object in flamegraphs & other profile formats.
+    pub fn frame_name(&self, obj: &Object) -> String {
+        obj.format(self.class_name_only)
+    }
+
+    // Writes the flamegraph frames of an object in a stack, separated by ';'.
+    fn write_frames(&self, line: &mut String, obj: &Object) -> fmt::Result {
+        let file = || obj.file.as_ref().map(|file| file.as_str());
+        match self.frame_format {
+            _ if obj.is_root() => write!(line, "{}", self.frame_name(obj)),
+            FrameFormat::Object => write!(line, "{}", self.frame_name(obj)),
+            FrameFormat::Class => write!(line, "{}", obj.kind),
+            FrameFormat::ClassType => write!(line, "{};{}", obj.kind, obj.internal_type),
+            FrameFormat::GemClass => {
+                let gem = match file() {
+                    Some(file) => gem(file).unwrap_or("(app)"),
+                    None => "(unknown)",
+                };
+                write!(line, "{};{}", gem, obj.kind)
+            }
+            FrameFormat::FileClass => {
+                write!(line, "{};{}", file().unwrap_or("(unknown)"), obj.kind)
+            }
+        }
+    }
+
+    // Calls `f` with the chain of dominators from the root down to each
+    // object, ending with the object itself; excluded kinds are left out.
+    pub fn dominator_stacks<E, F>(&self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(&[&Object]) -> Result<(), E>,
+    {
+        // Re-usable buffer
+        let mut objects: Vec<&Object> = Vec::new();
+
+        self.dominator_index_stacks(|stack| {
+            objects.extend(stack.iter().map(|&i| &self.dominated_subgraph[i]));
+            let result = f(&objects);
+            objects.clear();
+            result
+        })
+    }
+
+    // Like `dominator_stacks`, but of node indices.
+    fn dominator_index_stacks<E, F>(&self, mut f: F) -> Result<(), E>
+    where
+        F: FnMut(&[Index]) -> Result<(), E>,
+    {
+        // Re-usable buffer
+        let mut stack: Vec<Index> = Vec::new();
+
+        // In graph order rather than the dominator map's, so output is the
+        // same from run to run
+        for mut i in self.dominated_subgraph.node_indices() {
+            let node = &self.dominated_subgraph[i];
+            if !self.dominators.contains_key(&i) || self.is_excluded(node) {
+                continue;
+            }
+
+            stack.push(i);
+            while let Some(&d) = self.dominators.get(&i) {
+                if !self.is_excluded(&self.dominated_subgraph[d]) {
+                    stack.push(d);
+                }
+                i = d;
+            }
+            stack.reverse();
+
+            f(&stack)?;
+            stack.clear();
+        }
+
+        Ok(())
+    }
+
+    // How much of a stack flamegraphs draw, and the frame, if any, standing
+    // in for the rest: stacks are folded into a "[small]" frame at their
+    // first object retaining less than `flame_min_bytes`, other than the
+    // root, and cut off below `flame_max_depth`.
+    fn truncated(&self, stack: &[Index]) -> (usize, Option<&'static str>) {
+        let small = stack
+            .iter()
+            .skip(1)
+            .position(|i| self.subtree_sizes[i].bytes < self.flame_min_bytes)
+            .map(|p| p + 1);
+        let deep = self
+            .flame_max_depth
+            .map(|depth| depth + 1)
+            .filter(|&len| len < stack.len());
+        match (small, deep) {
+            (Some(small), Some(deep)) if deep <= small => (deep, None),
+            (Some(small), _) => (small, Some("[small]")),
+            (None, Some(deep)) => (deep, None),
+            (None, None) => (stack.len(), None),
+        }
+    }
+
+    // Produces valid input for inferno::flamegraph::from_lines
+    //
+    // The basic idea is that we treat every reachable byte as a sample.
+    // Subtrees retaining less than `flame_min_bytes` become a single
+    // "[small]" frame under their dominator, and objects below
+    // `flame_max_depth` add to their ancestor at that depth, with one line
+    // for each such frame.
+    pub fn flamegraph_lines(&self) -> Result<Vec<String>, std::fmt::Error> {
+        if self.merge_siblings {
+            return self.merged_flamegraph_lines();
+        }
+
+        let mut lines = Vec::with_capacity(self.dominated_subgraph.node_count());
+        let mut truncated: Vec<(String, usize)> = Vec::new();
+        let mut truncated_lines: FxHashMap<String, usize> = FxHashMap::default();
+
+        self.dominator_index_stacks(|stack| {
+            let (len, rest) = self.truncated(stack);
+
+            let mut line = String::new();
+            for &i in &stack[..len] {
+                self.write_frames(&mut line, &self.dominated_subgraph[i])?;
+                line.push(';');
+            }
+            match rest {
+                Some(frame) => line.push_str(frame),
+                None => {
+                    line.pop();
+                }
+            }
+
+            let node = stack.last().expect("stacks end with their object");
+            let bytes = self.dominated_subgraph[*node].bytes;
+            if len < stack.len() {
+                match truncated_lines.get(&line) {
+                    Some(&j) => truncated[j].1 += bytes,
+                    None => {
+                        truncated_lines.insert(line.clone(), truncated.len());
+                        truncated.push((line, bytes));
+                    }
+                }
+                return Ok(());
+            }
+
+            line.push(' ');
+            write!(line, "{}", bytes)?;
+
+            lines.push(line);
+            Ok(())
+        })?;
+
+        for (mut line, bytes) in truncated {
+            write!(line, " {}", bytes)?;
+            lines.push(line);
+        }
+
+        Ok(lines)
+    }
+
+    // Like `flamegraph_lines`, but with siblings whose frames are the same
+    // merged into one frame, labeled with the number of objects it merges,
+    // e.g. "Hash ×10,000". Objects' own frames are their classes.
+    fn merged_flamegraph_lines(&self) -> Result<Vec<String>, std::fmt::Error> {
+        // The merged frames, each after its parent, and the objects ending
+        // at each; the root is first
+        let mut frames: Vec<(usize, String)> = vec![(0, String::new())];
+        let mut totals: Vec<Stats> = vec![Stats::default()];
+        let mut children: FxHashMap<(usize, String), usize> = FxHashMap::default();
+        let mut key = String::new();
+
+        self.dominator_index_stacks(|stack| {
+            let (len, rest) = self.truncated(stack);
+            let mut keys = stack[1..len]
+                .iter()
+                .map(|&i| {
+                    let obj = &self.dominated_subgraph[i];
+                    key.clear();
+                    match self.frame_format {
+                        FrameFormat::Object => write!(key, "{}", obj.kind)?,
+                        _ => self.write_frames(&mut key, obj)?,
+                    }
+                    Ok(key.clone())
+                })
+                .collect::<Result<Vec<String>, fmt::Error>>()?;
+            keys.extend(rest.map(|frame| frame.to_string()));
+
+            let mut frame = 0;
+            for key in keys {
+                frame = *children
+                    .entry((frame, key))
+                    .or_insert_with_key(|(parent, key)| {
+                        frames.push((*parent, key.clone()));
+                        totals.push(Stats::default());
+                        frames.len() - 1
+                    });
+            }
+            // Objects cut off below the maximum depth add to their
+            // ancestor's bytes, but not its count
+            let node = stack.last().expect("stacks end with their object");
+            let mut stats = self.dominated_subgraph[*node].stats();
+            if len < stack.len() && rest.is_none() {
+                stats.count = 0;
+            }
+            totals[frame] = totals[frame].add(stats);
+            Ok(())
+        })?;
+
+        let mut stacks: Vec<String> = Vec::with_capacity(frames.len());
+        stacks.push(self.frame_name(&self.dominated_subgraph[self.root]));
+        let mut lines = Vec::with_capacity(frames.len());
+        for (j, (parent, key)) in frames.into_iter().enumerate() {
+            if j > 0 {
+                let mut stack = format!("{};{}", stacks[parent], key);
+                if totals[j].count > 1 {
+                    write!(stack, " ×{}", grouped(totals[j].count))?;
+                }
+                stacks.push(stack);
+            }
+            if totals[j].count > 0 {
+                lines.push(format!("{} {}", stacks[j], totals[j].bytes));
+            }
+        }
+
+        Ok(lines)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse::{self, LabelIndex, ParseOptions};
+    use rstest::rstest;
+
+    // Builds the small dumps tests need an object at a time. Kinds in
+    // capitals are internal types, and others class names, whose CLASS
+    // lines are added for their instances to be named after.
+    #[derive(Default)]
+    struct Dump {
+        roots: Vec<usize>,
+        lines: Vec<String>,
+        classes: Vec<(String, usize)>,
+    }
+
+    impl Dump {
+        fn new(roots: &[usize]) -> Dump {
+            Dump {
+                roots: roots.to_vec(),
+                ..Default::default()
+            }
+        }
+
+        fn object(
+            mut self,
+            address: usize,
+            kind: &str,
+            bytes: usize,
+            references: &[usize],
+        ) -> Dump {
+            let kind = if kind.chars().all(|c| c.is_ascii_uppercase()) {
+                match kind {
+                    "ARRAY" => format!(r#""type":"ARRAY", "length":{}"#, references.len()),
+                    "HASH" => format!(r#""type":"HASH", "size":{}"#, references.len()),
+                    _ => format!(r#""type":"{}""#, kind),
+                }
+            } else {
+                format!(
+                    r#""type":"OBJECT", "class":"{:#x}""#,
+                    self.class_address(kind)
+                )
+            };
+            self.lines.push(format!(
+                r#"{{"address":"{:#x}", {}, "references":[{}], "memsize":{}"#,
+                address,
+                kind,
+                quoted(references),
+                bytes
+            ));
+            self
+        }
+
+        // A class at a given address, for objects to reference.
+        fn class(mut self, address: usize, name: &str) -> Dump {
+            self.classes.push((name.to_string(), address));
+            self.lines.push(format!(
+                r#"{{"address":"{:#x}", "type":"CLASS", "name":"{}", "references":[], "memsize":0"#,
+                address, name
+            ));
+            self
+        }
+
+        // Adds fields to the last line, e.g. `"value":"text/html"`.
+        fn with(mut self, fields: &str) -> Dump {
+            let line = self.lines.last_mut().unwrap();
+            line.push_str(", ");
+            line.push_str(fields);
+            self
+        }
+
+        fn class_address(&mut self, name: &str) -> usize {
+            if let Some((_, address)) = self.classes.iter().find(|(n, _)| n == name) {
+                return *address;
+            }
+            let address = 0x1000 + self.classes.len();
+            *self = std::mem::take(self).class(address, name);
+            address
+        }
+
+        fn parse(&self, options: &ParseOptions) -> (Index, ReferenceGraph, LabelIndex) {
+            let mut text = format!(
+                "{{\"type\":\"ROOT\", \"root\":\"vm\", \"references\":[{}]}}\n",
+                quoted(&self.roots)
+            );
+            for line in &self.lines {
+                text.push_str(line);
+                text.push_str("}\n");
+            }
+            parse::parse_bytes(text.as_bytes(), options).unwrap()
+        }
+
+        fn graph(&self) -> (Index, ReferenceGraph) {
+            let (root, graph, _) = self.parse(&ParseOptions::default());
+            (root, graph)
+        }
+
+        fn analysis(&self) -> Analysis {
+            let (root, graph) = self.graph();
+            analyze(root, root, graph, false).unwrap()
+        }
+    }
+
+    fn quoted(addresses: &[usize]) -> String {
+        let addresses: Vec<String> = addresses.iter().map(|a| format!("\"{:#x}\"", a)).collect();
+        addresses.join(", ")
+    }
+
+    fn frames(dump: &Dump, frame_format: FrameFormat) -> Vec<String> {
+        let mut analysis = dump.analysis();
+        analysis.set_frame_format(frame_format);
+        let mut lines = analysis.flamegraph_lines().unwrap();
+        lines.sort();
+        lines
+    }
+
+    #[rstest]
+    #[case(FrameFormat::Object, "root;Foo[0x1];Hash[0x2][size=0] 20")]
+    #[case(FrameFormat::Class, "root;Foo;HASH 20")]
+    #[case(FrameFormat::ClassType, "root;Foo;OBJECT;HASH;HASH 20")]
+    #[case(FrameFormat::GemClass, "root;rails;Foo;(unknown);HASH 20")]
+    #[case(
+        FrameFormat::FileClass,
+        "root;/gems/rails-7.0.1/a.rb;Foo;(unknown);HASH 20"
+    )]
+    fn frame_formats(#[case] frame_format: FrameFormat, #[case] expected: &str) {
+        let dump = Dump::new(&[0x1])
+            .object(0x1, "Foo", 10, &[0x2])
+            .with(r#""file":"/gems/rails-7.0.1/a.rb""#)
+            .object(0x2, "HASH", 20, &[]);
+        assert_eq!(expected, frames(&dump, frame_format)[1]);
+    }
+
+    #[rstest]
+    #[case("/usr/lib/ruby/gems/3.2.0/gems/rails-7.0.1/lib/rails.rb", "rails")]
+    #[case(
+        "/usr/lib/ruby/gems/3.2.0/gems/nokogiri-1.15.0-x86_64-linux/lib/a.rb",
+        "nokogiri"
+    )]
+    #[case(
+        "/srv/vendor/bundle/ruby/3.2.0/bundler/gems/my-gem-a1b2c3d4e5f6/lib/a.rb",
+        "my-gem"
+    )]
+    #[case("/srv/app/models/user.rb", "(app)")]
+    fn frame_format_gems(#[case] file: &str, #[case] gem: &str) {
+        let dump = Dump::new(&[0x1])
+            .object(0x1, "OBJECT", 10, &[])
+            .with(&format!(r#""file":"{}""#, file));
+        let lines = frames(&dump, FrameFormat::GemClass);
+        assert_eq!(vec![format!("root;{};OBJECT 10", gem)], lines);
+    }
+
+    #[rstest]
+    #[case::unmerged(false, vec!["root;Array[0x1][len=1] 10", "root;Array[0x1][len=1] 50", "root;STRING[0x4] 40"])]
+    #[case::merged(true, vec!["root;ARRAY 60", "root;STRING 40"])]
+    fn flame_max_depth(#[case] merge_siblings: bool, #[case] expected: Vec<&str>) {
+        let mut analysis = Dump::new(&[0x1, 0x4])
+            .object(0x1, "ARRAY", 10, &[0x2])
+            .object(0x2, "ARRAY", 20, &[0x3])
+            .object(0x3, "STRING", 30, &[])
+            .object(0x4, "STRING", 40, &[])
+            .analysis();
+        analysis.set_merge_siblings(merge_siblings);
+        analysis.set_flame_max_depth(Some(1));
+        let mut lines = analysis.flamegraph_lines().unwrap();
+        lines.sort();
+        assert_eq!(expected, lines);
+    }
+
+    // Objects' own frames are merged by class too
+    #[rstest]
+    fn merge_siblings(
+        #[values(FrameFormat::Object, FrameFormat::Class)] frame_format: FrameFormat,
+    ) {
+        let mut analysis = Dump::new(&[0x1, 0x4])
+            .object(0x1, "ARRAY", 10, &[0x2, 0x3, 0x5])
+            .object(0x2, "STRING", 20, &[])
+            .object(0x3, "STRING", 30, &[])
+            .object(0x5, "SYMBOL", 5, &[])
+            .object(0x4, "HASH", 40, &[])
+            .analysis();
+        analysis.set_frame_format(frame_format);
+        analysis.set_merge_siblings(true);
+        let mut lines = analysis.flamegraph_lines().unwrap();
+        lines.sort();
+        assert_eq!(
+            vec![
+                "root;ARRAY 10",
+                "root;ARRAY;STRING ×2 50",
+                "root;ARRAY;SYMBOL 5",
+                "root;HASH 40"
+            ],
+            lines
+        );
+    }
+
+    #[rstest]
+    #[case::none(0, 4)]
+    #[case::some(35, 3)]
+    #[case::most(61, 1)]
+    fn flame_min_bytes(#[case] min_bytes: usize, #[case] expected: usize) {
+        let mut analysis = Dump::new(&[0x1, 0x4])
+            .object(0x1, "ARRAY", 10, &[0x2, 0x3])
+            .object(0x2, "STRING", 20, &[])
+            .object(0x3, "STRING", 30, &[])
+            .object(0x4, "STRING", 40, &[])
+            .analysis();
+        analysis.set_flame_min_bytes(min_bytes);
+        let lines = analysis.flamegraph_lines().unwrap();
+        assert_eq!(expected, lines.len(), "{:?}", lines);
+
+        // Every byte is still there, just in fewer frames
+        let total: usize = lines
+            .iter()
+            .map(|line| line.rsplit_once(' ').unwrap().1.parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(100, total);
+        if expected < 4 {
+            assert!(lines.iter().any(|line| line.contains(";[small] ")));
+        }
+    }
+
+    #[test]
+    fn class_dominators() {
+        // No one Foo dominates the Bar, nor the Bar the Strings, but Foos as
+        // a whole dominate Bars
+        let analysis = Dump::new(&[0x1, 0x2])
+            .object(0x1, "Foo", 10, &[0x3])
+            .object(0x2, "Foo", 10, &[0x3, 0x4])
+            .object(0x3, "Bar", 20, &[0x5])
+            .object(0x4, "Bar", 20, &[0x5])
+            .object(0x5, "STRING", 40, &[])
+            .analysis();
+        let (largest, rest) = analysis.class_dominator_stats(usize::MAX);
+        let largest: Vec<(&str, usize, usize)> = largest
+            .iter()
+            .map(|(class, stats)| (class.as_str(), stats.count, stats.bytes))
+            .collect();
+        assert_eq!(
+            vec![("Foo", 5, 100), ("Bar", 3, 80), ("STRING", 1, 40)],
+            largest
+        );
+        assert_eq!(0, rest.count);
+    }
+
+    #[rstest]
+    #[case("class", &["Sidekiq::Job::Args", "Sidekiq::Job", "ActiveRecord::Base", "STRING", "ROOT"])]
+    #[case("namespace", &["Sidekiq", "ActiveRecord", "STRING", "ROOT"])]
+    #[case("namespace:2", &["Sidekiq::Job", "ActiveRecord::Base", "STRING", "ROOT"])]
+    fn namespaces(#[case] group_by: GroupBy, #[case] expected: &[&str]) {
+        let (root, mut graph) = Dump::new(&[0x1, 0x2, 0x3, 0x4])
+            .object(0x1, "Sidekiq::Job::Args", 40, &[])
+            .object(0x2, "Sidekiq::Job", 30, &[])
+            .object(0x3, "ActiveRecord::Base", 20, &[])
+            .object(0x4, "STRING", 10, &[])
+            .graph();
+        group_by.apply(&mut graph);
+        let analysis = analyze(root, root, graph, false).unwrap();
+        let (live_by_kind, _) = analysis.live_stats_by_kind(usize::MAX);
+        let kinds: Vec<&str> = live_by_kind.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(expected, kinds.as_slice());
+    }
+
+    #[rstest]
+    #[case(KindView::Both, GroupBy::Class)]
+    #[case(KindView::Type, GroupBy::Class)]
+    #[case(KindView::Class, GroupBy::Namespace(1))]
+    fn class_patterns_ignore_kind_views(#[case] view: KindView, #[case] group_by: GroupBy) {
+        let (root, mut graph) = Dump::new(&[0x3])
+            .object(0x1, "Foo::Bar", 40, &[0x2])
+            .object(0x2, "STRING", 20, &[])
+            .object(0x3, "ARRAY", 10, &[0x1])
+            .graph();
+        view.apply(&mut graph);
+        group_by.apply(&mut graph);
+        let analysis = analyze(root, root, graph, false).unwrap();
+        let pattern: ClassPattern = "Foo::Bar".parse().unwrap();
+
+        let (holders, _) = analysis.holders_of(10, |obj| pattern.matches(&obj.class));
+        let holders: Vec<usize> = holders.iter().map(|(obj, _)| obj.address).collect();
+        assert_eq!(vec![0x3], holders);
+        let restricted = analysis.restrict_to(|obj| pattern.matches(&obj.class));
+        assert_eq!(60, restricted.dominated_totals().bytes);
+    }
+
+    #[test]
+    fn groups() {
+        let analysis = Dump::new(&[0x1, 0x2, 0x3])
+            .object(0x1, "Dry::Types::Sum", 40, &[0x2, 0x4])
+            .object(0x2, "Dry::Schema", 30, &[])
+            .object(0x3, "PointStruct", 20, &[0x4])
+            .object(0x4, "STRING", 10, &[])
+            .analysis();
+        let group = |name: &str, pattern: &str| Group {
+            name: name.to_string(),
+            patterns: vec![pattern.parse().unwrap()],
+        };
+        let groups = [group("dry", "^Dry::"), group("structs", "Struct$")];
+
+        let stats: Vec<(&str, usize, usize)> = groups
+            .iter()
+            .map(|group| {
+                let live = analysis.live_where(|obj| group.matches(obj));
+                let retained = analysis.retained_where(|obj| group.matches(obj));
+                (group.name.as_str(), live.bytes, retained.bytes)
+            })
+            .collect();
+        // The String shared with the struct is retained by neither group
+        assert_eq!(vec![("dry", 70, 70), ("structs", 20, 20)], stats);
+    }
+
+    #[test]
+    fn origins() {
+        let analysis = Dump::new(&[0x10, 0x1, 0x2, 0x4, 0x5])
+            .class(0x10, "Foo")
+            .with(r#""file":"/app/models/foo.rb""#)
+            .object(0x1, "Foo", 40, &[])
+            .object(0x2, "OBJECT", 30, &[0x3])
+            .with(r#""file":"/gems/rack-2.2.3/lib/rack.rb""#)
+            .object(0x3, "STRING", 20, &[])
+            .object(0x4, "HASH", 15, &[])
+            .with(r#""file":"/usr/lib/ruby/3.2.0/set.rb""#)
+            .object(0x5, "STRING", 10, &[])
+            .analysis();
+        let origins: Vec<(String, usize)> = analysis
+            .origin_stats()
+            .iter()
+            .map(|(origin, stats)| (origin.to_string(), stats.bytes))
+            .collect();
+        // Foo's instance is attributed to where Foo is defined, and the
+        // String rack's object retains to rack
+        assert_eq!(
+            vec![
+                ("rack".to_string(), 50),
+                ("(app)".to_string(), 40),
+                ("(stdlib)".to_string(), 15),
+                ("(unknown)".to_string(), 10)
+            ],
+            origins
+        );
+    }
+
+    #[test]
+    fn cycles() {
+        // 0x1 <-> 0x2 and 0x3 -> 0x4 -> 0x5 -> 0x3, held by 0x7 and with
+        // 0x6 hanging off it
+        let analysis = Dump::new(&[0x1, 0x7])
+            .object(0x7, "OBJECT", 10, &[0x3])
+            .object(0x1, "OBJECT", 10, &[0x2])
+            .object(0x2, "OBJECT", 10, &[0x1])
+            .object(0x3, "OBJECT", 10, &[0x4, 0x6])
+            .object(0x4, "OBJECT", 10, &[0x5])
+            .object(0x5, "OBJECT", 10, &[0x3, 0x4])
+            .object(0x6, "STRING", 100, &[])
+            .analysis();
+        let (cycles, rest) = analysis.cycles(1);
+        assert_eq!(1, cycles.len());
+        assert_eq!((3, 30), (cycles[0].stats.count, cycles[0].stats.bytes));
+        assert_eq!(0x3, cycles[0].head.address);
+        let path: Vec<usize> = cycles[0].path.iter().map(|obj| obj.address).collect();
+        assert_eq!(vec![0x3, 0x4, 0x5, 0x3], path);
+        // The String is only reachable through the cycle
+        assert_eq!(0x7, cycles[0].retainer.address);
+        assert_eq!((4, 130), (cycles[0].pinned.count, cycles[0].pinned.bytes));
+        assert_eq!((2, 20), (rest.count, rest.bytes));
+    }
+
+    #[test]
+    fn most_referenced() {
+        let analysis = Dump::new(&[0x1, 0x2, 0x3])
+            .object(0x1, "OBJECT", 10, &[0x4, 0x5])
+            .object(0x2, "OBJECT", 10, &[0x4, 0x5])
+            .object(0x3, "OBJECT", 10, &[0x4])
+            .object(0x4, "STRING", 10, &[])
+            .object(0x5, "SYMBOL", 10, &[])
+            .analysis();
+        let most: Vec<(usize, usize)> = analysis
+            .most_referenced(usize::MAX)
+            .iter()
+            .map(|(obj, referrers)| (obj.address, *referrers))
+            .collect();
+        // References from the root don't count
+        assert_eq!(vec![(0x4, 3), (0x5, 2)], most);
+    }
+
+    #[test]
+    fn holders_of() {
+        let analysis = Dump::new(&[0x1, 0x6])
+            .object(0x1, "OBJECT", 10, &[0x2])
+            .object(0x2, "ARRAY", 10, &[0x3, 0x4])
+            .object(0x3, "STRING", 20, &[])
+            .object(0x4, "STRING", 20, &[0x5])
+            .object(0x5, "STRING", 20, &[])
+            .object(0x6, "HASH", 10, &[0x7])
+            .object(0x7, "STRING", 30, &[])
+            .analysis();
+        let (holders, rest) = analysis.holders_of(1, |obj| obj.kind.as_str() == "STRING");
+        let holders: Vec<(usize, usize, usize)> = holders
+            .iter()
+            .map(|(obj, stats)| (obj.address, stats.count, stats.bytes))
+            .collect();
+        // The String within a String counts towards the Array holding both
+        assert_eq!(vec![(0x2, 3, 60)], holders);
+        assert_eq!((1, 30), (rest.count, rest.bytes));
+    }
+
+    #[test]
+    fn duplicate_strings() {
+        let (root, graph, index) = Dump::new(&[0x1, 0x2, 0x6])
+            .object(0x1, "Response", 10, &[0x3])
+            .object(0x2, "Response", 10, &[0x5])
+            .object(0x3, "ARRAY", 10, &[0x4])
+            .object(0x4, "STRING", 40, &[])
+            .with(r#""value":"text/html""#)
+            .object(0x5, "STRING", 50, &[])
+            .with(r#""value":"text/html""#)
+            .object(0x6, "STRING", 40, &[0x7, 0x8, 0x9, 0xa])
+            .with(r#""value":"unique""#)
+            .object(0x7, "STRING", 60, &[])
+            .with(r#""value":"/app/vendor/bundle/ruby/3.3.0/gems/rack-3.0.8/lib/rack.rb""#)
+            .object(0x8, "STRING", 60, &[])
+            .with(r#""value":"/app/vendor/bundle/ruby/3.3.0/gems/rack-3.0.8/lib/rack/utils.rb""#)
+            .object(0x9, "STRING", 40, &[])
+            .with(r#""value":"a\nb""#)
+            .object(0xa, "STRING", 40, &[])
+            .with(r#""value":"ab""#)
+            .parse(&ParseOptions {
+                string_hashes: true,
+                ..Default::default()
+            });
+        let analysis = analyze(root, root, graph, false).unwrap();
+        // Strings whose labels show the same prefix aren't copies
+        let (duplicates, rest) =
+            analysis.duplicate_strings(10, |obj| index.value_hash(obj.address));
+        assert_eq!(1, duplicates.len());
+        assert_eq!("text/html", duplicates[0].value);
+        assert_eq!(
+            (2, 90),
+            (duplicates[0].stats.count, duplicates[0].stats.bytes)
+        );
+        // All but the largest copy
+        assert_eq!(40, duplicates[0].wasted);
+        // The Array holding one copy is a container, so its owner holds it
+        let holders: Vec<(&str, usize)> = duplicates[0]
+            .holders
+            .iter()
+            .map(|(kind, copies)| (kind.as_str(), *copies))
+            .collect();
+        assert_eq!(vec![("Response", 2)], holders);
+        assert_eq!(0, rest.count);
+    }
+
+    #[test]
+    fn freed_by_roots() {
+        // 0x3 is referenced by both 0x1 and 0x2, so dominated by neither
+        let (root, graph) = Dump::new(&[0x1, 0x2, 0x4])
+            .object(0x1, "OBJECT", 10, &[0x3])
+            .object(0x2, "OBJECT", 10, &[0x3])
+            .object(0x3, "STRING", 40, &[])
+            .object(0x4, "STRING", 20, &[])
+            .graph();
+        let freed = |addresses: &[usize]| {
+            let set: HashSet<Index> = graph
+                .node_indices()
+                .filter(|&i| addresses.contains(&graph[i].address))
+                .collect();
+            let (kinds, rest) = retained_by_set_by_kind(root, &graph, &set, 10);
+            assert_eq!(0, rest.count);
+            kinds
+                .iter()
+                .map(|(kind, stats)| (kind.to_string(), stats.count, stats.bytes))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![("OBJECT".to_string(), 1, 10)], freed(&[0x1]));
+        assert_eq!(
+            vec![("STRING".to_string(), 1, 40), ("OBJECT".to_string(), 2, 20)],
+            freed(&[0x1, 0x2])
+        );
+    }
+
+    #[test]
+    fn largest_instances() {
+        let analysis = Dump::new(&[0x1, 0x2, 0x3])
+            .object(0x1, "STRING", 20, &[0x4])
+            .with(r#""value":"small""#)
+            .object(0x2, "STRING", 50, &[])
+            .with(r#""value":"large""#)
+            .object(0x3, "OBJECT", 100, &[])
+            .object(0x4, "STRING", 40, &[])
+            .with(r#""value":"shared""#)
+            .analysis();
+        let (largest, rest) = analysis.largest_instances(2, |obj| obj.kind.as_str() == "STRING");
+        let largest: Vec<(String, usize)> = largest
+            .iter()
+            .map(|(obj, retained)| (obj.to_string(), retained.bytes))
+            .collect();
+        // Ordered by their own sizes, not what they retain
+        assert_eq!(
+            vec![
+                ("String[0x2][large]".to_string(), 50),
+                ("String[0x4][shared]".to_string(), 40),
+            ],
+            largest
+        );
+        assert_eq!((1, 20), (rest.count, rest.bytes));
+    }
+
+    #[test]
+    fn depth_histogram() {
+        // A linked list 6 long, and a String next to it
+        let analysis = Dump::new(&[0x1, 0x7])
+            .object(0x1, "OBJECT", 10, &[0x2])
+            .object(0x2, "OBJECT", 10, &[0x3])
+            .object(0x3, "OBJECT", 10, &[0x4])
+            .object(0x4, "OBJECT", 10, &[0x5])
+            .object(0x5, "OBJECT", 10, &[0x6])
+            .object(0x6, "OBJECT", 10, &[])
+            .object(0x7, "STRING", 100, &[])
+            .analysis();
+        let bands: Vec<(usize, usize, usize, usize, &str)> = analysis
+            .depth_histogram()
+            .iter()
+            .map(|b| (b.min, b.max, b.stats.count, b.stats.bytes, b.kind.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (1, 1, 2, 110, "STRING"),
+                (2, 3, 2, 20, "OBJECT"),
+                (4, 7, 3, 30, "OBJECT"),
+            ],
+            bands
+        );
     }
 }
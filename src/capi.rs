@@ -0,0 +1,314 @@
+//! C ABI for embedding reap in other runtimes; see include/reap.h.
+//!
+//! Every pointer returned here is owned by the caller and must be released
+//! with the matching `reap_*_free` function. Panics don't unwind into the
+//! caller, but fail the call as errors do.
+use crate::analyze::{self, Analysis};
+use crate::object::*;
+use crate::parse;
+use crate::query::Query;
+use crate::summary;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReapStats {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+impl From<Stats> for ReapStats {
+    fn from(stats: Stats) -> Self {
+        ReapStats {
+            count: stats.count as u64,
+            bytes: stats.bytes as u64,
+        }
+    }
+}
+
+pub struct ReapAnalysis(Analysis);
+
+unsafe fn set_error(error: *mut *mut c_char, message: String) {
+    if !error.is_null() {
+        *error = to_c_string(message);
+    }
+}
+
+fn to_c_string(s: String) -> *mut c_char {
+    // Interior NULs can't be represented, so drop them
+    CString::new(s.replace('\0', ""))
+        .expect("NULs removed")
+        .into_raw()
+}
+
+// Runs `f`, returning `failed` instead if it panics, with the panic's
+// message in `*error` if the caller asked for one.
+unsafe fn guard<T, F: FnOnce() -> T>(error: *mut *mut c_char, failed: T, f: F) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            set_error(error, format!("reap panicked: {}", message));
+            failed
+        }
+    }
+}
+
+fn analyze<R: BufRead + Seek>(
+    reader: &mut R,
+    class_name_only: bool,
+) -> Result<Analysis, Box<dyn std::error::Error>> {
+    let (root, graph) = parse::parse(reader, class_name_only)?;
+    Ok(analyze::analyze(root, root, graph, class_name_only)?)
+}
+
+fn into_raw(
+    result: Result<Analysis, Box<dyn std::error::Error>>,
+    error: *mut *mut c_char,
+) -> *mut ReapAnalysis {
+    match result {
+        Ok(analysis) => Box::into_raw(Box::new(ReapAnalysis(analysis))),
+        Err(e) => {
+            unsafe { set_error(error, e.to_string()) };
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Parses and analyzes the JSON heap dump at `path`.
+///
+/// Returns NULL on failure, setting `*error` (if `error` isn't NULL) to a
+/// message to be freed with `reap_string_free`.
+///
+/// # Safety
+///
+/// `path` must be a valid NUL-terminated string, and `error` NULL or valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn reap_analyze_file(
+    path: *const c_char,
+    class_name_only: c_int,
+    error: *mut *mut c_char,
+) -> *mut ReapAnalysis {
+    if path.is_null() {
+        set_error(error, "path is NULL".to_string());
+        return ptr::null_mut();
+    }
+    let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+    guard(error, ptr::null_mut(), || {
+        let result = File::open(path)
+            .map_err(|e| e.into())
+            .and_then(|file| analyze(&mut BufReader::new(file), class_name_only != 0));
+        into_raw(result, error)
+    })
+}
+
+/// Parses and analyzes a JSON heap dump held in memory.
+///
+/// # Safety
+///
+/// `data` must be valid for reads of `len` bytes, and `error` NULL or valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn reap_analyze_buffer(
+    data: *const u8,
+    len: usize,
+    class_name_only: c_int,
+    error: *mut *mut c_char,
+) -> *mut ReapAnalysis {
+    if data.is_null() {
+        set_error(error, "data is NULL".to_string());
+        return ptr::null_mut();
+    }
+    let mut reader = Cursor::new(std::slice::from_raw_parts(data, len));
+    guard(error, ptr::null_mut(), || {
+        into_raw(analyze(&mut reader, class_name_only != 0), error)
+    })
+}
+
+/// # Safety
+///
+/// `analysis` must be NULL or returned by `reap_analyze_*`, and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn reap_analysis_free(analysis: *mut ReapAnalysis) {
+    if !analysis.is_null() {
+        drop(Box::from_raw(analysis));
+    }
+}
+
+/// Memory used by all objects reachable from the root.
+///
+/// # Safety
+///
+/// `analysis` must be a live pointer returned by `reap_analyze_*`.
+#[no_mangle]
+pub unsafe extern "C" fn reap_totals(analysis: *const ReapAnalysis) -> ReapStats {
+    guard(ptr::null_mut(), ReapStats::default(), || {
+        (*analysis).0.dominated_totals().into()
+    })
+}
+
+/// Writes the memory retained by the object at `address` to `*out`, returning
+/// 0 if there's no such reachable object.
+///
+/// # Safety
+///
+/// `analysis` must be a live pointer returned by `reap_analyze_*`, and `out`
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn reap_retained_stats(
+    analysis: *const ReapAnalysis,
+    address: u64,
+    out: *mut ReapStats,
+) -> c_int {
+    guard(ptr::null_mut(), 0, || {
+        match (*analysis).0.retained_stats(address as usize) {
+            Some((_, stats)) => {
+                *out = stats.into();
+                1
+            }
+            None => 0,
+        }
+    })
+}
+
+/// A JSON summary of the heap, with the `count` largest rows of per-kind
+/// tables, to be freed with `reap_string_free`, or NULL on failure.
+///
+/// # Safety
+///
+/// `analysis` must be a live pointer returned by `reap_analyze_*`.
+#[no_mangle]
+pub unsafe extern "C" fn reap_summary_json(
+    analysis: *const ReapAnalysis,
+    count: usize,
+) -> *mut c_char {
+    guard(ptr::null_mut(), ptr::null_mut(), || {
+        let summary = summary::summarize(&(*analysis).0, count, 0, &[]);
+        to_c_string(serde_json::to_string(&summary).expect("summary is serializable"))
+    })
+}
+
+/// A JSON array of up to `count` objects matching `query` (see reap::query),
+/// largest first, or NULL if the query is invalid, setting `*error`.
+///
+/// # Safety
+///
+/// `analysis` must be a live pointer returned by `reap_analyze_*`, `query` a
+/// valid NUL-terminated string, and `error` NULL or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn reap_query_json(
+    analysis: *const ReapAnalysis,
+    query: *const c_char,
+    count: usize,
+    error: *mut *mut c_char,
+) -> *mut c_char {
+    if query.is_null() {
+        set_error(error, "query is NULL".to_string());
+        return ptr::null_mut();
+    }
+    let query: Query = match CStr::from_ptr(query).to_string_lossy().parse() {
+        Ok(query) => query,
+        Err(e) => {
+            set_error(error, e.to_string());
+            return ptr::null_mut();
+        }
+    };
+
+    guard(error, ptr::null_mut(), || {
+        let rows = summary::largest_objects(&(*analysis).0, count, &query);
+        to_c_string(serde_json::to_string(&rows).expect("objects are serializable"))
+    })
+}
+
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library, and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn reap_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_analyze_file() {
+        unsafe {
+            let path = CString::new("test/heap.json").unwrap();
+            let analysis = reap_analyze_file(path.as_ptr(), 0, ptr::null_mut());
+            assert!(!analysis.is_null());
+            assert_eq!(
+                ReapStats {
+                    count: 15472,
+                    bytes: 3439119
+                },
+                reap_totals(analysis)
+            );
+
+            let mut stats = ReapStats::default();
+            assert_eq!(1, reap_retained_stats(analysis, 0x7f83df87dc40, &mut stats));
+            assert_eq!(25, stats.count);
+            assert_eq!(0, reap_retained_stats(analysis, 0x1, &mut stats));
+
+            let query = CString::new("class == \"Thread\"").unwrap();
+            let json = reap_query_json(analysis, query.as_ptr(), 2, ptr::null_mut());
+            let rows: serde_json::Value =
+                serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            assert_eq!(2, rows.as_array().unwrap().len());
+            reap_string_free(json);
+
+            let json = reap_summary_json(analysis, 3);
+            assert!(CStr::from_ptr(json)
+                .to_str()
+                .unwrap()
+                .contains("\"Thread\""));
+            reap_string_free(json);
+
+            reap_analysis_free(analysis);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        unsafe {
+            let mut error: *mut c_char = ptr::null_mut();
+            let path = CString::new("test/missing.json").unwrap();
+            assert!(reap_analyze_file(path.as_ptr(), 0, &mut error).is_null());
+            assert!(!error.is_null());
+            reap_string_free(error);
+
+            let data = b"not json";
+            let mut error: *mut c_char = ptr::null_mut();
+            assert!(reap_analyze_buffer(data.as_ptr(), data.len(), 0, &mut error).is_null());
+            assert!(CStr::from_ptr(error).to_str().unwrap().contains("JSON"));
+            reap_string_free(error);
+        }
+    }
+
+    #[test]
+    fn test_panics() {
+        unsafe {
+            let mut error: *mut c_char = ptr::null_mut();
+            assert_eq!(0, guard(&mut error, 0, || panic!("oops")));
+            assert_eq!(
+                "reap panicked: oops",
+                CStr::from_ptr(error).to_str().unwrap()
+            );
+            reap_string_free(error);
+
+            assert_eq!(1, guard(ptr::null_mut(), 0, || 1));
+        }
+    }
+}
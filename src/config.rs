@@ -0,0 +1,366 @@
+use crate::analyze::{FrameFormat, SortKey};
+use crate::detect::Rule;
+use crate::object::{ClassPattern, Group};
+use crate::threshold::{Limit, Measure, Threshold};
+use bytesize::ByteSize;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &str = "reap.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IoError(std::io::Error),
+    TomlError(toml::de::Error),
+    InvalidPattern(String, regex::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::IoError(err) => write!(f, "Could not read config: {}", err),
+            ConfigError::TomlError(err) => write!(f, "Invalid config: {}", err),
+            ConfigError::InvalidPattern(pattern, err) => {
+                write!(f, "Invalid class pattern {:?}: {}", pattern, err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Limits on the instances of one class (name or regex)
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Budget {
+    pub retained: Option<ByteSize>,
+    pub live: Option<ByteSize>,
+    pub count: Option<usize>,
+}
+
+// Flags instances of `class` retaining more than `retained` between them
+// under each instance of `under`, or in the whole heap.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    pub name: String,
+    pub class: String,
+    pub under: Option<String>,
+    pub retained: ByteSize,
+    pub advice: Option<String>,
+}
+
+// Defaults for command line options, named like the corresponding flags,
+// plus budgets to check the heap against.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub threshold: Option<f64>,
+    pub dot_min_bytes: Option<ByteSize>,
+    pub count: Option<usize>,
+    pub min_bytes: Option<ByteSize>,
+    pub sort: Option<SortKey>,
+    pub frame_format: Option<FrameFormat>,
+    pub flame_min_bytes: Option<ByteSize>,
+    pub flame_min_percent: Option<f64>,
+    pub class_name_only: bool,
+    pub exclude_kind: Vec<String>,
+    pub cut_edges_from: Vec<String>,
+    pub flamegraph: Option<PathBuf>,
+    pub folded: Option<PathBuf>,
+    pub speedscope: Option<PathBuf>,
+    pub pprof: Option<PathBuf>,
+    pub d3_json: Option<PathBuf>,
+    pub treemap: Option<PathBuf>,
+    pub sunburst: Option<PathBuf>,
+    pub dot: Option<PathBuf>,
+    pub webhook: Option<String>,
+    pub webhook_only_violations: bool,
+    pub budgets: BTreeMap<String, Budget>,
+    pub groups: BTreeMap<String, Vec<String>>,
+    pub analyzers: Vec<PathBuf>,
+    pub rules: Vec<RuleConfig>,
+}
+
+// Finds the nearest reap.toml in `dir` or its ancestors.
+pub fn discover(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+fn parse_pattern(pattern: &str) -> Result<ClassPattern, ConfigError> {
+    pattern
+        .parse()
+        .map_err(|e| ConfigError::InvalidPattern(pattern.to_string(), e))
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = fs::read_to_string(path).map_err(ConfigError::IoError)?;
+        let mut config: Config = text.parse()?;
+        if let Some(dir) = path.parent() {
+            config.resolve_paths(dir);
+        }
+        Ok(config)
+    }
+
+    // Makes relative paths relative to `dir`, the config's directory, rather
+    // than wherever reap runs from. Analyzers named without a directory are
+    // left to be found on the PATH, as a shell would.
+    fn resolve_paths(&mut self, dir: &Path) {
+        for path in [
+            &mut self.flamegraph,
+            &mut self.folded,
+            &mut self.speedscope,
+            &mut self.pprof,
+            &mut self.d3_json,
+            &mut self.treemap,
+            &mut self.sunburst,
+            &mut self.dot,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = dir.join(&*path);
+        }
+        for program in self.analyzers.iter_mut() {
+            if program.components().nth(1).is_some() {
+                *program = dir.join(&*program);
+            }
+        }
+    }
+
+    pub fn cut_edges_from(&self) -> Result<Vec<ClassPattern>, ConfigError> {
+        self.cut_edges_from
+            .iter()
+            .map(|p| parse_pattern(p))
+            .collect()
+    }
+
+    pub fn groups(&self) -> Result<Vec<Group>, ConfigError> {
+        self.groups
+            .iter()
+            .map(|(name, patterns)| {
+                Ok(Group {
+                    name: name.clone(),
+                    patterns: patterns
+                        .iter()
+                        .map(|p| parse_pattern(p))
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect()
+    }
+
+    pub fn rules(&self) -> Result<Vec<Rule>, ConfigError> {
+        self.rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    name: rule.name.clone(),
+                    class: parse_pattern(&rule.class)?,
+                    under: rule.under.as_deref().map(parse_pattern).transpose()?,
+                    retained: rule.retained.as_u64() as usize,
+                    advice: rule.advice.clone(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn budgets(&self) -> Result<Vec<Threshold>, ConfigError> {
+        let mut thresholds = vec![];
+        for (pattern, budget) in self.budgets.iter() {
+            let class = parse_pattern(pattern)?;
+
+            let limits = [
+                (Measure::Retained, budget.retained.map(bytes)),
+                (Measure::Live, budget.live.map(bytes)),
+                (Measure::Live, budget.count.map(Limit::Count)),
+            ];
+            for (measure, limit) in limits {
+                if let Some(limit) = limit {
+                    thresholds.push(Threshold {
+                        class: Some(class.clone()),
+                        measure,
+                        limit,
+                    });
+                }
+            }
+        }
+        Ok(thresholds)
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).map_err(ConfigError::TomlError)
+    }
+}
+
+fn bytes(size: ByteSize) -> Limit {
+    Limit::Bytes(size.as_u64() as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_budgets() {
+        let config: Config = r#"
+            [budgets.String]
+            retained = "50MB"
+            count = 100_000
+
+            [budgets."ActiveRecord::.*"]
+            live = 1024
+        "#
+        .parse()
+        .unwrap();
+
+        let budgets = config.budgets().unwrap();
+        let summary: Vec<String> = budgets.iter().map(|b| b.to_string()).collect();
+        assert_eq!(
+            vec![
+                "live memory of /ActiveRecord::.*/",
+                "retained memory of String",
+                "live objects of String",
+            ],
+            summary
+        );
+        assert_eq!(Limit::Bytes(50_000_000), budgets[1].limit);
+    }
+
+    #[test]
+    fn test_groups() {
+        let config: Config = r#"
+            [groups]
+            dry = ["^Dry::"]
+            structs = ["Struct$", "OpenStruct"]
+        "#
+        .parse()
+        .unwrap();
+
+        let groups = config.groups().unwrap();
+        let summary: Vec<(&str, usize)> = groups
+            .iter()
+            .map(|g| (g.name.as_str(), g.patterns.len()))
+            .collect();
+        assert_eq!(vec![("dry", 1), ("structs", 2)], summary);
+
+        let invalid: Config = "groups = { bad = [\"(\"] }".parse().unwrap();
+        assert!(invalid.groups().is_err());
+    }
+
+    #[test]
+    fn test_rules() {
+        let config: Config = r#"
+            [[rules]]
+            name = "sessions in caches"
+            class = "Session"
+            under = "ActiveSupport::Cache::.*"
+            retained = "50MB"
+
+            [[rules]]
+            name = "too many strings"
+            class = "String"
+            retained = "1GB"
+            advice = "check for duplicates"
+        "#
+        .parse()
+        .unwrap();
+
+        let rules = config.rules().unwrap();
+        assert_eq!(2, rules.len());
+        assert_eq!(
+            Some("/ActiveSupport::Cache::.*/".to_string()),
+            rules[0].under.as_ref().map(|p| p.to_string())
+        );
+        assert_eq!(50_000_000, rules[0].retained);
+        assert!(rules[1].under.is_none());
+
+        assert!("[[rules]]\nname = \"x\"\nclass = \"String\""
+            .parse::<Config>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config: Config = r#"
+            count = 20
+            min-bytes = "10KB"
+            dot-min-bytes = "10MB"
+            sort = "retained"
+            frame-format = "gem-class"
+            flame-min-percent = 0.1
+            exclude-kind = ["IMEMO"]
+            cut-edges-from = ["Concurrent::Map"]
+            flamegraph = "tmp/heap.svg"
+            analyzers = ["bin/heap-checks"]
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(Some(20), config.count);
+        assert_eq!(Some(ByteSize::kb(10)), config.min_bytes);
+        assert_eq!(Some(ByteSize::mb(10)), config.dot_min_bytes);
+        assert_eq!(Some(SortKey::Retained), config.sort);
+        assert_eq!(Some(FrameFormat::GemClass), config.frame_format);
+        assert_eq!(Some(0.1), config.flame_min_percent);
+        assert_eq!(vec!["IMEMO"], config.exclude_kind);
+        assert_eq!(1, config.cut_edges_from().unwrap().len());
+        assert_eq!(Some(PathBuf::from("tmp/heap.svg")), config.flamegraph);
+        assert_eq!(vec![PathBuf::from("bin/heap-checks")], config.analyzers);
+        assert!(config.budgets.is_empty());
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = std::env::temp_dir().join(format!("reap-config-{}", std::process::id()));
+        let nested = dir.join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(None, discover(&nested).filter(|p| p.starts_with(&dir)));
+
+        fs::write(dir.join(FILE_NAME), "count = 5").unwrap();
+        assert_eq!(Some(dir.join(FILE_NAME)), discover(&nested));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_relative_paths() {
+        let dir = std::env::temp_dir().join(format!("reap-config-paths-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(FILE_NAME);
+        fs::write(
+            &path,
+            r#"
+            flamegraph = "tmp/heap.svg"
+            dot = "/var/tmp/heap.dot"
+            analyzers = ["bin/heap-checks", "heap-checks"]
+            "#,
+        )
+        .unwrap();
+        let config = Config::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(Some(dir.join("tmp/heap.svg")), config.flamegraph);
+        assert_eq!(Some(PathBuf::from("/var/tmp/heap.dot")), config.dot);
+        assert_eq!(
+            vec![dir.join("bin/heap-checks"), PathBuf::from("heap-checks")],
+            config.analyzers
+        );
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        assert!("[budgets.String]\nretain = \"1MB\""
+            .parse::<Config>()
+            .is_err());
+    }
+}
@@ -0,0 +1,138 @@
+// A compact, read-only copy of a reference graph's edges, in compressed
+// sparse row form: each node's successors are a range of one flat array.
+// Traversals over it avoid petgraph's per-edge weights and linked lists,
+// which dominate memory and cache misses on graphs of millions of objects.
+use crate::object::ReferenceGraph;
+use crate::storage::{Allocator, Heap};
+use petgraph::graph::NodeIndex;
+
+type Index = NodeIndex<usize>;
+
+pub struct Csr<A: Allocator = Heap> {
+    // Where each node's successors start in `targets`
+    offsets: A::Vec<usize>,
+    targets: A::Vec<u32>,
+}
+
+impl Csr {
+    pub fn new(graph: &ReferenceGraph) -> Csr {
+        let edges = || {
+            graph
+                .raw_edges()
+                .iter()
+                .map(|e| (e.source().index(), e.target().index()))
+        };
+        let Ok(csr) = Csr::build(&Heap, graph.node_count(), graph.edge_count(), edges);
+        csr
+    }
+}
+
+impl<A: Allocator> Csr<A> {
+    // From `edges` (as pairs of node indices), which are read twice.
+    pub fn build<F, I>(
+        alloc: &A,
+        node_count: usize,
+        edge_count: usize,
+        edges: F,
+    ) -> Result<Csr<A>, A::Error>
+    where
+        F: Fn() -> I,
+        I: Iterator<Item = (usize, usize)>,
+    {
+        assert!(
+            node_count <= u32::MAX as usize,
+            "Too many nodes for a compact graph"
+        );
+        let mut offsets = alloc.filled(node_count + 1, 0)?;
+        for (source, _) in edges() {
+            offsets[source + 1] += 1;
+        }
+        for i in 0..node_count {
+            offsets[i + 1] += offsets[i];
+        }
+
+        // Fill each node's range from its start, then shift the starts back
+        let mut targets = alloc.filled(edge_count, 0)?;
+        for (source, target) in edges() {
+            targets[offsets[source]] = target as u32;
+            offsets[source] += 1;
+        }
+        for i in (0..node_count).rev() {
+            offsets[i + 1] = offsets[i];
+        }
+        offsets[0] = 0;
+        Ok(Csr { offsets, targets })
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn neighbors(&self, i: Index) -> impl Iterator<Item = Index> + '_ {
+        self.successors(i.index())
+            .iter()
+            .map(|&j| Index::new(j as usize))
+    }
+
+    pub fn successors(&self, i: usize) -> &[u32] {
+        &self.targets[self.offsets[i]..self.offsets[i + 1]]
+    }
+
+    // Nodes reachable from `root`, including itself, as a mask by index.
+    pub fn reachable(&self, root: Index) -> Vec<bool> {
+        let mut reachable = vec![false; self.node_count()];
+        reachable[root.index()] = true;
+        let mut stack = vec![root];
+        while let Some(i) = stack.pop() {
+            for j in self.neighbors(i) {
+                if !reachable[j.index()] {
+                    reachable[j.index()] = true;
+                    stack.push(j);
+                }
+            }
+        }
+        reachable
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::{Object, EDGE_WEIGHT};
+    use petgraph::Graph;
+
+    #[test]
+    fn test_csr() {
+        let mut graph: ReferenceGraph = Graph::default();
+        let nodes: Vec<Index> = (1..=4)
+            .map(|address| {
+                graph.add_node(Object {
+                    address,
+                    bytes: 1,
+                    kind: "Node".into(),
+                    class: "Node".into(),
+                    internal_type: "Node".into(),
+                    label: None,
+                    file: None,
+                })
+            })
+            .collect();
+        for (from, to) in [(0, 1), (2, 3), (0, 2), (1, 0)] {
+            graph.add_edge(nodes[from], nodes[to], EDGE_WEIGHT);
+        }
+
+        let csr = Csr::new(&graph);
+        assert_eq!((4, 4), (csr.node_count(), csr.edge_count()));
+        for &i in &nodes {
+            let mut expected: Vec<Index> = graph.neighbors(i).collect();
+            expected.sort();
+            assert_eq!(expected, csr.neighbors(i).collect::<Vec<Index>>());
+        }
+        assert_eq!(vec![true, true, true, true], csr.reachable(nodes[1]));
+        assert_eq!(vec![false, false, true, true], csr.reachable(nodes[2]));
+    }
+}
@@ -0,0 +1,141 @@
+// The dominator tree as nested frames, merged by name like a flamegraph's,
+// in the JSON format d3-flame-graph and other d3 hierarchy layouts consume.
+use crate::analyze::Analysis;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+// Name of the frame standing in for pruned ones
+const OTHER: &str = "(other)";
+
+#[derive(Debug, Serialize)]
+pub struct Frame {
+    pub name: String,
+
+    // Bytes used by this frame's objects and all the frames below it
+    pub value: usize,
+
+    pub children: Vec<Frame>,
+
+    // Position of each child by name
+    #[serde(skip)]
+    indices: HashMap<String, usize>,
+}
+
+impl Frame {
+    fn new(name: String) -> Frame {
+        Frame {
+            name,
+            value: 0,
+            children: vec![],
+            indices: HashMap::new(),
+        }
+    }
+
+    fn child(&mut self, name: String) -> &mut Frame {
+        let children = &mut self.children;
+        let i = *self.indices.entry(name).or_insert_with_key(|name| {
+            children.push(Frame::new(name.clone()));
+            children.len() - 1
+        });
+        &mut self.children[i]
+    }
+
+    // Folds children using fewer than `min_value` bytes into one frame, so
+    // that charts of large heaps stay small enough to render.
+    pub fn prune(&mut self, min_value: usize) {
+        let (mut keep, small): (Vec<Frame>, Vec<Frame>) = self
+            .children
+            .drain(..)
+            .partition(|child| child.value >= min_value);
+        for child in &mut keep {
+            child.prune(min_value);
+        }
+
+        let other: usize = small.iter().map(|child| child.value).sum();
+        if other > 0 {
+            let mut frame = Frame::new(OTHER.to_string());
+            frame.value = other;
+            keep.push(frame);
+            keep.sort_by(largest_first);
+        }
+        self.children = keep;
+    }
+
+    fn sort(&mut self) {
+        self.children.sort_by(largest_first);
+        self.indices.clear();
+        for child in &mut self.children {
+            child.sort();
+        }
+    }
+}
+
+// Orders frames by size, then name, so charts are the same from run to run.
+fn largest_first(a: &Frame, b: &Frame) -> Ordering {
+    b.value.cmp(&a.value).then_with(|| a.name.cmp(&b.name))
+}
+
+// Builds the frame tree, with children ordered largest first.
+pub fn tree(analysis: &Analysis) -> Frame {
+    let mut root: Option<Frame> = None;
+
+    analysis
+        .dominator_stacks(|stack| -> Result<(), ()> {
+            let bytes = stack.last().map_or(0, |obj| obj.bytes);
+            let mut frame = root.get_or_insert_with(|| Frame::new(analysis.frame_name(stack[0])));
+            frame.value += bytes;
+            for obj in &stack[1..] {
+                frame = frame.child(analysis.frame_name(obj));
+                frame.value += bytes;
+            }
+            Ok(())
+        })
+        .expect("building frames can't fail");
+
+    let mut root = root.unwrap_or_else(|| Frame::new(analysis.frame_name(analysis.root())));
+    root.sort();
+    root
+}
+
+pub fn to_json(analysis: &Analysis) -> serde_json::Result<String> {
+    serde_json::to_string(&tree(analysis))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use std::fs::File;
+    use std::io::BufReader;
+
+    fn check(frame: &Frame) {
+        let children: usize = frame.children.iter().map(|c| c.value).sum();
+        assert!(frame.value >= children);
+        assert!(frame.children.windows(2).all(|w| w[0].value >= w[1].value));
+        frame.children.iter().for_each(check);
+    }
+
+    #[test]
+    fn test_tree() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, true).unwrap();
+        let analysis = analyze::analyze(root, root, graph, true).unwrap();
+
+        let tree = tree(&analysis);
+        assert_eq!("root", tree.name);
+        assert_eq!(analysis.dominated_totals().bytes, tree.value);
+        check(&tree);
+
+        let mut pruned = super::tree(&analysis);
+        pruned.prune(tree.value / 100);
+        assert_eq!(tree.value, pruned.value);
+        check(&pruned);
+        assert!(pruned.children.len() < tree.children.len());
+        assert!(pruned.children.iter().any(|child| child.name == OTHER));
+
+        let json: serde_json::Value = serde_json::from_str(&to_json(&analysis).unwrap()).unwrap();
+        assert_eq!(tree.value, json["value"]);
+        assert!(json.get("indices").is_none());
+    }
+}
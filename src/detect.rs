@@ -0,0 +1,493 @@
+// Analyzers which look for known problems in a heap, each reporting the
+// objects holding on to the memory involved. Implement Analyzer for
+// heuristics of your own, or run them as external programs with External.
+use crate::analyze::Analysis;
+use crate::object::*;
+use bytesize::ByteSize;
+use log::warn;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    // What was found, e.g. "Rails.cache local store"
+    pub detector: String,
+    pub object: String,
+    pub address: usize,
+    pub retained: Stats,
+
+    // The nearest object dominating the found one that isn't a mere
+    // container, i.e. what keeps it alive
+    pub holder: String,
+    pub advice: String,
+}
+
+impl Finding {
+    // A finding about the object at `address`, if the analysis has it.
+    pub fn new<D: Into<String>, A: Into<String>>(
+        analysis: &Analysis,
+        detector: D,
+        address: usize,
+        advice: A,
+    ) -> Option<Finding> {
+        let (obj, retained) = analysis.retained_stats(address)?;
+        let holder = analysis
+            .dominator_chain(address)?
+            .into_iter()
+            .rev()
+            .skip(1)
+            .find(|d| d.is_root() || !CONTAINERS.contains(&d.internal_type.as_str()))
+            .map_or_else(|| "nothing".to_string(), |holder| holder.to_string());
+        Some(Finding {
+            detector: detector.into(),
+            object: obj.to_string(),
+            address,
+            retained,
+            holder,
+            advice: advice.into(),
+        })
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} retains {} ({} objects), held by {}; {}",
+            self.detector,
+            self.object,
+            ByteSize(self.retained.bytes as u64),
+            self.retained.count,
+            self.holder,
+            self.advice
+        )
+    }
+}
+
+pub trait Analyzer {
+    // Describes the analyzer in messages.
+    fn name(&self) -> String;
+
+    // Looks for problems in the heap, the most important first.
+    fn run(&self, analysis: &Analysis) -> Vec<Finding>;
+}
+
+// A cache recognized by the class of the object holding it.
+struct Cache {
+    name: &'static str,
+    matches: fn(&str) -> bool,
+    advice: &'static str,
+}
+
+const CACHES: &[Cache] = &[
+    Cache {
+        name: "Rails.cache local store",
+        matches: |kind| kind == "ActiveSupport::Cache::Strategy::LocalCache::LocalStore",
+        advice: "it grows until the request or job using it ends, so wrap long-running work in Rails.cache.with_local_cache blocks of its own",
+    },
+    Cache {
+        name: "prepared statement cache",
+        matches: |kind| kind.starts_with("ActiveRecord::") && kind.ends_with("StatementPool"),
+        advice: "lower statement_limit in database.yml, or set prepared_statements: false",
+    },
+    Cache {
+        name: "ActiveRecord query cache",
+        matches: |kind| kind == "ActiveRecord::ConnectionAdapters::QueryCache::Store",
+        advice: "long-running work with the query cache enabled, e.g. a job in ActiveRecord::Base.cache, should run its queries in uncached blocks",
+    },
+];
+
+const RACK_ENV: &str = "rack env";
+const RACK_ENV_ADVICE: &str =
+    "a middleware or other object keeping a request's env after the request retains everything in it";
+
+// Finds the Rails caches and rack envs retaining at least `min_bytes`. Envs
+// are found by their "rack.input" keys, so only when the labels of strings
+// include their values.
+pub struct Rails {
+    pub min_bytes: usize,
+}
+
+impl Analyzer for Rails {
+    fn name(&self) -> String {
+        "rails".to_string()
+    }
+
+    fn run(&self, analysis: &Analysis) -> Vec<Finding> {
+        let mut found: Vec<(&str, usize, &str)> = vec![];
+        for cache in CACHES {
+            let (objects, _) = analysis.objects_where(usize::MAX, |obj, stats| {
+                stats.bytes >= self.min_bytes && (cache.matches)(&obj.kind)
+            });
+            found.extend(
+                objects
+                    .iter()
+                    .map(|(obj, _)| (cache.name, obj.address, cache.advice)),
+            );
+        }
+
+        let mut envs: Vec<usize> = analysis
+            .references()
+            .filter(|(from, to)| {
+                from.internal_type == "HASH"
+                    && to
+                        .label
+                        .as_deref()
+                        .is_some_and(|label| label.ends_with("[rack.input]"))
+            })
+            .map(|(env, _)| env.address)
+            .collect();
+        envs.sort_unstable();
+        envs.dedup();
+        found.extend(envs.into_iter().map(|env| (RACK_ENV, env, RACK_ENV_ADVICE)));
+
+        let mut findings: Vec<Finding> = found
+            .into_iter()
+            .filter_map(|(detector, address, advice)| {
+                Finding::new(analysis, detector, address, advice)
+            })
+            .filter(|finding| finding.retained.bytes >= self.min_bytes)
+            .collect();
+        findings.sort_by(|a, b| {
+            b.retained
+                .bytes
+                .cmp(&a.retained.bytes)
+                .then(a.address.cmp(&b.address))
+        });
+        findings
+    }
+}
+
+// A rule flagging instances of a class retaining more than `retained`
+// between them, within each subtree headed by an instance of `under`, or in
+// the whole heap if it has none.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub class: ClassPattern,
+    pub under: Option<ClassPattern>,
+    pub retained: usize,
+    pub advice: Option<String>,
+}
+
+impl Analyzer for Rule {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn run(&self, analysis: &Analysis) -> Vec<Finding> {
+        let heads = analysis.retained_under(
+            |obj| match &self.under {
+                Some(under) => !obj.is_root() && under.matches(&obj.class),
+                None => obj.is_root(),
+            },
+            |obj| self.class.matches(&obj.class),
+        );
+        let advice = self.advice.clone().unwrap_or_else(|| {
+            format!(
+                "instances of {} under it retain more than {}",
+                self.class,
+                ByteSize(self.retained as u64)
+            )
+        });
+        let mut findings: Vec<Finding> = heads
+            .into_iter()
+            .filter(|(_, stats)| stats.bytes > self.retained)
+            .filter_map(|(head, stats)| {
+                let finding =
+                    Finding::new(analysis, self.name.as_str(), head.address, advice.as_str());
+                finding.map(|finding| Finding {
+                    retained: stats,
+                    ..finding
+                })
+            })
+            .collect();
+        findings.sort_by(|a, b| {
+            b.retained
+                .bytes
+                .cmp(&a.retained.bytes)
+                .then(a.address.cmp(&b.address))
+        });
+        findings
+    }
+}
+
+// An analyzer in a program of its own, e.g. one with heuristics specific to
+// an organization's code. It reads one JSON object per line from stdin,
+// for each object dominated by the root:
+//
+//   {"address":"0x1", "kind":"Foo", "label":null, "bytes":40,
+//    "retained":{"count":2,"bytes":80}, "dominator":"0x2", "references":["0x3"]}
+//
+// and writes one per line to stdout for each finding, which reap completes
+// with what the object retains and what holds it:
+//
+//   {"detector":"unbounded Foo", "address":"0x1", "advice":"..."}
+pub struct External {
+    pub program: PathBuf,
+}
+
+#[derive(Serialize)]
+struct ExternalObject<'a> {
+    address: String,
+    kind: &'a str,
+    label: Option<&'a str>,
+    bytes: usize,
+    retained: Stats,
+    dominator: Option<String>,
+    references: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct ExternalFinding {
+    detector: String,
+    address: String,
+    advice: String,
+}
+
+impl External {
+    fn write_objects<W: Write>(analysis: &Analysis, writer: W) -> std::io::Result<()> {
+        let mut references: FxHashMap<usize, Vec<String>> = FxHashMap::default();
+        for (from, to) in analysis.references() {
+            references
+                .entry(from.address)
+                .or_default()
+                .push(format!("{:#x}", to.address));
+        }
+        let dominators: FxHashMap<usize, usize> = analysis
+            .dominator_edges()
+            .map(|(d, obj)| (obj.address, d.address))
+            .collect();
+
+        let mut writer = BufWriter::new(writer);
+        for (obj, retained) in analysis.objects() {
+            if obj.is_root() {
+                continue;
+            }
+            let line = ExternalObject {
+                address: format!("{:#x}", obj.address),
+                kind: obj.kind.as_str(),
+                label: obj.label.as_deref(),
+                bytes: obj.bytes,
+                retained,
+                dominator: dominators.get(&obj.address).map(|d| format!("{:#x}", d)),
+                references: references.get(&obj.address).map_or(&[], |r| r.as_slice()),
+            };
+            serde_json::to_writer(&mut writer, &line)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+
+    fn try_run(&self, analysis: &Analysis) -> Result<Vec<Finding>, String> {
+        let mut child = Command::new(&self.program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("could not run it: {}", e))?;
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = child.stdout.take().expect("piped stdout");
+
+        // Writes the objects while reading findings, so that neither pipe
+        // fills up waiting for the other
+        let (written, lines) = std::thread::scope(|scope| {
+            let writer = scope.spawn(|| External::write_objects(analysis, stdin));
+            let lines: Vec<std::io::Result<String>> = BufReader::new(stdout).lines().collect();
+            (writer.join().expect("writer panicked"), lines)
+        });
+        let status = child.wait().map_err(|e| e.to_string())?;
+        if !status.success() {
+            return Err(format!("it failed ({})", status));
+        }
+        // It may stop reading once it has found what it's looking for
+        if let Err(err) = written {
+            if err.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(err.to_string());
+            }
+        }
+
+        let mut findings = vec![];
+        for line in lines {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let found: ExternalFinding = serde_json::from_str(&line)
+                .map_err(|e| format!("invalid finding {:?}: {}", line, e))?;
+            let address = crate::parse::parse_address(&found.address)
+                .map_err(|_| format!("invalid address {:?}", found.address))?;
+            match Finding::new(analysis, found.detector, address, found.advice) {
+                Some(finding) => findings.push(finding),
+                None => warn!("{}: no object at {:#x}", self.name(), address),
+            }
+        }
+        Ok(findings)
+    }
+}
+
+impl Analyzer for External {
+    fn name(&self) -> String {
+        self.program.display().to_string()
+    }
+
+    fn run(&self, analysis: &Analysis) -> Vec<Finding> {
+        self.try_run(analysis).unwrap_or_else(|err| {
+            warn!("Analyzer {}: {}", self.name(), err);
+            vec![]
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use rstest::rstest;
+    use std::io::Cursor;
+
+    const DUMP: &str = r#"{"type":"ROOT", "root":"vm", "references":["0x1", "0x5"]}
+{"address":"0x10", "type":"CLASS", "name":"Thread", "references":[], "memsize":0}
+{"address":"0x11", "type":"CLASS", "name":"ActiveSupport::Cache::Strategy::LocalCache::LocalStore", "references":[], "memsize":0}
+{"address":"0x12", "type":"CLASS", "name":"MyMiddleware", "references":[], "memsize":0}
+{"address":"0x1", "type":"OBJECT", "class":"0x10", "references":["0x2"], "memsize":40}
+{"address":"0x2", "type":"HASH", "size":1, "references":["0x3"], "memsize":40}
+{"address":"0x3", "type":"OBJECT", "class":"0x11", "references":["0x4"], "memsize":40}
+{"address":"0x4", "type":"STRING", "value":"cached", "references":[], "memsize":1000}
+{"address":"0x5", "type":"OBJECT", "class":"0x12", "references":["0x6"], "memsize":40}
+{"address":"0x6", "type":"HASH", "size":2, "references":["0x7", "0x8"], "memsize":40}
+{"address":"0x7", "type":"STRING", "value":"rack.input", "references":[], "memsize":40}
+{"address":"0x8", "type":"STRING", "value":"body", "references":[], "memsize":2000}
+"#;
+
+    fn analysis() -> Analysis {
+        let (root, graph) = parse::parse(&mut Cursor::new(DUMP), false).unwrap();
+        analyze::analyze(root, root, graph, false).unwrap()
+    }
+
+    #[test]
+    fn test_rails() {
+        let findings = Rails { min_bytes: 1000 }.run(&analysis());
+        let found: Vec<(&str, usize, usize, &str)> = findings
+            .iter()
+            .map(|f| {
+                (
+                    f.detector.as_str(),
+                    f.address,
+                    f.retained.bytes,
+                    f.holder.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (RACK_ENV, 0x6, 2080, "MyMiddleware[0x5]"),
+                ("Rails.cache local store", 0x3, 1040, "Thread[0x1]"),
+            ],
+            found
+        );
+        assert!(findings[1]
+            .to_string()
+            .starts_with("Rails.cache local store: ActiveSupport::Cache::Strategy::LocalCache::LocalStore[0x3] retains 1.0 KB (2 objects), held by Thread[0x1]; "));
+    }
+
+    // Objects of classes without a namespace, as if those were the app's own.
+    struct Unnamespaced;
+
+    impl Analyzer for Unnamespaced {
+        fn name(&self) -> String {
+            "unnamespaced".to_string()
+        }
+
+        fn run(&self, analysis: &Analysis) -> Vec<Finding> {
+            analysis
+                .objects()
+                .filter(|(obj, _)| obj.internal_type == "OBJECT" && !obj.kind.contains("::"))
+                .filter_map(|(obj, _)| Finding::new(analysis, "app object", obj.address, "ok"))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_analyzer() {
+        let analyzers: Vec<Box<dyn Analyzer>> = vec![Box::new(Unnamespaced)];
+        let mut found: Vec<(usize, String)> = analyzers
+            .iter()
+            .flat_map(|analyzer| analyzer.run(&analysis()))
+            .map(|finding| (finding.address, finding.holder))
+            .collect();
+        found.sort();
+        assert_eq!(
+            vec![(0x1, "root".to_string()), (0x5, "root".to_string())],
+            found
+        );
+    }
+
+    #[rstest]
+    #[case(Some("MyMiddleware"), 2000, vec![(0x5, 2040)])]
+    #[case(Some("MyMiddleware"), 2040, vec![])]
+    #[case(Some("Thread"), 0, vec![(0x1, 1000)])]
+    #[case(None, 3000, vec![(0, 3040)])]
+    fn test_rule(
+        #[case] under: Option<&str>,
+        #[case] retained: usize,
+        #[case] expected: Vec<(usize, usize)>,
+    ) {
+        let rule = Rule {
+            name: "big strings".to_string(),
+            class: "STRING".parse().unwrap(),
+            under: under.map(|under| under.parse().unwrap()),
+            retained,
+            advice: None,
+        };
+        let findings = rule.run(&analysis());
+        let found: Vec<(usize, usize)> = findings
+            .iter()
+            .map(|f| (f.address, f.retained.bytes))
+            .collect();
+        assert_eq!(expected, found);
+        if let Some(finding) = findings.first() {
+            let advice = format!(
+                "; instances of STRING under it retain more than {}",
+                ByteSize(retained as u64)
+            );
+            assert!(finding.to_string().ends_with(&advice));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_external() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("reap-analyzer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("middleware");
+        std::fs::write(
+            &program,
+            r#"#!/bin/sh
+grep '"kind":"MyMiddleware"' | sed 's/.*"address":"\(0x[0-9a-f]*\)".*/{"detector":"middleware","address":"\1","advice":"check it"}/'
+"#,
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let findings = External { program }.try_run(&analysis()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(1, findings.len());
+        assert_eq!(
+            "middleware: MyMiddleware[0x5] retains 2.1 KB (4 objects), held by root; check it",
+            findings[0].to_string()
+        );
+    }
+
+    #[test]
+    fn test_min_bytes() {
+        let findings = Rails { min_bytes: 2000 }.run(&analysis());
+        assert_eq!(1, findings.len());
+        assert_eq!(RACK_ENV, findings[0].detector);
+    }
+}
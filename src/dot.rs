@@ -0,0 +1,399 @@
+// The dominator tree in graphviz's dot language, with each node shaded and
+// sized by how much it retains, so the heaviest subtrees stand out.
+use crate::object::{Dominator, DominatorGraph, EDGE_WEIGHT};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::{Direction, Graph};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+// Fill colors of the nodes retaining the least and the most, and halfway
+// between them on a log scale (ColorBrewer's YlOrRd)
+const HEAT: [(u8, u8, u8); 3] = [(0xff, 0xff, 0xb2), (0xfd, 0x8d, 0x3c), (0xbd, 0x00, 0x26)];
+
+// Smallest & largest font sizes (in points) and node widths & heights (in
+// inches)
+const FONT_SIZE: (f64, f64) = (10.0, 24.0);
+const WIDTH: (f64, f64) = (0.75, 3.0);
+const HEIGHT: (f64, f64) = (0.5, 1.5);
+
+// Where a node retaining `bytes` is, from 0 to 1 on a log scale, among the
+// nodes of a graph retaining between `min` and `max` bytes.
+fn scale(bytes: usize, min: usize, max: usize) -> f64 {
+    let log = |bytes: usize| (bytes.max(1) as f64).ln();
+    let range = log(max) - log(min);
+    match range > 0.0 {
+        true => ((log(bytes) - log(min)) / range).clamp(0.0, 1.0),
+        false => 1.0,
+    }
+}
+
+fn between((from, to): (f64, f64), t: f64) -> f64 {
+    from + (to - from) * t
+}
+
+// The fill color of a node at `t` on the scale.
+fn heat(t: f64) -> String {
+    let (from, to, t) = match t < 0.5 {
+        true => (HEAT[0], HEAT[1], t * 2.0),
+        false => (HEAT[1], HEAT[2], t * 2.0 - 1.0),
+    };
+    let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        mix(from.0, to.0),
+        mix(from.1, to.1),
+        mix(from.2, to.2)
+    )
+}
+
+// What to group nodes into graphviz clusters by: their class, or the
+// top-level module their class is in (e.g. ActiveRecord for
+// ActiveRecord::Relation)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClusterBy {
+    Class,
+    Namespace,
+}
+
+impl std::str::FromStr for ClusterBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "class" => Ok(ClusterBy::Class),
+            "namespace" => Ok(ClusterBy::Namespace),
+            _ => Err(format!("Unknown cluster key {:?}", s)),
+        }
+    }
+}
+
+impl ClusterBy {
+    // The cluster of a node, if it's in one.
+    fn cluster<'a>(&self, node: &'a Dominator) -> Option<&'a str> {
+        if node.object.is_root() {
+            return None;
+        }
+        let class = node.object.kind.as_str();
+        match self {
+            ClusterBy::Class => Some(class),
+            ClusterBy::Namespace => class.split_once("::").map(|(namespace, _)| namespace),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    // Label each edge with the share of its source's retained bytes its
+    // target retains
+    pub edge_percentages: bool,
+
+    // Group nodes into clusters; clusters of one node are left out
+    pub cluster_by: Option<ClusterBy>,
+}
+
+// Fewest nodes in a chain worth collapsing into one
+const CHAIN_MIN: usize = 3;
+
+// One node standing in for a chain of nodes each dominating only the next,
+// retaining what its first does.
+fn chain(graph: &DominatorGraph, run: &[NodeIndex<usize>]) -> Dominator {
+    let first = &graph[run[0]];
+    let last = &graph[run[run.len() - 1]];
+    let mut object = first.object.clone();
+    object.bytes = run.iter().map(|&i| graph[i].object.bytes).sum();
+    object.label = Some(Box::from(format!(
+        "{} … {} ({} in a chain)",
+        first.object,
+        last.object,
+        run.len()
+    )));
+    Dominator {
+        object,
+        retained: first.retained,
+    }
+}
+
+// The tree with each chain of at least `CHAIN_MIN` nodes, other than the
+// root, in which every node but the last has only one child, collapsed into
+// one node, so long chains don't make the graph unreadably tall.
+pub fn collapse_chains(graph: &DominatorGraph) -> DominatorGraph {
+    let children = |i| {
+        let mut children: Vec<NodeIndex<usize>> =
+            graph.neighbors_directed(i, Direction::Outgoing).collect();
+        children.sort();
+        children
+    };
+
+    let mut collapsed: DominatorGraph = Graph::default();
+    let mut stack: Vec<(NodeIndex<usize>, Option<NodeIndex<usize>>)> = graph
+        .externals(Direction::Incoming)
+        .map(|i| (i, None))
+        .collect();
+    stack.reverse();
+    while let Some((i, parent)) = stack.pop() {
+        let mut run = vec![i];
+        if !graph[i].object.is_root() {
+            while let [only] = children(run[run.len() - 1])[..] {
+                run.push(only);
+            }
+        }
+        let node = match run.len() >= CHAIN_MIN {
+            true => chain(graph, &run),
+            false => {
+                run.truncate(1);
+                graph[i].clone()
+            }
+        };
+
+        let new = collapsed.add_node(node);
+        if let Some(parent) = parent {
+            collapsed.add_edge(parent, new, EDGE_WEIGHT);
+        }
+        let mut children = children(run[run.len() - 1]);
+        children.reverse();
+        stack.extend(children.into_iter().map(|child| (child, Some(new))));
+    }
+    collapsed
+}
+
+// Quotes a label for dot.
+fn quoted(label: &str) -> String {
+    format!("\"{}\"", label.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn write<W: Write>(graph: &DominatorGraph, options: &Options, mut writer: W) -> io::Result<()> {
+    let retained = || graph.node_weights().map(|node| node.retained.bytes);
+    let min = retained().min().unwrap_or(0);
+    let max = retained().max().unwrap_or(0);
+
+    let write_node = |writer: &mut W, indent: &str, i: NodeIndex<usize>| {
+        let node = &graph[i];
+        let t = scale(node.retained.bytes, min, max);
+        writeln!(
+            writer,
+            "{}{} [ label = {} fillcolor = \"{}\" fontsize = {:.1} width = {:.2} height = {:.2} ]",
+            indent,
+            i.index(),
+            quoted(&node.to_string()),
+            heat(t),
+            between(FONT_SIZE, t),
+            between(WIDTH, t),
+            between(HEIGHT, t)
+        )
+    };
+
+    let mut clusters: BTreeMap<&str, Vec<NodeIndex<usize>>> = BTreeMap::new();
+    if let Some(cluster_by) = options.cluster_by {
+        for i in graph.node_indices() {
+            if let Some(cluster) = cluster_by.cluster(&graph[i]) {
+                clusters.entry(cluster).or_default().push(i);
+            }
+        }
+        clusters.retain(|_, nodes| nodes.len() > 1);
+    }
+
+    writeln!(writer, "digraph {{")?;
+    writeln!(writer, "    node [ style = \"filled\" ]")?;
+    let mut clustered = vec![false; graph.node_count()];
+    for (n, (cluster, nodes)) in clusters.iter().enumerate() {
+        writeln!(writer, "    subgraph cluster_{} {{", n)?;
+        writeln!(writer, "        label = {}", quoted(cluster))?;
+        for &i in nodes {
+            write_node(&mut writer, "        ", i)?;
+            clustered[i.index()] = true;
+        }
+        writeln!(writer, "    }}")?;
+    }
+    for i in graph.node_indices() {
+        if !clustered[i.index()] {
+            write_node(&mut writer, "    ", i)?;
+        }
+    }
+    for edge in graph.edge_references() {
+        write!(
+            writer,
+            "    {} -> {}",
+            edge.source().index(),
+            edge.target().index()
+        )?;
+        let parent = graph[edge.source()].retained.bytes;
+        if options.edge_percentages && parent > 0 {
+            let share = graph[edge.target()].retained.bytes as f64 / parent as f64;
+            write!(writer, " [ label = \"{:.1}%\" ]", share * 100.0)?;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "}}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use rstest::rstest;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    #[rstest]
+    #[case::least(1, 0.0, "#ffffb2")]
+    #[case::middle(1000, 0.5, "#fd8d3c")]
+    #[case::most(1_000_000, 1.0, "#bd0026")]
+    fn test_scale(#[case] bytes: usize, #[case] t: f64, #[case] color: &str) {
+        assert_eq!(t, scale(bytes, 1, 1_000_000));
+        assert_eq!(color, heat(t));
+    }
+
+    #[test]
+    fn test_write() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        let graph = analysis.relevant_dominator_subgraph(0.005);
+
+        let mut dot = vec![];
+        write(&graph, &Options::default(), &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        let lines: Vec<&str> = dot.lines().collect();
+        assert_eq!(3 + graph.node_count() + graph.edge_count(), lines.len());
+        // The root retains the most
+        assert!(lines[2].starts_with("    0 [ label = \"root: "));
+        assert!(lines[2]
+            .ends_with("fillcolor = \"#bd0026\" fontsize = 24.0 width = 3.00 height = 1.50 ]"));
+    }
+
+    #[test]
+    fn test_edge_percentages() {
+        let dump = r#"{"type":"ROOT", "root":"vm", "references":["0x1"]}
+{"address":"0x1", "type":"ARRAY", "length":1, "references":["0x2"], "memsize":20}
+{"address":"0x2", "type":"STRING", "references":[], "memsize":60}
+"#;
+        let (root, graph) = parse::parse(&mut std::io::Cursor::new(dump), false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        let graph = analysis.relevant_dominator_subgraph(0.0);
+
+        let options = Options {
+            edge_percentages: true,
+            ..Default::default()
+        };
+        let mut dot = vec![];
+        write(&graph, &options, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains(" -> 1 [ label = \"100.0%\" ]"));
+        assert!(dot.contains(" -> 2 [ label = \"75.0%\" ]"));
+    }
+
+    #[test]
+    fn test_collapse_chains() {
+        let dump = r#"{"type":"ROOT", "root":"vm", "references":["0x1", "0x6"]}
+{"address":"0x1", "type":"ARRAY", "length":1, "references":["0x2"], "memsize":10}
+{"address":"0x2", "type":"ARRAY", "length":1, "references":["0x3"], "memsize":10}
+{"address":"0x3", "type":"ARRAY", "length":2, "references":["0x4", "0x5"], "memsize":10}
+{"address":"0x4", "type":"STRING", "references":[], "memsize":20}
+{"address":"0x5", "type":"STRING", "references":[], "memsize":20}
+{"address":"0x6", "type":"ARRAY", "length":1, "references":["0x7"], "memsize":10}
+{"address":"0x7", "type":"STRING", "references":[], "memsize":20}
+"#;
+        let (root, graph) = parse::parse(&mut std::io::Cursor::new(dump), false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        let graph = analysis.relevant_dominator_subgraph(0.0);
+        assert_eq!(8, graph.node_count());
+
+        // 0x1-0x3 become one node, but 0x6 and 0x7 are too short a chain
+        let collapsed = collapse_chains(&graph);
+        assert_eq!(6, collapsed.node_count());
+        assert_eq!(5, collapsed.edge_count());
+        let chain = collapsed
+            .node_weights()
+            .find(|node| node.object.address == 1)
+            .unwrap();
+        assert_eq!(
+            "Array[0x1][len=1] … Array[0x3][len=2] (3 in a chain): 30 B self, 40 B refs, 5 objects",
+            chain.to_string()
+        );
+        let root = NodeIndex::new(0);
+        assert!(collapsed[root].object.is_root());
+        assert_eq!(2, collapsed.neighbors(root).count());
+    }
+
+    #[rstest]
+    #[case::class(ClusterBy::Class, vec!["ActiveRecord::Relation", "STRING"])]
+    #[case::namespace(ClusterBy::Namespace, vec!["ActiveRecord"])]
+    fn test_cluster_by(#[case] cluster_by: ClusterBy, #[case] expected: Vec<&str>) {
+        let dump = r#"{"type":"ROOT", "root":"vm", "references":["0x1", "0x2", "0x3", "0x4", "0x5"]}
+{"address":"0x10", "type":"CLASS", "name":"ActiveRecord::Relation", "references":[], "memsize":0}
+{"address":"0x11", "type":"CLASS", "name":"ActiveRecord::Base", "references":[], "memsize":0}
+{"address":"0x1", "type":"OBJECT", "class":"0x10", "references":[], "memsize":10}
+{"address":"0x2", "type":"OBJECT", "class":"0x10", "references":[], "memsize":10}
+{"address":"0x3", "type":"OBJECT", "class":"0x11", "references":[], "memsize":10}
+{"address":"0x4", "type":"STRING", "references":[], "memsize":10}
+{"address":"0x5", "type":"STRING", "references":[], "memsize":10}
+"#;
+        let (root, graph) = parse::parse(&mut std::io::Cursor::new(dump), false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        let graph = analysis.relevant_dominator_subgraph(0.0);
+
+        let options = Options {
+            cluster_by: Some(cluster_by),
+            ..Default::default()
+        };
+        let mut dot = vec![];
+        write(&graph, &options, &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        let labels: Vec<&str> = dot
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("label = "))
+            .map(|label| label.trim_matches('"'))
+            .collect();
+        assert_eq!(expected, labels);
+        // Every node is written once, in a cluster or not
+        let nodes = dot
+            .lines()
+            .filter(|line| line.contains("[ label = "))
+            .count();
+        assert_eq!(graph.node_count(), nodes);
+    }
+
+    const TREE: &str = r#"{"type":"ROOT", "root":"vm", "references":["0x1", "0x4"]}
+{"address":"0x1", "type":"ARRAY", "length":2, "references":["0x2", "0x3"], "memsize":10}
+{"address":"0x2", "type":"STRING", "references":[], "memsize":20}
+{"address":"0x3", "type":"STRING", "references":[], "memsize":30}
+{"address":"0x4", "type":"STRING", "references":[], "memsize":40}
+"#;
+
+    #[test]
+    fn test_min_bytes() {
+        let (root, graph) = parse::parse(&mut std::io::Cursor::new(TREE), false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        let graph = analysis.dominator_subgraph(30, None);
+        let mut addresses: Vec<usize> = graph.node_weights().map(|n| n.object.address).collect();
+        addresses.sort();
+        assert_eq!(vec![0, 1, 3, 4], addresses);
+    }
+
+    #[rstest]
+    #[case::root(0, 2)]
+    #[case::children(1, 4)]
+    #[case::all(2, 5)]
+    fn test_max_depth(#[case] max_depth: usize, #[case] nodes: usize) {
+        let (root, graph) = parse::parse(&mut std::io::Cursor::new(TREE), false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        let graph = analysis.dominator_subgraph(0, Some(max_depth));
+        assert_eq!(nodes, graph.node_count());
+        assert_eq!(nodes - 1, graph.edge_count());
+
+        // What's left out is still counted
+        let deeper: Vec<String> = graph
+            .node_weights()
+            .filter(|node| node.object.to_string() == "(deeper)")
+            .map(|node| node.to_string())
+            .collect();
+        let expected = match max_depth {
+            0 => vec!["(deeper): 0 B self, 100 B refs, 4 objects"],
+            1 => vec!["(deeper): 0 B self, 50 B refs, 2 objects"],
+            _ => vec![],
+        };
+        assert_eq!(expected, deeper);
+    }
+}
@@ -0,0 +1,331 @@
+// A simple graph format for heaps that aren't Ruby's, with one JSON object
+// per line:
+//
+//   {"id": 1, "size": 40, "type": "Node", "refs": [2, 3], "root": true}
+//
+// `id`s are positive integers or "0x"-prefixed hex strings, and become the
+// objects' addresses. `size` (bytes, default 0), `refs`, `label` and `root`
+// are optional. Objects marked `root` are referenced from the root; if none
+// are, every object nothing else references is. Ids must be unique unless
+// a duplicate policy says which of several objects with the same id to keep.
+use crate::hooks::{Progress, CHECK_INTERVAL};
+use crate::object::*;
+use crate::parse::{
+    check_duplicates, parse_address, Integrity, OnDuplicate, ParseError, ParseOptions, Skipped,
+};
+use crate::timing;
+use log::debug;
+use petgraph::graph::NodeIndex;
+use petgraph::Graph;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use std::io::BufRead;
+use timed_function::timed;
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Id {
+    Number(usize),
+    Hex(String),
+}
+
+impl Id {
+    fn address(&self) -> Option<usize> {
+        match self {
+            Id::Number(n) => Some(*n),
+            Id::Hex(s) if s.starts_with("0x") => parse_address(s).ok(),
+            Id::Hex(_) => None,
+        }
+        .filter(|address| *address != 0)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Line {
+    id: Id,
+
+    #[serde(default)]
+    size: usize,
+
+    #[serde(rename = "type")]
+    object_type: String,
+
+    #[serde(default)]
+    refs: Vec<Id>,
+
+    label: Option<String>,
+
+    #[serde(default)]
+    root: bool,
+}
+
+// The object a line describes, what it references, and whether it's a root.
+fn read_line(
+    line: &str,
+    options: &ParseOptions,
+    indices: &FxHashMap<usize, NodeIndex<usize>>,
+) -> Result<(Object, Vec<usize>, bool), ParseError> {
+    let parsed: Line = serde_json::from_str(line).map_err(ParseError::JsonError)?;
+    let invalid = || {
+        ParseError::InvalidLine(
+            "Ids must be positive integers or \"0x\"-prefixed hex strings".to_string(),
+        )
+    };
+    let address = parsed.id.address().ok_or_else(invalid)?;
+    let refs = parsed
+        .refs
+        .iter()
+        .map(|id| id.address().ok_or_else(invalid))
+        .collect::<Result<Vec<usize>, ParseError>>()?;
+    if options.on_duplicate.unwrap_or(OnDuplicate::Error) == OnDuplicate::Error
+        && indices.contains_key(&address)
+    {
+        return Err(ParseError::InvalidLine(format!(
+            "Duplicate id {:#x}",
+            address
+        )));
+    }
+
+    let object = Object {
+        address,
+        bytes: parsed.size,
+        label: parsed
+            .label
+            .filter(|_| !options.class_name_only)
+            .map(String::into_boxed_str),
+        kind: parsed.object_type.as_str().into(),
+        class: parsed.object_type.as_str().into(),
+        internal_type: parsed.object_type.into(),
+        file: None,
+    };
+    Ok((object, refs, parsed.root))
+}
+
+#[timed]
+pub fn parse<R: BufRead>(
+    reader: &mut R,
+    options: &ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph), ParseError> {
+    let mut graph: ReferenceGraph = Graph::default();
+    let mut indices: FxHashMap<usize, NodeIndex<usize>> = FxHashMap::default();
+    // What each object references, and whether it's a root, by node index
+    let mut references: Vec<Vec<usize>> = vec![vec![]];
+    let mut is_root = vec![false];
+    let mut duplicates = 0;
+
+    let root_index = graph.add_node(Object::root());
+
+    let mut line = String::new();
+    let mut lines = 0;
+    let mut bytes = 0;
+    let mut skipped = Skipped::default();
+
+    while reader
+        .read_line(&mut line)
+        .map_err(|e| ParseError::InvalidLine(format!("Could not read line {}: {}", lines + 1, e)))?
+        > 0
+    {
+        let offset = bytes;
+        lines += 1;
+        bytes += line.len() as u64;
+        if lines % CHECK_INTERVAL == 0 {
+            options.hooks.check()?;
+            options.hooks.report(Progress::Parsing { lines, bytes });
+        }
+        if line.trim().is_empty() {
+            line.clear();
+            continue;
+        }
+
+        // Only the last line can lack a newline
+        let read = read_line(&line, options, &indices).map_err(|err| {
+            if line.ends_with('\n') {
+                err
+            } else {
+                ParseError::Truncated(Box::new(err))
+            }
+        });
+        let (object, refs, root) = match read {
+            Ok(read) => read,
+            Err(err) => {
+                skipped
+                    .skip(options, offset, err)
+                    .map_err(|err| err.at(lines, offset, line.as_bytes()))?;
+                line.clear();
+                continue;
+            }
+        };
+        let cut = options
+            .cut_edges_from
+            .iter()
+            .any(|p| p.matches(&object.class));
+        let refs = if cut { vec![] } else { refs };
+        match indices.get(&object.address) {
+            Some(_) if options.on_duplicate == Some(OnDuplicate::First) => duplicates += 1,
+            Some(&index) => {
+                duplicates += 1;
+                graph[index] = object;
+                references[index.index()] = refs;
+                is_root[index.index()] = root;
+            }
+            None => {
+                let address = object.address;
+                let index = graph.add_node(object);
+                indices.insert(address, index);
+                references.push(refs);
+                is_root.push(root);
+            }
+        }
+        line.clear();
+    }
+
+    if let Some(policy) = options.on_duplicate {
+        check_duplicates(policy, duplicates, 0)?;
+    }
+
+    let mut integrity = Integrity {
+        objects: indices.len(),
+        incomplete_last_line: skipped.truncated(),
+        ..Default::default()
+    };
+    let mut referenced = vec![false; graph.node_count()];
+    for (i, refs) in references.into_iter().enumerate() {
+        if refs.is_empty() {
+            continue;
+        }
+        let i = NodeIndex::new(i);
+        let mut dangling = 0;
+        for address in &refs {
+            match indices.get(address) {
+                Some(&j) => {
+                    graph.add_edge(i, j, EDGE_WEIGHT);
+                    referenced[j.index()] = true;
+                }
+                None => dangling += 1,
+            }
+        }
+        integrity.add_references(refs.len(), dangling);
+    }
+
+    let mut roots: Vec<NodeIndex<usize>> = graph
+        .node_indices()
+        .filter(|i| is_root[i.index()])
+        .collect();
+    if roots.is_empty() {
+        roots = indices
+            .values()
+            .copied()
+            .filter(|i| !referenced[i.index()])
+            .collect();
+        roots.sort();
+    }
+    integrity.roots = roots.len();
+    for i in roots {
+        graph.add_edge(root_index, i, EDGE_WEIGHT);
+    }
+
+    timing::nodes(graph.node_count());
+    skipped.report();
+    integrity.report();
+    debug!(
+        "Parsed {} objects & {} references",
+        graph.node_count(),
+        graph.edge_count()
+    );
+    Ok((root_index, graph))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analyze;
+    use rstest::rstest;
+    use std::io::Cursor;
+
+    const GRAPH: &str = r#"{"id": 1, "size": 10, "type": "App", "refs": [2, "0x3"]}
+{"id": 2, "size": 20, "type": "Cache", "refs": [4], "label": "cache"}
+{"id": "0x3", "size": 30, "type": "Buffer", "refs": [4]}
+
+{"id": 4, "size": 40, "type": "Buffer"}
+{"id": 5, "size": 50, "type": "Orphan", "refs": [5]}
+"#;
+
+    fn parse_str(input: &str) -> Result<(NodeIndex<usize>, ReferenceGraph), ParseError> {
+        parse(&mut Cursor::new(input), &ParseOptions::default())
+    }
+
+    fn unreachable_bytes(analysis: &analyze::Analysis) -> usize {
+        let (_, rest) = analysis.unreachable_stats_by_kind(0);
+        rest.bytes
+    }
+
+    #[test]
+    fn test_parse() {
+        let (root, graph) = parse_str(GRAPH).unwrap();
+        assert_eq!(6, graph.node_count());
+        // Five references plus one from the root to the unreferenced object
+        assert_eq!(6, graph.edge_count());
+
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        assert_eq!(100, analysis.dominated_totals().bytes);
+        assert_eq!(50, unreachable_bytes(&analysis));
+
+        let retained = |address| analysis.retained_stats(address).unwrap().1.bytes;
+        assert_eq!(100, retained(1));
+        assert_eq!(20, retained(2));
+        assert_eq!(30, retained(3));
+        assert_eq!(40, retained(4));
+        assert_eq!("cache", analysis.retained_stats(2).unwrap().0.to_string());
+    }
+
+    #[test]
+    fn test_explicit_roots() {
+        let input = GRAPH.replace(r#""id": 5,"#, r#""id": 5, "root": true,"#);
+        let (root, graph) = parse_str(&input).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        assert_eq!(50, analysis.dominated_totals().bytes);
+        assert_eq!(100, unreachable_bytes(&analysis));
+    }
+
+    #[rstest]
+    #[case::zero_id(r#"{"id": 0, "type": "Node"}"#)]
+    #[case::non_hex_id(r#"{"id": "node", "type": "Node"}"#)]
+    #[case::bad_reference(r#"{"id": 1, "type": "Node", "refs": [-1]}"#)]
+    #[case::duplicate_id("{\"id\": 1, \"type\": \"A\"}\n{\"id\": 1, \"type\": \"B\"}")]
+    fn test_invalid(#[case] input: &str) {
+        assert!(parse_str(input).is_err());
+
+        // Leniently, the line is left out
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let (_, graph) = parse(&mut Cursor::new(input), &lenient).unwrap();
+        assert!(graph.node_count() < 1 + input.lines().count());
+    }
+
+    #[rstest]
+    #[case::first(OnDuplicate::First, "A", 2)]
+    #[case::last(OnDuplicate::Last, "B", 3)]
+    fn test_on_duplicate(#[case] policy: OnDuplicate, #[case] kind: &str, #[case] child: usize) {
+        let input = r#"{"id": 1, "type": "A", "refs": [2], "root": true}
+{"id": 2, "type": "Node"}
+{"id": 1, "type": "B", "refs": [3], "root": true}
+{"id": 3, "type": "Node"}
+"#;
+        let options = ParseOptions {
+            on_duplicate: Some(policy),
+            ..Default::default()
+        };
+        let (root, graph) = parse(&mut Cursor::new(input), &options).unwrap();
+        assert_eq!(4, graph.node_count());
+        let roots: Vec<NodeIndex<usize>> = graph.neighbors(root).collect();
+        assert_eq!(1, roots.len());
+        assert_eq!(kind, graph[roots[0]].kind.as_str());
+        let children: Vec<usize> = graph
+            .neighbors(roots[0])
+            .map(|i| graph[i].address)
+            .collect();
+        assert_eq!(vec![child], children);
+    }
+}
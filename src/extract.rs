@@ -0,0 +1,198 @@
+// Writes part of a dump as a dump of its own: a ROOT line referencing the
+// given root, then the lines of the given objects with their references to
+// anything else left out. The classes of those objects are kept too, without
+// their references, so that the objects still have their class names.
+use crate::parse::parse_address;
+use rustc_hash::FxHashSet;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::io::{self, BufRead, Seek, SeekFrom, Write};
+
+// The fields of a line extracting needs. Lines are written as they were
+// read, but for their references, so nothing else of them is decoded: the
+// values of strings needn't even be UTF-8.
+#[derive(Deserialize)]
+struct Fields<'a> {
+    #[serde(rename = "type")]
+    object_type: Option<&'a str>,
+    address: Option<&'a str>,
+    class: Option<&'a str>,
+
+    #[serde(borrow)]
+    references: Option<&'a RawValue>,
+}
+
+impl Fields<'_> {
+    fn address(&self) -> Option<usize> {
+        parse_address(self.address?).ok()
+    }
+
+    fn class(&self) -> Option<usize> {
+        parse_address(self.class?).ok()
+    }
+}
+
+// Calls `f` with each line of the dump but its ROOT lines, without its line
+// ending, and the fields read from it.
+fn for_each_line<R, F>(reader: &mut R, mut f: F) -> io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(&[u8], Fields) -> io::Result<()>,
+{
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(());
+        }
+        let line = buf.trim_ascii_end();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Fields = serde_json::from_slice(line)?;
+        if fields.object_type != Some("ROOT") {
+            f(line, fields)?;
+        }
+    }
+}
+
+// Writes the line with only the references `keep` selects, leaving the rest
+// of it byte for byte as it was.
+fn write_line<W, K>(writer: &mut W, line: &[u8], fields: &Fields, keep: K) -> io::Result<()>
+where
+    W: Write,
+    K: Fn(&str) -> bool,
+{
+    let Some(raw) = fields.references else {
+        writer.write_all(line)?;
+        return writeln!(writer);
+    };
+    let references: Vec<&str> = serde_json::from_str(raw.get())?;
+    let kept: Vec<&str> = references.iter().copied().filter(|r| keep(r)).collect();
+    if kept.len() == references.len() {
+        writer.write_all(line)?;
+        return writeln!(writer);
+    }
+
+    // The references were borrowed from the line, so this is where they are
+    let start = raw.get().as_ptr() as usize - line.as_ptr() as usize;
+    let end = start + raw.get().len();
+    let kept: Vec<String> = kept.iter().map(|r| format!("\"{}\"", r)).collect();
+    writer.write_all(&line[..start])?;
+    write!(writer, "[{}]", kept.join(", "))?;
+    writer.write_all(&line[end..])?;
+    writeln!(writer)
+}
+
+// Writes the objects at `addresses` from the dump `reader` reads, rooted at
+// `root`, and returns how many lines were written, besides the ROOT line.
+pub fn extract<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    root: usize,
+    addresses: &FxHashSet<usize>,
+) -> io::Result<usize>
+where
+    R: BufRead + Seek,
+    W: Write,
+{
+    let mut classes: FxHashSet<usize> = FxHashSet::default();
+    for_each_line(reader, |_, fields| {
+        if fields.address().is_some_and(|a| addresses.contains(&a)) {
+            classes.extend(fields.class().filter(|c| !addresses.contains(c)));
+        }
+        Ok(())
+    })?;
+
+    writeln!(
+        writer,
+        r#"{{"type":"ROOT", "root":"extract", "references":["{:#x}"]}}"#,
+        root
+    )?;
+    let mut count = 0;
+    reader.seek(SeekFrom::Start(0))?;
+    for_each_line(reader, |line, fields| {
+        let Some(address) = fields.address() else {
+            return Ok(());
+        };
+        if addresses.contains(&address) {
+            write_line(writer, line, &fields, |r| {
+                parse_address(r).is_ok_and(|r| addresses.contains(&r))
+            })?;
+        } else if classes.contains(&address) {
+            write_line(writer, line, &fields, |_| false)?;
+        } else {
+            return Ok(());
+        }
+        count += 1;
+        Ok(())
+    })?;
+    writer.flush()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_extract() {
+        let dump = r#"{"type":"ROOT", "root":"vm", "references":["0x1", "0x4"]}
+{"address":"0x1", "type":"OBJECT", "class":"0x10", "references":["0x2", "0x4"], "memsize":10}
+{"address":"0x2", "type":"STRING", "references":["0x3"], "memsize":20, "value":"kept"}
+{"address":"0x3", "type":"STRING", "references":[], "memsize":30, "value":"also kept"}
+{"address":"0x4", "type":"STRING", "references":[], "memsize":40, "value":"secret"}
+{"address":"0x10", "type":"CLASS", "name":"Widget", "references":["0x4"], "memsize":50}
+"#;
+        let (root, graph) = parse::parse(&mut Cursor::new(dump), false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        let subtree = analysis.rooted_at(0x1).unwrap();
+        let addresses: FxHashSet<usize> = subtree.objects().map(|(obj, _)| obj.address).collect();
+
+        let mut out = vec![];
+        let count = extract(&mut Cursor::new(dump), &mut out, 0x1, &addresses).unwrap();
+        assert_eq!(4, count);
+        let extracted = String::from_utf8(out).unwrap();
+        assert!(!extracted.contains("secret"));
+        assert!(!extracted.contains("0x4"));
+
+        // The extract is a dump of its own, with the subtree and its class
+        let (root, graph) = parse::parse(&mut Cursor::new(extracted), false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        let (obj, retained) = analysis.retained_stats(0x1).unwrap();
+        assert_eq!("Widget", obj.kind.as_str());
+        assert_eq!((3, 60), (retained.count, retained.bytes));
+    }
+
+    #[test]
+    fn test_extract_copies_lines() {
+        let mut dump = br#"{"type":"ROOT", "root":"vm", "references":["0x1"]}
+{"address":"0x1", "type":"ARRAY", "length":2, "references":["0x2", "0x3"], "memsize":10, "flags":{"wb_protected":true}}
+{"address":"0x2", "type":"STRING", "bytesize":3, "value":"a"#
+            .to_vec();
+        dump.extend(b"\xff\xfe\"");
+        dump.extend(
+            br#", "references":[], "memsize":20}
+{"address":"0x3", "type":"STRING", "references":[], "memsize":30}
+"#,
+        );
+
+        let addresses: FxHashSet<usize> = [0x1, 0x2].into_iter().collect();
+        let mut out = vec![];
+        let count = extract(&mut Cursor::new(&dump), &mut out, 0x1, &addresses).unwrap();
+        assert_eq!(2, count);
+
+        // Only the references were rewritten, and the string's bytes kept
+        let mut expected =
+            br#"{"type":"ROOT", "root":"extract", "references":["0x1"]}
+{"address":"0x1", "type":"ARRAY", "length":2, "references":["0x2"], "memsize":10, "flags":{"wb_protected":true}}
+{"address":"0x2", "type":"STRING", "bytesize":3, "value":"a"#
+                .to_vec();
+        expected.extend(b"\xff\xfe\"");
+        expected.extend(b", \"references\":[], \"memsize\":20}\n");
+        assert_eq!(expected, out);
+    }
+}
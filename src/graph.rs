@@ -0,0 +1,294 @@
+use crate::object::*;
+use petgraph::graph::NodeIndex;
+use std::collections::{HashMap, VecDeque};
+
+type Index = NodeIndex<usize>;
+
+// The reference subgraph induced by every node within `depth` hops of
+// `center`, following references in either direction.
+pub fn neighborhood(graph: &ReferenceGraph, center: Index, depth: usize) -> ReferenceGraph {
+    let mut distances: HashMap<Index, usize> = HashMap::new();
+    distances.insert(center, 0);
+
+    let mut queue: VecDeque<Index> = VecDeque::new();
+    queue.push_back(center);
+
+    while let Some(i) = queue.pop_front() {
+        let distance = distances[&i];
+        if distance == depth {
+            continue;
+        }
+        for n in graph.neighbors_undirected(i) {
+            distances.entry(n).or_insert_with(|| {
+                queue.push_back(n);
+                distance + 1
+            });
+        }
+    }
+
+    graph.filter_map(
+        |i, w| {
+            if distances.contains_key(&i) {
+                Some(w.clone())
+            } else {
+                None
+            }
+        },
+        |_, e| Some(*e),
+    )
+}
+
+// Ruby allocates objects in pages aligned to this size
+const PAGE_SIZE: usize = 1 << 16;
+
+// Up to `count` addresses of objects on the same heap page as `address`,
+// nearest first, for addresses that are off by a slot or so.
+pub fn nearest(graph: &ReferenceGraph, address: usize, count: usize) -> Vec<usize> {
+    let page = address & !(PAGE_SIZE - 1);
+    let mut addresses: Vec<usize> = graph
+        .node_weights()
+        .filter(|obj| !obj.is_root())
+        .map(|obj| obj.address)
+        .filter(|a| *a & !(PAGE_SIZE - 1) == page)
+        .collect();
+    addresses.sort_unstable_by_key(|a| (a.abs_diff(address), *a));
+    addresses.dedup();
+    addresses.truncate(count);
+    addresses
+}
+
+// Everything reachable from `root` without going through `excluded`.
+fn reachable(graph: &ReferenceGraph, root: Index, excluded: &[Index]) -> Vec<bool> {
+    let mut seen = vec![false; graph.node_count()];
+    for &i in excluded {
+        seen[i.index()] = true;
+    }
+    let mut stack = vec![root];
+    seen[root.index()] = true;
+    while let Some(i) = stack.pop() {
+        for n in graph.neighbors(i) {
+            if !seen[n.index()] {
+                seen[n.index()] = true;
+                stack.push(n);
+            }
+        }
+    }
+    for &i in excluded {
+        seen[i.index()] = i == root;
+    }
+    seen
+}
+
+// The graph without the objects at `excluded` and the subtrees they
+// dominate, i.e. everything reachable from `root` only through them, as
+// though they had been freed. Returns the new root and what was removed.
+pub fn exclude_subtrees(
+    graph: &ReferenceGraph,
+    root: Index,
+    excluded: &[Index],
+) -> (Index, ReferenceGraph, Stats) {
+    let before = reachable(graph, root, &[]);
+    let after = reachable(graph, root, excluded);
+
+    let mut removed = Stats::default();
+    let mut new_root = root;
+    let mut kept = 0;
+    for i in graph.node_indices() {
+        if before[i.index()] && !after[i.index()] {
+            removed = removed.add(graph[i].stats());
+        } else {
+            if i == root {
+                new_root = Index::new(kept);
+            }
+            kept += 1;
+        }
+    }
+
+    let graph = graph.filter_map(
+        |i, w| (!before[i.index()] || after[i.index()]).then(|| w.clone()),
+        |_, e| Some(*e),
+    );
+    (new_root, graph, removed)
+}
+
+// The objects unreachable from `root` and the references between them, under
+// a new root referencing the ones nothing else unreachable references, and
+// one object of each cycle of them which is otherwise out of its reach, so
+// that dominators can be found among the garbage.
+pub fn unreachable(graph: &ReferenceGraph, root: Index) -> (Index, ReferenceGraph) {
+    let live = reachable(graph, root, &[]);
+    let mut garbage = graph.filter_map(
+        |i, w| (!live[i.index()]).then(|| w.clone()),
+        |_, e| Some(*e),
+    );
+
+    let new_root = garbage.add_node(Object::root());
+    let mut seen = vec![false; garbage.node_count()];
+    seen[new_root.index()] = true;
+    let mut entries: Vec<Index> = garbage
+        .node_indices()
+        .filter(|&i| {
+            i != new_root
+                && garbage
+                    .neighbors_directed(i, petgraph::Direction::Incoming)
+                    .next()
+                    .is_none()
+        })
+        .collect();
+    // Whatever the entries don't reach is in cycles; enter each from one member
+    let mut next = 0;
+    let mut unseen = 0;
+    loop {
+        let mut stack: Vec<Index> = entries[next..].to_vec();
+        next = entries.len();
+        for &i in &stack {
+            seen[i.index()] = true;
+        }
+        while let Some(i) = stack.pop() {
+            for n in garbage.neighbors(i) {
+                if !seen[n.index()] {
+                    seen[n.index()] = true;
+                    stack.push(n);
+                }
+            }
+        }
+        match seen[unseen..].iter().position(|s| !s) {
+            Some(i) => {
+                unseen += i;
+                entries.push(Index::new(unseen));
+            }
+            None => break,
+        }
+    }
+    for i in entries {
+        garbage.add_edge(new_root, i, EDGE_WEIGHT);
+    }
+    (new_root, garbage)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn chain(len: usize) -> ReferenceGraph {
+        let mut graph = ReferenceGraph::default();
+        let mut prev = graph.add_node(Object::root());
+        for address in 1..len {
+            let next = graph.add_node(Object {
+                address,
+                bytes: 1,
+                kind: "Object".into(),
+                class: "Object".into(),
+                internal_type: "Object".into(),
+                label: None,
+                file: None,
+            });
+            graph.add_edge(prev, next, EDGE_WEIGHT);
+            prev = next;
+        }
+        graph
+    }
+
+    #[rstest]
+    #[case(0, 1, 0)]
+    #[case(1, 3, 2)]
+    #[case(2, 5, 4)]
+    #[case(10, 6, 5)]
+    fn it_follows_references_in_both_directions(
+        #[case] depth: usize,
+        #[case] nodes: usize,
+        #[case] edges: usize,
+    ) {
+        let graph = chain(6);
+        let center = graph
+            .node_indices()
+            .find(|i| graph[*i].address == 2)
+            .unwrap();
+
+        let subgraph = neighborhood(&graph, center, depth);
+        assert_eq!(nodes, subgraph.node_count());
+        assert_eq!(edges, subgraph.edge_count());
+    }
+
+    #[test]
+    fn it_excludes_dominated_subtrees() {
+        // 0x1 -> 0x2 -> 0x3, with 0x3 also referenced from the root
+        let mut graph = chain(3);
+        let root = NodeIndex::new(0);
+        let shared = graph.add_node(Object {
+            address: 3,
+            bytes: 1,
+            kind: "Object".into(),
+            class: "Object".into(),
+            internal_type: "Object".into(),
+            label: None,
+            file: None,
+        });
+        graph.add_edge(NodeIndex::new(2), shared, EDGE_WEIGHT);
+        graph.add_edge(root, shared, EDGE_WEIGHT);
+
+        let (root, excluded, removed) = exclude_subtrees(&graph, root, &[NodeIndex::new(1)]);
+        assert_eq!((2, 2), (removed.count, removed.bytes));
+        assert!(excluded[root].is_root());
+        let addresses: Vec<usize> = excluded.node_weights().map(|obj| obj.address).collect();
+        assert_eq!(vec![0, 3], addresses);
+        assert_eq!(1, excluded.edge_count());
+    }
+
+    #[test]
+    fn it_roots_unreachable_objects() {
+        // 0x3 -> 0x4, and a cycle 0x5 <-> 0x6, both unreachable from the root
+        let mut graph = chain(3);
+        let ids: Vec<Index> = (3..7)
+            .map(|address| {
+                graph.add_node(Object {
+                    address,
+                    bytes: 1,
+                    kind: "Object".into(),
+                    class: "Object".into(),
+                    internal_type: "Object".into(),
+                    label: None,
+                    file: None,
+                })
+            })
+            .collect();
+        graph.add_edge(ids[0], ids[1], EDGE_WEIGHT);
+        graph.add_edge(ids[2], ids[3], EDGE_WEIGHT);
+        graph.add_edge(ids[3], ids[2], EDGE_WEIGHT);
+
+        let (root, garbage) = unreachable(&graph, NodeIndex::new(0));
+        assert!(garbage[root].is_root());
+        assert_eq!(5, garbage.node_count());
+        let mut entries: Vec<usize> = garbage
+            .neighbors(root)
+            .map(|i| garbage[i].address)
+            .collect();
+        entries.sort_unstable();
+        assert_eq!(vec![3, 5], entries);
+    }
+
+    #[rstest]
+    #[case::between(0x10028, vec![0x10030, 0x10000])]
+    #[case::exact(0x10000, vec![0x10000, 0x10030])]
+    #[case::other_page(0x20000, vec![])]
+    fn it_finds_nearest_addresses_on_the_page(
+        #[case] address: usize,
+        #[case] expected: Vec<usize>,
+    ) {
+        let mut graph = ReferenceGraph::default();
+        graph.add_node(Object::root());
+        for address in [0x10000, 0x10030, 0x10090, 0xfff8] {
+            graph.add_node(Object {
+                address,
+                bytes: 40,
+                kind: "Object".into(),
+                class: "Object".into(),
+                internal_type: "Object".into(),
+                label: None,
+                file: None,
+            });
+        }
+        assert_eq!(expected, nearest(&graph, address, 2));
+    }
+}
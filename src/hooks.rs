@@ -0,0 +1,71 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// How often parsing & analysis loops report progress and check for cancellation
+pub const CHECK_INTERVAL: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    // Lines & bytes of the dump parsed so far
+    Parsing { lines: usize, bytes: u64 },
+    // A phase of analysis has started
+    Analyzing { phase: &'static str },
+    // Nodes processed so far by the current phase of analysis
+    Nodes { done: usize, total: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+type Callback = Arc<dyn Fn(Progress) + Send + Sync>;
+
+// Lets an embedding application follow the progress of `parse` and `analyze`,
+// and abort them by setting a cancellation token.
+#[derive(Clone, Default)]
+pub struct Hooks {
+    progress: Option<Callback>,
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+impl Hooks {
+    pub fn with_progress<F: Fn(Progress) + Send + Sync + 'static>(mut self, f: F) -> Hooks {
+        self.progress = Some(Arc::new(f));
+        self
+    }
+
+    pub fn with_cancel(mut self, token: Arc<AtomicBool>) -> Hooks {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn report(&self, progress: Progress) {
+        if let Some(ref f) = self.progress {
+            f(progress);
+        }
+    }
+
+    pub fn check(&self) -> Result<(), Cancelled> {
+        match self.cancel {
+            Some(ref token) if token.load(Ordering::Relaxed) => Err(Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("progress", &self.progress.is_some())
+            .field("cancel", &self.cancel)
+            .finish()
+    }
+}
@@ -0,0 +1,120 @@
+// Every live instance of a class as a CSV file, with what it retains, where
+// it was allocated and its immediate dominator, for investigating offline.
+use crate::analyze::Analysis;
+use crate::neo4j::quote;
+use crate::object::*;
+use rustc_hash::FxHashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Which class's instances to write to which file, parsed from `CLASS=PATH`
+#[derive(Debug, Clone)]
+pub struct Export {
+    pub class: ClassPattern,
+    pub path: PathBuf,
+}
+
+impl FromStr for Export {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (class, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected CLASS=PATH, got {:?}", s))?;
+        if path.is_empty() {
+            return Err(format!("Expected CLASS=PATH, got {:?}", s));
+        }
+        Ok(Export {
+            class: class.parse().map_err(|e| format!("{}", e))?,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+// Writes the instances of `class` to `path`, returning how many there were.
+pub fn write(analysis: &Analysis, class: &ClassPattern, path: &Path) -> io::Result<usize> {
+    let dominators: FxHashMap<usize, &Object> = analysis
+        .dominator_edges()
+        .filter(|(_, obj)| class.matches(&obj.class))
+        .map(|(dominator, obj)| (obj.address, dominator))
+        .collect();
+
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(
+        out,
+        "address,kind,label,bytes,retained_bytes,retained_count,file,dominator"
+    )?;
+    let mut count = 0;
+    for (obj, retained) in analysis.objects() {
+        if obj.is_root() || !class.matches(&obj.class) {
+            continue;
+        }
+        writeln!(
+            out,
+            "{:#x},{},{},{},{},{},{},{}",
+            obj.address,
+            quote(&obj.kind),
+            quote(obj.label.as_deref().unwrap_or("")),
+            obj.bytes,
+            retained.bytes,
+            retained.count,
+            quote(obj.file.as_deref().unwrap_or("")),
+            quote(
+                &dominators
+                    .get(&obj.address)
+                    .map_or_else(String::new, |d| d.to_string())
+            ),
+        )?;
+        count += 1;
+    }
+    out.flush()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use std::fs;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write() {
+        let dump = r#"{"type":"ROOT", "root":"vm", "references":["0x1", "0x2"]}
+{"address":"0x1", "type":"OBJECT", "references":["0x3"], "memsize":10}
+{"address":"0x2", "type":"STRING", "references":[], "memsize":20, "value":"a \"quoted\" value", "file":"app/models/user.rb"}
+{"address":"0x3", "type":"STRING", "references":["0x2"], "memsize":30}
+"#;
+        let (root, graph) = parse::parse(&mut Cursor::new(dump), false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+
+        let path = std::env::temp_dir().join(format!("reap-instances-{}.csv", std::process::id()));
+        let export: Export = format!("STRING={}", path.display()).parse().unwrap();
+        let count = write(&analysis, &export.class, &export.path).unwrap();
+        let csv = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(2, count);
+        let mut rows: Vec<&str> = csv.lines().skip(1).collect();
+        rows.sort_unstable();
+        assert_eq!(
+            vec![
+                "0x2,\"STRING\",\"String[0x2][a \"\"quoted\"\" value]\",20,20,1,\"app/models/user.rb\",\"root\"",
+                "0x3,\"STRING\",\"\",30,30,1,\"\",\"OBJECT[0x1]\"",
+            ],
+            rows
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let export: Export = "ActiveRecord::Base=out/records.csv".parse().unwrap();
+        assert_eq!("ActiveRecord::Base", export.class.to_string());
+        assert_eq!(Path::new("out/records.csv"), export.path);
+        assert!("String".parse::<Export>().is_err());
+        assert!("String=".parse::<Export>().is_err());
+    }
+}
@@ -0,0 +1,249 @@
+// Interned strings: class names, types & allocation sites, which millions of
+// objects share, stored once each in an append-only arena and referred to by
+// a 4-byte id, instead of a String per object. Labels, which are unique to
+// their objects, are owned by them instead.
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::ops::Deref;
+use std::sync::{OnceLock, RwLock};
+
+// Strings are copied into chunks of this size, and larger ones allocated
+// on their own.
+const CHUNK_SIZE: usize = 1 << 20;
+
+// Ids index an append-only table of segments, each twice the size of the
+// last, so that a slot never moves once written and looking a symbol up
+// needs no lock, only the interner's writes do.
+const FIRST_SEGMENT: usize = 1 << 10;
+const SEGMENTS: usize = 32;
+type Segment = Box<[OnceLock<&'static str>]>;
+static STRINGS: [OnceLock<Segment>; SEGMENTS] = [const { OnceLock::new() }; SEGMENTS];
+
+// The segment and offset of the slot for the `index`th string.
+fn slot(index: usize) -> (usize, usize) {
+    let segment = (index / FIRST_SEGMENT + 1).ilog2() as usize;
+    (segment, index - ((1 << segment) - 1) * FIRST_SEGMENT)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(NonZeroU32);
+
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, Symbol>,
+    len: usize,
+    free: &'static mut [u8],
+}
+
+impl Interner {
+    fn store(&mut self, s: &str) -> &'static str {
+        if s.len() > CHUNK_SIZE / 4 {
+            return Box::leak(s.into());
+        }
+        if self.free.len() < s.len() {
+            self.free = Box::leak(vec![0; CHUNK_SIZE].into_boxed_slice());
+        }
+        let (stored, free) = std::mem::take(&mut self.free).split_at_mut(s.len());
+        self.free = free;
+        stored.copy_from_slice(s.as_bytes());
+        std::str::from_utf8(stored).unwrap()
+    }
+
+    fn insert(&mut self, s: &str) -> Symbol {
+        if let Some(&symbol) = self.ids.get(s) {
+            return symbol;
+        }
+        let stored = self.store(s);
+        let (segment, offset) = slot(self.len);
+        STRINGS[segment].get_or_init(|| {
+            (0..FIRST_SEGMENT << segment)
+                .map(|_| OnceLock::new())
+                .collect()
+        })[offset]
+            .set(stored)
+            .unwrap();
+        self.len += 1;
+        let id = u32::try_from(self.len).expect("Too many distinct strings");
+        let symbol = Symbol(NonZeroU32::new(id).unwrap());
+        self.ids.insert(stored, symbol);
+        symbol
+    }
+}
+
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+impl Symbol {
+    pub fn intern(s: &str) -> Symbol {
+        if let Some(&symbol) = interner().read().unwrap().ids.get(s) {
+            return symbol;
+        }
+        interner().write().unwrap().insert(s)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        let (segment, offset) = slot(self.0.get() as usize - 1);
+        STRINGS[segment].get().unwrap()[offset].get().unwrap()
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Symbol {
+        Symbol::intern(s)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(s: String) -> Symbol {
+        Symbol::intern(&s)
+    }
+}
+
+impl From<&String> for Symbol {
+    fn from(s: &String) -> Symbol {
+        Symbol::intern(s)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for Symbol {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<Symbol> for str {
+    fn eq(&self, other: &Symbol) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl PartialEq<Symbol> for &str {
+    fn eq(&self, other: &Symbol) -> bool {
+        *self == other.as_str()
+    }
+}
+
+// Ordered by content rather than id, so sorting by kind is alphabetical.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Symbol) -> Ordering {
+        if self == other {
+            Ordering::Equal
+        } else {
+            self.as_str().cmp(other.as_str())
+        }
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Symbol) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Symbol, D::Error> {
+        let s: std::borrow::Cow<'de, str> = Deserialize::deserialize(deserializer)?;
+        Ok(Symbol::intern(&s))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let string = Symbol::intern("String");
+        assert_eq!(string, Symbol::from("String".to_string()));
+        assert_ne!(string, Symbol::intern("Array"));
+        assert_eq!("String", string.as_str());
+        assert!(Symbol::intern("Array") < string);
+
+        let long = "x".repeat(CHUNK_SIZE);
+        assert_eq!(long, Symbol::intern(&long).as_str());
+        assert_eq!("", Symbol::intern("").as_str());
+
+        let json = serde_json::to_string(&string).unwrap();
+        assert_eq!("\"String\"", json);
+        assert_eq!(string, serde_json::from_str::<Symbol>(&json).unwrap());
+    }
+
+    #[test]
+    fn test_slot() {
+        assert_eq!((0, 0), slot(0));
+        assert_eq!((0, FIRST_SEGMENT - 1), slot(FIRST_SEGMENT - 1));
+        assert_eq!((1, 0), slot(FIRST_SEGMENT));
+        assert_eq!((1, 2 * FIRST_SEGMENT - 1), slot(3 * FIRST_SEGMENT - 1));
+        assert_eq!((2, 0), slot(3 * FIRST_SEGMENT));
+        let (segment, offset) = slot(u32::MAX as usize - 1);
+        assert!(segment < SEGMENTS && offset < FIRST_SEGMENT << segment);
+    }
+
+    #[test]
+    fn test_intern_concurrently() {
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    (0..5000)
+                        .map(|i| Symbol::intern(&format!("concurrent{}", (i * (t + 1)) % 5000)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for symbols in threads.into_iter().map(|t| t.join().unwrap()) {
+            for symbol in symbols {
+                assert_eq!(symbol, Symbol::intern(symbol.as_str()));
+                assert!(symbol.as_str().starts_with("concurrent"));
+            }
+        }
+    }
+}
@@ -1,3 +1,38 @@
 pub mod analyze;
+#[cfg(feature = "reap-capi")]
+pub mod capi;
+pub mod config;
+pub mod csr;
+pub mod d3;
+pub mod detect;
+pub mod dominator;
+pub mod dot;
+pub mod edgelist;
+pub mod extract;
+pub mod graph;
+pub mod hooks;
+pub mod instances;
+pub mod intern;
+pub mod malloc;
+pub mod neo4j;
 pub mod object;
+#[cfg(feature = "parquet")]
+pub mod parquet;
 pub mod parse;
+pub mod pprof;
+pub mod prometheus;
+pub mod query;
+pub mod smaps;
+pub mod snapshot;
+pub mod source;
+pub mod speedscope;
+#[cfg(feature = "mmap")]
+pub mod spill;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod storage;
+pub mod summary;
+pub mod threshold;
+pub mod timing;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -5,203 +5,2781 @@ extern crate serde_json;
 extern crate structopt;
 extern crate timed_function;
 
-mod analyze;
-mod object;
-mod parse;
-
-use crate::object::*;
 use bytesize::ByteSize;
+use indicatif::{ProgressBar, ProgressStyle};
 use inferno::flamegraph;
-use petgraph::dot;
+use petgraph::graph::NodeIndex;
+use reap::hooks::{Hooks, Progress};
+use reap::intern::Symbol;
+use reap::object::*;
+use reap::query::Query;
+use reap::threshold::{self, Limit, Measure, Threshold};
+use reap::{
+    analyze, config, d3, detect, dot, edgelist, extract, graph, instances, malloc, neo4j, parse,
+    pprof, prometheus, smaps, snapshot, source, speedscope, summary, timing,
+};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::error;
 use std::fmt::Display;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use structopt::clap::{AppSettings, Shell};
 use structopt::StructOpt;
 
+mod repl;
+#[cfg(feature = "serve")]
+mod server;
+#[cfg(feature = "tui")]
+mod tui;
+
 type Result<T> = std::result::Result<T, Box<dyn error::Error>>;
 
+// Whether to draw progress bars on stderr while loading & analyzing a dump;
+// they're also hidden whenever stderr isn't a terminal.
+static SHOW_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+fn progress_bar(len: Option<u64>) -> ProgressBar {
+    if !SHOW_PROGRESS.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    match len {
+        Some(len) => ProgressBar::new(len).with_style(
+            ProgressStyle::with_template("{msg} [{bar:40}] {bytes}/{total_bytes} ({eta})")
+                .unwrap()
+                .progress_chars("=> "),
+        ),
+        None => {
+            let spinner = ProgressBar::new_spinner();
+            spinner.enable_steady_tick(Duration::from_millis(100));
+            spinner
+        }
+    }
+}
+
+// Shows analysis phases on a spinner, turning it into a bar through the
+// graph's nodes for phases which report how far along they are.
+fn show_analysis(bar: &ProgressBar, progress: Progress) {
+    match progress {
+        Progress::Analyzing { phase } => {
+            bar.set_message(format!("Analyzing: {}", phase));
+            if bar.length().is_some() {
+                bar.unset_length();
+                bar.set_style(ProgressStyle::default_spinner());
+                bar.enable_steady_tick(Duration::from_millis(100));
+            }
+        }
+        Progress::Nodes { done, total } => {
+            if bar.length() != Some(total as u64) {
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "{msg} [{bar:40}] {human_pos}/{human_len} nodes ({eta})",
+                    )
+                    .unwrap()
+                    .progress_chars("=> "),
+                );
+                bar.set_length(total as u64);
+            }
+            bar.set_position(done as u64);
+        }
+        Progress::Parsing { .. } => {}
+    }
+}
+
 fn write_dot_file(graph: &ReferenceGraph, filename: &Path) -> Result<()> {
     let mut file = File::create(filename)?;
     write!(
         file,
         "{}",
-        dot::Dot::with_config(&graph, &[dot::Config::EdgeNoLabel])
+        petgraph::dot::Dot::with_config(&graph, &[petgraph::dot::Config::EdgeNoLabel])
+    )?;
+    Ok(())
+}
+
+// The smallest subtree flamegraphs draw: the larger of a size and a
+// percentage of the heap.
+fn flame_min(analysis: &analyze::Analysis, bytes: Option<ByteSize>, percent: Option<f64>) -> usize {
+    let total = analysis.dominated_totals().bytes as f64;
+    let by_percent = percent.map_or(0, |p| (total * p / 100.0) as usize);
+    by_percent.max(bytes.map_or(0, |b| b.as_u64() as usize))
+}
+
+// Which way flamegraphs grow from the root
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum FlameDirection {
+    // Bottom-up, like CPU flamegraphs
+    Normal,
+    // Top-down, as an icicle chart
+    #[default]
+    Inverted,
+}
+
+impl std::str::FromStr for FlameDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(FlameDirection::Normal),
+            "inverted" => Ok(FlameDirection::Inverted),
+            _ => Err(format!("Unknown flamegraph direction {:?}", s)),
+        }
+    }
+}
+
+// How flamegraphs are drawn; what's unset is left to inferno, except the
+// count name, which defaults to "bytes"
+#[derive(Debug, Clone, Default)]
+struct FlamegraphStyle {
+    palette: flamegraph::color::Palette,
+    direction: FlameDirection,
+
+    // Put each stack's last frame at the root, to see what's retained by
+    // class (with --frame-format class) rather than what retains it
+    reverse: bool,
+
+    // Order siblings by how much they retain, largest first, rather than by
+    // name
+    left_heavy: bool,
+
+    // Color frames by their class alone, so each class is the same color in
+    // every flamegraph
+    by_class: bool,
+
+    title: Option<String>,
+    subtitle: Option<String>,
+    notes: Option<String>,
+    width: Option<usize>,
+    font_size: Option<usize>,
+    count_name: Option<String>,
+
+    // Leave out inferno's scripts for searching and zooming, for viewers
+    // which don't run them anyway
+    no_javascript: bool,
+}
+
+// Days since 1970-01-01 as a (year, month, day) date, after Howard Hinnant's
+// `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// When a file was last modified, in UTC, which for a heap dump is about when
+// it was taken.
+fn modified_date(path: &Path) -> Option<String> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    let secs = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let minutes = secs.rem_euclid(86_400) / 60;
+    Some(format!(
+        "{}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    ))
+}
+
+// A subtitle describing the dump a flamegraph was drawn from, so it can be
+// shared without losing track of that.
+fn flamegraph_subtitle(input: &Path, totals: Stats) -> String {
+    let name = input.file_name().unwrap_or(input.as_os_str());
+    let mut parts = vec![name.to_string_lossy().into_owned()];
+    parts.extend(modified_date(input));
+    parts.push(format!(
+        "{} in {} objects",
+        ByteSize(totals.bytes as u64),
+        totals.count
+    ));
+    parts.join(", ")
+}
+
+// The class of a flamegraph frame: its label up to the first bracket, which
+// precedes the address in labels of objects.
+fn frame_class(frame: &str) -> &str {
+    frame.split('[').next().unwrap_or(frame)
+}
+
+// The color of each frame in `lines`, which the palette picks from a hash of
+// the frame's class.
+fn class_colors(
+    lines: &[String],
+    palette: flamegraph::color::Palette,
+) -> Result<flamegraph::color::PaletteMap> {
+    let frames: BTreeSet<&str> = lines
+        .iter()
+        .filter_map(|line| line.rsplit_once(' '))
+        .flat_map(|(stack, _)| stack.split(';'))
+        .collect();
+    let classes: BTreeSet<&str> = frames.iter().map(|frame| frame_class(frame)).collect();
+
+    // inferno's colors are private, so have it pick them for a flamegraph
+    // with a frame for each class
+    let mut by_class = flamegraph::color::PaletteMap::default();
+    let mut opts = flamegraph::Options::default();
+    opts.colors = palette;
+    opts.deterministic = true;
+    opts.min_width = 0.0;
+    opts.palette_map = Some(&mut by_class);
+    let class_lines: Vec<String> = classes.iter().map(|c| format!("{} 1", c)).collect();
+    flamegraph::from_lines(
+        &mut opts,
+        class_lines.iter().map(|s| s.as_str()),
+        std::io::sink(),
     )?;
+
+    let mut colors = flamegraph::color::PaletteMap::default();
+    for frame in frames {
+        if let Some(color) = by_class.get(frame_class(frame)) {
+            colors.insert(frame, color);
+        }
+    }
+    Ok(colors)
+}
+
+// `lines` in depth-first order of the frames they share, with the heaviest
+// frames at each level first.
+fn left_heavy(lines: &[String]) -> Vec<&str> {
+    fn split(line: &str) -> (&str, usize) {
+        match line.rsplit_once(' ') {
+            Some((stack, count)) => (stack, count.parse().unwrap_or(0)),
+            None => (line, 0),
+        }
+    }
+
+    // Total of each stack prefix
+    let mut totals: HashMap<&str, usize> = HashMap::new();
+    for line in lines {
+        let (stack, count) = split(line);
+        let ends = stack.match_indices(';').map(|(i, _)| i);
+        for end in ends.chain(std::iter::once(stack.len())) {
+            *totals.entry(&stack[..end]).or_default() += count;
+        }
+    }
+
+    let mut sorted: Vec<&str> = lines.iter().map(|line| line.as_str()).collect();
+    sorted.sort_by_cached_key(|line| {
+        let (stack, _) = split(line);
+        let mut start = 0;
+        stack
+            .split(';')
+            .map(|frame| {
+                let end = start + frame.len();
+                start = end + 1;
+                (std::cmp::Reverse(totals[&stack[..end]]), frame)
+            })
+            .collect::<Vec<_>>()
+    });
+    sorted
+}
+
+fn render_flamegraph<W: Write>(lines: &[String], style: &FlamegraphStyle, writer: W) -> Result<()> {
+    let mut palette_map = match style.by_class {
+        true => Some(class_colors(lines, style.palette)?),
+        false => None,
+    };
+    let mut opts = flamegraph::Options::default();
+    opts.direction = match style.direction {
+        FlameDirection::Normal => flamegraph::Direction::Straight,
+        FlameDirection::Inverted => flamegraph::Direction::Inverted,
+    };
+    opts.reverse_stack_order = style.reverse;
+    opts.count_name = style.count_name.clone().unwrap_or("bytes".to_string());
+    opts.colors = style.palette;
+    opts.palette_map = palette_map.as_mut();
+    if let Some(title) = &style.title {
+        opts.title.clone_from(title);
+    }
+    opts.subtitle.clone_from(&style.subtitle);
+    if let Some(notes) = &style.notes {
+        opts.notes.clone_from(notes);
+    }
+    opts.no_javascript = style.no_javascript;
+    opts.image_width = style.width;
+    if let Some(font_size) = style.font_size {
+        opts.font_size = font_size;
+    }
+
+    if style.left_heavy && !style.reverse {
+        // inferno insists on sorted lines except in flame charts, which it
+        // draws in reverse order, but otherwise the same
+        opts.flame_chart = true;
+        flamegraph::from_lines(&mut opts, left_heavy(lines).into_iter().rev(), writer)?;
+    } else {
+        flamegraph::from_lines(&mut opts, lines.iter().map(|s| s.as_str()), writer)?;
+    }
+    Ok(())
+}
+
+fn write_flamegraph(lines: &[String], style: &FlamegraphStyle, filename: &Path) -> Result<()> {
+    render_flamegraph(lines, style, File::create(filename)?)
+}
+
+// Smallest share of the heap that HTML charts draw separately
+const CHART_MIN_FRACTION: f64 = 0.0001;
+
+const TREEMAP: &str = include_str!("ui/treemap.html");
+const SUNBURST: &str = include_str!("ui/sunburst.html");
+
+// Writes a page from one of the templates in ui/, filled in with the
+// dominator tree frames.
+fn write_chart(template: &str, analysis: &analyze::Analysis, filename: &Path) -> Result<()> {
+    let mut frames = d3::tree(analysis);
+    frames.prune((frames.value as f64 * CHART_MIN_FRACTION) as usize);
+
+    // Escape "</" so the data can't close the script tag it's embedded in
+    let json = serde_json::to_string(&frames)?.replace("</", "<\\/");
+    std::fs::write(filename, template.replacen("/*REAP_DATA*/null", &json, 1))?;
+    Ok(())
+}
+
+// Writes via a temporary file so collectors never read a partial file.
+fn write_textfile(text: &str, filename: &Path) -> Result<()> {
+    let mut tmp = filename.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, text)?;
+    std::fs::rename(&tmp, filename)?;
+    Ok(())
+}
+
+fn write_folded(lines: &[String], filename: &Path) -> Result<()> {
+    let file = File::create(filename)?;
+    let mut writer = std::io::BufWriter::new(file);
+    for line in lines {
+        writeln!(writer, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn print_largest<K: Display>(largest: &[(K, Stats)], rest: Stats) {
+    if largest.is_empty() {
+        println!("None");
+        return;
+    }
+
+    for (k, stats) in largest {
+        println!(
+            "{}: {} ({} objects)",
+            k,
+            ByteSize(stats.bytes as u64),
+            stats.count
+        );
+    }
+
+    if rest.count > 0 {
+        println!(
+            "...: {} ({} objects)",
+            ByteSize(rest.bytes as u64),
+            rest.count
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Origin,
+    Live,
+    Objects,
+    Retained,
+    Unreachable,
+    Groups,
+    Budgets,
+    Memory,
+}
+
+impl std::str::FromStr for Section {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "origin" => Ok(Section::Origin),
+            "live" => Ok(Section::Live),
+            "objects" => Ok(Section::Objects),
+            "retained" => Ok(Section::Retained),
+            "unreachable" => Ok(Section::Unreachable),
+            "groups" => Ok(Section::Groups),
+            "budgets" => Ok(Section::Budgets),
+            "memory" => Ok(Section::Memory),
+            _ => Err(format!("Unknown section {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    Ruby,
+    Edges,
+}
+
+impl std::str::FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ruby" => Ok(InputFormat::Ruby),
+            "edges" => Ok(InputFormat::Edges),
+            _ => Err(format!("Unknown input format {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimingsFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for TimingsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TimingsFormat::Text),
+            "json" => Ok(TimingsFormat::Json),
+            _ => Err(format!("Unknown timings format {:?}", s)),
+        }
+    }
+}
+
+fn print_timings(format: TimingsFormat) -> Result<()> {
+    match format {
+        TimingsFormat::Json => eprintln!("{}", timing::to_json()?),
+        TimingsFormat::Text => {
+            for phase in timing::phases() {
+                let nodes = match phase.nodes {
+                    Some(n) => format!(" ({} nodes)", n),
+                    None => String::new(),
+                };
+                eprintln!(
+                    "{:indent$}{}: {:.3}s{}",
+                    "",
+                    phase.name,
+                    phase.duration.as_secs_f64(),
+                    nodes,
+                    indent = phase.depth * 2
+                );
+            }
+        }
+    }
     Ok(())
 }
 
-fn write_flamegraph(lines: &[String], filename: &Path) -> Result<()> {
-    let mut opts = flamegraph::Options::default();
-    opts.direction = flamegraph::Direction::Inverted;
-    opts.count_name = "bytes".to_string();
+// Controls which parts of the report are printed to stdout.
+struct Output {
+    count: usize,
+    only: Vec<Section>,
+    quiet: bool,
+}
+
+impl Output {
+    fn includes(&self, section: Section) -> bool {
+        self.only.is_empty() || self.only.contains(&section)
+    }
+
+    fn header(&self, title: &str) {
+        if !self.quiet {
+            println!("\n{}:", title);
+        }
+    }
+
+    fn note(&self, text: &str) {
+        if !self.quiet {
+            println!("\n{}", text);
+        }
+    }
+}
+
+fn print_report(analysis: &analyze::Analysis, output: &Output, subtree_roots: &[usize]) {
+    if output.includes(Section::Origin) {
+        // Only dumps of processes tracing allocations say where objects come from
+        let origins = analysis.origin_stats();
+        if origins.iter().any(|(o, _)| *o != analyze::Origin::Unknown) {
+            output.header("Live memory by origin");
+            print_origins(&origins, analysis.dominated_totals());
+        }
+    }
+
+    if output.includes(Section::Live) {
+        output.header("Object types using the most live memory");
+        let (largest, rest) = analysis.live_stats_by_kind(output.count);
+        print_largest(&largest, rest);
+    }
+
+    if output.includes(Section::Objects) {
+        output.header("Objects retaining the most live memory");
+        let (largest, rest) = analysis.dominator_subtree_stats(output.count);
+        print_largest(&largest, rest);
+    }
+
+    if output.includes(Section::Retained) {
+        output.header("Object types retaining the most live memory");
+        let (largest, rest) = analysis.retained_stats_by_kind(output.count);
+        print_largest(&largest, rest);
+    }
+
+    if output.includes(Section::Unreachable) {
+        if subtree_roots.is_empty() {
+            output.header("Objects unreachable from root");
+        } else {
+            let addresses: Vec<String> =
+                subtree_roots.iter().map(|a| format!("{:#x}", a)).collect();
+            output.header(&format!(
+                "Objects reachable from, but not dominated by, {}",
+                addresses.join(", ")
+            ));
+        }
+        let (largest, rest) = analysis.unreachable_stats_by_kind(output.count);
+        print_largest(&largest, rest);
+    }
+}
+
+fn print_cycles(cycles: &[analyze::Cycle], rest: Stats) {
+    if cycles.is_empty() {
+        println!("None");
+        return;
+    }
+
+    for cycle in cycles {
+        println!(
+            "{}: {} ({} objects)",
+            cycle.head,
+            ByteSize(cycle.stats.bytes as u64),
+            cycle.stats.count
+        );
+        let path: Vec<String> = cycle.path.iter().map(|obj| obj.to_string()).collect();
+        println!("  cycle: {}", path.join(" -> "));
+        println!(
+            "  retained by {}, pinning {} ({} objects)",
+            cycle.retainer,
+            ByteSize(cycle.pinned.bytes as u64),
+            cycle.pinned.count
+        );
+    }
+
+    if rest.count > 0 {
+        println!(
+            "...: {} ({} objects)",
+            ByteSize(rest.bytes as u64),
+            rest.count
+        );
+    }
+}
+
+// Groups the objects by class, in the order of each class's first object.
+fn print_fan_in(objects: &[(&Object, usize)]) {
+    if objects.is_empty() {
+        println!("None");
+        return;
+    }
+
+    let mut classes: Vec<(&str, Vec<(&Object, usize)>)> = vec![];
+    for &(obj, referrers) in objects {
+        match classes
+            .iter_mut()
+            .find(|(kind, _)| *kind == obj.kind.as_str())
+        {
+            Some((_, objects)) => objects.push((obj, referrers)),
+            None => classes.push((obj.kind.as_str(), vec![(obj, referrers)])),
+        }
+    }
+    for (kind, objects) in classes {
+        println!("{}:", kind);
+        for (obj, referrers) in objects {
+            println!("  {}: {} referrers", obj, referrers);
+        }
+    }
+}
+
+fn print_duplicate_strings(duplicates: &[analyze::DuplicateString], rest: Stats) {
+    if duplicates.is_empty() {
+        println!("None");
+        return;
+    }
+
+    for duplicate in duplicates {
+        let holders: Vec<String> = duplicate
+            .holders
+            .iter()
+            .take(3)
+            .map(|(kind, copies)| format!("{} ({})", kind, copies))
+            .collect();
+        println!(
+            "{:?}: {} copies, {} ({} wasted), held by {}",
+            duplicate.value,
+            duplicate.stats.count,
+            ByteSize(duplicate.stats.bytes as u64),
+            ByteSize(duplicate.wasted as u64),
+            holders.join(", ")
+        );
+    }
+
+    if rest.count > 0 {
+        println!(
+            "...: {} copies, {} wasted",
+            rest.count,
+            ByteSize(rest.bytes as u64)
+        );
+    }
+}
+
+fn print_origins(origins: &[(analyze::Origin, Stats)], total: Stats) {
+    for (origin, stats) in origins {
+        println!(
+            "{}: {} ({:.1}%, {} objects)",
+            origin,
+            ByteSize(stats.bytes as u64),
+            100.0 * stats.bytes as f64 / total.bytes.max(1) as f64,
+            stats.count
+        );
+    }
+}
+
+fn print_shared_regions(regions: &[analyze::SharedRegion], rest: Stats) {
+    if regions.is_empty() {
+        println!("None");
+        return;
+    }
+
+    for region in regions {
+        println!(
+            "{}: {} ({} objects)",
+            region.head,
+            ByteSize(region.stats.bytes as u64),
+            region.stats.count
+        );
+        let referrers: Vec<String> = region.referrers.iter().map(|r| r.to_string()).collect();
+        println!("  referenced by: {}", referrers.join(", "));
+    }
+
+    if rest.count > 0 {
+        println!(
+            "...: {} ({} objects)",
+            ByteSize(rest.bytes as u64),
+            rest.count
+        );
+    }
+}
+
+fn load(file: &Path, class_name_only: bool) -> Result<(NodeIndex<usize>, ReferenceGraph)> {
+    load_with_options(
+        file,
+        &parse::ParseOptions {
+            class_name_only,
+            ..Default::default()
+        },
+    )
+}
+
+fn load_with_options(
+    file: &Path,
+    options: &parse::ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph)> {
+    let (root, graph, _) = load_with_labels(file, options)?;
+    Ok((root, graph))
+}
+
+#[cfg(feature = "mmap")]
+fn load_with_labels(
+    file: &Path,
+    options: &parse::ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph, parse::LabelIndex)> {
+    let progress = progress_bar(Some(std::fs::metadata(file)?.len())).with_message("Parsing");
+    let bar = progress.clone();
+    let options = parse::ParseOptions {
+        hooks: Hooks::default().with_progress(move |p| {
+            if let Progress::Parsing { bytes, .. } = p {
+                bar.set_position(bytes)
+            }
+        }),
+        ..options.clone()
+    };
+    let result = parse::parse_file(file, &options);
+    progress.finish_and_clear();
+    Ok(result?)
+}
+
+#[cfg(not(feature = "mmap"))]
+fn load_with_labels(
+    file: &Path,
+    options: &parse::ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph, parse::LabelIndex)> {
+    let file = File::open(file)?;
+    let metadata = file.metadata()?;
+    // Pipes can't be read twice, so are copied to a temporary file first
+    if !metadata.is_file() {
+        return Ok(parse::parse_stream(&mut BufReader::new(file), options)?);
+    }
+    let progress = progress_bar(Some(metadata.len())).with_message("Parsing");
+    let mut reader = BufReader::new(progress.wrap_read(file));
+    let result = parse::parse_with_labels(&mut reader, options);
+    progress.finish_and_clear();
+    Ok(result?)
+}
+
+// Analyzes a dump larger than memory, printing the sections of the report
+// which don't need the whole graph.
+#[cfg(feature = "mmap")]
+fn spill_report(
+    file: &Path,
+    dir: &Path,
+    options: &parse::ParseOptions,
+    view: KindView,
+    group_by: GroupBy,
+    output: &Output,
+) -> Result<()> {
+    let spill_dir = reap::spill::SpillDir::new(dir).map_err(|e| {
+        format!(
+            "Could not write temporary files to {}: {}",
+            dir.display(),
+            e
+        )
+    })?;
+    let progress = progress_bar(Some(std::fs::metadata(file)?.len())).with_message("Parsing");
+    let bar = progress.clone();
+    let options = parse::ParseOptions {
+        hooks: Hooks::default().with_progress(move |p| match p {
+            Progress::Parsing { bytes, .. } => bar.set_position(bytes),
+            p => show_analysis(&bar, p),
+        }),
+        ..options.clone()
+    };
+    let result = reap::spill::analyze(file, &spill_dir, &options, view, group_by, output.count);
+    progress.finish_and_clear();
+    let report = result?;
+
+    let top = |rows: &[(reap::intern::Symbol, Stats)]| {
+        let n = rows.len().min(output.count);
+        let rest = rows[n..]
+            .iter()
+            .fold(Stats::default(), |mut rest, (_, stats)| rest.add(*stats));
+        (rows[..n].to_vec(), rest)
+    };
+    if output.includes(Section::Live) {
+        output.header("Object types using the most live memory");
+        let (largest, rest) = top(&report.live_by_kind);
+        print_largest(&largest, rest);
+    }
+    if output.includes(Section::Objects) {
+        output.header("Objects retaining the most live memory");
+        print_largest(&report.largest, report.others);
+    }
+    if output.includes(Section::Retained) {
+        output.header("Object types retaining the most live memory");
+        let (largest, rest) = top(&report.retained_by_kind);
+        print_largest(&largest, rest);
+    }
+    if output.includes(Section::Unreachable) {
+        output.header("Objects unreachable from root");
+        let (largest, rest) = top(&report.unreachable_by_kind);
+        print_largest(&largest, rest);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "mmap"))]
+fn spill_report(
+    _: &Path,
+    _: &Path,
+    _: &parse::ParseOptions,
+    _: KindView,
+    _: GroupBy,
+    _: &Output,
+) -> Result<()> {
+    Err("reap was built without the `mmap` feature; reinstall with `cargo install reap --features mmap`".into())
+}
+
+// Reads the labels of objects the report shows from the dump, or those of
+// all objects if `all` (i.e. for outputs of the whole dominator tree).
+fn read_labels(
+    analysis: &mut analyze::Analysis,
+    index: &parse::LabelIndex,
+    file: &Path,
+    count: usize,
+    all: bool,
+) -> Result<()> {
+    let addresses: Vec<usize> = if all {
+        analysis.objects().map(|(obj, _)| obj.address).collect()
+    } else {
+        let (largest, _) = analysis.dominator_subtree_stats(count);
+        let (regions, _) = analysis.shared_regions(count);
+        largest
+            .iter()
+            .map(|(obj, _)| obj.address)
+            .chain(regions.iter().flat_map(|region| {
+                std::iter::once(region.head.address)
+                    .chain(region.referrers.iter().map(|r| r.address))
+            }))
+            .collect()
+    };
+    let mut reader = BufReader::new(File::open(file)?);
+    let labels = index.labels(&mut reader, addresses)?;
+    analysis.set_labels(labels);
+    Ok(())
+}
+
+fn load_edges(
+    file: &Path,
+    options: &parse::ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph)> {
+    let file = File::open(file)?;
+    let progress = progress_bar(Some(file.metadata()?.len())).with_message("Parsing");
+    let mut reader = BufReader::new(progress.wrap_read(file));
+    let result = edgelist::parse(&mut reader, options);
+    progress.finish_and_clear();
+    Ok(result?)
+}
+
+fn load_snapshot(
+    file: &Path,
+    options: &parse::ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph)> {
+    let file_name = file.display().to_string();
+    let file = File::open(file)?;
+    let progress = progress_bar(Some(file.metadata()?.len())).with_message("Loading");
+    let mut reader = BufReader::new(progress.wrap_read(file));
+    let result = snapshot::load(&mut reader);
+    progress.finish_and_clear();
+
+    let (root, graph, metadata) = result?;
+    if metadata != snapshot::Metadata::from(options) {
+        log::warn!(
+            "{} was parsed with {:?}, which take precedence over the options given",
+            file_name,
+            metadata
+        );
+    }
+    Ok((root, graph))
+}
+
+fn find_address(graph: &ReferenceGraph, address: usize) -> Result<NodeIndex<usize>> {
+    Ok(graph
+        .node_indices()
+        .find(|i| graph[*i].address == address)
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "Given address not found")
+        })?)
+}
+
+// Checks there are objects at `addresses`, or with `snap`, replaces those
+// missing with the nearest object on the same heap page, as addresses copied
+// from other tools are often off by a slot.
+fn find_roots(graph: &ReferenceGraph, addresses: &mut [usize], snap: bool) -> Result<()> {
+    let found: HashSet<usize> = graph
+        .node_weights()
+        .map(|obj| obj.address)
+        .filter(|a| addresses.contains(a))
+        .collect();
+    for address in addresses.iter_mut().filter(|a| !found.contains(a)) {
+        let nearest = graph::nearest(graph, *address, 3);
+        match nearest.first() {
+            Some(&n) if snap => {
+                log::warn!(
+                    "No object at {:#x}; rooting at the nearest, {:#x}, instead",
+                    address,
+                    n
+                );
+                *address = n;
+            }
+            Some(_) => {
+                let nearest: Vec<String> = nearest.iter().map(|a| format!("{:#x}", a)).collect();
+                return Err(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!(
+                        "No object at {:#x}; the nearest on its heap page are {} (--root-nearest uses the first)",
+                        address,
+                        nearest.join(", ")
+                    ),
+                )));
+            }
+            None => {
+                return Err(Box::new(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("No object at {:#x}, nor on its heap page", address),
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_addresses(file: &Path) -> Result<Vec<usize>> {
+    let reader = BufReader::new(File::open(file)?);
+    let mut addresses = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        addresses.push(parse::parse_address(line)?);
+    }
+    Ok(addresses)
+}
+
+fn analyze_graph(
+    root: NodeIndex<usize>,
+    graph: ReferenceGraph,
+    rooted_at: Option<usize>,
+    class_name_only: bool,
+) -> Result<analyze::Analysis> {
+    let subgraph_root = rooted_at
+        .map(|address| find_address(&graph, address))
+        .unwrap_or(Ok(root))?;
+
+    let progress = progress_bar(None);
+    let spinner = progress.clone();
+    let hooks = Hooks::default().with_progress(move |p| show_analysis(&spinner, p));
+    let result = analyze::analyze_with_hooks(root, subgraph_root, graph, class_name_only, &hooks);
+    progress.finish_and_clear();
+
+    Ok(result.map_err(std::io::Error::other)?)
+}
+
+fn children(file: &Path, address: usize, count: usize, class_name_only: bool) -> Result<()> {
+    let (root, graph) = load(file, class_name_only)?;
+    let analysis = analyze_graph(root, graph, None, class_name_only)?;
+    println!();
+
+    let (object, stats) = analysis.retained_stats(address).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Given address not reachable from root",
+        )
+    })?;
+    print_largest(&[(object, stats)], Stats::default());
+
+    println!("\nDominator tree children of {}:", object);
+    let (largest, rest) = analysis.dominator_children(address, count).unwrap();
+    print_largest(&largest, rest);
+
+    Ok(())
+}
+
+fn only_in(file: &Path, baseline: &Path, count: usize, class_name_only: bool) -> Result<()> {
+    let baseline_addresses: HashSet<usize> = {
+        let (_, graph) = load(baseline, class_name_only)?;
+        graph.node_weights().map(|obj| obj.address).collect()
+    };
+    let is_new = |obj: &Object| !baseline_addresses.contains(&obj.address);
+
+    let (root, graph) = load(file, class_name_only)?;
+    let analysis = analyze_graph(root, graph, None, class_name_only)?;
+    println!();
+
+    println!("Object types using the most live memory, not in baseline:");
+    let (largest, rest) = analysis.live_stats_by_kind_where(count, is_new);
+    print_largest(&largest, rest);
+
+    println!("\nObjects retaining the most live memory, not in baseline:");
+    let (largest, rest) = analysis.dominator_subtree_stats_where(count, is_new);
+    print_largest(&largest, rest);
+
+    println!("\nObject types retaining the most live memory, not in baseline:");
+    let (largest, rest) = analysis.retained_stats_by_kind_where(count, is_new);
+    print_largest(&largest, rest);
+
+    Ok(())
+}
+
+fn stats(file: &Path) -> Result<()> {
+    let (root, graph) = load(file, true)?;
+    let analysis = analyze_graph(root, graph, None, true)?;
+    println!();
+    print_graph_stats(&analysis.graph_stats());
+
+    println!("\nDominator tree depths:");
+    print_depth_histogram(&analysis.depth_histogram());
+    Ok(())
+}
+
+fn print_graph_stats(stats: &analyze::GraphStats) {
+    let total = stats.live.bytes + stats.unreachable.bytes;
+    println!(
+        "Objects: {} live ({}), {} unreachable ({}, {:.1}% of bytes)",
+        stats.live.count,
+        ByteSize(stats.live.bytes as u64),
+        stats.unreachable.count,
+        ByteSize(stats.unreachable.bytes as u64),
+        100.0 * stats.unreachable.bytes as f64 / total.max(1) as f64
+    );
+    println!("References between live objects: {}", stats.references);
+    let print_percentiles = |name: &str, p: &analyze::Percentiles| {
+        println!(
+            "{}: median {}, p90 {}, p99 {}, max {} (mean {:.1})",
+            name, p.median, p.p90, p.p99, p.max, p.mean
+        );
+    };
+    print_percentiles("Referrers per object", &stats.referrers_per_object);
+    print_percentiles("References per object", &stats.references_per_object);
+    println!(
+        "Reference cycles: {}, of {} objects",
+        stats.cycles, stats.cyclic
+    );
+    print_percentiles("Dominator tree depth", &stats.dominator_depth);
+}
+
+fn print_depth_histogram(bands: &[analyze::DepthBand]) {
+    for band in bands {
+        let depths = if band.min == band.max {
+            band.min.to_string()
+        } else {
+            format!("{}-{}", band.min, band.max)
+        };
+        println!(
+            "{}: {} ({} objects), mostly {}",
+            depths,
+            ByteSize(band.stats.bytes as u64),
+            band.stats.count,
+            band.kind
+        );
+    }
+}
+
+#[cfg(feature = "tui")]
+fn tui(file: &Path, class_name_only: bool) -> Result<()> {
+    let (root, graph) = load(file, class_name_only)?;
+    let analysis = analyze_graph(root, graph, None, class_name_only)?;
+    Ok(tui::run(analysis)?)
+}
+
+#[cfg(not(feature = "tui"))]
+fn tui(_: &Path, _: bool) -> Result<()> {
+    Err("reap was built without the `tui` feature; reinstall with `cargo install reap --features tui`".into())
+}
+
+#[cfg(feature = "serve")]
+fn serve(file: &Path, host: &str, port: u16, class_name_only: bool) -> Result<()> {
+    let (root, graph) = load(file, class_name_only)?;
+    let analysis = analyze_graph(root, graph, None, class_name_only)?;
+    server::run(analysis, host, port)
+}
+
+#[cfg(not(feature = "serve"))]
+fn serve(_: &Path, _: &str, _: u16, _: bool) -> Result<()> {
+    Err("reap was built without the `serve` feature; reinstall with `cargo install reap --features serve`".into())
+}
+
+#[cfg(feature = "sqlite")]
+fn write_sqlite(analysis: &analyze::Analysis, path: &Path) -> Result<usize> {
+    Ok(reap::sqlite::write(analysis, path)?)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn write_sqlite(_: &analyze::Analysis, _: &Path) -> Result<usize> {
+    Err("reap was built without the `sqlite` feature; reinstall with `cargo install reap --features sqlite`".into())
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet(analysis: &analyze::Analysis, dir: &Path) -> Result<(usize, usize)> {
+    Ok(reap::parquet::write(analysis, dir)?)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet(_: &analyze::Analysis, _: &Path) -> Result<(usize, usize)> {
+    Err("reap was built without the `parquet` feature; reinstall with `cargo install reap --features parquet`".into())
+}
+
+// Loads the given config file, or else the nearest reap.toml unless disabled.
+fn load_config(path: Option<PathBuf>, no_config: bool) -> Result<config::Config> {
+    let path = match path {
+        Some(path) => Some(path),
+        None if no_config => None,
+        None => config::discover(&std::env::current_dir()?),
+    };
+    match path {
+        Some(path) => {
+            log::info!("Loading config from {}", path.display());
+            Ok(config::Config::load(path.as_path())?)
+        }
+        None => Ok(config::Config::default()),
+    }
+}
+
+fn query(file: &Path, query: &Query, count: usize, class_name_only: bool) -> Result<()> {
+    let (root, graph) = load(file, class_name_only)?;
+    let analysis = analyze_graph(root, graph, None, class_name_only)?;
+
+    println!("\nObjects matching {}:", query);
+    let (largest, rest) = analysis.objects_where(count, |obj, stats| {
+        !obj.is_root() && query.matches(obj, stats)
+    });
+    print_largest(&largest, rest);
+
+    Ok(())
+}
+
+fn top_instances(file: &Path, class: &ClassPattern, count: usize) -> Result<()> {
+    let (root, graph) = load(file, false)?;
+    let analysis = analyze_graph(root, graph, None, false)?;
+
+    println!("\nLargest instances of {}:", class);
+    let (largest, rest) = analysis.largest_instances(count, |obj| class.matches(&obj.class));
+    print_instances(&largest, rest);
+
+    Ok(())
+}
+
+fn print_instances(instances: &[(&Object, Stats)], rest: Stats) {
+    if instances.is_empty() {
+        println!("None");
+        return;
+    }
+
+    for (obj, retained) in instances {
+        println!(
+            "{}: {} self, {} retained ({} objects)",
+            obj,
+            ByteSize(obj.bytes as u64),
+            ByteSize(retained.bytes as u64),
+            retained.count
+        );
+    }
+
+    if rest.count > 0 {
+        println!(
+            "...: {} self ({} more instances)",
+            ByteSize(rest.bytes as u64),
+            rest.count
+        );
+    }
+}
+
+fn compare(file: &Path, (a, b): (usize, usize), count: usize, class_name_only: bool) -> Result<()> {
+    let (root, graph) = load(file, class_name_only)?;
+    let analysis = analyze_graph(root, graph, None, class_name_only)?;
+    let subtree = |address: usize| {
+        let (obj, total) = analysis.retained_stats(address).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{:#x} not reachable from root", address),
+            )
+        })?;
+        let subtree = analysis.rooted_at(address).unwrap();
+        let (kinds, _) = subtree.live_stats_by_kind(usize::MAX);
+        let kinds: Vec<(Symbol, Stats)> = kinds.into_iter().map(|(k, s)| (*k, s)).collect();
+        Ok::<_, std::io::Error>((obj, total, kinds))
+    };
+    let (a_obj, a_total, a_kinds) = subtree(a)?;
+    let (b_obj, b_total, b_kinds) = subtree(b)?;
+
+    println!("\nA: {}: {}", a_obj, format_stats(a_total));
+    println!("B: {}: {}", b_obj, format_stats(b_total));
+    println!("B - A: {}", format_delta(a_total, b_total));
+
+    println!("\nTypes retained by A and B:");
+    let (rows, rest) = side_by_side(&a_kinds, &b_kinds, count);
+    print_side_by_side(&rows, rest);
+
+    Ok(())
+}
+
+// A kind, with its stats in each of two subtrees
+type SideBySide = (Symbol, Stats, Stats);
+
+// Pairs up the stats of each kind in `a` and `b`, those differing the most
+// in bytes first, returning `count` of them and the totals of the rest.
+fn side_by_side(
+    a: &[(Symbol, Stats)],
+    b: &[(Symbol, Stats)],
+    count: usize,
+) -> (Vec<SideBySide>, (Stats, Stats)) {
+    let mut kinds: HashMap<Symbol, (Stats, Stats)> = HashMap::new();
+    for (kind, stats) in a {
+        kinds.entry(*kind).or_default().0 = *stats;
+    }
+    for (kind, stats) in b {
+        kinds.entry(*kind).or_default().1 = *stats;
+    }
+    let mut rows: Vec<SideBySide> = kinds.into_iter().map(|(k, (a, b))| (k, a, b)).collect();
+    rows.sort_unstable_by(|(k, a1, b1), (l, a2, b2)| {
+        b2.bytes
+            .abs_diff(a2.bytes)
+            .cmp(&b1.bytes.abs_diff(a1.bytes))
+            .then_with(|| k.cmp(l))
+    });
+
+    let count = count.min(rows.len());
+    let rest = rows[count..].iter().fold(
+        (Stats::default(), Stats::default()),
+        |(mut a, mut b), (_, x, y)| (a.add(*x), b.add(*y)),
+    );
+    rows.truncate(count);
+    (rows, rest)
+}
+
+fn format_stats(stats: Stats) -> String {
+    format!("{} ({} objects)", ByteSize(stats.bytes as u64), stats.count)
+}
+
+fn format_delta(a: Stats, b: Stats) -> String {
+    let sign = |a: usize, b: usize| if b < a { "-" } else { "+" };
+    format!(
+        "{}{} ({}{} objects)",
+        sign(a.bytes, b.bytes),
+        ByteSize(a.bytes.abs_diff(b.bytes) as u64),
+        sign(a.count, b.count),
+        a.count.abs_diff(b.count)
+    )
+}
+
+fn print_side_by_side(rows: &[SideBySide], (a, b): (Stats, Stats)) {
+    if rows.is_empty() {
+        println!("None");
+        return;
+    }
+
+    for (kind, a, b) in rows {
+        println!(
+            "{}: {} vs {}, {}",
+            kind,
+            format_stats(*a),
+            format_stats(*b),
+            format_delta(*a, *b)
+        );
+    }
+
+    if a.count + b.count > 0 {
+        println!(
+            "...: {} vs {}, {}",
+            format_stats(a),
+            format_stats(b),
+            format_delta(a, b)
+        );
+    }
+}
+
+fn extract(file: &Path, address: usize, output: &Path) -> Result<()> {
+    // Creating the output would truncate the dump before it's read again
+    if output.canonicalize().ok() == Some(file.canonicalize()?) {
+        return Err(Box::new(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Output would overwrite the dump being extracted from",
+        )));
+    }
+    let (root, graph) = load(file, true)?;
+    let analysis = analyze_graph(root, graph, None, true)?;
+    let subtree = analysis.rooted_at(address).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "Given address not reachable from root",
+        )
+    })?;
+    let addresses: rustc_hash::FxHashSet<usize> =
+        subtree.objects().map(|(obj, _)| obj.address).collect();
+
+    let mut reader = BufReader::new(File::open(file)?);
+    let mut writer = std::io::BufWriter::new(File::create(output)?);
+    let count = extract::extract(&mut reader, &mut writer, address, &addresses)?;
+    println!("Wrote {} objects to {}", count, output.display());
+
+    Ok(())
+}
+
+fn print_accounting(accounting: &smaps::Accounting) {
+    let percent = |bytes: usize| 100.0 * bytes as f64 / accounting.rss.max(1) as f64;
+    println!("RSS: {}", ByteSize(accounting.rss as u64));
+    println!(
+        "Ruby heap: {} ({:.1}% of RSS)",
+        ByteSize(accounting.ruby_heap as u64),
+        percent(accounting.ruby_heap)
+    );
+    println!(
+        "Unaccounted native memory: {} ({:.1}% of RSS)",
+        ByteSize(accounting.native as u64),
+        percent(accounting.native)
+    );
+    println!(
+        "Mapped files: {} ({:.1}% of RSS)",
+        ByteSize(accounting.file as u64),
+        percent(accounting.file)
+    );
+}
+
+fn print_malloc_report(report: &malloc::Report, output: &Output) {
+    let stats = &report.stats;
+    let percent = |bytes: usize| 100.0 * bytes as f64 / stats.in_use.max(1) as f64;
+    output.header(&format!(
+        "Allocator memory ({}, {} arenas)",
+        stats.allocator, stats.arenas
+    ));
+    println!("In use: {}", ByteSize(stats.in_use as u64));
+    println!("Held from the OS: {}", ByteSize(stats.system as u64));
+    println!(
+        "Ruby heap: {} ({:.1}% of in use)",
+        ByteSize(report.ruby_heap as u64),
+        percent(report.ruby_heap)
+    );
+    println!(
+        "Not accounted for by Ruby objects: {} ({:.1}% of in use)",
+        ByteSize(report.unattributed as u64),
+        percent(report.unattributed)
+    );
+
+    output.header("DATA classes not reporting their native memory, by estimated share of it");
+    let largest: Vec<_> = report.data.iter().take(output.count).collect();
+    if largest.is_empty() {
+        println!("None");
+    }
+    for (class, share) in largest {
+        println!(
+            "{}: {} ({} of {} objects)",
+            class.name,
+            ByteSize(*share as u64),
+            class.opaque,
+            class.count
+        );
+    }
+}
+
+fn print_findings(findings: &[detect::Finding]) {
+    if findings.is_empty() {
+        println!("None");
+        return;
+    }
+
+    for finding in findings {
+        println!("{}", finding);
+    }
+}
+
+// Each group's live and retained memory, the one retaining the most first.
+fn print_groups(analysis: &analyze::Analysis, groups: &[Group]) {
+    if groups.is_empty() {
+        println!("None");
+        return;
+    }
+
+    let mut rows: Vec<(&str, Stats, Stats)> = groups
+        .iter()
+        .map(|group| {
+            (
+                group.name.as_str(),
+                analysis.live_where(|obj| group.matches(obj)),
+                analysis.retained_where(|obj| group.matches(obj)),
+            )
+        })
+        .collect();
+    rows.sort_by(|(a, _, x), (b, _, y)| y.bytes.cmp(&x.bytes).then_with(|| a.cmp(b)));
+    for (name, live, retained) in rows {
+        println!(
+            "{}: {} live ({} objects), {} retained ({} objects)",
+            name,
+            ByteSize(live.bytes as u64),
+            live.count,
+            ByteSize(retained.bytes as u64),
+            retained.count
+        );
+    }
+}
+
+fn print_violations(violations: &[threshold::Violation], total: usize) {
+    if violations.is_empty() {
+        println!("All {} thresholds passed", total);
+        return;
+    }
+
+    for violation in violations {
+        println!("{}", violation);
+    }
+    println!("{} of {} thresholds exceeded", violations.len(), total);
+}
+
+// Where to POST a JSON summary of the results, if anywhere.
+struct Webhook {
+    url: String,
+    only_violations: bool,
+}
+
+impl Webhook {
+    fn new(url: Option<String>, only_violations: bool, config: &config::Config) -> Option<Webhook> {
+        url.or_else(|| config.webhook.clone()).map(|url| Webhook {
+            url,
+            only_violations: only_violations || config.webhook_only_violations,
+        })
+    }
+
+    fn notify(
+        &self,
+        analysis: &analyze::Analysis,
+        thresholds: usize,
+        violations: &[threshold::Violation],
+    ) -> Result<bool> {
+        if self.only_violations && violations.is_empty() {
+            return Ok(false);
+        }
+        let summary = summary::summarize(analysis, 10, thresholds, violations);
+        post_json(&self.url, &serde_json::to_value(&summary)?)?;
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "webhook")]
+fn post_json(url: &str, body: &serde_json::Value) -> Result<()> {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .post(url)
+        .send_json(body)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "webhook"))]
+fn post_json(_: &str, _: &serde_json::Value) -> Result<()> {
+    Err("reap was built without the `webhook` feature; reinstall with `cargo install reap --features webhook`".into())
+}
+
+fn assert(file: &Path, thresholds: &[Threshold], webhook: Option<Webhook>) -> Result<()> {
+    let (root, graph) = load(file, true)?;
+    let analysis = analyze_graph(root, graph, None, true)?;
+
+    let violations = threshold::check(&analysis, thresholds);
+    println!("\nThreshold violations:");
+    print_violations(&violations, thresholds.len());
+    if let Some(webhook) = webhook {
+        if webhook.notify(&analysis, thresholds.len(), &violations)? {
+            println!("\nPosted summary to {}", webhook.url);
+        }
+    }
+    if !violations.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// Options of the subcommands which, like the main command's, can default to
+// what reap.toml says.
+#[derive(StructOpt, Debug)]
+struct Defaults {
+    /// Remove address from object labels
+    #[structopt(long = "class-name-only")]
+    class_name_only: bool,
+
+    /// Keep addresses in object labels, even if the config file removes them
+    #[structopt(long, conflicts_with = "class-name-only")]
+    no_class_name_only: bool,
+
+    /// TOML config file with defaults for these options [default: nearest reap.toml]
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Don't look for a reap.toml in the current directory or its parents
+    #[structopt(long, conflicts_with = "config")]
+    no_config: bool,
+}
+
+impl Defaults {
+    // The config, and whether to remove addresses from labels
+    fn load(self) -> Result<(config::Config, bool)> {
+        let config = load_config(self.config, self.no_config)?;
+        let class_name_only =
+            class_name_only(self.class_name_only, self.no_class_name_only, &config);
+        Ok((config, class_name_only))
+    }
+}
+
+// Either flag decides over the config file.
+fn class_name_only(given: bool, not: bool, config: &config::Config) -> bool {
+    given || (config.class_name_only && !not)
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// List the immediate children of an object in the dominator tree
+    Children {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+
+        /// Address of the object whose children to list
+        #[structopt(name = "ADDRESS")]
+        address: String,
+
+        /// Print this many of the children retaining the most memory [default: 10]
+        #[structopt(short, long)]
+        count: Option<usize>,
+
+        #[structopt(flatten)]
+        defaults: Defaults,
+    },
+
+    /// Report live objects whose addresses do not appear in an earlier dump of the same process
+    OnlyIn {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+
+        /// Path to the earlier JSON heap dump to compare against
+        #[structopt(short, long, parse(from_os_str))]
+        baseline: PathBuf,
+
+        /// Print this many of the types & objects retaining the most memory [default: 10]
+        #[structopt(short, long)]
+        count: Option<usize>,
+
+        #[structopt(flatten)]
+        defaults: Defaults,
+    },
+
+    /// Check a heap dump against thresholds, exiting with an error if any are exceeded
+    Assert {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+
+        /// Maximum memory retained by instances of a class, e.g. String=50MB
+        #[structopt(long, number_of_values = 1, parse(try_from_str = parse_max_retained))]
+        max_retained: Vec<Threshold>,
+
+        /// Maximum memory used by instances of a class, e.g. String=50MB
+        #[structopt(long, number_of_values = 1, parse(try_from_str = parse_max_live))]
+        max_live: Vec<Threshold>,
+
+        /// Maximum number of instances of a class, e.g. String=100_000
+        #[structopt(long, number_of_values = 1, parse(try_from_str = threshold::parse_class_count))]
+        max_instances: Vec<Threshold>,
+
+        /// Maximum memory used by all live objects
+        #[structopt(long, parse(try_from_str = threshold::parse_bytes))]
+        max_live_bytes: Option<usize>,
+
+        /// Maximum number of live objects
+        #[structopt(long, parse(try_from_str = threshold::parse_count))]
+        max_live_objects: Option<usize>,
+
+        /// Also check the budgets in this TOML config file [default: nearest reap.toml]
+        #[structopt(long, parse(from_os_str))]
+        config: Option<PathBuf>,
+
+        /// Don't look for a reap.toml in the current directory or its parents
+        #[structopt(long, conflicts_with = "config")]
+        no_config: bool,
+
+        /// POST a JSON summary of the results to this URL (requires the `webhook` feature)
+        #[structopt(long)]
+        webhook: Option<String>,
+
+        /// Only POST to the webhook if a threshold is exceeded
+        #[structopt(long)]
+        webhook_only_violations: bool,
+    },
+
+    /// Print the objects matching a filter expression, e.g. 'class =~ /^ActiveRecord/ && retained > 1MB'
+    Query {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+
+        /// Fields: class, address, bytes, retained, count; operators: == != < <= > >= =~ !~ && || !
+        #[structopt(name = "EXPRESSION")]
+        expression: Query,
+
+        /// Print this many of the matching objects retaining the most memory [default: 10]
+        #[structopt(short, long)]
+        count: Option<usize>,
+
+        #[structopt(flatten)]
+        defaults: Defaults,
+    },
+
+    /// Print structural metrics of a heap dump: sizes, degrees, cycles and dominator tree depths
+    Stats {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+    },
+
+    /// Print the largest instances of a class, with their labels, addresses and retained sizes
+    TopInstances {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+
+        /// Class (name or regex) whose instances to list
+        #[structopt(long)]
+        class: ClassPattern,
+
+        /// Print this many of the largest instances
+        #[structopt(short = "n", long, default_value = "10")]
+        count: usize,
+    },
+
+    /// Compare what two objects in a heap dump retain, by kind, side by side with the differences
+    Compare {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+
+        /// Address of the first object
+        #[structopt(name = "A")]
+        a: String,
+
+        /// Address of the second object
+        #[structopt(name = "B")]
+        b: String,
+
+        /// Print this many of the types differing the most [default: 10]
+        #[structopt(short, long)]
+        count: Option<usize>,
+
+        #[structopt(flatten)]
+        defaults: Defaults,
+    },
+
+    /// Write the subtree an object dominates as a heap dump of its own, e.g. to share a small reproducer
+    Extract {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+
+        /// Address of the object whose subtree to extract
+        #[structopt(short, long)]
+        root: String,
+
+        /// Path to write the extracted dump to
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    /// Load a heap dump once, then answer commands about it from stdin
+    Repl {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+
+        /// Print this many of the types & objects retaining the most memory [default: 10]
+        #[structopt(short, long)]
+        count: Option<usize>,
+
+        #[structopt(flatten)]
+        defaults: Defaults,
+    },
+
+    /// Serve a JSON API for querying the heap over HTTP (requires the `serve` feature)
+    Serve {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+
+        /// Port to listen on
+        #[structopt(short, long, default_value = "8080")]
+        port: u16,
+
+        /// Address to listen on
+        #[structopt(long, default_value = "127.0.0.1")]
+        host: String,
+
+        #[structopt(flatten)]
+        defaults: Defaults,
+    },
+
+    /// Explore the heap interactively (requires the `tui` feature)
+    Tui {
+        /// Path to JSON heap dump file to process
+        #[structopt(name = "INPUT", parse(from_os_str))]
+        input: PathBuf,
+
+        #[structopt(flatten)]
+        defaults: Defaults,
+    },
+
+    /// Print a completion script for this shell
+    Completions {
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
+}
+
+fn parse_max_retained(s: &str) -> std::result::Result<Threshold, String> {
+    threshold::parse_class_bytes(Measure::Retained, s)
+}
+
+fn parse_max_live(s: &str) -> std::result::Result<Threshold, String> {
+    threshold::parse_class_bytes(Measure::Live, s)
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "reap",
+    settings = &[AppSettings::ArgsNegateSubcommands],
+)]
+struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
+    /// Path to JSON heap dump file to process
+    #[structopt(name = "INPUT", parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    /// Filter to subtree rooted at object with this address; may be repeated
+    #[structopt(short, long, number_of_values = 1)]
+    root: Vec<String>,
+
+    /// Leave out the object with this address and everything it dominates, as though they were freed; may be repeated
+    #[structopt(long, number_of_values = 1)]
+    exclude_root: Vec<String>,
+
+    /// If a --root address isn't in the dump, use the nearest object on its heap page instead
+    #[structopt(long)]
+    root_nearest: bool,
+
+    /// Filter to subtree rooted at the instance of this class (name or regex) retaining the most memory
+    #[structopt(long)]
+    root_class: Option<ClassPattern>,
+
+    /// Root at this many of the largest instances of --root-class
+    #[structopt(long, default_value = "1")]
+    root_class_count: usize,
+
+    /// Flamegraph SVG output for dominator tree
+    #[structopt(short, long, parse(from_os_str))]
+    flamegraph: Option<PathBuf>,
+
+    /// Color palette of the flamegraph [default: hot]
+    #[structopt(long, possible_values = flamegraph::color::Palette::VARIANTS)]
+    palette: Option<flamegraph::color::Palette>,
+
+    /// Color flamegraph frames by class, the same way in every flamegraph, for comparing dumps
+    #[structopt(long)]
+    color_by_class: bool,
+
+    /// Grow the flamegraph up from the root, or down from it as an icicle chart [default: inverted]
+    #[structopt(long, possible_values = &["normal", "inverted"])]
+    flame_direction: Option<FlameDirection>,
+
+    /// Reverse flamegraph stacks, so frames of the objects at their ends are at the root
+    #[structopt(long)]
+    flame_reverse: bool,
+
+    /// Order flamegraph frames by how much they retain, largest first, rather than by name
+    #[structopt(long, conflicts_with = "flame-reverse")]
+    flame_left_heavy: bool,
+
+    /// Title of the flamegraph [default: Flame Graph]
+    #[structopt(long)]
+    flame_title: Option<String>,
+
+    /// Subtitle of the flamegraph [default: the dump's file name, date and totals]
+    #[structopt(long)]
+    flame_subtitle: Option<String>,
+
+    /// Notes to embed in the flamegraph, e.g. the commit or host a dump came from
+    #[structopt(long)]
+    flame_notes: Option<String>,
+
+    /// Leave out the flamegraph's scripts for searching and zooming (Ctrl-F and clicking frames)
+    #[structopt(long)]
+    flame_no_javascript: bool,
+
+    /// Width of the flamegraph in pixels [default: fit the browser window]
+    #[structopt(long)]
+    flame_width: Option<usize>,
+
+    /// Font size of the flamegraph [default: 12]
+    #[structopt(long)]
+    flame_font_size: Option<usize>,
+
+    /// What the flamegraph calls the sizes it shows [default: bytes]
+    #[structopt(long)]
+    flame_count_name: Option<String>,
+
+    /// Folded stack output for dominator tree
+    #[structopt(long, parse(from_os_str))]
+    folded: Option<PathBuf>,
+
+    /// speedscope JSON output for dominator tree
+    #[structopt(long, parse(from_os_str))]
+    speedscope: Option<PathBuf>,
+
+    /// Gzipped pprof profile output for dominator tree
+    #[structopt(long, parse(from_os_str))]
+    pprof: Option<PathBuf>,
+
+    /// d3-flame-graph JSON output for dominator tree
+    #[structopt(long, parse(from_os_str))]
+    d3_json: Option<PathBuf>,
+
+    /// Zoomable treemap HTML output for dominator tree
+    #[structopt(long, parse(from_os_str))]
+    treemap: Option<PathBuf>,
+
+    /// Interactive sunburst chart HTML output for dominator tree
+    #[structopt(long, parse(from_os_str))]
+    sunburst: Option<PathBuf>,
+
+    /// Dot file output for dominator tree
+    #[structopt(short, long, parse(from_os_str))]
+    dot: Option<PathBuf>,
+
+    /// Label dot edges with the share of the parent's retained memory the child retains
+    #[structopt(long)]
+    dot_edge_percentages: bool,
+
+    /// Collapse chains of objects each dominating only the next into one dot node
+    #[structopt(long)]
+    dot_collapse_chains: bool,
+
+    /// Include nodes retaining at least this much memory (e.g. 10MB) in dot output
+    #[structopt(long)]
+    dot_min_bytes: Option<ByteSize>,
+
+    /// Include only nodes this many levels or fewer below the root in dot output, with a "(deeper)" node under each at the last level for everything below it
+    #[structopt(long)]
+    dot_max_depth: Option<usize>,
+
+    /// Group dot nodes into clusters by class, or by the top-level module of their class
+    #[structopt(long, possible_values = &["class", "namespace"])]
+    dot_cluster_by: Option<dot::ClusterBy>,
+
+    /// Format of INPUT: a Ruby heap dump, or reap's generic JSONL edge list
+    #[structopt(long, default_value = "ruby", possible_values = &["ruby", "edges"])]
+    format: InputFormat,
+
+    /// Have this running Ruby process (with rbtrace required) dump its heap, and analyze that
+    #[structopt(long, conflicts_with_all = &["INPUT", "load-snapshot"])]
+    pid: Option<u32>,
+
+    /// Read object labels from INPUT only when needed, to save memory on huge dumps
+    #[structopt(long, conflicts_with = "save-snapshot")]
+    lazy_labels: bool,
+
+    /// Group and label objects by their class, internal type (e.g. OBJECT), or both
+    #[structopt(long, default_value = "class", possible_values = &["class", "type", "both"])]
+    kind: KindView,
+
+    /// Group objects by their whole class name, or by its leading module path, e.g. namespace:2 for Sidekiq::Job, in per-kind tables and flamegraph frames
+    #[structopt(long, default_value = "class")]
+    group_by: GroupBy,
+
+    /// Skip malformed lines in INPUT, such as truncated ones, with a warning of how many, rather than stopping
+    #[structopt(long)]
+    lenient: bool,
+
+    /// Which of several objects in INPUT with the same address to keep, or whether to stop [default: last for Ruby dumps, error for edge lists]
+    #[structopt(long, possible_values = &["first", "last", "error"])]
+    on_duplicate: Option<parse::OnDuplicate>,
+
+    /// Keep the graph in temporary files in this directory rather than in memory, to analyze dumps larger than RAM
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with_all = &[
+            "root", "root-class", "flamegraph", "folded", "speedscope", "pprof", "d3-json",
+            "treemap", "sunburst", "dot", "pid", "lazy-labels", "smaps", "malloc-stats",
+            "save-snapshot", "load-snapshot", "neo4j-dir", "sqlite", "parquet",
+            "prometheus-textfile", "neighborhood", "retained-set", "shared", "class", "webhook",
+            "exclude-root", "unreachable-dominators",
+        ],
+    )]
+    spill_dir: Option<PathBuf>,
+
+    /// Compare the Ruby heap with the RSS of the process in this saved /proc/<pid>/smaps
+    #[structopt(long, parse(from_os_str), conflicts_with = "pid")]
+    smaps: Option<PathBuf>,
+
+    /// Compare the Ruby heap with this capture of glibc's malloc_stats() or jemalloc's malloc_stats_print()
+    #[structopt(long, parse(from_os_str), conflicts_with = "load-snapshot")]
+    malloc_stats: Option<PathBuf>,
+
+    /// Save the parsed graph to this file, to analyze it again quickly with --load-snapshot
+    #[structopt(long, parse(from_os_str))]
+    save_snapshot: Option<PathBuf>,
+
+    /// Analyze a graph saved with --save-snapshot instead of parsing INPUT
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["INPUT", "save-snapshot", "lazy-labels"])]
+    load_snapshot: Option<PathBuf>,
+
+    /// Write objects & references as neo4j-admin import CSVs to this directory
+    #[structopt(long, parse(from_os_str))]
+    neo4j_dir: Option<PathBuf>,
+
+    /// Write every instance of a class to a CSV file, e.g. String=strings.csv, with its size, retained size, allocation site and immediate dominator; may be repeated
+    #[structopt(long, number_of_values = 1)]
+    instances_csv: Vec<instances::Export>,
+
+    /// Write objects, references & dominators to this SQLite database
+    #[structopt(long, parse(from_os_str))]
+    sqlite: Option<PathBuf>,
+
+    /// Write objects & edges as Parquet datasets to this directory
+    #[structopt(long, parse(from_os_str))]
+    parquet: Option<PathBuf>,
+
+    /// Write per-class live & retained bytes as Prometheus gauges to this file
+    #[structopt(long, parse(from_os_str))]
+    prometheus_textfile: Option<PathBuf>,
+
+    /// Include nodes retaining at least this fraction of memory in dot output [default: 0.005, or 0 with --dot-min-bytes or --dot-max-depth]
+    #[structopt(short, long)]
+    threshold: Option<f64>,
+
+    /// Print this many of the types & objects retaining the most memory [default: 10]
+    #[structopt(short, long)]
+    count: Option<usize>,
+
+    /// Omit types & objects using less than this much memory (e.g. 10KB) from printed reports
+    #[structopt(long)]
+    min_bytes: Option<ByteSize>,
+
+    /// Order reports by bytes, count, or retained bytes [default: bytes]
+    #[structopt(long, possible_values = &["bytes", "count", "retained"])]
+    sort: Option<analyze::SortKey>,
+
+    /// Frames each object contributes to flamegraph stacks: itself, its class, its class then internal type, or the gem or file allocating it (if the dump traced allocations) then its class [default: object]
+    #[structopt(long, possible_values = &["object", "class", "class-type", "gem-class", "file-class"])]
+    frame_format: Option<analyze::FrameFormat>,
+
+    /// Fold subtrees retaining less than this much memory (e.g. 64KB) into a "[small]" frame of flamegraphs and folded stacks
+    #[structopt(long)]
+    flame_min_bytes: Option<ByteSize>,
+
+    /// Fold subtrees retaining less than this percentage of the heap into a "[small]" frame, like --flame-min-bytes
+    #[structopt(long)]
+    flame_min_percent: Option<f64>,
+
+    /// Draw at most this many levels of the dominator tree below the root in flamegraphs, counting the bytes of deeper objects as their ancestors'
+    #[structopt(long)]
+    flame_max_depth: Option<usize>,
+
+    /// Merge siblings of the same class into one flamegraph frame, labeled with how many objects it merges (e.g. "Hash ×10,000")
+    #[structopt(long)]
+    flame_merge_siblings: bool,
+
+    /// Remove address from flamegraph labels
+    #[structopt(long = "class-name-only")]
+    class_name_only: bool,
+
+    /// Keep addresses in flamegraph labels, even if the config file removes them
+    #[structopt(long, conflicts_with = "class-name-only")]
+    no_class_name_only: bool,
+
+    /// Write the reference graph around the object with this address
+    #[structopt(long, requires = "neighborhood-dot")]
+    neighborhood: Option<String>,
+
+    /// Include objects up to this many references away in neighborhood output
+    #[structopt(long, default_value = "1")]
+    depth: usize,
+
+    /// Dot file output for neighborhood
+    #[structopt(long, parse(from_os_str), requires = "neighborhood")]
+    neighborhood_dot: Option<PathBuf>,
+
+    /// Print the memory retained by the set of addresses (one per line) in this file
+    #[structopt(long, parse(from_os_str))]
+    retained_set: Option<PathBuf>,
+
+    /// Print the largest regions kept alive by several referrers rather than a single dominator
+    #[structopt(long)]
+    shared: bool,
+
+    /// Print the classes retaining the most memory as a whole, between all their instances
+    #[structopt(long)]
+    class_dominators: bool,
+
+    /// Print the largest reference cycles (strongly connected components), with a path around each
+    #[structopt(long)]
+    cycles: bool,
+
+    /// Print the objects referenced by the most others, grouped by class
+    #[structopt(long)]
+    fan_in: bool,
+
+    /// Print the string values with the most bytes wasted in duplicate copies, with the classes holding them
+    #[structopt(long, conflicts_with = "load-snapshot")]
+    duplicate_strings: bool,
+
+    /// Find dominators among the objects unreachable from the root, and print those retaining the most garbage
+    #[structopt(long)]
+    unreachable_dominators: bool,
+
+    /// Print the objects holding the most instances of this class (name or regex), by the instances' own sizes
+    #[structopt(long)]
+    holders_of: Option<ClassPattern>,
+
+    /// Look for memory held by Rails caches (Rails.cache local store, prepared statements, query cache) and by objects keeping rack envs
+    #[structopt(long)]
+    rails: bool,
+
+    /// Report only Rails caches and envs retaining at least this much [default: 1MB]
+    #[structopt(long, requires = "rails")]
+    rails_min_bytes: Option<ByteSize>,
+
+    /// Run this program as an analyzer, reading objects as JSON lines and writing findings (see reap::detect::External)
+    #[structopt(long, parse(from_os_str))]
+    analyzer: Vec<PathBuf>,
+
+    /// Restrict reports to instances of this class (name or regex) and what they retain
+    #[structopt(long)]
+    class: Option<ClassPattern>,
+
+    /// Leave these kinds (e.g. IMEMO,ICLASS) out of per-kind reports and flamegraph frames
+    #[structopt(long, use_delimiter = true)]
+    exclude_kind: Vec<String>,
+
+    /// Treat instances of these classes (names or regexes) as not retaining what they reference
+    #[structopt(long, use_delimiter = true)]
+    cut_edges_from: Vec<ClassPattern>,
+
+    /// Print only these report sections
+    #[structopt(
+        long,
+        use_delimiter = true,
+        possible_values = &["origin", "live", "objects", "retained", "unreachable", "groups", "budgets", "memory"],
+    )]
+    only: Vec<Section>,
+
+    /// TOML config file with defaults for these options and per-class budgets [default: nearest reap.toml]
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Don't look for a reap.toml in the current directory or its parents
+    #[structopt(long, conflicts_with = "config")]
+    no_config: bool,
+
+    /// POST a JSON summary, including budget violations, to this URL (requires the `webhook` feature)
+    #[structopt(long)]
+    webhook: Option<String>,
+
+    /// Only POST to the webhook if a budget is exceeded
+    #[structopt(long)]
+    webhook_only_violations: bool,
+
+    /// Print report rows without the version banner, section headers, or output file notes
+    #[structopt(short, long)]
+    quiet: bool,
+
+    /// Print how long each phase took to stderr, as text or json
+    #[structopt(long, possible_values = &["text", "json"])]
+    timings: Option<TimingsFormat>,
+
+    /// Don't draw progress bars while loading & analyzing the dump
+    #[structopt(long, global = true)]
+    no_progress: bool,
+
+    /// Log progress & timings to stderr; repeat for more detail
+    #[structopt(short, long, global = true, parse(from_occurrences))]
+    verbose: u8,
+}
+
+fn main() -> Result<()> {
+    const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+    let opt = Opt::from_args();
+
+    let level = match opt.verbose {
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+    SHOW_PROGRESS.store(!opt.no_progress, Ordering::Relaxed);
+
+    if let Some(Command::Completions { shell }) = opt.command {
+        Opt::clap().gen_completions_to("reap", shell, &mut std::io::stdout());
+        return Ok(());
+    }
+
+    if !opt.quiet {
+        println!("reap v{}", VERSION);
+    }
+
+    match opt.command {
+        Some(Command::Children {
+            input,
+            address,
+            count,
+            defaults,
+        }) => {
+            let address = parse::parse_address(address.as_str()).expect("Invalid address");
+            let (config, class_name_only) = defaults.load()?;
+            let count = count.or(config.count).unwrap_or(10);
+            return children(input.as_path(), address, count, class_name_only);
+        }
+        Some(Command::OnlyIn {
+            input,
+            baseline,
+            count,
+            defaults,
+        }) => {
+            let (config, class_name_only) = defaults.load()?;
+            let count = count.or(config.count).unwrap_or(10);
+            return only_in(input.as_path(), baseline.as_path(), count, class_name_only);
+        }
+        Some(Command::Assert {
+            input,
+            max_retained,
+            max_live,
+            max_instances,
+            max_live_bytes,
+            max_live_objects,
+            config,
+            no_config,
+            webhook,
+            webhook_only_violations,
+        }) => {
+            let config = load_config(config, no_config)?;
+            let webhook = Webhook::new(webhook, webhook_only_violations, &config);
+            let mut thresholds = config.budgets()?;
+            thresholds.extend(max_retained);
+            thresholds.extend(max_live);
+            thresholds.extend(max_instances);
+            thresholds.extend(max_live_bytes.map(|max| Threshold {
+                class: None,
+                measure: Measure::Live,
+                limit: Limit::Bytes(max),
+            }));
+            thresholds.extend(max_live_objects.map(|max| Threshold {
+                class: None,
+                measure: Measure::Live,
+                limit: Limit::Count(max),
+            }));
+            return assert(input.as_path(), &thresholds, webhook);
+        }
+        Some(Command::Query {
+            input,
+            expression,
+            count,
+            defaults,
+        }) => {
+            let (config, class_name_only) = defaults.load()?;
+            let count = count.or(config.count).unwrap_or(10);
+            return query(input.as_path(), &expression, count, class_name_only);
+        }
+        Some(Command::Stats { input }) => {
+            return stats(input.as_path());
+        }
+        Some(Command::TopInstances {
+            input,
+            class,
+            count,
+        }) => {
+            return top_instances(input.as_path(), &class, count);
+        }
+        Some(Command::Compare {
+            input,
+            a,
+            b,
+            count,
+            defaults,
+        }) => {
+            let a = parse::parse_address(a.as_str()).expect("Invalid address");
+            let b = parse::parse_address(b.as_str()).expect("Invalid address");
+            let (config, class_name_only) = defaults.load()?;
+            let count = count.or(config.count).unwrap_or(10);
+            return compare(input.as_path(), (a, b), count, class_name_only);
+        }
+        Some(Command::Extract {
+            input,
+            root,
+            output,
+        }) => {
+            let address = parse::parse_address(root.as_str()).expect("Invalid address");
+            return extract(input.as_path(), address, output.as_path());
+        }
+        Some(Command::Repl {
+            input,
+            count,
+            defaults,
+        }) => {
+            let (config, class_name_only) = defaults.load()?;
+            let count = count.or(config.count).unwrap_or(10);
+            let (root, graph) = load(input.as_path(), class_name_only)?;
+            let analysis = analyze_graph(root, graph, None, class_name_only)?;
+            return repl::run(analysis, class_name_only, count);
+        }
+        Some(Command::Serve {
+            input,
+            port,
+            host,
+            defaults,
+        }) => {
+            let (_, class_name_only) = defaults.load()?;
+            return serve(input.as_path(), &host, port, class_name_only);
+        }
+        Some(Command::Tui { input, defaults }) => {
+            let (_, class_name_only) = defaults.load()?;
+            return tui(input.as_path(), class_name_only);
+        }
+        Some(Command::Completions { .. }) | None => {}
+    }
+
+    let captured = match opt.pid {
+        Some(pid) => {
+            let progress = progress_bar(None)
+                .with_message(format!("Waiting for process {} to dump its heap", pid));
+            let result = source::LiveProcess::new(pid).capture();
+            progress.finish_and_clear();
+            Some(result.map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+    // Read right after the dump, to compare with it
+    let smaps = match (opt.pid, opt.smaps) {
+        (Some(pid), _) => smaps::read(pid)
+            .map_err(|e| log::warn!("Not comparing RSS with the heap: {}", e))
+            .ok(),
+        (None, Some(path)) => Some(smaps::parse(&mut BufReader::new(File::open(path)?))?),
+        (None, None) => None,
+    };
+    let input = opt
+        .input
+        .or(captured)
+        .or(opt.load_snapshot.clone())
+        .unwrap_or_else(|| {
+            structopt::clap::Error::with_description(
+                "The following required arguments were not provided:\n    <INPUT>",
+                structopt::clap::ErrorKind::MissingRequiredArgument,
+            )
+            .exit()
+        });
+
+    let mut subtree_roots: Vec<usize> = opt
+        .root
+        .iter()
+        .map(|r| {
+            parse::parse_address(r.as_str()).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid subtree root address {:?}: {}", r, e),
+                )
+            })
+        })
+        .collect::<std::result::Result<_, _>>()?;
+
+    // Options given on the command line take precedence over the config file
+    let config = load_config(opt.config, opt.no_config)?;
+    let budgets = config.budgets()?;
+    let groups = config.groups()?;
+    let mut analyzers: Vec<Box<dyn detect::Analyzer>> = vec![];
+    if opt.rails {
+        analyzers.push(Box::new(detect::Rails {
+            min_bytes: opt.rails_min_bytes.unwrap_or(ByteSize::mb(1)).as_u64() as usize,
+        }));
+    }
+    let programs = if opt.analyzer.is_empty() {
+        config.analyzers.clone()
+    } else {
+        opt.analyzer
+    };
+    for program in programs {
+        analyzers.push(Box::new(detect::External { program }));
+    }
+    for rule in config.rules()? {
+        analyzers.push(Box::new(rule));
+    }
+    let class_name_only = class_name_only(opt.class_name_only, opt.no_class_name_only, &config);
+    // A size or depth limit stands in for the default threshold
+    let dot_min_bytes = opt.dot_min_bytes.or(config.dot_min_bytes);
+    let threshold = match opt.threshold.or(config.threshold) {
+        Some(threshold) => threshold,
+        None if dot_min_bytes.is_some() || opt.dot_max_depth.is_some() => 0.0,
+        None => 0.005,
+    };
+    let min_bytes = opt.min_bytes.or(config.min_bytes).unwrap_or_default();
+    let sort = opt.sort.or(config.sort).unwrap_or_default();
+    let frame_format = opt.frame_format.or(config.frame_format).unwrap_or_default();
+    let flame_min_bytes = opt.flame_min_bytes.or(config.flame_min_bytes);
+    let flame_min_percent = opt.flame_min_percent.or(config.flame_min_percent);
+    let exclude_kind = if opt.exclude_kind.is_empty() {
+        config.exclude_kind.clone()
+    } else {
+        opt.exclude_kind
+    };
+    let cut_edges_from = if opt.cut_edges_from.is_empty() {
+        config.cut_edges_from()?
+    } else {
+        opt.cut_edges_from
+    };
+
+    let output = Output {
+        count: opt.count.or(config.count).unwrap_or(10),
+        only: opt.only,
+        quiet: opt.quiet,
+    };
+
+    let parse_options = parse::ParseOptions {
+        class_name_only,
+        cut_edges_from,
+        lazy_labels: opt.lazy_labels,
+        string_hashes: opt.duplicate_strings,
+        lenient: opt.lenient,
+        on_duplicate: opt.on_duplicate,
+        ..Default::default()
+    };
+
+    if let Some(dir) = opt.spill_dir {
+        spill_report(
+            input.as_path(),
+            dir.as_path(),
+            &parse_options,
+            opt.kind,
+            opt.group_by,
+            &output,
+        )?;
+        if let Some(format) = opt.timings {
+            print_timings(format)?;
+        }
+        return Ok(());
+    }
+
+    let (mut root, mut graph, label_index) = match opt.load_snapshot {
+        Some(_) => {
+            let (root, graph) = load_snapshot(input.as_path(), &parse_options)?;
+            (root, graph, parse::LabelIndex::default())
+        }
+        None if opt.format == InputFormat::Edges => {
+            let (root, graph) = load_edges(input.as_path(), &parse_options)?;
+            (root, graph, parse::LabelIndex::default())
+        }
+        None => load_with_labels(input.as_path(), &parse_options)?,
+    };
+
+    find_roots(&graph, &mut subtree_roots, opt.root_nearest)?;
+
+    if let Some(path) = opt.save_snapshot {
+        let mut writer = std::io::BufWriter::new(File::create(&path)?);
+        let metadata = snapshot::Metadata::from(&parse_options);
+        snapshot::save(&mut writer, root, &graph, &metadata)?;
+        output.note(&format!("Saved snapshot to {}", path.display()));
+    }
+    opt.kind.apply(&mut graph);
+    opt.group_by.apply(&mut graph);
+
+    if !opt.exclude_root.is_empty() {
+        let excluded = opt
+            .exclude_root
+            .iter()
+            .map(|r| {
+                let address = parse::parse_address(r.as_str()).map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Invalid excluded root address {:?}: {}", r, e),
+                    )
+                })?;
+                find_address(&graph, address)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let removed;
+        (root, graph, removed) = graph::exclude_subtrees(&graph, root, &excluded);
+        output.note(&format!(
+            "Excluded {} ({} objects) retained by {}",
+            ByteSize(removed.bytes as u64),
+            removed.count,
+            opt.exclude_root.join(", ")
+        ));
+    }
+
+    if let (Some(address), Some(path)) = (opt.neighborhood, opt.neighborhood_dot) {
+        let center = find_address(
+            &graph,
+            parse::parse_address(address.as_str()).expect("Invalid neighborhood address"),
+        )?;
+        let neighborhood = graph::neighborhood(&graph, center, opt.depth);
+        write_dot_file(&neighborhood, path.as_path())?;
+        output.note(&format!(
+            "Wrote {} nodes & {} edges to {}",
+            neighborhood.node_count(),
+            neighborhood.edge_count(),
+            path.display()
+        ));
+    }
+
+    // Everything in the dump, before narrowing down to any subtree
+    let ruby_heap: usize = graph.node_weights().map(|obj| obj.bytes).sum();
+
+    let retained_set = match opt.retained_set {
+        Some(ref file) => {
+            let set = read_addresses(file.as_path())?
+                .into_iter()
+                .map(|address| find_address(&graph, address))
+                .collect::<Result<HashSet<_>>>()?;
+            Some((set.len(), analyze::retained_by_set(root, &graph, &set)))
+        }
+        None => None,
+    };
+
+    // What dropping the subtree roots would free, which can include objects
+    // they don't dominate between them. A single root frees just what it
+    // dominates, which its retained types already show.
+    let freed = if subtree_roots.len() < 2 {
+        None
+    } else {
+        let set = subtree_roots
+            .iter()
+            .map(|address| find_address(&graph, *address))
+            .collect::<Result<HashSet<_>>>()?;
+        Some(analyze::retained_by_set_by_kind(
+            root,
+            &graph,
+            &set,
+            output.count,
+        ))
+    };
+
+    // A single subtree is cheaper to analyze directly, but otherwise we derive
+    // each subtree from the analysis of the whole heap
+    let single_root = match subtree_roots.as_slice() {
+        [address] if opt.root_class.is_none() => Some(*address),
+        _ => None,
+    };
+    let garbage = opt
+        .unreachable_dominators
+        .then(|| graph::unreachable(&graph, root));
+    let analysis = analyze_graph(root, graph, single_root, class_name_only)?;
+
+    if let Some(ref pattern) = opt.root_class {
+        let (largest, _) = analysis
+            .dominator_subtree_stats_where(opt.root_class_count, |obj| pattern.matches(&obj.class));
+        if largest.is_empty() {
+            return Err(Box::new(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No reachable instances of {}", pattern),
+            )));
+        }
+        subtree_roots.extend(largest.iter().map(|(obj, _)| obj.address));
+    }
+
+    let (analysis, separate) = if single_root.is_some() || subtree_roots.is_empty() {
+        (analysis, vec![])
+    } else {
+        let not_found = || {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Given subtree root address not found",
+            )
+        };
+        let separate = if subtree_roots.len() > 1 {
+            subtree_roots
+                .iter()
+                .map(|address| analysis.rooted_at(*address).ok_or_else(not_found))
+                .collect::<std::result::Result<Vec<_>, _>>()?
+        } else {
+            vec![]
+        };
+        let union = analysis
+            .rooted_at_all(&subtree_roots)
+            .ok_or_else(not_found)?;
+        (union, separate)
+    };
+
+    let finish = |analysis: analyze::Analysis| {
+        let mut analysis = match opt.class {
+            Some(ref pattern) => analysis.restrict_to(|obj| pattern.matches(&obj.class)),
+            None => analysis,
+        };
+        analysis.exclude_kinds(exclude_kind.iter().cloned());
+        analysis.set_min_bytes(min_bytes.as_u64() as usize);
+        analysis.set_sort(sort);
+        analysis.set_frame_format(frame_format);
+        analysis.set_flame_min_bytes(flame_min(&analysis, flame_min_bytes, flame_min_percent));
+        analysis.set_merge_siblings(opt.flame_merge_siblings);
+        analysis.set_flame_max_depth(opt.flame_max_depth);
+        analysis
+    };
+    let mut analysis = finish(analysis);
+    let mut separate: Vec<analyze::Analysis> = separate.into_iter().map(finish).collect();
+
+    if !label_index.is_empty() {
+        let whole_tree = [
+            &opt.flamegraph,
+            &opt.folded,
+            &opt.speedscope,
+            &opt.pprof,
+            &opt.d3_json,
+            &opt.treemap,
+            &opt.sunburst,
+            &opt.dot,
+            &config.flamegraph,
+            &config.folded,
+            &config.speedscope,
+            &config.pprof,
+            &config.d3_json,
+            &config.treemap,
+            &config.sunburst,
+            &config.dot,
+        ]
+        .iter()
+        .any(|path| path.is_some())
+            || opt.neo4j_dir.is_some()
+            || !opt.instances_csv.is_empty()
+            || opt.duplicate_strings
+            || opt.sqlite.is_some()
+            || opt.parquet.is_some()
+            || !groups.is_empty()
+            || !analyzers.is_empty();
+        for analysis in std::iter::once(&mut analysis).chain(separate.iter_mut()) {
+            read_labels(analysis, &label_index, &input, output.count, whole_tree)?;
+        }
+        log::debug!("Indexed labels of {} objects", label_index.len());
+    }
+
+    if let (Some(file), Some((count, stats))) = (opt.retained_set, retained_set) {
+        output.header(&format!(
+            "Memory retained by the {} objects in {}",
+            count,
+            file.display()
+        ));
+        print_largest(&[("set", stats)], Stats::default());
+    }
+
+    let output_phase = timing::start("output");
+    print_report(&analysis, &output, &subtree_roots);
+
+    for (address, analysis) in subtree_roots.iter().zip(separate.iter()) {
+        output.note(&format!("Subtree rooted at {:#x}:", address));
+        print_report(analysis, &output, &[*address]);
+    }
+
+    if let Some((largest, rest)) = freed {
+        let addresses: Vec<String> = subtree_roots.iter().map(|a| format!("{:#x}", a)).collect();
+        output.header(&format!(
+            "Object types only reachable through {}, freed if dropped",
+            addresses.join(", ")
+        ));
+        print_largest(&largest, rest);
+    }
 
-    let file = File::create(filename)?;
-    flamegraph::from_lines(&mut opts, lines.iter().map(|s| s.as_str()), file).unwrap();
-    Ok(())
-}
+    if let Some(smaps) = smaps {
+        if output.includes(Section::Memory) {
+            output.header("Process memory");
+            print_accounting(&smaps::account(smaps, ruby_heap));
+        }
+    }
 
-fn write_folded(lines: &[String], filename: &Path) -> Result<()> {
-    let file = File::create(filename)?;
-    let mut writer = std::io::BufWriter::new(file);
-    for line in lines {
-        writeln!(writer, "{}", line)?;
+    if let Some(path) = opt.malloc_stats {
+        if output.includes(Section::Memory) {
+            let stats = malloc::parse(&mut BufReader::new(File::open(path)?))?;
+            let classes = malloc::data_classes(&mut BufReader::new(File::open(&input)?))?;
+            print_malloc_report(&malloc::correlate(stats, ruby_heap, classes), &output);
+        }
     }
-    Ok(())
-}
 
-fn print_largest<K: Display>(largest: &[(K, Stats)], rest: Stats) {
-    if largest.is_empty() {
-        println!("None");
-        return;
+    if opt.shared {
+        output.header("Largest regions retained by multiple referrers");
+        let (regions, rest) = analysis.shared_regions(output.count);
+        print_shared_regions(&regions, rest);
     }
 
-    for (k, stats) in largest {
-        println!(
-            "{}: {} ({} objects)",
-            k,
-            ByteSize(stats.bytes as u64),
-            stats.count
-        );
+    if opt.class_dominators {
+        output.header("Classes retaining the most live memory as a whole");
+        let (largest, rest) = analysis.class_dominator_stats(output.count);
+        print_largest(&largest, rest);
     }
 
-    if rest.count > 0 {
-        println!(
-            "...: {} ({} objects)",
-            ByteSize(rest.bytes as u64),
-            rest.count
-        );
+    if opt.cycles {
+        output.header("Largest reference cycles");
+        let (cycles, rest) = analysis.cycles(output.count);
+        print_cycles(&cycles, rest);
     }
-}
 
-fn parse(
-    file: &Path,
-    rooted_at: Option<usize>,
-    class_name_only: bool,
-) -> Result<analyze::Analysis> {
-    let file = File::open(file)?;
-    let mut reader = BufReader::new(file);
-    let (root, graph) = parse::parse(&mut reader, class_name_only)?;
+    if opt.fan_in {
+        output.header("Objects with the most referrers");
+        print_fan_in(&analysis.most_referenced(output.count));
+    }
 
-    let subgraph_root = rooted_at
-        .map(|address| {
-            graph
-                .node_indices()
-                .find(|i| graph[*i].address == address)
-                .ok_or_else(|| {
-                    std::io::Error::new(
-                        std::io::ErrorKind::NotFound,
-                        "Given subtree root address not found",
-                    )
-                })
-        })
-        .unwrap_or(Ok(root))?;
+    if opt.duplicate_strings {
+        output.header("Most duplicated string values");
+        let (duplicates, rest) =
+            analysis.duplicate_strings(output.count, |obj| label_index.value_hash(obj.address));
+        print_duplicate_strings(&duplicates, rest);
+    }
 
-    Ok(
-        analyze::analyze(root, subgraph_root, graph, class_name_only)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?,
-    )
-}
+    if let Some((root, graph)) = garbage {
+        let garbage = analyze_graph(root, graph, None, class_name_only)?;
+        output.header("Unreachable objects retaining the most garbage");
+        let (largest, rest) =
+            garbage.dominator_subtree_stats_where(output.count, |obj| !obj.is_root());
+        print_largest(&largest, rest);
 
-#[derive(StructOpt, Debug)]
-#[structopt(name = "reap")]
-struct Opt {
-    /// Path to JSON heap dump file to process
-    #[structopt(name = "INPUT", parse(from_os_str))]
-    input: PathBuf,
+        output.header("Unreachable object types retaining the most garbage");
+        let (largest, rest) =
+            garbage.retained_stats_by_kind_where(output.count, |obj| !obj.is_root());
+        print_largest(&largest, rest);
+    }
 
-    /// Filter to subtree rooted at object with this address
-    #[structopt(short, long)]
-    root: Option<String>,
+    if let Some(ref pattern) = opt.holders_of {
+        output.header(&format!("Objects holding the most {}", pattern));
+        let (largest, rest) = analysis.holders_of(output.count, |obj| pattern.matches(&obj.class));
+        print_largest(&largest, rest);
+    }
 
-    /// Flamegraph SVG output for dominator tree
-    #[structopt(short, long, parse(from_os_str))]
-    flamegraph: Option<PathBuf>,
+    if !analyzers.is_empty() {
+        output.header("Findings");
+        let findings: Vec<detect::Finding> = analyzers
+            .iter()
+            .flat_map(|analyzer| analyzer.run(&analysis))
+            .collect();
+        print_findings(&findings);
+    }
 
-    /// Folded stack output for dominator tree
-    #[structopt(long, parse(from_os_str))]
-    folded: Option<PathBuf>,
+    if !groups.is_empty() && output.includes(Section::Groups) {
+        output.header("Groups using the most memory");
+        print_groups(&analysis, &groups);
+    }
 
-    /// Dot file output for dominator tree
-    #[structopt(short, long, parse(from_os_str))]
-    dot: Option<PathBuf>,
+    let violations = threshold::check(&analysis, &budgets);
+    if !budgets.is_empty() && output.includes(Section::Budgets) {
+        output.header("Budgets exceeded");
+        print_violations(&violations, budgets.len());
+    }
 
-    /// Include nodes retaining at least this fraction of memory in dot output
-    #[structopt(short, long, default_value = "0.005")]
-    threshold: f64,
+    if let Some(webhook) = Webhook::new(opt.webhook, opt.webhook_only_violations, &config) {
+        if webhook.notify(&analysis, budgets.len(), &violations)? {
+            output.note(&format!("Posted summary to {}", webhook.url));
+        }
+    }
 
-    /// Print this many of the types & objects retaining the most memory
-    #[structopt(short, long, default_value = "10")]
-    count: usize,
+    if let Some(path) = opt.flamegraph.or(config.flamegraph) {
+        let lines = analysis.flamegraph_lines()?;
+        let style = FlamegraphStyle {
+            palette: opt.palette.unwrap_or_default(),
+            by_class: opt.color_by_class,
+            direction: opt.flame_direction.unwrap_or_default(),
+            reverse: opt.flame_reverse,
+            left_heavy: opt.flame_left_heavy,
+            title: opt.flame_title.clone(),
+            subtitle: opt
+                .flame_subtitle
+                .clone()
+                .or_else(|| Some(flamegraph_subtitle(&input, analysis.dominated_totals()))),
+            notes: opt.flame_notes.clone(),
+            width: opt.flame_width,
+            font_size: opt.flame_font_size,
+            count_name: opt.flame_count_name.clone(),
+            no_javascript: opt.flame_no_javascript,
+        };
+        write_flamegraph(&lines, &style, path.as_path())?;
+        output.note(&format!(
+            "Wrote {} nodes to {}",
+            lines.len(),
+            path.display()
+        ));
+    }
 
-    /// Remove address from flamegraph labels
-    #[structopt(long = "class-name-only")]
-    class_name_only: bool,
-}
+    if let Some(path) = opt.folded.or(config.folded) {
+        let lines = analysis.flamegraph_lines()?;
+        write_folded(&lines, path.as_path())?;
+        output.note(&format!(
+            "Wrote {} nodes to {}",
+            lines.len(),
+            path.display()
+        ));
+    }
 
-fn main() -> Result<()> {
-    const VERSION: &str = env!("CARGO_PKG_VERSION");
-    println!("reap v{}", VERSION);
+    if let Some(path) = opt.speedscope.or(config.speedscope) {
+        let name = input.file_name().unwrap_or(input.as_os_str());
+        let json = speedscope::to_json(&analysis, &name.to_string_lossy())?;
+        std::fs::write(&path, json)?;
+        output.note(&format!("Wrote speedscope profile to {}", path.display()));
+    }
 
-    let opt = Opt::from_args();
+    if let Some(path) = opt.pprof.or(config.pprof) {
+        pprof::write(&analysis, std::io::BufWriter::new(File::create(&path)?))?;
+        output.note(&format!("Wrote pprof profile to {}", path.display()));
+    }
 
-    let subtree_root = opt
-        .root
-        .map(|r| parse::parse_address(r.as_str()).expect("Invalid subtree root address"));
+    if let Some(path) = opt.d3_json.or(config.d3_json) {
+        std::fs::write(&path, d3::to_json(&analysis)?)?;
+        output.note(&format!("Wrote d3 frames to {}", path.display()));
+    }
 
-    let class_name_only = opt.class_name_only;
+    if let Some(path) = opt.treemap.or(config.treemap) {
+        write_chart(TREEMAP, &analysis, path.as_path())?;
+        output.note(&format!("Wrote treemap to {}", path.display()));
+    }
 
-    let analysis = parse(opt.input.as_path(), subtree_root, class_name_only)?;
-    println!();
+    if let Some(path) = opt.sunburst.or(config.sunburst) {
+        write_chart(SUNBURST, &analysis, path.as_path())?;
+        output.note(&format!("Wrote sunburst chart to {}", path.display()));
+    }
 
-    println!("Object types using the most live memory:");
-    let (largest, rest) = analysis.live_stats_by_kind(opt.count);
-    print_largest(&largest, rest);
+    if let Some(path) = opt.dot.or(config.dot) {
+        let min_bytes = (analysis.dominated_totals().bytes as f64 * threshold.abs()) as usize;
+        let min_bytes = min_bytes.max(dot_min_bytes.map_or(0, |b| b.as_u64() as usize));
+        let mut dom_graph = analysis.dominator_subgraph(min_bytes, opt.dot_max_depth);
+        if opt.dot_collapse_chains {
+            dom_graph = dot::collapse_chains(&dom_graph);
+        }
+        let options = dot::Options {
+            edge_percentages: opt.dot_edge_percentages,
+            cluster_by: opt.dot_cluster_by,
+        };
+        dot::write(
+            &dom_graph,
+            &options,
+            std::io::BufWriter::new(File::create(&path)?),
+        )?;
+        output.note(&format!(
+            "Wrote {} nodes & {} edges to {}",
+            dom_graph.node_count(),
+            dom_graph.edge_count(),
+            path.display()
+        ));
+    }
 
-    println!("\nObjects retaining the most live memory:");
-    let (largest, rest) = analysis.dominator_subtree_stats(opt.count);
-    print_largest(&largest, rest);
+    if let Some(dir) = opt.neo4j_dir {
+        let (nodes, edges) = neo4j::write(&analysis, dir.as_path())?;
+        output.note(&format!(
+            "Wrote {} nodes & {} relationships to {}",
+            nodes,
+            edges,
+            dir.display()
+        ));
+    }
 
-    println!("\nObject types retaining the most live memory:");
-    let (largest, rest) = analysis.retained_stats_by_kind(opt.count);
-    print_largest(&largest, rest);
+    for export in &opt.instances_csv {
+        let count = instances::write(&analysis, &export.class, &export.path)?;
+        output.note(&format!(
+            "Wrote {} instances of {} to {}",
+            count,
+            export.class,
+            export.path.display()
+        ));
+    }
 
-    if subtree_root.is_none() {
-        println!("\nObjects unreachable from root:");
-        let (largest, rest) = analysis.unreachable_stats_by_kind(opt.count);
-        print_largest(&largest, rest);
-    } else {
-        println!(
-            "\nObjects reachable from, but not dominated by, {}:",
-            subtree_root.unwrap(),
-        );
-        let (largest, rest) = analysis.unreachable_stats_by_kind(opt.count);
-        print_largest(&largest, rest);
+    if let Some(path) = opt.sqlite {
+        let objects = write_sqlite(&analysis, path.as_path())?;
+        output.note(&format!("Wrote {} objects to {}", objects, path.display()));
     }
 
-    if let Some(output) = opt.flamegraph {
-        let lines = analysis.flamegraph_lines()?;
-        write_flamegraph(&lines, output.as_path())?;
-        println!("\nWrote {} nodes to {}", lines.len(), output.display());
+    if let Some(dir) = opt.parquet {
+        let (objects, edges) = write_parquet(&analysis, dir.as_path())?;
+        output.note(&format!(
+            "Wrote {} objects & {} edges to {}",
+            objects,
+            edges,
+            dir.display()
+        ));
     }
 
-    if let Some(output) = opt.folded {
-        let lines = analysis.flamegraph_lines()?;
-        write_folded(&lines, output.as_path())?;
-        println!("\nWrote {} nodes to {}", lines.len(), output.display());
+    if let Some(path) = opt.prometheus_textfile {
+        write_textfile(&prometheus::metrics(&analysis)?, path.as_path())?;
+        output.note(&format!("Wrote metrics to {}", path.display()));
     }
 
-    if let Some(output) = opt.dot {
-        let dom_graph = analysis.relevant_dominator_subgraph(opt.threshold.abs());
-        write_dot_file(&dom_graph, output.as_path())?;
-        println!(
-            "\nWrote {} nodes & {} edges to {}",
-            dom_graph.node_count(),
-            dom_graph.edge_count(),
-            output.display()
-        );
+    drop(output_phase);
+
+    if let Some(format) = opt.timings {
+        print_timings(format)?;
     }
 
     Ok(())
@@ -211,6 +2789,16 @@ fn main() -> Result<()> {
 mod test {
     use super::*;
     use rstest::rstest;
+
+    fn parse(
+        file: &Path,
+        rooted_at: Option<usize>,
+        class_name_only: bool,
+    ) -> Result<analyze::Analysis> {
+        let (root, graph) = load(file, class_name_only)?;
+        analyze_graph(root, graph, rooted_at, class_name_only)
+    }
+
     #[rstest]
     #[case(false)]
     #[case(true)]
@@ -284,6 +2872,210 @@ mod test {
         assert_eq!(24, dom_graph.edge_count());
     }
 
+    #[test]
+    fn left_heavy_order() {
+        let lines: Vec<String> = [
+            "root;A[0x1] 10",
+            "root;A[0x1];C[0x3] 5",
+            "root;A[0x1];D[0x4] 30",
+            "root;B[0x2] 50",
+        ]
+        .iter()
+        .map(|l| l.to_string())
+        .collect();
+        assert_eq!(
+            vec![
+                "root;B[0x2] 50",
+                "root;A[0x1] 10",
+                "root;A[0x1];D[0x4] 30",
+                "root;A[0x1];C[0x3] 5",
+            ],
+            left_heavy(&lines)
+        );
+
+        // Each way of drawing it is a valid flamegraph
+        for style in [
+            FlamegraphStyle {
+                direction: FlameDirection::Normal,
+                ..Default::default()
+            },
+            FlamegraphStyle {
+                reverse: true,
+                ..Default::default()
+            },
+            FlamegraphStyle {
+                left_heavy: true,
+                ..Default::default()
+            },
+        ] {
+            let mut svg = vec![];
+            render_flamegraph(&lines, &style, &mut svg).unwrap();
+            assert!(String::from_utf8(svg).unwrap().contains("D[0x4]"));
+        }
+    }
+
+    #[rstest]
+    #[case::none(None, None, 0)]
+    #[case::bytes(Some(ByteSize::b(1000)), None, 1000)]
+    #[case::percent(None, Some(10.0), 343_911)]
+    #[case::larger(Some(ByteSize::b(400_000)), Some(10.0), 400_000)]
+    fn flame_min_sizes(
+        #[case] bytes: Option<ByteSize>,
+        #[case] percent: Option<f64>,
+        #[case] expected: usize,
+    ) {
+        let analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+        assert_eq!(expected, flame_min(&analysis, bytes, percent));
+    }
+
+    #[test]
+    fn analysis_progress() {
+        let bar = ProgressBar::hidden();
+        show_analysis(
+            &bar,
+            Progress::Analyzing {
+                phase: "dominators",
+            },
+        );
+        assert_eq!(None, bar.length());
+        show_analysis(
+            &bar,
+            Progress::Nodes {
+                done: 10,
+                total: 40,
+            },
+        );
+        assert_eq!((Some(40), 10), (bar.length(), bar.position()));
+        show_analysis(
+            &bar,
+            Progress::Nodes {
+                done: 30,
+                total: 40,
+            },
+        );
+        assert_eq!((Some(40), 30), (bar.length(), bar.position()));
+        show_analysis(&bar, Progress::Analyzing { phase: "subgraph" });
+        assert_eq!(None, bar.length());
+        assert_eq!("Analyzing: subgraph", bar.message());
+    }
+
+    #[test]
+    fn only_sections() {
+        let opt =
+            Opt::from_iter_safe(["reap", "heap.json", "--only", "live,unreachable", "-q"]).unwrap();
+        let output = Output {
+            count: 10,
+            only: opt.only,
+            quiet: opt.quiet,
+        };
+        assert!(output.quiet);
+        assert!(output.includes(Section::Live));
+        assert!(output.includes(Section::Unreachable));
+        assert!(!output.includes(Section::Objects));
+        assert!(!output.includes(Section::Memory));
+
+        let opt = Opt::from_iter_safe(["reap", "heap.json"]).unwrap();
+        assert!(!opt.quiet);
+        assert!(opt.only.is_empty());
+        assert!(Opt::from_iter_safe(["reap", "heap.json", "--only", "everything"]).is_err());
+    }
+
+    #[rstest]
+    #[case(&[], false, false)]
+    #[case(&[], true, true)]
+    #[case(&["--class-name-only"], false, true)]
+    #[case(&["--no-class-name-only"], true, false)]
+    fn class_name_only_flags(
+        #[case] flags: &[&str],
+        #[case] configured: bool,
+        #[case] expected: bool,
+    ) {
+        let config: config::Config = format!("class-name-only = {}", configured).parse().unwrap();
+        let args = ["reap", "heap.json"].iter().chain(flags);
+        let opt = Opt::from_iter_safe(args).unwrap();
+        assert_eq!(
+            expected,
+            class_name_only(opt.class_name_only, opt.no_class_name_only, &config)
+        );
+
+        // Subcommands read the same flags, and the config file
+        let args = ["reap", "children", "heap.json", "0x1", "--no-config"]
+            .iter()
+            .chain(flags);
+        let Some(Command::Children { defaults, .. }) = Opt::from_iter_safe(args).unwrap().command
+        else {
+            panic!("not the children subcommand");
+        };
+        let given = flags.contains(&"--class-name-only");
+        assert_eq!(given, defaults.load().unwrap().1);
+    }
+
+    #[test]
+    fn color_by_class() {
+        let colors = |lines: &[&str]| {
+            let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+            class_colors(&lines, flamegraph::color::Palette::default()).unwrap()
+        };
+        let first = colors(&["root;Foo[0x1];String[0x2] 10", "root;Foo[0x3] 20"]);
+        let second = colors(&["root;Bar[0x9];Foo[0x8] 10"]);
+
+        let foo = first.get("Foo[0x1]");
+        assert!(foo.is_some());
+        assert_eq!(foo, first.get("Foo[0x3]"));
+        assert_ne!(foo, first.get("String[0x2]"));
+        // Whatever else is in the flamegraph
+        assert_eq!(foo, second.get("Foo[0x8]"));
+    }
+
+    #[rstest]
+    #[case(0, (1970, 1, 1))]
+    #[case(-1, (1969, 12, 31))]
+    #[case(11_016, (2000, 2, 29))]
+    #[case(20_741, (2026, 10, 15))]
+    fn civil_dates(#[case] days: i64, #[case] expected: (i64, u32, u32)) {
+        assert_eq!(expected, civil_from_days(days));
+    }
+
+    #[test]
+    fn flamegraph_style() {
+        let lines = vec!["root;Foo[0x1] 10".to_string()];
+        let style = FlamegraphStyle {
+            title: Some("Before deploy".to_string()),
+            subtitle: Some("heap.json, 10B in 1 objects".to_string()),
+            width: Some(800),
+            font_size: Some(14),
+            count_name: Some("bytes retained".to_string()),
+            ..Default::default()
+        };
+        let mut svg = vec![];
+        render_flamegraph(&lines, &style, &mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains("Before deploy"));
+        assert!(svg.contains("heap.json, 10B in 1 objects"));
+        assert!(svg.contains(r#"width="800""#));
+        assert!(svg.contains("fontsize = 14"));
+        assert!(svg.contains("bytes retained"));
+    }
+
+    #[rstest]
+    #[case::interactive(false)]
+    #[case::still(true)]
+    fn flamegraph_scripts(#[case] no_javascript: bool) {
+        let lines = vec!["root;Foo[0x1] 10".to_string()];
+        let style = FlamegraphStyle {
+            notes: Some("deploy 1234abc".to_string()),
+            no_javascript,
+            ..Default::default()
+        };
+        let mut svg = vec![];
+        render_flamegraph(&lines, &style, &mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains("deploy 1234abc"));
+        // Searching, and zooming in on a frame by clicking it
+        assert_eq!(!no_javascript, svg.contains("function search_prompt("));
+        assert_eq!(!no_javascript, svg.contains("function zoom("));
+    }
+
     #[rstest]
     #[case(false)]
     #[case(true)]
@@ -299,4 +3091,377 @@ mod test {
             assert_eq!(lines_with_memory_addresses, frame_lines.len());
         }
     }
+
+    #[test]
+    fn dominator_children() {
+        let analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+
+        let (largest, rest) = analysis
+            .dominator_children(140204367666240, usize::MAX)
+            .unwrap();
+        assert_eq!(0, rest.count);
+        assert_eq!(
+            24,
+            largest.iter().map(|(_, stats)| stats.count).sum::<usize>()
+        );
+        assert_eq!(
+            "Hash[0x7f83e10452d8][size=5]",
+            largest[0].0.to_string().as_str()
+        );
+        assert_eq!(6, largest[0].1.count);
+
+        assert!(analysis.dominator_children(1, usize::MAX).is_none());
+    }
+
+    #[test]
+    fn retained_by_set() {
+        let (root, graph) = load(Path::new("test/heap.json"), false).unwrap();
+        let thread = find_address(&graph, 140204367666240).unwrap();
+        let other_thread = find_address(&graph, 0x7f83e107cd78).unwrap();
+
+        let single = analyze::retained_by_set(root, &graph, &HashSet::from([thread]));
+        assert_eq!(25, single.count);
+        assert_eq!(1053052, single.bytes);
+
+        let other = analyze::retained_by_set(root, &graph, &HashSet::from([other_thread]));
+        let both = analyze::retained_by_set(root, &graph, &HashSet::from([thread, other_thread]));
+        assert!(both.count >= single.count + other.count);
+        assert!(both.bytes >= single.bytes + other.bytes);
+    }
+
+    #[test]
+    fn only_in_baseline() {
+        let analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+        let (_, graph) = load(Path::new("test/heap.json"), false).unwrap();
+        let baseline: HashSet<usize> = graph
+            .node_weights()
+            .filter(|obj| obj.kind != "String")
+            .map(|obj| obj.address)
+            .collect();
+        let is_new = |obj: &Object| !baseline.contains(&obj.address);
+
+        let (live_by_kind, _) = analysis.live_stats_by_kind_where(usize::MAX, is_new);
+        assert_eq!(1, live_by_kind.len());
+        assert_eq!("String", live_by_kind[0].0);
+        assert_eq!(9235, live_by_kind[0].1.count);
+
+        let (retained_by_kind, _) = analysis.retained_stats_by_kind_where(usize::MAX, is_new);
+        assert_eq!(9408, retained_by_kind[0].1.count);
+
+        let (largest, rest) = analysis.dominator_subtree_stats_where(usize::MAX, is_new);
+        assert_eq!(9235, largest.len() + rest.count);
+    }
+
+    #[test]
+    fn shared_regions() {
+        let analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+
+        let (regions, _) = analysis.shared_regions(10);
+        assert_eq!(10, regions.len());
+        for region in regions {
+            assert!(region.referrers.len() > 1);
+            assert!(region.referrers.iter().all(|r| !r.is_root()));
+            assert!(region.stats.bytes >= region.head.bytes);
+        }
+    }
+
+    #[rstest]
+    #[case("Thread", 70)]
+    #[case("Thre.d", 130)]
+    #[case("^(Thread|String)$", 9301)]
+    #[case("NoSuchClass", 0)]
+    fn restricted_to_class(#[case] pattern: ClassPattern, #[case] count: usize) {
+        let analysis = parse(Path::new("test/heap.json"), None, false)
+            .unwrap()
+            .restrict_to(|obj| pattern.matches(&obj.class));
+
+        // The root itself is always included
+        assert_eq!(count + 1, analysis.dominated_totals().count);
+
+        let (children, _) = analysis.dominator_children(0, usize::MAX).unwrap();
+        assert!(children.iter().all(|(obj, _)| pattern.matches(&obj.class)));
+    }
+
+    #[test]
+    fn excluded_kinds() {
+        let mut analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+        analysis.exclude_kinds(["Thread".to_string(), "IMEMO".to_string()]);
+
+        let (live_by_kind, _) = analysis.live_stats_by_kind(usize::MAX);
+        let (dead_by_kind, _) = analysis.unreachable_stats_by_kind(usize::MAX);
+        let (retained_by_kind, _) = analysis.retained_stats_by_kind(usize::MAX);
+        for (by_kind, _) in [live_by_kind, dead_by_kind, retained_by_kind]
+            .iter()
+            .flatten()
+        {
+            assert_ne!("Thread", by_kind.as_str());
+            assert_ne!("IMEMO", by_kind.as_str());
+        }
+
+        let excluded_frame = regex::Regex::new(r"(^|;)(Thread|IMEMO)\[0x[0-9a-f]+\]( |;)").unwrap();
+        let lines = analysis.flamegraph_lines().unwrap();
+        assert!(lines.iter().all(|l| !excluded_frame.is_match(l)));
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn rooted_at_matches_subtree_analysis(#[case] class_name_only: bool) {
+        let whole = parse(Path::new("test/heap.json"), None, class_name_only).unwrap();
+        let derived = whole.rooted_at(140204367666240).unwrap();
+        let direct = parse(
+            Path::new("test/heap.json"),
+            Some(140204367666240),
+            class_name_only,
+        )
+        .unwrap();
+
+        assert_eq!(
+            direct.dominated_totals().count,
+            derived.dominated_totals().count
+        );
+        assert_eq!(
+            direct.dominated_totals().bytes,
+            derived.dominated_totals().bytes
+        );
+
+        let mut direct_dead = direct.unreachable_stats_by_kind(usize::MAX).0;
+        let mut derived_dead = derived.unreachable_stats_by_kind(usize::MAX).0;
+        direct_dead.sort_unstable_by_key(|(k, _)| k.to_string());
+        derived_dead.sort_unstable_by_key(|(k, _)| k.to_string());
+        assert_eq!(format!("{:?}", direct_dead), format!("{:?}", derived_dead));
+
+        let mut direct_lines = direct.flamegraph_lines().unwrap();
+        let mut derived_lines = derived.flamegraph_lines().unwrap();
+        direct_lines.sort();
+        derived_lines.sort();
+        assert_eq!(direct_lines, derived_lines);
+    }
+
+    #[test]
+    fn paths_to_object() {
+        let analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+        let address = 0x7f83df87dc40;
+
+        let path = analysis.shortest_path(address).unwrap();
+        assert!(path.first().unwrap().is_root());
+        assert_eq!(address, path.last().unwrap().address);
+        for pair in path.windows(2) {
+            let referrers = analysis.referrers(pair[1].address).unwrap();
+            assert!(referrers.iter().any(|r| r.address == pair[0].address));
+        }
+
+        let chain = analysis.dominator_chain(address).unwrap();
+        assert!(chain.first().unwrap().is_root());
+        assert_eq!(address, chain.last().unwrap().address);
+        assert!(chain.len() <= path.len());
+
+        assert!(analysis.shortest_path(0x1).is_none());
+    }
+
+    #[test]
+    fn analysis_cancelled() {
+        let (root, graph) = load(Path::new("test/heap.json"), false).unwrap();
+        let hooks = Hooks::default().with_cancel(std::sync::Arc::new(AtomicBool::new(true)));
+        let result = analyze::analyze_with_hooks(root, root, graph, false, &hooks);
+        assert!(matches!(result, Err(analyze::AnalysisError::Cancelled)));
+    }
+
+    #[test]
+    fn rooted_at_multiple() {
+        let whole = parse(Path::new("test/heap.json"), None, false).unwrap();
+        let thread = 140204367666240;
+        let other_thread = 0x7f83e107cd78;
+
+        let first = whole.rooted_at(thread).unwrap().dominated_totals();
+        let second = whole.rooted_at(other_thread).unwrap().dominated_totals();
+        let union = whole.rooted_at_all(&[thread, other_thread]).unwrap();
+
+        // Includes the synthetic root
+        assert_eq!(
+            first.count + second.count + 1,
+            union.dominated_totals().count
+        );
+        assert_eq!(first.bytes + second.bytes, union.dominated_totals().bytes);
+
+        let (children, _) = union.dominator_children(0, usize::MAX).unwrap();
+        assert_eq!(2, children.len());
+
+        // A subtree nested within another adds nothing to the union
+        let (nested, _) = whole.dominator_children(thread, 1).unwrap();
+        let nested_union = whole.rooted_at_all(&[thread, nested[0].0.address]).unwrap();
+        assert_eq!(first.count, nested_union.dominated_totals().count);
+    }
+
+    #[rstest]
+    #[case(KindView::Class, "String", "STRING")]
+    #[case(KindView::Type, "STRING", "String")]
+    #[case(KindView::Both, "String (STRING)", "String")]
+    fn kind_view(#[case] view: KindView, #[case] present: &str, #[case] absent: &str) {
+        let (root, mut graph) = load(Path::new("test/heap.json"), false).unwrap();
+        view.apply(&mut graph);
+        let analysis = analyze_graph(root, graph, None, false).unwrap();
+        let (live_by_kind, _) = analysis.live_stats_by_kind(usize::MAX);
+        let kinds: HashSet<&str> = live_by_kind.iter().map(|(k, _)| k.as_str()).collect();
+        assert!(kinds.contains(present), "{:?}", kinds);
+        assert!(!kinds.contains(absent), "{:?}", kinds);
+
+        // Only the grouping changes
+        let totals = analysis.dominated_totals();
+        assert_eq!((15472, 3439119), (totals.count, totals.bytes));
+    }
+
+    #[test]
+    fn side_by_side() {
+        let stats = |count, bytes| Stats { count, bytes };
+        let a = vec![
+            ("String".into(), stats(10, 400)),
+            ("Hash".into(), stats(2, 200)),
+            ("Array".into(), stats(1, 40)),
+        ];
+        let b = vec![
+            ("String".into(), stats(12, 480)),
+            ("Proc".into(), stats(1, 80)),
+        ];
+        let (rows, (rest_a, rest_b)) = super::side_by_side(&a, &b, 3);
+        let rows: Vec<(&str, usize, usize)> = rows
+            .iter()
+            .map(|(kind, a, b)| (kind.as_str(), a.bytes, b.bytes))
+            .collect();
+        // Largest differences first, including kinds only one side has
+        assert_eq!(
+            vec![("Hash", 200, 0), ("Proc", 0, 80), ("String", 400, 480)],
+            rows
+        );
+        assert_eq!((1, 40), (rest_a.count, rest_a.bytes));
+        assert_eq!(0, rest_b.count);
+        assert_eq!(
+            "-120 B (-1 objects)",
+            format_delta(stats(3, 200), stats(2, 80))
+        );
+    }
+
+    #[test]
+    fn extract_in_place() {
+        let len = std::fs::metadata("test/heap.json").unwrap().len();
+        let output = Path::new("test/../test/heap.json");
+        assert!(extract(Path::new("test/heap.json"), 0x7f83df87dc40, output).is_err());
+        assert_eq!(len, std::fs::metadata("test/heap.json").unwrap().len());
+    }
+
+    #[test]
+    fn graph_stats() {
+        let analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+        let stats = analysis.graph_stats();
+        assert_eq!(15472, stats.live.count);
+        assert_eq!(
+            stats.references as f64 / (stats.live.count - 1) as f64,
+            stats.references_per_object.mean
+        );
+        assert!(stats.referrers_per_object.median <= stats.referrers_per_object.p90);
+        assert!(stats.referrers_per_object.p99 <= stats.referrers_per_object.max);
+        assert!(stats.cyclic >= 2 * stats.cycles);
+        assert!(stats.dominator_depth.median >= 1);
+    }
+
+    #[test]
+    fn root_nearest() {
+        let (_, graph) = load(Path::new("test/heap.json"), false).unwrap();
+        let thread = 0x7f83e107cd78;
+
+        let mut roots = vec![thread];
+        find_roots(&graph, &mut roots, false).unwrap();
+        assert_eq!(vec![thread], roots);
+
+        // Off by a byte, it's suggested, and snapped to with --root-nearest
+        let mut roots = vec![thread + 1];
+        let err = find_roots(&graph, &mut roots, false).unwrap_err();
+        assert!(err.to_string().contains("0x7f83e107cd78"), "{}", err);
+        find_roots(&graph, &mut roots, true).unwrap();
+        assert_eq!(vec![thread], roots);
+
+        // Nothing is on the page of an address far from the heap
+        assert!(find_roots(&graph, &mut [0x1000], true).is_err());
+    }
+
+    #[test]
+    fn min_bytes() {
+        let mut analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+        let kinds = analysis.live_stats_by_kind(usize::MAX).0.len();
+
+        analysis.set_min_bytes(100_000);
+        let (largest, rest) = analysis.live_stats_by_kind(usize::MAX);
+        assert!(!largest.is_empty());
+        assert!(largest.len() < kinds);
+        assert!(largest.iter().all(|(_, stats)| stats.bytes >= 100_000));
+        assert_eq!(
+            analysis.dominated_totals().bytes,
+            largest.iter().map(|(_, s)| s.bytes).sum::<usize>() + rest.bytes
+        );
+
+        let (largest, _) = analysis.live_stats_by_kind(1);
+        assert_eq!(1, largest.len());
+    }
+
+    #[rstest]
+    #[case(analyze::SortKey::Bytes, "Thread")]
+    #[case(analyze::SortKey::Count, "String")]
+    #[case(analyze::SortKey::Retained, "ROOT")]
+    fn sort_key(#[case] sort: analyze::SortKey, #[case] first: &str) {
+        let mut analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+        analysis.set_sort(sort);
+
+        let (largest, _) = analysis.live_stats_by_kind(3);
+        assert_eq!(first, largest[0].0);
+
+        let (largest, _) = analysis.dominator_subtree_stats(10);
+        let keys: Vec<usize> = largest
+            .iter()
+            .map(|(_, s)| match sort {
+                analyze::SortKey::Count => s.count,
+                _ => s.bytes,
+            })
+            .collect();
+        assert!(keys.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn deterministic_order() {
+        let analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+
+        // Ties are broken by address, and by kind name for per-kind rows
+        let (largest, _) = analysis.dominator_subtree_stats(usize::MAX);
+        assert!(largest.windows(2).all(|w| {
+            let ((a, x), (b, y)) = (w[0], w[1]);
+            x.bytes > y.bytes || (x.bytes == y.bytes && a.address < b.address)
+        }));
+        let (largest, _) = analysis.unreachable_stats_by_kind(usize::MAX);
+        assert!(largest.windows(2).all(|w| {
+            let ((a, x), (b, y)) = (w[0], w[1]);
+            x.bytes > y.bytes || (x.bytes == y.bytes && a < b)
+        }));
+
+        let again = parse(Path::new("test/heap.json"), None, false).unwrap();
+        assert_eq!(
+            analysis.flamegraph_lines().unwrap(),
+            again.flamegraph_lines().unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case("treemap", TREEMAP)]
+    #[case("sunburst", SUNBURST)]
+    fn chart(#[case] name: &str, #[case] template: &str) {
+        let analysis = parse(Path::new("test/heap.json"), None, false).unwrap();
+        let file = format!("reap-{}-{}.html", name, std::process::id());
+        let path = std::env::temp_dir().join(file);
+        write_chart(template, &analysis, &path).unwrap();
+        let html = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let start = html.find("const DATA = ").unwrap() + "const DATA = ".len();
+        let end = start + html[start..].find(";\n").unwrap();
+        let frames: serde_json::Value = serde_json::from_str(&html[start..end]).unwrap();
+        assert_eq!(3439119, frames["value"]);
+    }
 }
@@ -0,0 +1,331 @@
+// Allocator statistics captured alongside a dump, from glibc's malloc_stats()
+// or jemalloc's malloc_stats_print(), compared with the memory Ruby objects
+// account for, to estimate what native extensions allocate behind them.
+use crate::parse::parse_address;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+
+// Memory of an object slot; DATA objects reporting no more than this don't
+// tell Ruby about the native memory they hold.
+const SLOT_SIZE: usize = 40;
+
+#[derive(Debug)]
+pub enum MallocError {
+    IoError(std::io::Error),
+    InvalidLine(String),
+    UnknownFormat,
+}
+
+impl fmt::Display for MallocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MallocError::IoError(err) => write!(f, "Could not read malloc stats: {}", err),
+            MallocError::InvalidLine(line) => write!(f, "Invalid malloc stats line: {}", line),
+            MallocError::UnknownFormat => write!(
+                f,
+                "Not the output of glibc's malloc_stats() or jemalloc's malloc_stats_print()"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MallocError {}
+
+impl From<std::io::Error> for MallocError {
+    fn from(err: std::io::Error) -> Self {
+        MallocError::IoError(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Allocator {
+    Glibc,
+    Jemalloc,
+}
+
+impl fmt::Display for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Allocator::Glibc => write!(f, "glibc"),
+            Allocator::Jemalloc => write!(f, "jemalloc"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MallocStats {
+    pub allocator: Allocator,
+
+    // Bytes handed out by malloc & not yet freed
+    pub in_use: usize,
+
+    // Bytes the allocator holds from the OS, including free & fragmented space
+    pub system: usize,
+
+    pub arenas: usize,
+}
+
+fn number(line: &str, value: &str) -> Result<usize, MallocError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| MallocError::InvalidLine(line.to_string()))
+}
+
+// glibc prints each arena's, then the total, "system bytes" & "in use bytes".
+fn parse_glibc(lines: &[String]) -> Result<Option<MallocStats>, MallocError> {
+    let total = match lines
+        .iter()
+        .position(|l| l.starts_with("Total (incl. mmap)"))
+    {
+        Some(total) => total,
+        None => return Ok(None),
+    };
+    let mut stats = MallocStats {
+        allocator: Allocator::Glibc,
+        in_use: 0,
+        system: 0,
+        arenas: lines[..total]
+            .iter()
+            .filter(|l| l.starts_with("Arena "))
+            .count(),
+    };
+    for line in &lines[total + 1..] {
+        match line.split_once('=') {
+            Some((key, value)) if key.trim() == "system bytes" => {
+                stats.system = number(line, value)?
+            }
+            Some((key, value)) if key.trim() == "in use bytes" => {
+                stats.in_use = number(line, value)?
+            }
+            _ => {}
+        }
+    }
+    Ok(Some(stats))
+}
+
+// jemalloc summarizes all arenas on one line, e.g. "Allocated: 1, active: 2,
+// metadata: 3 (n_thp 0), resident: 4, mapped: 5, retained: 6".
+fn parse_jemalloc(lines: &[String]) -> Result<Option<MallocStats>, MallocError> {
+    let summary = match lines.iter().find(|l| l.starts_with("Allocated:")) {
+        Some(summary) => summary,
+        None => return Ok(None),
+    };
+    let fields: HashMap<&str, &str> = summary
+        .split(',')
+        .filter_map(|field| field.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.split_whitespace().next().unwrap_or("")))
+        .collect();
+    let field = |key: &str| match fields.get(key) {
+        Some(value) => number(summary, value),
+        None => Err(MallocError::InvalidLine(summary.to_string())),
+    };
+    let arenas = match lines.iter().find_map(|l| l.strip_prefix("Arenas:")) {
+        Some(arenas) => number(arenas, arenas)?,
+        None => 0,
+    };
+    Ok(Some(MallocStats {
+        allocator: Allocator::Jemalloc,
+        in_use: field("Allocated")?,
+        system: field("resident")?,
+        arenas,
+    }))
+}
+
+pub fn parse<R: BufRead>(reader: &mut R) -> Result<MallocStats, MallocError> {
+    let lines = reader
+        .lines()
+        .map(|line| line.map(|l| l.trim().to_string()))
+        .collect::<Result<Vec<String>, _>>()?;
+    match parse_jemalloc(&lines)? {
+        Some(stats) => Ok(stats),
+        None => parse_glibc(&lines)?.ok_or(MallocError::UnknownFormat),
+    }
+}
+
+// Instances of a class of DATA objects, which wrap native structs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataClass {
+    pub name: String,
+    pub count: usize,
+    pub bytes: usize,
+
+    // Instances reporting no memory beyond their slot
+    pub opaque: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct Line {
+    address: Option<String>,
+
+    #[serde(rename = "type")]
+    object_type: String,
+
+    class: Option<String>,
+    name: Option<String>,
+
+    #[serde(rename = "struct")]
+    struct_name: Option<String>,
+
+    memsize: Option<usize>,
+}
+
+// Reads the DATA objects of a Ruby heap dump, grouped by class, or by the
+// name of the struct they wrap if their class has none.
+pub fn data_classes<R: BufRead>(reader: &mut R) -> Result<Vec<DataClass>, MallocError> {
+    let mut names: HashMap<usize, String> = HashMap::new();
+    let mut data: Vec<(Option<usize>, String, usize)> = vec![];
+
+    let mut buffer = vec![];
+    while reader.read_until(b'\n', &mut buffer)? > 0 {
+        let line = String::from_utf8_lossy(&buffer);
+        let parsed: Result<Line, _> = serde_json::from_str(&line);
+        buffer.clear();
+        let parsed = match parsed {
+            Ok(parsed) => parsed,
+            Err(_) => continue,
+        };
+        let address = |a: &Option<String>| a.as_ref().and_then(|a| parse_address(a).ok());
+        match parsed.object_type.as_str() {
+            "CLASS" | "MODULE" => {
+                if let (Some(address), Some(name)) = (address(&parsed.address), parsed.name) {
+                    names.insert(address, name);
+                }
+            }
+            "DATA" => data.push((
+                address(&parsed.class),
+                parsed.struct_name.unwrap_or_else(|| "DATA".to_string()),
+                parsed.memsize.unwrap_or(0),
+            )),
+            _ => {}
+        }
+    }
+
+    let mut classes: HashMap<String, DataClass> = HashMap::new();
+    for (class, struct_name, bytes) in data {
+        let name = class
+            .and_then(|c| names.get(&c).cloned())
+            .unwrap_or(struct_name);
+        let class = classes.entry(name.clone()).or_insert(DataClass {
+            name,
+            count: 0,
+            bytes: 0,
+            opaque: 0,
+        });
+        class.count += 1;
+        class.bytes += bytes;
+        if bytes <= SLOT_SIZE {
+            class.opaque += 1;
+        }
+    }
+    let mut classes: Vec<DataClass> = classes.into_values().collect();
+    classes.sort_by(|a, b| b.opaque.cmp(&a.opaque).then_with(|| a.name.cmp(&b.name)));
+    Ok(classes)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub stats: MallocStats,
+
+    // Memory of every object in the dump
+    pub ruby_heap: usize,
+
+    // Memory in use that Ruby objects don't account for
+    pub unattributed: usize,
+
+    // DATA classes with opaque instances, with the share of the unattributed
+    // memory their instances would hold if each held the same amount
+    pub data: Vec<(DataClass, usize)>,
+}
+
+pub fn correlate(stats: MallocStats, ruby_heap: usize, classes: Vec<DataClass>) -> Report {
+    let unattributed = stats.in_use.saturating_sub(ruby_heap);
+    let opaque: usize = classes.iter().map(|c| c.opaque).sum();
+    let data = classes
+        .into_iter()
+        .filter(|c| c.opaque > 0)
+        .map(|c| {
+            let share = (unattributed as u128 * c.opaque as u128 / opaque as u128) as usize;
+            (c, share)
+        })
+        .collect();
+    Report {
+        stats,
+        ruby_heap,
+        unattributed,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+
+    const GLIBC: &str = "\
+Arena 0:
+system bytes     =    2000000
+in use bytes     =    1500000
+Arena 1:
+system bytes     =     500000
+in use bytes     =     300000
+Total (incl. mmap):
+system bytes     =    3000000
+in use bytes     =    2300000
+max mmap regions =          2
+max mmap bytes   =     500000
+";
+
+    const JEMALLOC: &str = "\
+___ Begin jemalloc statistics ___
+Version: \"5.3.0-0-g54eaed1d8b56b1aa528be3bdd1877e59c56fa90c\"
+Arenas: 4
+Allocated: 2300000, active: 2500000, metadata: 100000 (n_thp 0), resident: 3000000, mapped: 3500000, retained: 0
+--- End jemalloc statistics ---
+";
+
+    #[rstest]
+    #[case::glibc(GLIBC, Allocator::Glibc, 2)]
+    #[case::jemalloc(JEMALLOC, Allocator::Jemalloc, 4)]
+    fn test_parse(#[case] input: &str, #[case] allocator: Allocator, #[case] arenas: usize) {
+        assert_eq!(
+            MallocStats {
+                allocator,
+                in_use: 2300000,
+                system: 3000000,
+                arenas,
+            },
+            parse(&mut Cursor::new(input)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        assert!(matches!(
+            parse(&mut Cursor::new("Rss: 4 kB\n")),
+            Err(MallocError::UnknownFormat)
+        ));
+    }
+
+    #[test]
+    fn test_correlate() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let classes = data_classes(&mut reader).unwrap();
+        let encoding = classes.iter().find(|c| c.name == "Encoding").unwrap();
+        assert_eq!(
+            (101, 101 * 40, 101),
+            (encoding.count, encoding.bytes, encoding.opaque)
+        );
+
+        let stats = parse(&mut Cursor::new(GLIBC)).unwrap();
+        let report = correlate(stats, 2000000, classes);
+        assert_eq!(300000, report.unattributed);
+        assert!(report.data.iter().all(|(c, _)| c.opaque > 0));
+        let shares: usize = report.data.iter().map(|(_, share)| share).sum();
+        assert!(shares <= report.unattributed && shares > report.unattributed - 10);
+    }
+}
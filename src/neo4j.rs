@@ -0,0 +1,101 @@
+// Objects dominated by the root, their references and the dominator tree as
+// CSV files in the format `neo4j-admin database import` reads, e.g.
+//
+//   neo4j-admin database import full --nodes=nodes.csv --relationships=edges.csv
+//
+// after which (:Object)-[:REFERENCES|DOMINATES]->(:Object) can be queried with
+// Cypher. Unreachable objects are left out, since their references aren't kept.
+use crate::analyze::Analysis;
+use crate::object::*;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub const NODES_FILE: &str = "nodes.csv";
+pub const EDGES_FILE: &str = "edges.csv";
+
+fn id(obj: &Object) -> String {
+    format!("{:#x}", obj.address)
+}
+
+// Always quoted, with quotes doubled
+pub(crate) fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+// Writes both files into `dir`, creating it if needed, and returns the number
+// of nodes & relationships written.
+pub fn write(analysis: &Analysis, dir: &Path) -> io::Result<(usize, usize)> {
+    fs::create_dir_all(dir)?;
+
+    let mut nodes = BufWriter::new(File::create(dir.join(NODES_FILE))?);
+    writeln!(
+        nodes,
+        "address:ID,kind,label,bytes:long,retained_bytes:long,retained_count:long,:LABEL"
+    )?;
+    let mut node_count = 0;
+    for (obj, retained) in analysis.objects() {
+        writeln!(
+            nodes,
+            "{},{},{},{},{},{},Object",
+            id(obj),
+            quote(&obj.kind),
+            quote(obj.label.as_deref().unwrap_or("")),
+            obj.bytes,
+            retained.bytes,
+            retained.count,
+        )?;
+        node_count += 1;
+    }
+    nodes.flush()?;
+
+    let mut edges = BufWriter::new(File::create(dir.join(EDGES_FILE))?);
+    writeln!(edges, ":START_ID,:END_ID,:TYPE")?;
+    let mut edge_count = 0;
+    let references = analysis.references().map(|edge| (edge, "REFERENCES"));
+    let dominators = analysis.dominator_edges().map(|edge| (edge, "DOMINATES"));
+    for ((from, to), kind) in references.chain(dominators) {
+        writeln!(edges, "{},{},{}", id(from), id(to), kind)?;
+        edge_count += 1;
+    }
+    edges.flush()?;
+
+    Ok((node_count, edge_count))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use std::io::BufReader;
+
+    #[test]
+    fn test_write() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("reap-neo4j-{}", std::process::id()));
+        let (nodes, edges) = write(&analysis, &dir).unwrap();
+        let nodes_csv = fs::read_to_string(dir.join(NODES_FILE)).unwrap();
+        let edges_csv = fs::read_to_string(dir.join(EDGES_FILE)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(15472, nodes);
+        assert_eq!(nodes + 1, nodes_csv.lines().count());
+        assert_eq!(edges + 1, edges_csv.lines().count());
+        assert!(nodes_csv.contains("\n0x7f83df87dc40,\"Thread\",\"\",1049160,1053052,25,Object\n"));
+        assert_eq!(
+            nodes - 1,
+            edges_csv
+                .lines()
+                .filter(|l| l.ends_with(",DOMINATES"))
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!("\"a \"\"b\"\", c\"", quote("a \"b\", c"));
+    }
+}
@@ -1,17 +1,35 @@
+use crate::intern::Symbol;
 use bytesize::ByteSize;
 use petgraph::{Directed, Graph};
+use regex::Regex;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Object {
     pub address: usize,
     pub bytes: usize,
-    pub kind: String,
-    pub label: Option<String>,
+
+    // What reports group & label it by: its class unless --kind or
+    // --group-by chose otherwise
+    pub kind: Symbol,
+
+    // Its class's name where the dump has it, otherwise its internal type,
+    // which class patterns match whatever its kind shows
+    pub class: Symbol,
+
+    // The dump's type for it, e.g. OBJECT or HASH
+    pub internal_type: Symbol,
+    pub label: Option<Box<str>>,
+
+    // Where it was allocated, if the dump traced allocations
+    pub file: Option<Symbol>,
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, Serialize)]
 pub struct Stats {
     pub count: usize,
     pub bytes: usize,
@@ -29,8 +47,11 @@ impl Object {
         Object {
             address: 0,
             bytes: 0,
-            kind: "ROOT".to_string(),
-            label: Some("root".to_string()),
+            kind: "ROOT".into(),
+            class: "ROOT".into(),
+            internal_type: "ROOT".into(),
+            label: Some("root".into()),
+            file: None,
         }
     }
 
@@ -38,23 +59,11 @@ impl Object {
         self.address == 0
     }
 
-    pub fn with_dominator_stats(&self, stats: Stats) -> Object {
-        let mut clone = self.clone();
-        clone.label = Some(format!(
-            "{}: {} self, {} refs, {} objects",
-            self,
-            ByteSize(self.bytes as u64),
-            ByteSize((stats.bytes - self.bytes) as u64),
-            stats.count
-        ));
-        clone
-    }
-
     pub fn format(&self, class_name_only: bool) -> String {
         if let Some(ref label) = self.label {
             label.to_string()
         } else if class_name_only {
-            self.kind.clone()
+            self.kind.to_string()
         } else {
             format!("{}[{:#x}]", self.kind, self.address)
         }
@@ -95,4 +104,187 @@ impl Stats {
 
 pub type ReferenceGraph = Graph<Object, &'static str, Directed, usize>;
 
+// An object in (part of) the dominator tree, with what it retains
+#[derive(Debug, Clone)]
+pub struct Dominator {
+    pub object: Object,
+    pub retained: Stats,
+}
+
+impl Display for Dominator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} self, {} refs, {} objects",
+            self.object,
+            ByteSize(self.object.bytes as u64),
+            ByteSize((self.retained.bytes - self.object.bytes) as u64),
+            self.retained.count
+        )
+    }
+}
+
+pub type DominatorGraph = Graph<Dominator, &'static str, Directed, usize>;
+
+// Which of objects' kinds reports group and label them by: their classes
+// (e.g. Foo), internal types (OBJECT), or both (Foo (OBJECT)).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KindView {
+    #[default]
+    Class,
+    Type,
+    Both,
+}
+
+impl FromStr for KindView {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "class" => Ok(KindView::Class),
+            "type" => Ok(KindView::Type),
+            "both" => Ok(KindView::Both),
+            _ => Err(format!("Unknown kind view {:?}", s)),
+        }
+    }
+}
+
+impl KindView {
+    // The kind of an object of class `kind` and type `internal_type`.
+    pub fn kind(self, kind: Symbol, internal_type: Symbol) -> Symbol {
+        match self {
+            KindView::Class => kind,
+            KindView::Type => internal_type,
+            KindView::Both if kind == internal_type => kind,
+            KindView::Both => format!("{} ({})", kind, internal_type).into(),
+        }
+    }
+
+    // Replaces each object's kind with what this view shows.
+    pub fn apply(self, graph: &mut ReferenceGraph) {
+        if self == KindView::Class {
+            return;
+        }
+        let mut kinds: FxHashMap<(Symbol, Symbol), Symbol> = FxHashMap::default();
+        for obj in graph.node_weights_mut() {
+            obj.kind = *kinds
+                .entry((obj.kind, obj.internal_type))
+                .or_insert_with(|| self.kind(obj.kind, obj.internal_type));
+        }
+    }
+}
+
+// Whether reports group objects by their whole class name, or only by its
+// first `depth` segments (e.g. ActiveRecord, or Sidekiq::Job for depth 2).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GroupBy {
+    #[default]
+    Class,
+    Namespace(usize),
+}
+
+impl FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "class" => Ok(GroupBy::Class),
+            None if s == "namespace" => Ok(GroupBy::Namespace(1)),
+            Some(("namespace", depth)) => match depth.parse() {
+                Ok(depth) if depth > 0 => Ok(GroupBy::Namespace(depth)),
+                _ => Err(format!("Invalid namespace depth {:?}", depth)),
+            },
+            _ => Err(format!("Unknown grouping {:?}", s)),
+        }
+    }
+}
+
+impl GroupBy {
+    // The group of objects of kind `kind`.
+    pub fn kind(self, kind: Symbol) -> Symbol {
+        match self {
+            GroupBy::Class => kind,
+            GroupBy::Namespace(depth) => match kind.as_str().match_indices("::").nth(depth - 1) {
+                Some((end, _)) => kind.as_str()[..end].into(),
+                None => kind,
+            },
+        }
+    }
+
+    // Replaces each object's kind with its group.
+    pub fn apply(self, graph: &mut ReferenceGraph) {
+        if self == GroupBy::Class {
+            return;
+        }
+        let mut kinds: FxHashMap<Symbol, Symbol> = FxHashMap::default();
+        for obj in graph.node_weights_mut() {
+            obj.kind = *kinds.entry(obj.kind).or_insert_with(|| self.kind(obj.kind));
+        }
+    }
+}
+
 pub const EDGE_WEIGHT: &str = "";
+
+// Internal types of objects which only hold other objects for their owners.
+pub const CONTAINERS: &[&str] = &["ARRAY", "HASH", "IMEMO", "STRING", "ICLASS"];
+
+// Matches object kinds either by exact class name, or by regex if the pattern
+// contains anything other than the characters allowed in a class name.
+#[derive(Debug, Clone)]
+pub enum ClassPattern {
+    Name(String),
+    Regex(Regex),
+}
+
+impl ClassPattern {
+    pub fn matches(&self, kind: &str) -> bool {
+        match self {
+            ClassPattern::Name(name) => name == kind,
+            ClassPattern::Regex(regex) => regex.is_match(kind),
+        }
+    }
+}
+
+impl FromStr for ClassPattern {
+    type Err = regex::Error;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        if pattern
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == ':')
+        {
+            Ok(ClassPattern::Name(pattern.to_string()))
+        } else {
+            Ok(ClassPattern::Regex(Regex::new(pattern)?))
+        }
+    }
+}
+
+// A named bucket of objects, e.g. all of a library's, matched by their
+// classes or labels.
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub name: String,
+    pub patterns: Vec<ClassPattern>,
+}
+
+impl Group {
+    pub fn matches(&self, obj: &Object) -> bool {
+        self.patterns.iter().any(|pattern| {
+            pattern.matches(&obj.class)
+                || obj
+                    .label
+                    .as_deref()
+                    .is_some_and(|label| pattern.matches(label))
+        })
+    }
+}
+
+impl Display for ClassPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ClassPattern::Name(name) => write!(f, "{}", name),
+            ClassPattern::Regex(regex) => write!(f, "/{}/", regex),
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// Objects dominated by the root, their references and the dominator tree as
+// Parquet datasets, for analyzing many dumps at once in e.g. DuckDB:
+//
+//   SELECT filename, kind, sum(bytes) FROM read_parquet('*/objects.parquet', filename = true)
+//   GROUP BY ALL;
+//
+// Unreachable objects are left out, since their references aren't kept.
+use crate::analyze::Analysis;
+use ::parquet::basic::Compression;
+use ::parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use ::parquet::errors::ParquetError;
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::writer::SerializedFileWriter;
+use ::parquet::schema::parser::parse_message_type;
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+
+pub const OBJECTS_FILE: &str = "objects.parquet";
+pub const EDGES_FILE: &str = "edges.parquet";
+
+const OBJECTS_SCHEMA: &str = "
+message objects {
+    REQUIRED INT64 address;
+    REQUIRED BYTE_ARRAY kind (UTF8);
+    OPTIONAL BYTE_ARRAY label (UTF8);
+    REQUIRED INT64 bytes;
+    REQUIRED INT64 retained_bytes;
+    REQUIRED INT64 retained_count;
+}";
+
+// Kind is either "references" or "dominates"
+const EDGES_SCHEMA: &str = "
+message edges {
+    REQUIRED INT64 from_address;
+    REQUIRED INT64 to_address;
+    REQUIRED BYTE_ARRAY kind (UTF8);
+}";
+
+// Rows buffered before being written out as a row group
+const ROW_GROUP_SIZE: usize = 1 << 20;
+
+// Values of one column within a row group, with definition levels for
+// optional columns.
+enum Column {
+    Int64(Vec<i64>),
+    Text(Vec<ByteArray>, Option<Vec<i16>>),
+}
+
+impl Column {
+    fn int64(&mut self, n: usize) {
+        if let Column::Int64(values) = self {
+            values.push(n as i64);
+        }
+    }
+
+    fn text(&mut self, s: Option<&str>) {
+        if let Column::Text(values, levels) = self {
+            match (s, levels) {
+                (Some(s), Some(levels)) => {
+                    values.push(s.into());
+                    levels.push(1);
+                }
+                (None, Some(levels)) => levels.push(0),
+                (s, None) => values.push(s.unwrap_or_default().into()),
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Column::Int64(values) => values.clear(),
+            Column::Text(values, levels) => {
+                values.clear();
+                if let Some(levels) = levels {
+                    levels.clear();
+                }
+            }
+        }
+    }
+}
+
+struct Writer {
+    writer: SerializedFileWriter<File>,
+    columns: Vec<Column>,
+    rows: usize,
+    total: usize,
+}
+
+impl Writer {
+    fn new(path: &Path, schema: &str, columns: Vec<Column>) -> Result<Writer, ParquetError> {
+        let schema = Arc::new(parse_message_type(schema)?);
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let file = File::create(path)?;
+        Ok(Writer {
+            writer: SerializedFileWriter::new(file, schema, Arc::new(props))?,
+            columns,
+            rows: 0,
+            total: 0,
+        })
+    }
+
+    // Counts a row whose values have been added to the columns.
+    fn row(&mut self) -> Result<(), ParquetError> {
+        self.rows += 1;
+        if self.rows == ROW_GROUP_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ParquetError> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let mut row_group = self.writer.next_row_group()?;
+        for column in &mut self.columns {
+            let mut writer = row_group.next_column()?.expect("schema matches columns");
+            match column {
+                Column::Int64(values) => {
+                    writer
+                        .typed::<Int64Type>()
+                        .write_batch(values, None, None)?;
+                }
+                Column::Text(values, levels) => {
+                    writer
+                        .typed::<ByteArrayType>()
+                        .write_batch(values, levels.as_deref(), None)?;
+                }
+            }
+            writer.close()?;
+            column.clear();
+        }
+        row_group.close()?;
+        self.total += self.rows;
+        self.rows = 0;
+        Ok(())
+    }
+
+    // Returns the number of rows written.
+    fn close(mut self) -> Result<usize, ParquetError> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(self.total)
+    }
+}
+
+// Writes both datasets into `dir`, creating it if needed, and returns the
+// number of objects & edges written.
+pub fn write(analysis: &Analysis, dir: &Path) -> Result<(usize, usize), ParquetError> {
+    fs::create_dir_all(dir)?;
+
+    let columns = vec![
+        Column::Int64(vec![]),
+        Column::Text(vec![], None),
+        Column::Text(vec![], Some(vec![])),
+        Column::Int64(vec![]),
+        Column::Int64(vec![]),
+        Column::Int64(vec![]),
+    ];
+    let mut objects = Writer::new(&dir.join(OBJECTS_FILE), OBJECTS_SCHEMA, columns)?;
+    for (obj, retained) in analysis.objects() {
+        let c = &mut objects.columns;
+        c[0].int64(obj.address);
+        c[1].text(Some(&obj.kind));
+        c[2].text(obj.label.as_deref());
+        c[3].int64(obj.bytes);
+        c[4].int64(retained.bytes);
+        c[5].int64(retained.count);
+        objects.row()?;
+    }
+    let object_count = objects.close()?;
+
+    let columns = vec![
+        Column::Int64(vec![]),
+        Column::Int64(vec![]),
+        Column::Text(vec![], None),
+    ];
+    let mut edges = Writer::new(&dir.join(EDGES_FILE), EDGES_SCHEMA, columns)?;
+    let references = analysis.references().map(|edge| (edge, "references"));
+    let dominators = analysis.dominator_edges().map(|edge| (edge, "dominates"));
+    for ((from, to), kind) in references.chain(dominators) {
+        let c = &mut edges.columns;
+        c[0].int64(from.address);
+        c[1].int64(to.address);
+        c[2].text(Some(kind));
+        edges.row()?;
+    }
+    let edge_count = edges.close()?;
+
+    Ok((object_count, edge_count))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use ::parquet::file::reader::{FileReader, SerializedFileReader};
+    use ::parquet::record::RowAccessor;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_write() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("reap-parquet-{}", std::process::id()));
+        let (objects, edges) = write(&analysis, &dir).unwrap();
+        assert_eq!(15472, objects);
+
+        let reader =
+            SerializedFileReader::new(File::open(dir.join(OBJECTS_FILE)).unwrap()).unwrap();
+        assert_eq!(objects as i64, reader.metadata().file_metadata().num_rows());
+        let thread = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap())
+            .find(|row| row.get_long(0).unwrap() == 0x7f83df87dc40)
+            .unwrap();
+        assert_eq!("Thread", thread.get_string(1).unwrap());
+        assert_eq!(1053052, thread.get_long(4).unwrap());
+
+        let reader = SerializedFileReader::new(File::open(dir.join(EDGES_FILE)).unwrap()).unwrap();
+        assert_eq!(edges as i64, reader.metadata().file_metadata().num_rows());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
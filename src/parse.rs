@@ -1,75 +1,403 @@
+use crate::hooks::{Cancelled, Hooks, Progress, CHECK_INTERVAL};
+use crate::intern::Symbol;
 use crate::object::*;
+use crate::timing;
+use log::{debug, warn};
 use petgraph::graph::NodeIndex;
 use petgraph::Graph;
+use rustc_hash::{FxHashMap, FxHashSet};
 use serde::Deserialize;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
-use std::io::BufRead;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{BufRead, Read, Seek, SeekFrom};
+#[cfg(feature = "mmap")]
+use std::path::Path;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use timed_function::timed;
 
+// Instances of these classes don't keep the objects they reference alive, so
+// we drop their outgoing edges rather than let them retain memory.
+// ObjectSpace::WeakKeyMap isn't one: it holds its values strongly, and dumps
+// don't say which of its references are to keys.
+pub(crate) const WEAK_REFERENCE_CLASSES: &[&str] = &["ObjectSpace::WeakMap", "WeakRef"];
+
+// Borrows from the line where it can, as allocating for each field of each
+// line is a large part of the cost of parsing.
 #[derive(Debug, Deserialize)]
-struct Line {
-    address: Option<String>,
+pub(crate) struct Line<'a> {
+    address: Option<&'a str>,
     memsize: Option<usize>,
 
-    #[serde(default)]
-    references: Vec<String>,
+    #[serde(rename = "type", borrow)]
+    object_type: Cow<'a, str>,
+
+    class: Option<&'a str>,
 
-    #[serde(rename = "type")]
-    object_type: String,
+    #[serde(borrow)]
+    name: Option<Cow<'a, str>>,
 
-    class: Option<String>,
-    name: Option<String>,
     length: Option<usize>,
     size: Option<usize>,
-    value: Option<String>,
+
+    #[serde(borrow)]
+    value: Option<Cow<'a, str>>,
+
+    #[serde(borrow)]
+    file: Option<Cow<'a, str>>,
+}
+
+// The references on a line, which the second pass reads once every object
+// has been added to the graph.
+#[derive(Debug, Deserialize)]
+pub(crate) struct References<'a> {
+    pub(crate) address: Option<&'a str>,
+
+    #[serde(borrow, default)]
+    pub(crate) references: Vec<&'a str>,
 }
 
 #[derive(Debug)]
-struct ParsedLine {
-    object: Object,
-    references: Vec<usize>,
-    module: Option<usize>,
-    name: Option<String>,
+pub(crate) struct ParsedLine {
+    pub(crate) object: Object,
+    pub(crate) module: Option<usize>,
+    pub(crate) name: Option<String>,
+
+    // A hash of a string's whole value, if `options.string_hashes` asked
+    pub(crate) value_hash: Option<u64>,
 }
 
 #[derive(Debug)]
 pub enum ParseError {
+    IoError(std::io::Error),
     JsonError(serde_json::Error),
     InvalidLine(String),
+    // A malformed last line without a newline, as a dump cut short ends with
+    Truncated(Box<ParseError>),
+    // Any of the above, with where in the dump the line is
+    Line(LineError),
+    Cancelled,
+}
+
+// How much of a malformed line errors quote
+const CONTEXT_LEN: usize = 200;
+
+#[derive(Debug)]
+pub struct LineError {
+    // Counting from 1
+    pub line: usize,
+    pub offset: u64,
+
+    // The start of the line
+    pub context: String,
+    pub cause: Box<ParseError>,
+}
+
+impl ParseError {
+    // Locates an error with a line of the dump; others are passed through.
+    pub(crate) fn at(self, line: usize, offset: u64, text: &[u8]) -> ParseError {
+        match self {
+            ParseError::JsonError(_) | ParseError::InvalidLine(_) | ParseError::Truncated(_) => {
+                let text = String::from_utf8_lossy(text);
+                let text = text.trim_end();
+                let context = match text.char_indices().nth(CONTEXT_LEN) {
+                    Some((end, _)) => format!("{}...", &text[..end]),
+                    None => text.to_string(),
+                };
+                ParseError::Line(LineError {
+                    line,
+                    offset,
+                    context,
+                    cause: Box::new(self),
+                })
+            }
+            err => err,
+        }
+    }
+
+    // The JSON error behind this one, if the line wasn't valid JSON or
+    // didn't describe an object.
+    pub fn json_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            ParseError::JsonError(err) => Some(err),
+            ParseError::Truncated(err) => err.json_error(),
+            ParseError::Line(err) => err.cause.json_error(),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            ParseError::IoError(err) => write!(f, "Could not read dump: {}", err),
             ParseError::JsonError(err) => write!(f, "JSON error: {}", err),
             ParseError::InvalidLine(line) => write!(f, "Invalid line: {}", line),
+            ParseError::Truncated(err) => {
+                write!(
+                    f,
+                    "Incomplete last line, so the dump may be truncated: {}",
+                    err
+                )
+            }
+            ParseError::Line(err) => write!(
+                f,
+                "Line {} (byte {}): {}\n    {}",
+                err.line, err.offset, err.cause, err.context
+            ),
+            ParseError::Cancelled => write!(f, "Parsing cancelled"),
         }
     }
 }
 
-impl std::error::Error for ParseError {}
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::IoError(err) => Some(err),
+            ParseError::JsonError(err) => Some(err),
+            ParseError::Truncated(err) => Some(err.as_ref()),
+            ParseError::Line(err) => Some(err.cause.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ParseError {
+    fn from(err: std::io::Error) -> Self {
+        ParseError::IoError(err)
+    }
+}
+
+// Signs of a dump cut short, e.g. by the process being killed while dumping,
+// which parsing tallies so the analysis of what's there isn't trusted blindly.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Integrity {
+    pub roots: usize,
+    pub objects: usize,
+    pub references: usize,
+
+    // References to addresses no line describes, and the objects holding them
+    pub dangling: usize,
+    pub dangling_referrers: usize,
+
+    pub incomplete_last_line: bool,
+
+    // Objects nothing references, taken as roots when there are no ROOT lines
+    pub synthesized_roots: usize,
+}
+
+// Complete dumps have a handful of references to objects missing from them,
+// far fewer than this fraction of all references
+const MAX_DANGLING: f64 = 0.01;
+
+impl Integrity {
+    pub fn merge(&mut self, other: Integrity) {
+        self.roots += other.roots;
+        self.objects += other.objects;
+        self.references += other.references;
+        self.dangling += other.dangling;
+        self.dangling_referrers += other.dangling_referrers;
+        self.incomplete_last_line |= other.incomplete_last_line;
+        self.synthesized_roots += other.synthesized_roots;
+    }
 
-impl Line {
+    // Counts a line's references, of which `dangling` weren't found.
+    pub(crate) fn add_references(&mut self, references: usize, dangling: usize) {
+        self.references += references;
+        self.dangling += dangling;
+        self.dangling_referrers += (dangling > 0) as usize;
+    }
+
+    // Why the dump looks truncated, if it does.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = vec![];
+        if self.incomplete_last_line {
+            warnings.push("its last line is incomplete".to_string());
+        }
+        if self.roots == 0 && self.objects > 0 {
+            warnings.push(format!(
+                "it has no ROOT lines, so the {} of its {} objects nothing references were taken as roots",
+                self.synthesized_roots, self.objects
+            ));
+        }
+        let fraction = self.dangling as f64 / self.references.max(1) as f64;
+        if fraction > MAX_DANGLING {
+            warnings.push(format!(
+                "{} of its {} references ({:.1}%), held by {} objects, are to objects missing from it",
+                self.dangling,
+                self.references,
+                fraction * 100.0,
+                self.dangling_referrers
+            ));
+        }
+        warnings
+    }
+
+    pub(crate) fn report(&self) {
+        let warnings = self.warnings();
+        if !warnings.is_empty() {
+            warn!(
+                "The dump may be truncated, and its analysis incomplete: {}",
+                warnings.join("; ")
+            );
+        }
+    }
+}
+
+// Malformed lines left out in lenient mode.
+#[derive(Debug, Default)]
+pub(crate) struct Skipped {
+    // Where each line the first pass skipped starts, so the second skips it too
+    offsets: FxHashSet<u64>,
+    count: usize,
+    first: Option<(u64, String)>,
+    truncated: bool,
+}
+
+impl Skipped {
+    // Records that the line at `offset` is malformed, or returns the error
+    // unless parsing is lenient.
+    pub(crate) fn skip(
+        &mut self,
+        options: &ParseOptions,
+        offset: u64,
+        err: ParseError,
+    ) -> Result<(), ParseError> {
+        match err {
+            ParseError::JsonError(_) | ParseError::InvalidLine(_) | ParseError::Truncated(_)
+                if options.lenient =>
+            {
+                self.truncated |= matches!(err, ParseError::Truncated(_));
+                self.count += 1;
+                self.offsets.insert(offset);
+                if self.first.as_ref().is_none_or(|(first, _)| offset < *first) {
+                    self.first = Some((offset, err.to_string()));
+                }
+                Ok(())
+            }
+            err => Err(err),
+        }
+    }
+
+    pub(crate) fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub(crate) fn contains(&self, offset: u64) -> bool {
+        self.offsets.contains(&offset)
+    }
+
+    pub(crate) fn report(&self) {
+        if let Some((offset, ref err)) = self.first {
+            warn!(
+                "Skipped {} malformed line(s); the first, at byte {}: {}",
+                self.count, offset, err
+            );
+        }
+    }
+}
+
+// Decodes a line, replacing invalid UTF-8 as Ruby doesn't always produce
+// valid UTF-8 in dumps; `scratch` holds the line's replacement if needed.
+pub(crate) fn decode<'a, T: Deserialize<'a>>(
+    line: &'a [u8],
+    scratch: &'a mut Vec<u8>,
+) -> Result<T, ParseError> {
+    match str::from_utf8(line) {
+        Ok(line) => serde_json::from_str(line),
+        Err(_) => {
+            *scratch = String::from_utf8_lossy(line).into_owned().into_bytes();
+            serde_json::from_slice(scratch)
+        }
+    }
+    .map_err(ParseError::JsonError)
+}
+
+// A dump's lines, which parsing reads twice.
+pub(crate) trait Lines {
+    // Calls `f` with each line and the offset it starts at.
+    fn each<F>(&mut self, f: F) -> Result<(), ParseError>
+    where
+        F: FnMut(u64, &[u8]) -> Result<(), ParseError>;
+}
+
+// Lines of a reader, read from wherever it was when parsing started.
+struct ReaderLines<'r, R> {
+    reader: &'r mut R,
+    start: u64,
+    buffer: Vec<u8>,
+}
+
+impl<'r, R: BufRead + Seek> ReaderLines<'r, R> {
+    fn new(reader: &'r mut R) -> Result<Self, ParseError> {
+        let start = reader.stream_position()?;
+        Ok(ReaderLines {
+            reader,
+            start,
+            buffer: vec![],
+        })
+    }
+}
+
+impl<R: BufRead + Seek> Lines for ReaderLines<'_, R> {
+    fn each<F>(&mut self, mut f: F) -> Result<(), ParseError>
+    where
+        F: FnMut(u64, &[u8]) -> Result<(), ParseError>,
+    {
+        self.reader.seek(SeekFrom::Start(self.start))?;
+        let mut offset = self.start;
+        loop {
+            self.buffer.clear();
+            let bytes_read = self.reader.read_until(0x0A, &mut self.buffer)?;
+            if bytes_read == 0 {
+                return Ok(());
+            }
+            f(offset, &self.buffer)?;
+            offset += bytes_read as u64;
+        }
+    }
+}
+
+// Lines of a dump in memory, e.g. a memory-mapped file.
+pub(crate) struct SliceLines<'a>(pub(crate) &'a [u8]);
+
+impl Lines for SliceLines<'_> {
+    fn each<F>(&mut self, mut f: F) -> Result<(), ParseError>
+    where
+        F: FnMut(u64, &[u8]) -> Result<(), ParseError>,
+    {
+        let mut offset = 0;
+        for line in self.0.split_inclusive(|b| *b == 0x0A) {
+            f(offset, line)?;
+            offset += line.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+impl Line<'_> {
     pub fn parse(self, class_name_only: bool) -> Option<ParsedLine> {
         let mut object = Object {
             address: self
                 .address
-                .as_ref()
-                .and_then(|a| parse_address(a.as_str()).ok())
+                .and_then(|a| parse_address(a).ok())
                 .unwrap_or(0),
             bytes: self.memsize.unwrap_or(0),
-            kind: self.object_type,
+            kind: self.object_type.as_ref().into(),
+            class: self.object_type.as_ref().into(),
+            internal_type: self.object_type.as_ref().into(),
             label: None,
+            file: self.file.as_deref().map(Symbol::from),
         };
 
         if object.address == 0 && object.kind != "ROOT" {
             return None;
         }
 
-        if !class_name_only {
-            object.label = match object.kind.as_str() {
+        let label = if !class_name_only {
+            match object.kind.as_str() {
                 "CLASS" | "MODULE" | "ICLASS" => self
                     .name
                     .clone()
@@ -104,7 +432,7 @@ impl Line {
                 _ => None,
             }
         } else {
-            object.label = match object.kind.as_str() {
+            match object.kind.as_str() {
                 "CLASS" | "MODULE" | "ICLASS" => {
                     self.name.clone().map(|n| format!("{}[{}]", n, object.kind))
                 }
@@ -113,111 +441,851 @@ impl Line {
                 "STRING" => Some(String::from("String")),
                 _ => None,
             }
-        }
+        };
+        object.label = label.map(String::into_boxed_str);
         Some(ParsedLine {
-            references: self
-                .references
-                .iter()
-                .flat_map(|r| parse_address(r.as_str()).ok())
-                .collect(),
-            module: self.class.and_then(|c| parse_address(c.as_str()).ok()),
-            name: self.name,
+            module: self.class.and_then(|c| parse_address(c).ok()),
+            name: self.name.map(Cow::into_owned),
             object,
+            value_hash: None,
         })
     }
+
+    // A hash of a string's whole value, which its label only shows the
+    // start of.
+    fn value_hash(&self) -> Option<u64> {
+        if self.object_type != "STRING" {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        self.value.as_deref()?.hash(&mut hasher);
+        Some(hasher.finish())
+    }
 }
 
 pub fn parse_address(addr: &str) -> Result<usize, std::num::ParseIntError> {
-    usize::from_str_radix(&addr[2..], 16)
+    usize::from_str_radix(addr.get(2..).unwrap_or_default(), 16)
 }
 
-#[timed]
-pub fn parse<R: BufRead>(
+// Which of several objects with the same address to keep, as dumps
+// concatenated from partial ones can have, or whether to fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnDuplicate {
+    First,
+    Last,
+    Error,
+}
+
+impl std::str::FromStr for OnDuplicate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(OnDuplicate::First),
+            "last" => Ok(OnDuplicate::Last),
+            "error" => Ok(OnDuplicate::Error),
+            _ => Err(format!("Unknown duplicate policy {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for OnDuplicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OnDuplicate::First => "first",
+            OnDuplicate::Last => "last",
+            OnDuplicate::Error => "error",
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ParseOptions {
+    // Label objects with only their class names.
+    pub class_name_only: bool,
+
+    // Drop outgoing references from instances of these classes, in addition
+    // to the built-in weak reference classes.
+    pub cut_edges_from: Vec<ClassPattern>,
+
+    // Progress reporting & cancellation for embedding applications.
+    pub hooks: Hooks,
+
+    // Leave labels out, recording where to find them in a LabelIndex instead.
+    pub lazy_labels: bool,
+
+    // Record a hash of each string's whole value in the LabelIndex, to tell
+    // strings apart which labels show the same.
+    pub string_hashes: bool,
+
+    // Skip malformed lines, with a warning of how many, rather than failing.
+    pub lenient: bool,
+
+    // What to do with objects with the same address as another; Ruby dumps
+    // keep the last by default, and edge lists fail.
+    pub on_duplicate: Option<OnDuplicate>,
+}
+
+impl ParseOptions {
+    // Records `value` for an object's address, keeping the value for the
+    // object which will be kept of several with that address.
+    pub(crate) fn keep<V>(&self, map: &mut FxHashMap<usize, V>, address: usize, value: V) {
+        if self.on_duplicate == Some(OnDuplicate::First) {
+            map.entry(address).or_insert(value);
+        } else {
+            map.insert(address, value);
+        }
+    }
+}
+
+// Reports objects dropped for having the same address as another, or fails.
+pub(crate) fn check_duplicates(
+    policy: OnDuplicate,
+    duplicates: usize,
+    first: usize,
+) -> Result<(), ParseError> {
+    match policy {
+        _ if duplicates == 0 => Ok(()),
+        OnDuplicate::Error => Err(ParseError::InvalidLine(format!(
+            "Duplicate address {:#x}",
+            first
+        ))),
+        _ => {
+            warn!(
+                "{} object(s) had the same address as another; kept the {} of each",
+                duplicates, policy
+            );
+            Ok(())
+        }
+    }
+}
+
+// Where the line describing each labelled object starts, so that labels &
+// string values can be read when needed rather than all kept in memory.
+#[derive(Debug, Default)]
+pub struct LabelIndex {
+    offsets: FxHashMap<usize, u64>,
+    class_name_only: bool,
+    value_hashes: FxHashMap<usize, u64>,
+}
+
+impl LabelIndex {
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    // A hash of a string's whole value, if parsed with `string_hashes`.
+    pub fn value_hash(&self, address: usize) -> Option<u64> {
+        self.value_hashes.get(&address).copied()
+    }
+
+    // Reads the line describing an object into `line`, if it's indexed.
+    fn read_line<R: BufRead + Seek>(
+        &self,
+        reader: &mut R,
+        address: usize,
+        line: &mut Vec<u8>,
+    ) -> Result<bool, ParseError> {
+        let offset = match self.offsets.get(&address) {
+            Some(&offset) => offset,
+            None => return Ok(false),
+        };
+        line.clear();
+        reader
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| reader.read_until(0x0A, line))
+            .map_err(|e| ParseError::InvalidLine(format!("{} at offset {}", e, offset)))?;
+        Ok(true)
+    }
+
+    // Labels of the given objects which have one, reading their lines from
+    // the dump they were parsed from in file order.
+    pub fn labels<R: BufRead + Seek>(
+        &self,
+        reader: &mut R,
+        addresses: impl IntoIterator<Item = usize>,
+    ) -> Result<HashMap<usize, Box<str>>, ParseError> {
+        let mut addresses: Vec<usize> = addresses
+            .into_iter()
+            .filter(|a| self.offsets.contains_key(a))
+            .collect();
+        addresses.sort_unstable_by_key(|a| self.offsets[a]);
+        addresses.dedup();
+
+        let mut labels = HashMap::with_capacity(addresses.len());
+        let (mut line, mut scratch) = (vec![], vec![]);
+        for address in addresses {
+            if !self.read_line(reader, address, &mut line)? {
+                continue;
+            }
+            let label = decode::<Line>(&line, &mut scratch)?
+                .parse(self.class_name_only)
+                .and_then(|parsed| parsed.object.label);
+            if let Some(label) = label {
+                labels.insert(address, label);
+            }
+        }
+        Ok(labels)
+    }
+
+    // The full value of a string, which labels truncate.
+    pub fn value<R: BufRead + Seek>(
+        &self,
+        reader: &mut R,
+        address: usize,
+    ) -> Result<Option<String>, ParseError> {
+        let (mut line, mut scratch) = (vec![], vec![]);
+        if !self.read_line(reader, address, &mut line)? {
+            return Ok(None);
+        }
+        let line: Line = decode(&line, &mut scratch)?;
+        Ok(line.value.map(Cow::into_owned))
+    }
+}
+
+impl From<Cancelled> for ParseError {
+    fn from(_: Cancelled) -> Self {
+        ParseError::Cancelled
+    }
+}
+
+pub fn parse<R: BufRead + Seek>(
     reader: &mut R,
     class_name_only: bool,
 ) -> Result<(NodeIndex<usize>, ReferenceGraph), ParseError> {
-    let mut graph: ReferenceGraph = Graph::default();
-    let mut indices: HashMap<usize, NodeIndex<usize>> = HashMap::new();
-    let mut references: HashMap<usize, Vec<usize>> = HashMap::new();
-    let mut instances: HashMap<usize, usize> = HashMap::new();
-    let mut names: HashMap<usize, String> = HashMap::new();
+    parse_with_options(
+        reader,
+        &ParseOptions {
+            class_name_only,
+            ..Default::default()
+        },
+    )
+}
+
+pub fn parse_with_options<R: BufRead + Seek>(
+    reader: &mut R,
+    options: &ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph), ParseError> {
+    let (root, graph, _) = parse_with_labels(reader, options)?;
+    Ok((root, graph))
+}
+
+// Parses the dump, also returning the index of labels left out if
+// `options.lazy_labels` is set.
+pub fn parse_with_labels<R: BufRead + Seek>(
+    reader: &mut R,
+    options: &ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph, LabelIndex), ParseError> {
+    let (root, graph, label_index, _) = parse_lines(&mut ReaderLines::new(reader)?, options)?;
+    Ok((root, graph, label_index))
+}
+
+// Parses a dump already in memory, without copying its lines.
+pub fn parse_bytes(
+    bytes: &[u8],
+    options: &ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph, LabelIndex), ParseError> {
+    let (root, graph, label_index, _) = parse_slice(bytes, options)?;
+    Ok((root, graph, label_index))
+}
+
+fn parse_slice(bytes: &[u8], options: &ParseOptions) -> Result<Parsed, ParseError> {
+    #[cfg(feature = "parallel")]
+    return parse_chunks(bytes, options);
+    #[cfg(not(feature = "parallel"))]
+    return parse_lines(&mut SliceLines(bytes), options);
+}
+
+// Parses a dump from a reader which can't seek, e.g. a pipe, by copying it to
+// a temporary file first, since parsing reads it twice, rather than holding
+// all of it in memory. Labels are kept whatever `options.lazy_labels` says,
+// as the stream couldn't be read again for them later.
+pub fn parse_stream<R: Read>(
+    reader: &mut R,
+    options: &ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph, LabelIndex), ParseError> {
+    static STREAMS: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "reap-stream-{}-{}.json",
+        std::process::id(),
+        STREAMS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let options = ParseOptions {
+        lazy_labels: false,
+        ..options.clone()
+    };
+    let result = (|| {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        std::io::copy(reader, &mut file)?;
+        file.seek(SeekFrom::Start(0))?;
+        parse_copy(file, &options)
+    })();
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+#[cfg(feature = "mmap")]
+fn parse_copy(
+    file: std::fs::File,
+    options: &ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph, LabelIndex), ParseError> {
+    if file.metadata()?.len() == 0 {
+        return parse_bytes(&[], options);
+    }
+    // Safety: the copy is private to this parse
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    parse_bytes(&map, options)
+}
+
+#[cfg(not(feature = "mmap"))]
+fn parse_copy(
+    file: std::fs::File,
+    options: &ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph, LabelIndex), ParseError> {
+    parse_with_labels(&mut std::io::BufReader::new(file), options)
+}
+
+// Parses a dump file by mapping it into memory, which saves copying each line
+// out of the page cache. Pipes and other files which aren't regular files
+// can't be mapped, so are streamed instead.
+#[cfg(feature = "mmap")]
+pub fn parse_file(
+    path: &Path,
+    options: &ParseOptions,
+) -> Result<(NodeIndex<usize>, ReferenceGraph, LabelIndex), ParseError> {
+    let mut file = std::fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return parse_stream(&mut file, options);
+    }
+    if metadata.len() == 0 {
+        // Empty files can't be mapped
+        return parse_bytes(&[], options);
+    }
+    // Safety: the dump mustn't be modified while it's being parsed, as with
+    // any other reader of it
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    parse_bytes(&map, options)
+}
+
+type Edge = (NodeIndex<usize>, NodeIndex<usize>);
 
-    let root = Object::root();
-    let root_address = root.address;
-    let root_index = graph.add_node(root);
-    indices.insert(root_address, root_index);
-    references.insert(root_address, Vec::new());
+type Parsed = (NodeIndex<usize>, ReferenceGraph, LabelIndex, Integrity);
 
-    let mut line_buffer = vec![];
+// Shards of the map from addresses to nodes, built in parallel if possible
+const SHARDS: usize = 64;
 
-    while let Ok(bytes_read) = reader.read_until(0x0A, &mut line_buffer) {
-        if bytes_read == 0 {
-            break;
+// Where each object is in the graph, split by address into shards that can
+// be built independently.
+struct Indices {
+    shards: Vec<FxHashMap<usize, NodeIndex<usize>>>,
+}
+
+impl Indices {
+    fn shard(address: usize) -> usize {
+        // Fibonacci hashing, as addresses are aligned
+        ((address as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 58) as usize % SHARDS
+    }
+
+    // The shard of `nodes`, and those of them with the same address as
+    // another which it leaves out under `policy`.
+    fn build_shard(
+        graph: &ReferenceGraph,
+        nodes: Vec<NodeIndex<usize>>,
+        policy: OnDuplicate,
+    ) -> (FxHashMap<usize, NodeIndex<usize>>, Vec<NodeIndex<usize>>) {
+        use std::collections::hash_map::Entry;
+
+        let mut shard = FxHashMap::with_capacity_and_hasher(nodes.len(), Default::default());
+        let mut duplicates = vec![];
+        for i in nodes {
+            match shard.entry(graph[i].address) {
+                Entry::Vacant(entry) => {
+                    entry.insert(i);
+                }
+                Entry::Occupied(_) if policy == OnDuplicate::First => duplicates.push(i),
+                Entry::Occupied(mut entry) => duplicates.push(entry.insert(i)),
+            }
         }
+        (shard, duplicates)
+    }
 
-        let line = String::from_utf8_lossy(&line_buffer).to_string();
+    // The index, and the nodes left out of it for duplicating another's
+    // address, in order.
+    fn build(graph: &ReferenceGraph, policy: OnDuplicate) -> (Indices, Vec<NodeIndex<usize>>) {
+        let mut nodes = vec![vec![]; SHARDS];
+        for i in graph.node_indices() {
+            nodes[Indices::shard(graph[i].address)].push(i);
+        }
 
-        let parsed: Result<ParsedLine, ParseError> = serde_json::from_str::<Line>(&line)
-            .map_err(ParseError::JsonError)?
-            .parse(class_name_only)
-            .ok_or_else(|| ParseError::InvalidLine(line.clone()));
+        #[cfg(feature = "parallel")]
+        let shards = {
+            use rayon::prelude::*;
+            nodes
+                .into_par_iter()
+                .map(|nodes| Indices::build_shard(graph, nodes, policy))
+                .collect::<Vec<_>>()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let shards = nodes
+            .into_iter()
+            .map(|nodes| Indices::build_shard(graph, nodes, policy))
+            .collect::<Vec<_>>();
 
-        match parsed {
-            Ok(parsed) => {
-                if parsed.object.is_root() {
-                    let refs = references.get_mut(&root_address).ok_or_else(|| {
-                        ParseError::InvalidLine(format!(
-                            "Root address {} not found in references",
-                            root_address
-                        ))
-                    })?;
-                    refs.extend_from_slice(parsed.references.as_slice());
+        let (shards, duplicates): (Vec<_>, Vec<_>) = shards.into_iter().unzip();
+        let mut duplicates: Vec<NodeIndex<usize>> = duplicates.into_iter().flatten().collect();
+        duplicates.sort_unstable();
+        (Indices { shards }, duplicates)
+    }
+
+    fn get(&self, address: usize) -> Option<NodeIndex<usize>> {
+        self.shards[Indices::shard(address)].get(&address).copied()
+    }
+}
+
+// The graph as parsing builds it. The dump is read twice, so that references
+// never need to be held for the whole dump: the first pass adds every object
+// to the graph, and the second adds the references between them.
+pub(crate) struct Builder<'o> {
+    options: &'o ParseOptions,
+    root: NodeIndex<usize>,
+    graph: ReferenceGraph,
+    indices: Indices,
+    instances: FxHashMap<usize, usize>,
+    names: FxHashMap<usize, Symbol>,
+    label_index: LabelIndex,
+    cut: usize,
+    skipped: Skipped,
+    integrity: Integrity,
+
+    // Where each object's line starts, by node index less the root's, until
+    // duplicates are resolved
+    offsets: Vec<u64>,
+
+    // Lines of objects dropped as duplicates, which the second pass skips
+    duplicates: FxHashSet<u64>,
+}
+
+impl<'o> Builder<'o> {
+    fn new(options: &'o ParseOptions) -> Self {
+        let mut graph: ReferenceGraph = Graph::default();
+        let root = graph.add_node(Object::root());
+        Builder {
+            options,
+            root,
+            graph,
+            indices: Indices { shards: vec![] },
+            instances: FxHashMap::default(),
+            names: FxHashMap::default(),
+            label_index: LabelIndex {
+                offsets: FxHashMap::default(),
+                class_name_only: options.class_name_only,
+                value_hashes: FxHashMap::default(),
+            },
+            cut: 0,
+            skipped: Skipped::default(),
+            integrity: Integrity::default(),
+            offsets: vec![],
+            duplicates: FxHashSet::default(),
+        }
+    }
+
+    // First pass: the object a line describes, unless it's a root.
+    pub(crate) fn decode_object(
+        options: &ParseOptions,
+        line: &[u8],
+        scratch: &mut Vec<u8>,
+    ) -> Result<Option<ParsedLine>, ParseError> {
+        let parsed = decode::<Line>(line, scratch)
+            .and_then(|decoded| {
+                let value_hash = decoded.value_hash().filter(|_| options.string_hashes);
+                let mut parsed = decoded.parse(options.class_name_only).ok_or_else(|| {
+                    ParseError::InvalidLine(
+                        "No address, or an ARRAY without a length or HASH without a size"
+                            .to_string(),
+                    )
+                })?;
+                parsed.value_hash = value_hash;
+                Ok(parsed)
+            })
+            .map_err(|err| {
+                // Only the last line can lack a newline
+                if line.ends_with(b"\n") {
+                    err
                 } else {
-                    let address = parsed.object.address;
-                    indices.insert(address, graph.add_node(parsed.object));
+                    ParseError::Truncated(Box::new(err))
+                }
+            })?;
+        Ok(Some(parsed).filter(|parsed| !parsed.object.is_root()))
+    }
 
-                    if !parsed.references.is_empty() {
-                        references.insert(address, parsed.references);
-                    }
-                    if let Some(module) = parsed.module {
-                        instances.insert(address, module);
-                    }
-                    if let Some(name) = parsed.name {
-                        names.insert(address, name);
-                    }
+    // Tallies a line decoded by the first pass.
+    fn count_object(&mut self, parsed: &Option<ParsedLine>) {
+        match parsed {
+            Some(_) => self.integrity.objects += 1,
+            None => self.integrity.roots += 1,
+        }
+    }
+
+    fn add_object(&mut self, offset: u64, parsed: ParsedLine) {
+        let mut object = parsed.object;
+        let address = object.address;
+        if self.options.lazy_labels && object.label.take().is_some() {
+            self.options
+                .keep(&mut self.label_index.offsets, address, offset);
+        }
+        self.graph.add_node(object);
+        self.offsets.push(offset);
+
+        if let Some(module) = parsed.module {
+            self.options.keep(&mut self.instances, address, module);
+        }
+        if let Some(name) = parsed.name {
+            self.options.keep(&mut self.names, address, name.into());
+        }
+        if let Some(hash) = parsed.value_hash {
+            self.options
+                .keep(&mut self.label_index.value_hashes, address, hash);
+        }
+    }
+
+    // Drops objects with the same address as another, names objects after
+    // their classes, and indexes them by address, once every object has
+    // been read.
+    fn finish_objects(&mut self) -> Result<(), ParseError> {
+        let policy = self.options.on_duplicate.unwrap_or(OnDuplicate::Last);
+        let (indices, duplicates) = Indices::build(&self.graph, policy);
+        let first = duplicates.first().map_or(0, |i| self.graph[*i].address);
+        check_duplicates(policy, duplicates.len(), first)?;
+        self.indices = indices;
+        if !duplicates.is_empty() {
+            self.duplicates = duplicates
+                .iter()
+                .map(|i| self.offsets[i.index() - 1])
+                .collect();
+
+            // There are no edges yet, so the rest need only be renumbered
+            let mut keep = vec![true; self.graph.node_count()];
+            for i in duplicates {
+                keep[i.index()] = false;
+            }
+            let (nodes, _) = std::mem::take(&mut self.graph).into_nodes_edges();
+            for (node, keep) in nodes.into_iter().zip(keep) {
+                if keep {
+                    self.graph.add_node(node.weight);
+                }
+            }
+            self.indices = Indices::build(&self.graph, policy).0;
+        }
+        self.offsets = vec![];
+
+        for obj in self.graph.node_weights_mut() {
+            if let Some(module) = self.instances.get(&obj.address) {
+                if let Some(name) = self.names.get(module) {
+                    obj.kind = *name;
+                    obj.class = *name;
                 }
             }
-            Err(e) => {
-                return Err(e);
+        }
+        self.instances = FxHashMap::default();
+        self.names = FxHashMap::default();
+        Ok(())
+    }
+
+    // Whether the second pass skips the line at `offset`.
+    fn skips(&self, offset: u64) -> bool {
+        self.skipped.contains(offset) || self.duplicates.contains(&offset)
+    }
+
+    // Second pass: adds the edges for a line's references to `edges`, and
+    // tallies them in `integrity`, returning how many were cut instead.
+    fn decode_references(
+        &self,
+        line: &[u8],
+        scratch: &mut Vec<u8>,
+        edges: &mut Vec<Edge>,
+        integrity: &mut Integrity,
+    ) -> Result<usize, ParseError> {
+        let parsed: References = decode(line, scratch)?;
+        if parsed.references.is_empty() {
+            return Ok(0);
+        }
+        // Only ROOT lines lack an address, as the first pass checked
+        let address = match parsed.address {
+            Some(address) => parse_address(address).unwrap_or(0),
+            None => 0,
+        };
+        let i = self
+            .indices
+            .get(address)
+            .expect("the first pass added every object");
+        let kind = self.graph[i].kind.as_str();
+        if WEAK_REFERENCE_CLASSES.contains(&kind)
+            || self.options.cut_edges_from.iter().any(|p| p.matches(kind))
+        {
+            return Ok(parsed.references.len());
+        }
+        let mut dangling = 0;
+        for &s in &parsed.references {
+            match parse_address(s).ok().and_then(|s| self.indices.get(s)) {
+                Some(j) => edges.push((i, j)),
+                None => dangling += 1,
+            }
+        }
+        integrity.add_references(parsed.references.len(), dangling);
+        Ok(0)
+    }
+
+    fn add_edges(&mut self, edges: &mut Vec<Edge>) {
+        self.graph.reserve_edges(edges.len());
+        for (i, j) in edges.drain(..) {
+            self.graph.add_edge(i, j, EDGE_WEIGHT);
+        }
+    }
+
+    // Takes the objects nothing references as roots, as dumps from older
+    // tooling lack ROOT lines, returning how many there are.
+    fn synthesize_roots(&mut self) -> usize {
+        let mut referenced = vec![false; self.graph.node_count()];
+        referenced[self.root.index()] = true;
+        for edge in self.graph.raw_edges() {
+            referenced[edge.target().index()] = true;
+        }
+        let roots: Vec<NodeIndex<usize>> = self
+            .graph
+            .node_indices()
+            .filter(|i| !referenced[i.index()])
+            .collect();
+        for &i in &roots {
+            self.graph.add_edge(self.root, i, EDGE_WEIGHT);
+        }
+        roots.len()
+    }
+
+    fn finish(mut self) -> Parsed {
+        if self.integrity.roots == 0 {
+            self.integrity.synthesized_roots = self.synthesize_roots();
+        }
+        timing::nodes(self.graph.node_count());
+        self.skipped.report();
+        let integrity = Integrity {
+            incomplete_last_line: self.skipped.truncated(),
+            ..self.integrity
+        };
+        integrity.report();
+        debug!(
+            "Parsed {} objects & {} references ({} cut)",
+            self.graph.node_count(),
+            self.graph.edge_count(),
+            self.cut
+        );
+        (self.root, self.graph, self.label_index, integrity)
+    }
+}
+
+#[timed]
+fn parse_lines<L: Lines>(lines: &mut L, options: &ParseOptions) -> Result<Parsed, ParseError> {
+    let mut builder = Builder::new(options);
+    let mut scratch = vec![];
+    let mut count = 0;
+    let mut bytes = 0;
+
+    lines.each(|offset, line| {
+        count += 1;
+        bytes += line.len() as u64;
+        if count % CHECK_INTERVAL == 0 {
+            options.hooks.check()?;
+            options.hooks.report(Progress::Parsing {
+                lines: count,
+                bytes,
+            });
+        }
+        match Builder::decode_object(options, line, &mut scratch) {
+            Ok(parsed) => {
+                builder.count_object(&parsed);
+                if let Some(parsed) = parsed {
+                    builder.add_object(offset, parsed);
+                }
             }
+            Err(err) => builder
+                .skipped
+                .skip(options, offset, err)
+                .map_err(|err| err.at(count, offset, line))?,
+        }
+        Ok(())
+    })?;
+    builder.finish_objects()?;
+
+    count = 0;
+    let mut edges = vec![];
+    let mut integrity = Integrity::default();
+    lines.each(|offset, line| {
+        count += 1;
+        if count % CHECK_INTERVAL == 0 {
+            options.hooks.check()?;
+        }
+        if builder.skips(offset) {
+            return Ok(());
+        }
+        match builder.decode_references(line, &mut scratch, &mut edges, &mut integrity) {
+            Ok(cut) => builder.cut += cut,
+            Err(err) => builder
+                .skipped
+                .skip(options, offset, err)
+                .map_err(|err| err.at(count, offset, line))?,
         }
+        builder.add_edges(&mut edges);
+        Ok(())
+    })?;
+    builder.integrity.merge(integrity);
+    Ok(builder.finish())
+}
+
+// Roughly how much of the dump each thread parses at a time
+#[cfg(feature = "parallel")]
+const CHUNK_SIZE: usize = 1 << 20;
+
+// Chunks parsed before their results are added to the graph, which bounds
+// how many parsed lines are held at once
+#[cfg(feature = "parallel")]
+const CHUNKS_PER_BATCH: usize = 64;
 
-        line_buffer.clear();
+// Splits the dump into chunks of whole lines, with the offsets they start at.
+#[cfg(feature = "parallel")]
+fn chunks(bytes: &[u8]) -> Vec<(u64, &[u8])> {
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < bytes.len() {
+        let end = match bytes[(start + CHUNK_SIZE).min(bytes.len())..]
+            .iter()
+            .position(|b| *b == 0x0A)
+        {
+            Some(newline) => (start + CHUNK_SIZE).min(bytes.len()) + newline + 1,
+            None => bytes.len(),
+        };
+        chunks.push((start as u64, &bytes[start..end]));
+        start = end;
     }
+    chunks
+}
+
+// Locates an error with the line of the dump starting at `offset`, counting
+// the lines before it, which is only worth it for the error that stops parsing.
+#[cfg(feature = "parallel")]
+fn at_offset(err: ParseError, bytes: &[u8], offset: u64) -> ParseError {
+    let (before, rest) = bytes.split_at(offset as usize);
+    let line = before.iter().filter(|b| **b == 0x0A).count() + 1;
+    let end = rest.iter().position(|b| *b == 0x0A).unwrap_or(rest.len());
+    err.at(line, offset, &rest[..end])
+}
+
+// Like parse_lines, but decoding chunks of lines on all cores, then adding
+// what they describe to the graph in order.
+#[cfg(feature = "parallel")]
+#[timed]
+fn parse_chunks(bytes: &[u8], options: &ParseOptions) -> Result<Parsed, ParseError> {
+    use rayon::prelude::*;
+
+    let chunks = chunks(bytes);
+    let mut builder = Builder::new(options);
+    let mut count = 0;
 
-    for (node, successors) in references {
-        let i = &indices[&node];
-        for s in successors {
-            if let Some(j) = indices.get(&s) {
-                graph.add_edge(*i, *j, EDGE_WEIGHT);
+    for batch in chunks.chunks(CHUNKS_PER_BATCH) {
+        options.hooks.check()?;
+        let decoded = batch
+            .par_iter()
+            .map(|(start, chunk)| {
+                let mut scratch = vec![];
+                let mut objects = vec![];
+                SliceLines(chunk).each(|offset, line| {
+                    objects.push((
+                        start + offset,
+                        Builder::decode_object(options, line, &mut scratch),
+                    ));
+                    Ok(())
+                })?;
+                Ok(objects)
+            })
+            .collect::<Result<Vec<_>, ParseError>>()?;
+        for (offset, parsed) in decoded.into_iter().flatten() {
+            count += 1;
+            match parsed {
+                Ok(parsed) => {
+                    builder.count_object(&parsed);
+                    if let Some(parsed) = parsed {
+                        builder.add_object(offset, parsed);
+                    }
+                }
+                Err(err) => builder
+                    .skipped
+                    .skip(options, offset, err)
+                    .map_err(|err| at_offset(err, bytes, offset))?,
             }
         }
+        let (start, chunk) = batch[batch.len() - 1];
+        options.hooks.report(Progress::Parsing {
+            lines: count,
+            bytes: start + chunk.len() as u64,
+        });
     }
+    builder.finish_objects()?;
 
-    for obj in graph.node_weights_mut() {
-        if let Some(module) = instances.get(&obj.address) {
-            if let Some(name) = names.get(module) {
-                name.clone_into(&mut obj.kind);
+    for batch in chunks.chunks(CHUNKS_PER_BATCH) {
+        options.hooks.check()?;
+        let decoded = batch
+            .par_iter()
+            .map(|(start, chunk)| {
+                let mut scratch = vec![];
+                let mut edges = vec![];
+                let mut cut = 0;
+                let mut integrity = Integrity::default();
+                let mut errors = vec![];
+                SliceLines(chunk).each(|offset, line| {
+                    let offset = start + offset;
+                    if !builder.skips(offset) {
+                        match builder.decode_references(
+                            line,
+                            &mut scratch,
+                            &mut edges,
+                            &mut integrity,
+                        ) {
+                            Ok(n) => cut += n,
+                            Err(err) => errors.push((offset, err)),
+                        }
+                    }
+                    Ok(())
+                })?;
+                Ok((edges, cut, integrity, errors))
+            })
+            .collect::<Result<Vec<_>, ParseError>>()?;
+        for (mut edges, cut, integrity, errors) in decoded {
+            builder.add_edges(&mut edges);
+            builder.cut += cut;
+            builder.integrity.merge(integrity);
+            for (offset, err) in errors {
+                builder
+                    .skipped
+                    .skip(options, offset, err)
+                    .map_err(|err| at_offset(err, bytes, offset))?;
             }
         }
     }
 
-    Ok((root_index, graph))
+    Ok(builder.finish())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::analyze;
     use rstest::rstest;
     use std::fs::File;
     use std::io::{BufReader, Cursor};
@@ -272,4 +1340,411 @@ mod test {
         let res = parse(&mut input.input_buffer, input.class_name_only);
         assert!(res.is_ok());
     }
+
+    // Reading the dump from memory gives the same graph as reading it in lines
+    #[test]
+    fn test_parse_bytes() {
+        let bytes = std::fs::read("test/heap.json").unwrap();
+        let (_, expected) = parse(&mut Cursor::new(&bytes), false).unwrap();
+        let options = ParseOptions::default();
+        let check = |(_, graph, _): (NodeIndex<usize>, ReferenceGraph, LabelIndex)| {
+            assert_eq!(expected.node_count(), graph.node_count());
+            assert_eq!(expected.edge_count(), graph.edge_count());
+        };
+
+        check(parse_bytes(&bytes, &options).unwrap());
+        #[cfg(feature = "mmap")]
+        check(parse_file(Path::new("test/heap.json"), &options).unwrap());
+    }
+
+    // Pipes can't be mapped or read twice, but parse all the same
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_pipe() {
+        let (_, expected) = parse(
+            &mut BufReader::new(File::open("test/heap.json").unwrap()),
+            false,
+        )
+        .unwrap();
+        let check = |(_, graph, _): (NodeIndex<usize>, ReferenceGraph, LabelIndex)| {
+            assert_eq!(expected.node_count(), graph.node_count());
+            assert_eq!(expected.edge_count(), graph.edge_count());
+        };
+
+        let mut cat = std::process::Command::new("cat")
+            .arg("test/heap.json")
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        check(parse_stream(cat.stdout.as_mut().unwrap(), &ParseOptions::default()).unwrap());
+        cat.wait().unwrap();
+        // The copy it was parsed from is gone
+        let prefix = format!("reap-stream-{}-", std::process::id());
+        assert!(!std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .any(|entry| entry
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with(&prefix)));
+
+        #[cfg(feature = "mmap")]
+        {
+            let fifo = std::env::temp_dir().join(format!("reap-fifo-{}", std::process::id()));
+            let status = std::process::Command::new("mkfifo")
+                .arg(&fifo)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            let writer = {
+                let fifo = fifo.clone();
+                std::thread::spawn(move || std::fs::copy("test/heap.json", fifo).unwrap())
+            };
+            let parsed = parse_file(&fifo, &ParseOptions::default());
+            writer.join().unwrap();
+            std::fs::remove_file(&fifo).unwrap();
+            check(parsed.unwrap());
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_chunks() {
+        let bytes = std::fs::read("test/heap.json").unwrap();
+        let chunks = chunks(&bytes);
+        assert!(chunks.len() > 1);
+
+        let mut offset = 0;
+        for (start, chunk) in chunks {
+            assert_eq!(offset, start);
+            assert_eq!(Some(&0x0A), chunk.last());
+            offset += chunk.len() as u64;
+        }
+        assert_eq!(bytes.len() as u64, offset);
+    }
+
+    #[test]
+    fn test_indices() {
+        let mut graph: ReferenceGraph = Graph::default();
+        let objects: Vec<NodeIndex<usize>> = [8, 16, 8]
+            .iter()
+            .map(|&address| {
+                graph.add_node(Object {
+                    address,
+                    bytes: 0,
+                    kind: "Object".into(),
+                    class: "Object".into(),
+                    internal_type: "Object".into(),
+                    label: None,
+                    file: None,
+                })
+            })
+            .collect();
+
+        let (indices, duplicates) = Indices::build(&graph, OnDuplicate::Last);
+        assert_eq!(Some(objects[2]), indices.get(8));
+        assert_eq!(Some(objects[1]), indices.get(16));
+        assert_eq!(None, indices.get(24));
+        assert_eq!(vec![objects[0]], duplicates);
+
+        let (indices, duplicates) = Indices::build(&graph, OnDuplicate::First);
+        assert_eq!(Some(objects[0]), indices.get(8));
+        assert_eq!(vec![objects[2]], duplicates);
+    }
+
+    #[rstest]
+    #[case::first(OnDuplicate::First, 10, 0x2)]
+    #[case::last(OnDuplicate::Last, 30, 0x3)]
+    fn test_on_duplicate(#[case] policy: OnDuplicate, #[case] bytes: usize, #[case] child: usize) {
+        let dump = r#"{"type":"ROOT", "root":"vm", "references":["0x1"]}
+{"address":"0x1", "type":"OBJECT", "references":["0x2"], "memsize":10}
+{"address":"0x2", "type":"OBJECT", "references":[], "memsize":20}
+{"address":"0x1", "type":"OBJECT", "references":["0x3"], "memsize":30}
+{"address":"0x3", "type":"OBJECT", "references":[], "memsize":40}
+"#;
+        let options = ParseOptions {
+            on_duplicate: Some(policy),
+            ..Default::default()
+        };
+        let check = |(root, graph, _): (NodeIndex<usize>, ReferenceGraph, LabelIndex)| {
+            assert_eq!(4, graph.node_count());
+            let kept = graph.neighbors(root).next().unwrap();
+            assert_eq!((0x1, bytes), (graph[kept].address, graph[kept].bytes));
+            // Only the kept object's references are its own
+            let children: Vec<usize> = graph.neighbors(kept).map(|i| graph[i].address).collect();
+            assert_eq!(vec![child], children);
+        };
+        check(parse_with_labels(&mut Cursor::new(dump), &options).unwrap());
+        check(parse_bytes(dump.as_bytes(), &options).unwrap());
+
+        let error = ParseOptions {
+            on_duplicate: Some(OnDuplicate::Error),
+            ..Default::default()
+        };
+        assert!(parse_bytes(dump.as_bytes(), &error).is_err());
+    }
+
+    #[rstest]
+    #[case::truncated(r#"{"address":"0x3", "type":"OBJECT", "refer"#)]
+    #[case::interleaved(r#"{"address":"0x3", "type":"OBJECT"}{"address":"0x4", "type":"OBJECT"}"#)]
+    #[case::no_address(r#"{"type":"OBJECT", "references":["0x2"], "memsize":40}"#)]
+    #[case::bad_references(r#"{"address":"0x3", "type":"OBJECT", "references":[3]}"#)]
+    fn test_lenient(#[case] line: &str) {
+        let dump = format!(
+            r#"{{"type":"ROOT", "root":"vm", "references":["0x1"]}}
+{{"address":"0x1", "type":"OBJECT", "references":["0x2"], "memsize":40}}
+{}
+{{"address":"0x2", "type":"OBJECT", "references":[], "memsize":40}}
+"#,
+            line
+        );
+        assert!(parse(&mut Cursor::new(&dump), false).is_err());
+
+        let options = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let check = |(root, graph, _): (NodeIndex<usize>, ReferenceGraph, LabelIndex)| {
+            let addresses: Vec<usize> = graph.node_weights().map(|o| o.address).collect();
+            assert!(addresses.contains(&0x2));
+            // Nothing the malformed line references is attributed to the root
+            assert_eq!(1, graph.neighbors(root).count());
+        };
+        check(parse_with_labels(&mut Cursor::new(&dump), &options).unwrap());
+        check(parse_bytes(dump.as_bytes(), &options).unwrap());
+    }
+
+    #[rstest]
+    #[case::json(r#"{"address":"0x3", "type":"OBJECT", "references":[3]}"#, true)]
+    #[case::no_address(r#"{"type":"OBJECT", "references":["0x2"], "memsize":40}"#, false)]
+    fn test_line_error(#[case] line: &str, #[case] json: bool) {
+        let dump = format!(
+            r#"{{"type":"ROOT", "root":"vm", "references":["0x1"]}}
+{{"address":"0x1", "type":"OBJECT", "references":["0x2"], "memsize":40}}
+{}
+{{"address":"0x2", "type":"OBJECT", "references":[], "memsize":40}}
+"#,
+            line
+        );
+        let offset = dump.find(line).unwrap() as u64;
+        let check = |err: ParseError| {
+            assert_eq!(json, err.json_error().is_some());
+            assert!(err
+                .to_string()
+                .starts_with(&format!("Line 3 (byte {}): ", offset)));
+            match err {
+                ParseError::Line(err) => {
+                    assert_eq!((3, offset), (err.line, err.offset));
+                    assert_eq!(line, err.context);
+                }
+                err => panic!("{:?}", err),
+            }
+        };
+        check(parse_with_labels(&mut Cursor::new(&dump), &ParseOptions::default()).unwrap_err());
+        check(parse_bytes(dump.as_bytes(), &ParseOptions::default()).unwrap_err());
+    }
+
+    #[test]
+    fn test_integrity() {
+        let bytes = std::fs::read("test/heap.json").unwrap();
+        let (_, _, _, integrity) = parse_slice(&bytes, &ParseOptions::default()).unwrap();
+        assert_eq!((4, 4), (integrity.roots, integrity.dangling));
+        assert!(integrity.warnings().is_empty());
+
+        // Cut short mid-line
+        let truncated = &bytes[..bytes.len() / 2 + 7];
+        assert!(!truncated.ends_with(b"\n"));
+        let err = parse_slice(truncated, &ParseOptions::default()).unwrap_err();
+        match err {
+            ParseError::Line(err) => assert!(matches!(*err.cause, ParseError::Truncated(_))),
+            err => panic!("{:?}", err),
+        }
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let (_, _, _, integrity) = parse_slice(truncated, &lenient).unwrap();
+        assert!(integrity.incomplete_last_line);
+        assert!(integrity.dangling > integrity.references / 100);
+        assert_eq!(2, integrity.warnings().len());
+
+        // Without its ROOT lines
+        let objects: Vec<u8> = bytes
+            .split_inclusive(|b| *b == 0x0A)
+            .filter(|line| !line.starts_with(br#"{"type":"ROOT""#))
+            .flatten()
+            .copied()
+            .collect();
+        let (root, graph, _, integrity) = parse_slice(&objects, &ParseOptions::default()).unwrap();
+        assert_eq!(0, integrity.roots);
+        assert_eq!(1, integrity.warnings().len());
+
+        // Objects nothing references stand in for them, leaving unreachable
+        // only cycles nothing outside them references
+        assert!(integrity.synthesized_roots > 0);
+        assert_eq!(integrity.synthesized_roots, graph.neighbors(root).count());
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        let (_, rest) = analysis.unreachable_stats_by_kind(0);
+        assert!(rest.count < integrity.objects / 100);
+    }
+
+    #[test]
+    fn test_synthesized_roots() {
+        let dump = r#"{"address":"0x1", "type":"OBJECT", "references":["0x2"], "memsize":10}
+{"address":"0x2", "type":"OBJECT", "references":["0x2"], "memsize":20}
+{"address":"0x3", "type":"OBJECT", "references":[], "memsize":30}
+"#;
+        let (root, graph, _, integrity) =
+            parse_slice(dump.as_bytes(), &ParseOptions::default()).unwrap();
+        let mut roots: Vec<usize> = graph.neighbors(root).map(|i| graph[i].address).collect();
+        roots.sort_unstable();
+        assert_eq!(vec![0x1, 0x3], roots);
+        assert_eq!(2, integrity.synthesized_roots);
+    }
+
+    #[rstest]
+    #[case::weak_map("ObjectSpace::WeakMap", false)]
+    #[case::weak_ref("WeakRef", false)]
+    #[case::strong("Hash", true)]
+    fn test_weak_references(#[case] class: &str, #[case] retains: bool) {
+        let mut input = Cursor::new(format!(
+            r#"{{"type":"ROOT", "root":"vm", "references":["0x1"]}}
+{{"address":"0x10", "type":"CLASS", "name":"{}", "references":[], "memsize":100}}
+{{"address":"0x1", "type":"DATA", "class":"0x10", "references":["0x2"], "memsize":40}}
+{{"address":"0x2", "type":"OBJECT", "references":[], "memsize":40}}
+"#,
+            class
+        ));
+        let (root, graph) = parse(&mut input, false).unwrap();
+        let find = |address| {
+            graph
+                .node_indices()
+                .find(|i| graph[*i].address == address)
+                .unwrap()
+        };
+
+        assert_eq!(class, graph[find(0x1)].kind);
+        assert!(graph.find_edge(root, find(0x1)).is_some());
+        assert_eq!(retains, graph.find_edge(find(0x1), find(0x2)).is_some());
+    }
+
+    // A value only a WeakKeyMap references is still live, and retained by it
+    #[test]
+    fn test_weak_key_map_values() {
+        let mut input = Cursor::new(
+            r#"{"type":"ROOT", "root":"vm", "references":["0x1", "0x3"]}
+{"address":"0x10", "type":"CLASS", "name":"ObjectSpace::WeakKeyMap", "references":[], "memsize":100}
+{"address":"0x1", "type":"DATA", "class":"0x10", "references":["0x2", "0x3"], "memsize":40}
+{"address":"0x2", "type":"OBJECT", "references":[], "memsize":50}
+{"address":"0x3", "type":"OBJECT", "references":[], "memsize":60}
+"#,
+        );
+        let (root, graph) = parse(&mut input, false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        let (_, retained) = analysis.retained_stats(0x1).unwrap();
+        assert_eq!((2, 90), (retained.count, retained.bytes));
+        // Only the class, which nothing references, is unreachable
+        let (unreachable, _) = analysis.unreachable_stats_by_kind(10);
+        assert_eq!(1, unreachable.len());
+        assert_eq!((1, 100), (unreachable[0].1.count, unreachable[0].1.bytes));
+    }
+
+    #[rstest]
+    #[case::by_name("Cache", false)]
+    #[case::by_regex("^Ca", false)]
+    #[case::unmatched("Registry", true)]
+    fn test_cut_edges_from(#[case] pattern: &str, #[case] retains: bool) {
+        let mut input = Cursor::new(
+            r#"{"type":"ROOT", "root":"vm", "references":["0x1"]}
+{"address":"0x10", "type":"CLASS", "name":"Cache", "references":[], "memsize":100}
+{"address":"0x1", "type":"OBJECT", "class":"0x10", "references":["0x2"], "memsize":40}
+{"address":"0x2", "type":"OBJECT", "references":[], "memsize":40}
+"#,
+        );
+        let options = ParseOptions {
+            cut_edges_from: vec![pattern.parse().unwrap()],
+            ..Default::default()
+        };
+        let (_, graph) = parse_with_options(&mut input, &options).unwrap();
+
+        assert_eq!(4, graph.node_count());
+        assert_eq!(if retains { 2 } else { 1 }, graph.edge_count());
+    }
+
+    #[rstest]
+    #[case::not_cancelled(false)]
+    #[case::cancelled(true)]
+    fn test_hooks(#[case] cancel: bool) {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::{Arc, Mutex};
+
+        let reports = Arc::new(Mutex::new(vec![]));
+        let r = reports.clone();
+        let options = ParseOptions {
+            hooks: Hooks::default()
+                .with_progress(move |p| r.lock().unwrap().push(p))
+                .with_cancel(Arc::new(AtomicBool::new(cancel))),
+            ..Default::default()
+        };
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let result = parse_with_options(&mut reader, &options);
+
+        if cancel {
+            assert!(matches!(result, Err(ParseError::Cancelled)));
+            assert!(reports.lock().unwrap().is_empty());
+        } else {
+            assert!(result.is_ok());
+            assert!(matches!(
+                reports.lock().unwrap()[..],
+                [Progress::Parsing { lines: 10_000, .. }]
+            ));
+        }
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_lazy_labels(#[case] class_name_only: bool) {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (_, eager) = parse(&mut reader, class_name_only).unwrap();
+
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let options = ParseOptions {
+            class_name_only,
+            lazy_labels: true,
+            ..Default::default()
+        };
+        let (_, lazy, index) = parse_with_labels(&mut reader, &options).unwrap();
+        assert!(lazy
+            .node_weights()
+            .all(|obj| obj.is_root() || obj.label.is_none()));
+        let labelled = eager
+            .node_weights()
+            .filter(|obj| !obj.is_root() && obj.label.is_some());
+        assert_eq!(labelled.count(), index.len());
+
+        let labels = index
+            .labels(&mut reader, lazy.node_weights().map(|obj| obj.address))
+            .unwrap();
+        assert!(eager
+            .node_weights()
+            .filter(|obj| !obj.is_root())
+            .all(|obj| obj.label.as_ref() == labels.get(&obj.address)));
+    }
+
+    #[test]
+    fn test_string_value() {
+        let json = "{\"address\":\"0x1\", \"type\":\"STRING\", \"value\":\"x\\ny\"}\n";
+        let mut input = Cursor::new(json.as_bytes().to_vec());
+        let options = ParseOptions {
+            lazy_labels: true,
+            ..Default::default()
+        };
+        let (_, _, index) = parse_with_labels(&mut input, &options).unwrap();
+        assert_eq!(
+            Some("x\ny".to_string()),
+            index.value(&mut input, 0x1).unwrap()
+        );
+        assert_eq!(None, index.value(&mut input, 0x2).unwrap());
+    }
 }
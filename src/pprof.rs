@@ -0,0 +1,209 @@
+// The dominator tree as a pprof profile (see profile.proto in
+// github.com/google/pprof), in which each object is a sample of its own size
+// whose stack is its chain of dominators, so retained memory shows up as
+// cumulative values in `go tool pprof` and compatible tools.
+use crate::analyze::Analysis;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+// Field numbers from profile.proto
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION: u32 = 4;
+const PROFILE_FUNCTION: u32 = 5;
+const PROFILE_STRING_TABLE: u32 = 6;
+const PROFILE_DEFAULT_SAMPLE_TYPE: u32 = 14;
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+const SAMPLE_LOCATION_ID: u32 = 1;
+const SAMPLE_VALUE: u32 = 2;
+const LOCATION_ID: u32 = 1;
+const LOCATION_LINE: u32 = 4;
+const LINE_FUNCTION_ID: u32 = 1;
+const FUNCTION_ID: u32 = 1;
+const FUNCTION_NAME: u32 = 2;
+
+const VARINT: u32 = 0;
+const LENGTH_DELIMITED: u32 = 2;
+
+// Just enough of the protobuf wire format to write a profile.
+#[derive(Default)]
+struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u32) {
+        self.varint(u64::from(field << 3 | wire_type));
+    }
+
+    fn uint(&mut self, field: u32, value: u64) {
+        self.key(field, VARINT);
+        self.varint(value);
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, LENGTH_DELIMITED);
+        self.varint(bytes.len() as u64);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn message(&mut self, field: u32, message: Message) {
+        self.bytes(field, &message.buf);
+    }
+
+    fn packed(&mut self, field: u32, values: &[u64]) {
+        let mut packed = Message::default();
+        for value in values {
+            packed.varint(*value);
+        }
+        self.message(field, packed);
+    }
+}
+
+// Profile strings are referred to by their index in a table.
+struct Strings {
+    table: Vec<String>,
+    indices: HashMap<String, u64>,
+}
+
+impl Strings {
+    fn new() -> Strings {
+        // The first string must be empty
+        Strings {
+            table: vec![String::new()],
+            indices: HashMap::from([(String::new(), 0)]),
+        }
+    }
+
+    fn index(&mut self, s: &str) -> u64 {
+        if let Some(&i) = self.indices.get(s) {
+            return i;
+        }
+        let i = self.table.len() as u64;
+        self.table.push(s.to_string());
+        self.indices.insert(s.to_string(), i);
+        i
+    }
+}
+
+fn value_type(strings: &mut Strings, kind: &str, unit: &str) -> Message {
+    let mut value_type = Message::default();
+    value_type.uint(VALUE_TYPE_TYPE, strings.index(kind));
+    value_type.uint(VALUE_TYPE_UNIT, strings.index(unit));
+    value_type
+}
+
+// The uncompressed profile.
+pub fn to_bytes(analysis: &Analysis) -> Vec<u8> {
+    let mut profile = Message::default();
+    let mut strings = Strings::new();
+
+    let objects = value_type(&mut strings, "inuse_objects", "count");
+    profile.message(PROFILE_SAMPLE_TYPE, objects);
+    let space = value_type(&mut strings, "inuse_space", "bytes");
+    profile.message(PROFILE_SAMPLE_TYPE, space);
+
+    // One function, and one location calling it, per distinct frame; both
+    // use the frame name's string index as their ID, which is never 0.
+    let mut ids: Vec<u64> = Vec::new();
+    let mut seen: HashSet<u64> = HashSet::new();
+    let mut locations: Vec<u64> = Vec::new();
+    analysis
+        .dominator_stacks(|stack| -> Result<(), ()> {
+            // Samples list the leaf first
+            locations.clear();
+            for obj in stack.iter().rev() {
+                let id = strings.index(&analysis.frame_name(obj));
+                if seen.insert(id) {
+                    ids.push(id);
+                }
+                locations.push(id);
+            }
+
+            let mut sample = Message::default();
+            sample.packed(SAMPLE_LOCATION_ID, &locations);
+            let bytes = stack.last().map_or(0, |obj| obj.bytes);
+            sample.packed(SAMPLE_VALUE, &[1, bytes as u64]);
+            profile.message(PROFILE_SAMPLE, sample);
+            Ok(())
+        })
+        .expect("collecting samples can't fail");
+
+    for &id in &ids {
+        let mut line = Message::default();
+        line.uint(LINE_FUNCTION_ID, id);
+        let mut location = Message::default();
+        location.uint(LOCATION_ID, id);
+        location.message(LOCATION_LINE, line);
+        profile.message(PROFILE_LOCATION, location);
+
+        let mut function = Message::default();
+        function.uint(FUNCTION_ID, id);
+        function.uint(FUNCTION_NAME, id);
+        profile.message(PROFILE_FUNCTION, function);
+    }
+
+    let default_sample_type = strings.index("inuse_space");
+    for s in &strings.table {
+        profile.bytes(PROFILE_STRING_TABLE, s.as_bytes());
+    }
+    profile.uint(PROFILE_DEFAULT_SAMPLE_TYPE, default_sample_type);
+
+    profile.buf
+}
+
+// Writes the profile gzip-compressed, as pprof tools expect.
+pub fn write<W: Write>(analysis: &Analysis, writer: W) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(writer, Compression::default());
+    encoder.write_all(&to_bytes(analysis))?;
+    encoder.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use flate2::read::GzDecoder;
+    use std::fs::File;
+    use std::io::{BufReader, Read};
+
+    #[test]
+    fn test_varint() {
+        let mut message = Message::default();
+        message.uint(1, 300);
+        assert_eq!(vec![0x08, 0xac, 0x02], message.buf);
+    }
+
+    #[test]
+    fn test_write() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, true).unwrap();
+        let analysis = analyze::analyze(root, root, graph, true).unwrap();
+
+        let mut gzipped = vec![];
+        write(&analysis, &mut gzipped).unwrap();
+        let mut profile = vec![];
+        GzDecoder::new(gzipped.as_slice())
+            .read_to_end(&mut profile)
+            .unwrap();
+        assert_eq!(to_bytes(&analysis), profile);
+
+        // Starts with the sample types, whose names come first in the string table
+        assert_eq!(&[0x0a, 0x04, 0x08, 0x01, 0x10, 0x02], &profile[..6]);
+        let contains = |s: &[u8]| profile.windows(s.len()).any(|w| w == s);
+        assert!(contains(b"\x32\x0dinuse_objects"));
+        assert!(contains(b"\x32\x06Thread"));
+    }
+}
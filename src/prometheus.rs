@@ -0,0 +1,116 @@
+// Per-class heap composition in the Prometheus text exposition format, for
+// the node_exporter textfile collector or a scrape endpoint.
+use crate::analyze::Analysis;
+use crate::object::*;
+use std::fmt::Write;
+
+// Label for classes folded into the remainder by --min-bytes
+const OTHER: &str = "(other)";
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn gauge<'a, I>(out: &mut String, name: &str, help: &str, samples: I) -> std::fmt::Result
+where
+    I: IntoIterator<Item = (&'a str, usize)>,
+{
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} gauge", name)?;
+    for (class, value) in samples {
+        writeln!(out, "{}{{class=\"{}\"}} {}", name, escape(class), value)?;
+    }
+    Ok(())
+}
+
+pub fn metrics(analysis: &Analysis) -> Result<String, std::fmt::Error> {
+    let mut out = String::new();
+
+    let (live, live_rest) = analysis.live_stats_by_kind(usize::MAX);
+    let (retained, retained_rest) = analysis.retained_stats_by_kind(usize::MAX);
+    let live: Vec<(&str, Stats)> = live
+        .into_iter()
+        .map(|(kind, stats)| (kind.as_str(), stats))
+        .chain(std::iter::once((OTHER, live_rest)))
+        .collect();
+    let retained: Vec<(&str, Stats)> = retained
+        .into_iter()
+        .map(|(kind, stats)| (kind.as_str(), stats))
+        .chain(std::iter::once((OTHER, retained_rest)))
+        .collect();
+
+    gauge(
+        &mut out,
+        "reap_live_bytes",
+        "Memory used by live instances of the class.",
+        live.iter().map(|(class, stats)| (*class, stats.bytes)),
+    )?;
+    gauge(
+        &mut out,
+        "reap_live_objects",
+        "Number of live instances of the class.",
+        live.iter().map(|(class, stats)| (*class, stats.count)),
+    )?;
+    gauge(
+        &mut out,
+        "reap_retained_bytes",
+        "Memory retained by instances of the class.",
+        retained.iter().map(|(class, stats)| (*class, stats.bytes)),
+    )?;
+
+    let total = analysis.dominated_totals();
+    let (_, unreachable) = analysis.unreachable_stats_by_kind(0);
+    for (name, help, value) in [
+        (
+            "reap_heap_live_bytes",
+            "Memory used by all live objects.",
+            total.bytes,
+        ),
+        (
+            "reap_heap_live_objects",
+            "Number of live objects.",
+            total.count,
+        ),
+        (
+            "reap_heap_unreachable_bytes",
+            "Memory used by objects unreachable from the root.",
+            unreachable.bytes,
+        ),
+    ] {
+        writeln!(out, "# HELP {} {}", name, help)?;
+        writeln!(out, "# TYPE {} gauge", name)?;
+        writeln!(out, "{} {}", name, value)?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use std::fs::File;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_metrics() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+
+        let metrics = metrics(&analysis).unwrap();
+        let lines: Vec<&str> = metrics.lines().collect();
+        assert!(lines.contains(&"reap_live_bytes{class=\"Thread\"} 2099840"));
+        assert!(lines.contains(&"reap_live_objects{class=\"String\"} 9235"));
+        assert!(lines.contains(&"reap_retained_bytes{class=\"Thread\"} 2104164"));
+        assert!(lines.contains(&"reap_heap_live_objects 15472"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("a\\\"b\\\\c\\n", escape("a\"b\\c\n"));
+    }
+}
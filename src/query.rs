@@ -0,0 +1,424 @@
+// A small filter language over objects and the memory they retain, e.g.
+// `class =~ /^ActiveRecord/ && retained > 1MB`.
+//
+//   expr       := and ("||" and)*
+//   and        := unary ("&&" unary)*
+//   unary      := "!" unary | "(" expr ")" | comparison
+//   comparison := field op value
+//
+// Fields are `class` (compared with ==, != to a "string", or with =~, !~ to
+// a /regex/), and `address`, `bytes` (the object's own size), `retained` and
+// `count` (bytes & objects retained), which are compared with ==, !=, <, <=,
+// >, >= to numbers like 0x7f83df87dc40, 1_000 or 10KB.
+use crate::object::*;
+use crate::threshold;
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    pub message: String,
+    // Byte offset in the query where the problem was found
+    pub offset: usize,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Class,
+    Address,
+    Bytes,
+    Retained,
+    Count,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Match,
+    NotMatch,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Number(Field, Op, usize),
+    Class(Op, String),
+    ClassRegex(Op, Regex),
+}
+
+#[derive(Debug, Clone)]
+pub struct Query {
+    source: String,
+    expr: Expr,
+}
+
+impl Query {
+    pub fn matches(&self, obj: &Object, retained: Stats) -> bool {
+        self.expr.eval(obj, retained)
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+            len: s.len(),
+        };
+        let expr = parser.expr()?;
+        match parser.peek() {
+            None => Ok(Query {
+                source: s.to_string(),
+                expr,
+            }),
+            Some((offset, token)) => {
+                Err(error(format!("Unexpected {}", token.describe()), *offset))
+            }
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, obj: &Object, retained: Stats) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(obj, retained) && b.eval(obj, retained),
+            Expr::Or(a, b) => a.eval(obj, retained) || b.eval(obj, retained),
+            Expr::Not(e) => !e.eval(obj, retained),
+            Expr::Class(op, name) => (obj.class == *name) == (*op == Op::Eq),
+            Expr::ClassRegex(op, re) => re.is_match(&obj.class) == (*op == Op::Match),
+            Expr::Number(field, op, value) => {
+                let actual = match field {
+                    Field::Address => obj.address,
+                    Field::Bytes => obj.bytes,
+                    Field::Retained => retained.bytes,
+                    Field::Count => retained.count,
+                    Field::Class => unreachable!("class is not numeric"),
+                };
+                match op {
+                    Op::Eq => actual == *value,
+                    Op::Ne => actual != *value,
+                    Op::Lt => actual < *value,
+                    Op::Le => actual <= *value,
+                    Op::Gt => actual > *value,
+                    Op::Ge => actual >= *value,
+                    Op::Match | Op::NotMatch => unreachable!("numbers can't be matched"),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Ident(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Number(usize),
+    Str(String),
+    Regex(Regex),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(name) => format!("`{}`", name),
+            Token::Op(op) => format!("operator {:?}", op),
+            Token::And => "`&&`".to_string(),
+            Token::Or => "`||`".to_string(),
+            Token::Not => "`!`".to_string(),
+            Token::Open => "`(`".to_string(),
+            Token::Close => "`)`".to_string(),
+            Token::Number(n) => format!("number {}", n),
+            Token::Str(s) => format!("string {:?}", s),
+            Token::Regex(re) => format!("regex /{}/", re),
+        }
+    }
+}
+
+fn error(message: String, offset: usize) -> QueryError {
+    QueryError { message, offset }
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let mut tokens = vec![];
+    let mut chars = s.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        let rest = &s[start..];
+
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let symbol = [
+            ("&&", Token::And),
+            ("||", Token::Or),
+            ("==", Token::Op(Op::Eq)),
+            ("!=", Token::Op(Op::Ne)),
+            ("<=", Token::Op(Op::Le)),
+            (">=", Token::Op(Op::Ge)),
+            ("=~", Token::Op(Op::Match)),
+            ("!~", Token::Op(Op::NotMatch)),
+            ("<", Token::Op(Op::Lt)),
+            (">", Token::Op(Op::Gt)),
+            ("!", Token::Not),
+            ("(", Token::Open),
+            (")", Token::Close),
+        ]
+        .into_iter()
+        .find(|(text, _)| rest.starts_with(text));
+        if let Some((text, token)) = symbol {
+            tokens.push((start, token));
+            for _ in 0..text.len() {
+                chars.next();
+            }
+            continue;
+        }
+
+        if c == '"' || c == '\'' || c == '/' {
+            chars.next();
+            let mut text = String::new();
+            let mut closed = false;
+            while let Some((_, d)) = chars.next() {
+                if d == c {
+                    closed = true;
+                    break;
+                }
+                // Keep escapes in regexes, since the regex crate handles them
+                if d == '\\' {
+                    if let Some(&(_, e)) = chars.peek() {
+                        if e == c || c != '/' {
+                            chars.next();
+                            text.push(e);
+                            continue;
+                        }
+                    }
+                }
+                text.push(d);
+            }
+            if !closed {
+                return Err(error(format!("Unterminated {}", c), start));
+            }
+            let token = if c == '/' {
+                Token::Regex(Regex::new(&text).map_err(|e| error(e.to_string(), start))?)
+            } else {
+                Token::Str(text)
+            };
+            tokens.push((start, token));
+            continue;
+        }
+
+        if c.is_alphanumeric() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, d)) = chars.peek() {
+                if d.is_alphanumeric() || d == '_' || d == '.' {
+                    end = i + d.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let word = &s[start..end];
+            let token = if c.is_ascii_digit() {
+                let number = match word.strip_prefix("0x") {
+                    Some(hex) => {
+                        usize::from_str_radix(&hex.replace('_', ""), 16).map_err(|e| e.to_string())
+                    }
+                    None => threshold::parse_bytes(word),
+                };
+                Token::Number(number.map_err(|e| error(e, start))?)
+            } else {
+                Token::Ident(word.to_string())
+            };
+            tokens.push((start, token));
+            continue;
+        }
+
+        return Err(error(format!("Unexpected {:?}", c), start));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<(usize, Token), QueryError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| error(format!("Expected {}", expected), self.len))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expr(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.and()?;
+        while let Some((_, Token::Or)) = self.peek() {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.unary()?;
+        while let Some((_, Token::And)) = self.peek() {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, QueryError> {
+        match self.next("a comparison")? {
+            (_, Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            (_, Token::Open) => {
+                let expr = self.expr()?;
+                match self.next("`)`")? {
+                    (_, Token::Close) => Ok(expr),
+                    (offset, token) => Err(error(
+                        format!("Expected `)` but found {}", token.describe()),
+                        offset,
+                    )),
+                }
+            }
+            (offset, Token::Ident(name)) => self.comparison(&name, offset),
+            (offset, token) => Err(error(
+                format!("Expected a field but found {}", token.describe()),
+                offset,
+            )),
+        }
+    }
+
+    fn comparison(&mut self, name: &str, offset: usize) -> Result<Expr, QueryError> {
+        let field = match name {
+            "class" => Field::Class,
+            "address" => Field::Address,
+            "bytes" => Field::Bytes,
+            "retained" => Field::Retained,
+            "count" => Field::Count,
+            _ => return Err(error(format!("Unknown field `{}`", name), offset)),
+        };
+        let op = match self.next("an operator")? {
+            (_, Token::Op(op)) => op,
+            (offset, token) => {
+                return Err(error(
+                    format!("Expected an operator but found {}", token.describe()),
+                    offset,
+                ))
+            }
+        };
+        let (value_offset, value) = self.next("a value")?;
+
+        match (field, op, value) {
+            (Field::Class, Op::Eq | Op::Ne, Token::Str(s)) => Ok(Expr::Class(op, s)),
+            (Field::Class, Op::Match | Op::NotMatch, Token::Regex(re)) => {
+                Ok(Expr::ClassRegex(op, re))
+            }
+            (Field::Class, _, _) => Err(error(
+                "`class` can only be compared to a \"string\" with == or != or a /regex/ with =~ or !~".to_string(),
+                offset,
+            )),
+            (_, Op::Match | Op::NotMatch, _) => Err(error(
+                format!("`{}` can't be matched against a regex", name),
+                offset,
+            )),
+            (_, _, Token::Number(n)) => Ok(Expr::Number(field, op, n)),
+            (_, _, token) => Err(error(
+                format!("Expected a number but found {}", token.describe()),
+                value_offset,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn object(kind: &str, bytes: usize) -> Object {
+        Object {
+            address: 0x10,
+            bytes,
+            kind: kind.into(),
+            class: kind.into(),
+            internal_type: kind.into(),
+            label: None,
+            file: None,
+        }
+    }
+
+    #[rstest]
+    #[case("class == \"String\"", true)]
+    #[case("class != 'String'", false)]
+    #[case("class =~ /^Str/", true)]
+    #[case("class !~ /^Str/", false)]
+    #[case("retained > 1MB", true)]
+    #[case("retained > 1MB && count < 10", false)]
+    #[case("retained > 1MB && count < 10 || bytes == 40", true)]
+    #[case("!(bytes >= 40)", false)]
+    #[case("address == 0x10 && bytes <= 1_000", true)]
+    fn test_matches(#[case] query: &str, #[case] expected: bool) {
+        let query: Query = query.parse().unwrap();
+        let retained = Stats {
+            count: 20,
+            bytes: 2_000_000,
+        };
+        assert_eq!(expected, query.matches(&object("String", 40), retained));
+    }
+
+    #[rstest]
+    #[case("", 0)]
+    #[case("size > 1", 0)]
+    #[case("class > 1", 0)]
+    #[case("bytes =~ /1/", 0)]
+    #[case("bytes > \"1\"", 8)]
+    #[case("bytes > 1 &&", 12)]
+    #[case("(bytes > 1", 10)]
+    #[case("class =~ /(/", 9)]
+    #[case("class == \"String", 9)]
+    #[case("bytes > 1 bytes", 10)]
+    fn test_errors(#[case] query: &str, #[case] offset: usize) {
+        assert_eq!(offset, query.parse::<Query>().unwrap_err().offset);
+    }
+}
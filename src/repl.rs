@@ -0,0 +1,247 @@
+// Answers questions about a dump kept in memory, so that each one doesn't
+// require parsing a large dump again.
+use crate::{analyze_graph, load, print_largest, Result};
+use bytesize::ByteSize;
+use reap::analyze::Analysis;
+use reap::object::*;
+use reap::parse;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+const HELP: &str = "Commands:
+  top [N]              Types & objects using or retaining the most memory
+  inspect ADDRESS      Stats, referrers & dominator tree children of an object
+  paths ADDRESS        How an object is reached from, and retained by, the root
+  diff-save            Remember per-type stats of the current dump
+  diff [N]             Compare per-type stats of the current dump to the saved ones
+  load FILE            Replace the current dump with another one
+  help                 Print this message
+  quit                 Exit";
+
+pub struct Repl {
+    analysis: Analysis,
+    class_name_only: bool,
+    count: usize,
+    baseline: Option<HashMap<String, Stats>>,
+}
+
+pub fn run(analysis: Analysis, class_name_only: bool, count: usize) -> Result<()> {
+    let mut repl = Repl {
+        analysis,
+        class_name_only,
+        count,
+        baseline: None,
+    };
+
+    println!("Type `help` for a list of commands");
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("reap> ");
+        io::stdout().flush()?;
+
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        match repl.execute(&line) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+}
+
+fn parse_count(arg: Option<&str>, default: usize) -> Result<usize> {
+    Ok(arg.map(|n| n.parse()).transpose()?.unwrap_or(default))
+}
+
+fn parse_address(arg: Option<&str>) -> Result<usize> {
+    let arg = arg.ok_or("Expected an address, e.g. 0x7f83df87dc40")?;
+    Ok(parse::parse_address(arg)?)
+}
+
+fn print_objects(objects: &[&Object]) {
+    if objects.is_empty() {
+        println!("None");
+    }
+    for obj in objects {
+        println!("{}", obj);
+    }
+}
+
+fn by_kind(analysis: &Analysis) -> HashMap<String, Stats> {
+    analysis
+        .live_stats_by_kind(usize::MAX)
+        .0
+        .into_iter()
+        .map(|(kind, stats)| (kind.to_string(), stats))
+        .collect()
+}
+
+fn signed_bytes(delta: i64) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{}{}", sign, ByteSize(delta.unsigned_abs()))
+}
+
+impl Repl {
+    // Runs one command, returning whether to keep going.
+    pub fn execute(&mut self, line: &str) -> Result<bool> {
+        let mut args = line.split_whitespace();
+        let command = match args.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+
+        match command {
+            "top" => self.top(parse_count(args.next(), self.count)?),
+            "inspect" => self.inspect(parse_address(args.next())?)?,
+            "paths" => self.paths(parse_address(args.next())?)?,
+            "diff-save" => {
+                self.baseline = Some(by_kind(&self.analysis));
+                println!("Saved stats for comparison");
+            }
+            "diff" => self.diff(parse_count(args.next(), self.count)?)?,
+            "load" => {
+                let file = args.next().ok_or("Expected a file")?;
+                let (root, graph) = load(Path::new(file), self.class_name_only)?;
+                self.analysis = analyze_graph(root, graph, None, self.class_name_only)?;
+                println!("Loaded {}", file);
+            }
+            "help" => println!("{}", HELP),
+            "quit" | "exit" => return Ok(false),
+            _ => return Err(format!("Unknown command {:?}; try `help`", command).into()),
+        }
+        Ok(true)
+    }
+
+    fn top(&self, count: usize) {
+        println!("\nObject types using the most live memory:");
+        let (largest, rest) = self.analysis.live_stats_by_kind(count);
+        print_largest(&largest, rest);
+
+        println!("\nObjects retaining the most live memory:");
+        let (largest, rest) = self.analysis.dominator_subtree_stats(count);
+        print_largest(&largest, rest);
+
+        println!("\nObject types retaining the most live memory:");
+        let (largest, rest) = self.analysis.retained_stats_by_kind(count);
+        print_largest(&largest, rest);
+    }
+
+    fn inspect(&self, address: usize) -> Result<()> {
+        let not_found = || format!("No reachable object at {:#x}", address);
+        let (obj, stats) = self
+            .analysis
+            .retained_stats(address)
+            .ok_or_else(not_found)?;
+        println!(
+            "{}: {} self, {} retained ({} objects)",
+            obj,
+            ByteSize(obj.bytes as u64),
+            ByteSize(stats.bytes as u64),
+            stats.count
+        );
+
+        println!("\nReferenced by:");
+        let referrers = self.analysis.referrers(address).ok_or_else(not_found)?;
+        print_objects(&referrers);
+
+        println!("\nDominator tree children:");
+        let (children, rest) = self
+            .analysis
+            .dominator_children(address, self.count)
+            .ok_or_else(not_found)?;
+        print_largest(&children, rest);
+        Ok(())
+    }
+
+    fn paths(&self, address: usize) -> Result<()> {
+        let not_found = || format!("No reachable object at {:#x}", address);
+
+        println!("\nShortest path from root:");
+        let path = self.analysis.shortest_path(address).ok_or_else(not_found)?;
+        print_objects(&path);
+
+        println!("\nRetained via dominators:");
+        let chain = self
+            .analysis
+            .dominator_chain(address)
+            .ok_or_else(not_found)?;
+        print_objects(&chain);
+        Ok(())
+    }
+
+    fn diff(&self, count: usize) -> Result<()> {
+        let baseline = self
+            .baseline
+            .as_ref()
+            .ok_or("Nothing to compare to; run `diff-save` first")?;
+        let current = by_kind(&self.analysis);
+
+        let mut deltas: Vec<(&String, i64, i64)> = baseline
+            .keys()
+            .chain(current.keys())
+            .collect::<std::collections::HashSet<_>>()
+            .into_iter()
+            .map(|kind| {
+                let before = baseline.get(kind).copied().unwrap_or_default();
+                let after = current.get(kind).copied().unwrap_or_default();
+                (
+                    kind,
+                    after.bytes as i64 - before.bytes as i64,
+                    after.count as i64 - before.count as i64,
+                )
+            })
+            .filter(|(_, bytes, count)| *bytes != 0 || *count != 0)
+            .collect();
+        deltas.sort_by_key(|(kind, bytes, _)| (std::cmp::Reverse(bytes.abs()), *kind));
+
+        println!("\nChange in live memory by type since `diff-save`:");
+        if deltas.is_empty() {
+            println!("None");
+        }
+        for (kind, bytes, objects) in deltas.into_iter().take(count) {
+            println!("{}: {} ({:+} objects)", kind, signed_bytes(bytes), objects);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn repl() -> Repl {
+        let (root, graph) = load(Path::new("test/heap.json"), false).unwrap();
+        Repl {
+            analysis: analyze_graph(root, graph, None, false).unwrap(),
+            class_name_only: false,
+            count: 3,
+            baseline: None,
+        }
+    }
+
+    #[rstest]
+    #[case("", Some(true))]
+    #[case("top 2", Some(true))]
+    #[case("inspect 0x7f83df87dc40", Some(true))]
+    #[case("paths 0x7f83df87dc40", Some(true))]
+    #[case("inspect 0x1", None)]
+    #[case("inspect", None)]
+    #[case("diff", None)]
+    #[case("bogus", None)]
+    #[case("quit", Some(false))]
+    fn commands(#[case] line: &str, #[case] expected: Option<bool>) {
+        assert_eq!(expected, repl().execute(line).ok());
+    }
+
+    #[test]
+    fn diff_after_save() {
+        let mut repl = repl();
+        assert!(repl.execute("diff-save").unwrap());
+        assert!(repl.execute("diff").unwrap());
+    }
+}
@@ -0,0 +1,329 @@
+// Serves a JSON API over an analyzed dump, so dashboards can query it
+// without re-running the CLI, and a bundled page for browsing it.
+use crate::{render_flamegraph, FlamegraphStyle, Result};
+use reap::analyze::Analysis;
+use reap::intern::Symbol;
+use reap::object::*;
+use reap::parse;
+use reap::prometheus;
+use reap::query::Query;
+use serde::Serialize;
+use std::collections::HashMap;
+use tiny_http::{Header, Method, Response, Server};
+
+const DEFAULT_LIMIT: usize = 100;
+
+// A single page exploring the dump through the API below
+const UI: &str = include_str!("ui/index.html");
+
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    fn json<T: Serialize>(value: &T) -> Reply {
+        match serde_json::to_vec(value) {
+            Ok(body) => Reply {
+                status: 200,
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Reply::error(500, &e.to_string()),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Reply {
+        let body = serde_json::json!({ "error": message });
+        Reply {
+            status,
+            content_type: "application/json",
+            body: body.to_string().into_bytes(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ObjectJson {
+    address: String,
+    kind: String,
+    label: String,
+    bytes: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retained: Option<Stats>,
+}
+
+impl ObjectJson {
+    fn new(obj: &Object, retained: Option<Stats>) -> ObjectJson {
+        ObjectJson {
+            address: format!("{:#x}", obj.address),
+            kind: obj.kind.to_string(),
+            label: obj.to_string(),
+            bytes: obj.bytes,
+            retained,
+        }
+    }
+
+    fn all(objects: &[&Object]) -> Vec<ObjectJson> {
+        objects.iter().map(|o| ObjectJson::new(o, None)).collect()
+    }
+}
+
+#[derive(Serialize)]
+struct ClassJson<'a> {
+    kind: &'a str,
+    live: Stats,
+    retained: Stats,
+}
+
+pub fn run(analysis: Analysis, host: &str, port: u16) -> Result<()> {
+    let server = Server::http((host, port)).map_err(|e| e.to_string())?;
+    println!("\nServing UI & API on http://{}:{}", host, port);
+
+    for request in server.incoming_requests() {
+        let reply = match request.method() {
+            Method::Get => route(&analysis, request.url()),
+            _ => Reply::error(405, "Only GET is supported"),
+        };
+        log::info!("GET {} {}", request.url(), reply.status);
+
+        let header = Header::from_bytes("Content-Type", reply.content_type)
+            .expect("content type is a valid header");
+        let response = Response::from_data(reply.body)
+            .with_status_code(reply.status)
+            .with_header(header);
+        if let Err(e) = request.respond(response) {
+            log::warn!("Failed to respond: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        decoded.push(b);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn query_params(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((k, v)) => (decode(k), decode(v)),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+// Handles a GET of the given URL (path plus query string).
+pub fn route(analysis: &Analysis, url: &str) -> Reply {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params = query_params(query);
+    let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+        Some(Ok(limit)) => limit,
+        Some(Err(e)) => return Reply::error(400, &format!("Invalid limit: {}", e)),
+        None => DEFAULT_LIMIT,
+    };
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        [""] | ["index.html"] => Reply {
+            status: 200,
+            content_type: "text/html; charset=utf-8",
+            body: UI.as_bytes().to_vec(),
+        },
+        ["metrics"] => match prometheus::metrics(analysis) {
+            Ok(metrics) => Reply {
+                status: 200,
+                content_type: "text/plain; version=0.0.4",
+                body: metrics.into_bytes(),
+            },
+            Err(e) => Reply::error(500, &e.to_string()),
+        },
+        ["api", "summary"] => summary(analysis),
+        ["api", "classes"] => classes(analysis, limit, params.get("sort")),
+        ["api", "objects"] => objects(analysis, limit, params.get("query")),
+        ["api", "objects", address] => with_address(address, |a| inspect(analysis, a, limit)),
+        ["api", "objects", address, "paths"] => with_address(address, |a| paths(analysis, a)),
+        ["api", "flamegraph"] => flamegraph(analysis, params.get("format")),
+        _ => Reply::error(404, &format!("No such endpoint {}", path)),
+    }
+}
+
+fn with_address<F: Fn(usize) -> Option<Reply>>(address: &str, f: F) -> Reply {
+    match parse::parse_address(address) {
+        Ok(a) => f(a)
+            .unwrap_or_else(|| Reply::error(404, &format!("No reachable object at {}", address))),
+        Err(e) => Reply::error(400, &format!("Invalid address {}: {}", address, e)),
+    }
+}
+
+fn summary(analysis: &Analysis) -> Reply {
+    let (classes, _) = analysis.live_stats_by_kind(usize::MAX);
+    let (_, unreachable) = analysis.unreachable_stats_by_kind(0);
+    Reply::json(&serde_json::json!({
+        "live": analysis.dominated_totals(),
+        "unreachable": unreachable,
+        "classes": classes.len(),
+    }))
+}
+
+fn classes(analysis: &Analysis, limit: usize, sort: Option<&String>) -> Reply {
+    let retained: HashMap<&Symbol, Stats> = analysis
+        .retained_stats_by_kind(usize::MAX)
+        .0
+        .into_iter()
+        .collect();
+    let mut rows: Vec<ClassJson> = analysis
+        .live_stats_by_kind(usize::MAX)
+        .0
+        .into_iter()
+        .map(|(kind, live)| ClassJson {
+            kind,
+            live,
+            retained: retained.get(kind).copied().unwrap_or_default(),
+        })
+        .collect();
+
+    let key: fn(&ClassJson) -> usize = match sort.map(|s| s.as_str()) {
+        None | Some("bytes") => |row| row.live.bytes,
+        Some("count") => |row| row.live.count,
+        Some("retained") => |row| row.retained.bytes,
+        Some(other) => return Reply::error(400, &format!("Unknown sort key {:?}", other)),
+    };
+    rows.sort_by_key(|row| std::cmp::Reverse(key(row)));
+    rows.truncate(limit);
+    Reply::json(&rows)
+}
+
+fn objects(analysis: &Analysis, limit: usize, query: Option<&String>) -> Reply {
+    let query: Option<Query> = match query.map(|q| q.parse()) {
+        Some(Ok(query)) => Some(query),
+        Some(Err(e)) => return Reply::error(400, &format!("Invalid query: {}", e)),
+        None => None,
+    };
+    let (largest, _) = analysis.objects_where(limit, |obj, stats| {
+        !obj.is_root() && query.as_ref().is_none_or(|q| q.matches(obj, stats))
+    });
+    let rows: Vec<ObjectJson> = largest
+        .into_iter()
+        .map(|(obj, stats)| ObjectJson::new(obj, Some(stats)))
+        .collect();
+    Reply::json(&rows)
+}
+
+fn inspect(analysis: &Analysis, address: usize, limit: usize) -> Option<Reply> {
+    let (obj, stats) = analysis.retained_stats(address)?;
+    let referrers = analysis.referrers(address)?;
+    let (children, rest) = analysis.dominator_children(address, limit)?;
+    let children: Vec<ObjectJson> = children
+        .into_iter()
+        .map(|(child, stats)| ObjectJson::new(child, Some(stats)))
+        .collect();
+    Some(Reply::json(&serde_json::json!({
+        "object": ObjectJson::new(obj, Some(stats)),
+        "referrers": ObjectJson::all(&referrers),
+        "children": children,
+        "other_children": rest,
+    })))
+}
+
+fn paths(analysis: &Analysis, address: usize) -> Option<Reply> {
+    let shortest = analysis.shortest_path(address)?;
+    let dominators = analysis.dominator_chain(address)?;
+    Some(Reply::json(&serde_json::json!({
+        "shortest": ObjectJson::all(&shortest),
+        "dominators": ObjectJson::all(&dominators),
+    })))
+}
+
+fn flamegraph(analysis: &Analysis, format: Option<&String>) -> Reply {
+    let lines = match analysis.flamegraph_lines() {
+        Ok(lines) => lines,
+        Err(e) => return Reply::error(500, &e.to_string()),
+    };
+    match format.map(|f| f.as_str()) {
+        None | Some("folded") => Reply {
+            status: 200,
+            content_type: "text/plain; charset=utf-8",
+            body: lines.join("\n").into_bytes(),
+        },
+        Some("svg") => {
+            let mut svg = vec![];
+            match render_flamegraph(&lines, &FlamegraphStyle::default(), &mut svg) {
+                Ok(()) => Reply {
+                    status: 200,
+                    content_type: "image/svg+xml",
+                    body: svg,
+                },
+                Err(e) => Reply::error(500, &e.to_string()),
+            }
+        }
+        Some(other) => Reply::error(400, &format!("Unknown format {:?}", other)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze_graph, load};
+    use rstest::rstest;
+    use std::path::Path;
+
+    fn analysis() -> Analysis {
+        let (root, graph) = load(Path::new("test/heap.json"), false).unwrap();
+        analyze_graph(root, graph, None, false).unwrap()
+    }
+
+    #[rstest]
+    #[case("/", 200)]
+    #[case("/metrics", 200)]
+    #[case("/api/summary", 200)]
+    #[case("/api/classes?sort=retained&limit=3", 200)]
+    #[case("/api/classes?sort=nope", 400)]
+    #[case("/api/objects?query=class%20%3D~%20%2F%5EThr%2F", 200)]
+    #[case("/api/objects?query=bogus", 400)]
+    #[case("/api/objects?limit=x", 400)]
+    #[case("/api/objects/0x7f83df87dc40", 200)]
+    #[case("/api/objects/0x7f83df87dc40/paths", 200)]
+    #[case("/api/objects/0x1", 404)]
+    #[case("/api/objects/zzz", 400)]
+    #[case("/api/flamegraph", 200)]
+    #[case("/api/flamegraph?format=svg", 200)]
+    #[case("/api/nope", 404)]
+    fn routes(#[case] url: &str, #[case] status: u16) {
+        assert_eq!(status, route(&analysis(), url).status);
+    }
+
+    #[test]
+    fn objects_query() {
+        let reply = route(
+            &analysis(),
+            "/api/objects?limit=2&query=class+%3D%3D+%22Thread%22",
+        );
+        let rows: serde_json::Value = serde_json::from_slice(&reply.body).unwrap();
+        let rows = rows.as_array().unwrap();
+        assert_eq!(2, rows.len());
+        assert!(rows.iter().all(|row| row["kind"] == "Thread"));
+    }
+}
@@ -0,0 +1,148 @@
+// A process's resident memory by kind of mapping, from Linux's
+// /proc/<pid>/smaps, to tell how much of it the Ruby heap accounts for.
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+#[derive(Debug)]
+pub enum SmapsError {
+    IoError(std::io::Error),
+    InvalidLine(String),
+}
+
+impl fmt::Display for SmapsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SmapsError::IoError(err) => write!(f, "Could not read smaps: {}", err),
+            SmapsError::InvalidLine(line) => write!(f, "Invalid smaps line: {}", line),
+        }
+    }
+}
+
+impl std::error::Error for SmapsError {}
+
+impl From<std::io::Error> for SmapsError {
+    fn from(err: std::io::Error) -> Self {
+        SmapsError::IoError(err)
+    }
+}
+
+// Resident bytes, in total and split between mappings of files (code,
+// shared libraries, mmapped data) and anonymous ones (malloc arenas, the
+// Ruby heap, stacks).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Smaps {
+    pub rss: usize,
+    pub file: usize,
+    pub anonymous: usize,
+}
+
+pub fn parse<R: BufRead>(reader: &mut R) -> Result<Smaps, SmapsError> {
+    let mut smaps = Smaps::default();
+    let mut file_backed = false;
+
+    for line in reader.lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        let first = match fields.next() {
+            Some(first) => first,
+            None => continue,
+        };
+
+        if !first.ends_with(':') {
+            // A mapping's header: address range, permissions, offset,
+            // device, inode & path, where only files have an inode
+            let inode = fields
+                .nth(3)
+                .ok_or_else(|| SmapsError::InvalidLine(line.clone()))?;
+            file_backed = inode != "0";
+        } else if first == "Rss:" {
+            let kb: usize = fields
+                .next()
+                .and_then(|kb| kb.parse().ok())
+                .ok_or_else(|| SmapsError::InvalidLine(line.clone()))?;
+            let bytes = kb * 1024;
+            smaps.rss += bytes;
+            if file_backed {
+                smaps.file += bytes;
+            } else {
+                smaps.anonymous += bytes;
+            }
+        }
+    }
+    Ok(smaps)
+}
+
+pub fn read(pid: u32) -> Result<Smaps, SmapsError> {
+    let file = File::open(format!("/proc/{}/smaps", pid))?;
+    parse(&mut BufReader::new(file))
+}
+
+// How much of the process's memory the Ruby heap explains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Accounting {
+    pub rss: usize,
+    pub file: usize,
+
+    // Memory of every object in the dump, as reported by ObjectSpace.memsize_of
+    pub ruby_heap: usize,
+
+    // Anonymous memory the heap doesn't account for: native extensions,
+    // malloc fragmentation, free heap slots, thread stacks, ...
+    pub native: usize,
+}
+
+pub fn account(smaps: Smaps, ruby_heap: usize) -> Accounting {
+    Accounting {
+        rss: smaps.rss,
+        file: smaps.file,
+        ruby_heap,
+        native: smaps.anonymous.saturating_sub(ruby_heap),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    const SMAPS: &str = "\
+55d0c8a00000-55d0c8a01000 r-xp 00000000 fd:01 1048602                    /usr/bin/ruby
+Size:                  4 kB
+Rss:                   4 kB
+Pss:                   4 kB
+VmFlags: rd ex mr mw me dw sd
+55d0c9b6e000-55d0cb2a4000 rw-p 00000000 00:00 0                          [heap]
+Size:              23768 kB
+Rss:               23000 kB
+VmFlags: rd wr mr mw me ac sd
+7f1d3c000000-7f1d3c021000 rw-p 00000000 00:00 0
+Size:                132 kB
+Rss:                 100 kB
+7f1d40a00000-7f1d40c00000 r--p 00000000 fd:01 1050210                    /usr/lib/libruby.so.3.2
+Rss:                1000 kB
+";
+
+    #[test]
+    fn test_parse() {
+        let smaps = parse(&mut Cursor::new(SMAPS)).unwrap();
+        assert_eq!(
+            Smaps {
+                rss: 24104 * 1024,
+                file: 1004 * 1024,
+                anonymous: 23100 * 1024,
+            },
+            smaps
+        );
+
+        let accounting = account(smaps, 20000 * 1024);
+        assert_eq!(3100 * 1024, accounting.native);
+        assert_eq!(0, account(smaps, usize::MAX).native);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(parse(&mut Cursor::new("Rss: lots kB\n")).is_err());
+        assert!(parse(&mut Cursor::new("55d0c8a00000-55d0c8a01000 r-xp\n")).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+// A binary copy of a parsed graph, so that repeated analyses of the same dump
+// can skip parsing it, which dominates their runtime.
+use crate::object::*;
+use crate::parse::ParseOptions;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Read, Write};
+
+const MAGIC: &[u8; 8] = b"REAPSNAP";
+
+// Bumped whenever the layout of snapshots or of the graph changes
+const VERSION: u32 = 4;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    IoError(std::io::Error),
+    NotASnapshot,
+    UnsupportedVersion(u32),
+    EncodingError(bincode::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::IoError(err) => write!(f, "Could not access snapshot: {}", err),
+            SnapshotError::NotASnapshot => write!(f, "Not a reap snapshot"),
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "Snapshot version {} isn't supported (expected {}); parse the dump again",
+                version, VERSION
+            ),
+            SnapshotError::EncodingError(err) => write!(f, "Invalid snapshot: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(err: std::io::Error) -> Self {
+        SnapshotError::IoError(err)
+    }
+}
+
+impl From<bincode::Error> for SnapshotError {
+    fn from(err: bincode::Error) -> Self {
+        SnapshotError::EncodingError(err)
+    }
+}
+
+// Parse options the graph was produced with, which affect its contents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub class_name_only: bool,
+    pub cut_edges_from: Vec<String>,
+}
+
+impl From<&ParseOptions> for Metadata {
+    fn from(options: &ParseOptions) -> Self {
+        Metadata {
+            class_name_only: options.class_name_only,
+            cut_edges_from: options
+                .cut_edges_from
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+        }
+    }
+}
+
+pub fn save<W: Write>(
+    writer: &mut W,
+    root: NodeIndex<usize>,
+    graph: &ReferenceGraph,
+    metadata: &Metadata,
+) -> Result<(), SnapshotError> {
+    writer.write_all(MAGIC)?;
+    bincode::serialize_into(&mut *writer, &VERSION)?;
+    bincode::serialize_into(&mut *writer, metadata)?;
+    bincode::serialize_into(&mut *writer, &root.index())?;
+
+    // Edges all have the same weight, so only their endpoints are kept
+    let nodes: Vec<&Object> = graph.raw_nodes().iter().map(|n| &n.weight).collect();
+    let edges: Vec<(usize, usize)> = graph
+        .raw_edges()
+        .iter()
+        .map(|e| (e.source().index(), e.target().index()))
+        .collect();
+    bincode::serialize_into(&mut *writer, &nodes)?;
+    bincode::serialize_into(&mut *writer, &edges)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load<R: Read>(
+    reader: &mut R,
+) -> Result<(NodeIndex<usize>, ReferenceGraph, Metadata), SnapshotError> {
+    let mut magic = [0; MAGIC.len()];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|_| SnapshotError::NotASnapshot)?;
+    if &magic != MAGIC {
+        return Err(SnapshotError::NotASnapshot);
+    }
+    let version: u32 = bincode::deserialize_from(&mut *reader)?;
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    let metadata: Metadata = bincode::deserialize_from(&mut *reader)?;
+    let root: usize = bincode::deserialize_from(&mut *reader)?;
+    let nodes: Vec<Object> = bincode::deserialize_from(&mut *reader)?;
+    let edges: Vec<(usize, usize)> = bincode::deserialize_from(&mut *reader)?;
+    if root >= nodes.len() || edges.iter().any(|&(a, b)| a.max(b) >= nodes.len()) {
+        return Err(SnapshotError::NotASnapshot);
+    }
+
+    let mut graph = ReferenceGraph::with_capacity(nodes.len(), edges.len());
+    for node in nodes {
+        graph.add_node(node);
+    }
+    for (a, b) in edges {
+        graph.add_edge(NodeIndex::new(a), NodeIndex::new(b), EDGE_WEIGHT);
+    }
+    Ok((NodeIndex::new(root), graph, metadata))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parse;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_round_trip() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let options = ParseOptions {
+            cut_edges_from: vec!["Thread".parse().unwrap()],
+            ..Default::default()
+        };
+        let (root, graph) = parse::parse_with_options(&mut reader, &options).unwrap();
+
+        let mut buf = vec![];
+        save(&mut buf, root, &graph, &Metadata::from(&options)).unwrap();
+        let (loaded_root, loaded, metadata) = load(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(root, loaded_root);
+        assert_eq!(graph.node_count(), loaded.node_count());
+        assert!(graph
+            .raw_edges()
+            .iter()
+            .zip(loaded.raw_edges())
+            .all(|(a, b)| (a.source(), a.target()) == (b.source(), b.target())));
+        assert_eq!(graph[root].address, loaded[loaded_root].address);
+        assert_eq!(vec!["Thread".to_string()], metadata.cut_edges_from);
+        assert!(graph
+            .node_indices()
+            .all(|i| graph[i] == loaded[i] && graph[i].label == loaded[i].label));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(matches!(
+            load(&mut b"{\"type\":".as_slice()),
+            Err(SnapshotError::NotASnapshot)
+        ));
+
+        let mut buf = MAGIC.to_vec();
+        buf.extend(bincode::serialize(&(VERSION + 1)).unwrap());
+        assert!(matches!(
+            load(&mut buf.as_slice()),
+            Err(SnapshotError::UnsupportedVersion(_))
+        ));
+    }
+}
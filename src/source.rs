@@ -0,0 +1,253 @@
+// Where heap graphs come from. Implement HeapSource to analyze dumps in
+// other formats with the rest of reap, without changing parse.rs.
+use crate::edgelist;
+use crate::object::*;
+use crate::parse::{self, ParseOptions};
+use petgraph::graph::NodeIndex;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+pub type SourceError = Box<dyn std::error::Error + Send + Sync>;
+
+pub trait HeapSource {
+    // Describes the source in messages, e.g. with a file name.
+    fn describe(&self) -> String;
+
+    // Produces the graph of objects, with the index of its root.
+    fn load(
+        &mut self,
+        options: &ParseOptions,
+    ) -> Result<(NodeIndex<usize>, ReferenceGraph), SourceError>;
+}
+
+// A dump written by Ruby's ObjectSpace.dump_all, with one JSON object per line.
+pub struct RubyJson<R> {
+    reader: R,
+    name: String,
+}
+
+impl RubyJson<BufReader<File>> {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(RubyJson::new(
+            BufReader::new(File::open(path)?),
+            path.display().to_string(),
+        ))
+    }
+}
+
+impl<R: BufRead + Seek> RubyJson<R> {
+    pub fn new(reader: R, name: String) -> Self {
+        RubyJson { reader, name }
+    }
+}
+
+impl<R: BufRead + Seek> HeapSource for RubyJson<R> {
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+
+    fn load(
+        &mut self,
+        options: &ParseOptions,
+    ) -> Result<(NodeIndex<usize>, ReferenceGraph), SourceError> {
+        Ok(parse::parse_with_options(&mut self.reader, options)?)
+    }
+}
+
+// A graph in reap's generic edge-list format, described in edgelist.rs.
+pub struct EdgeList<R> {
+    reader: R,
+    name: String,
+}
+
+impl EdgeList<BufReader<File>> {
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(EdgeList::new(
+            BufReader::new(File::open(path)?),
+            path.display().to_string(),
+        ))
+    }
+}
+
+impl<R: BufRead> EdgeList<R> {
+    pub fn new(reader: R, name: String) -> Self {
+        EdgeList { reader, name }
+    }
+}
+
+impl<R: BufRead> HeapSource for EdgeList<R> {
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+
+    fn load(
+        &mut self,
+        options: &ParseOptions,
+    ) -> Result<(NodeIndex<usize>, ReferenceGraph), SourceError> {
+        Ok(edgelist::parse(&mut self.reader, options)?)
+    }
+}
+
+// How long to wait for a live process to finish dumping its heap
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// A running Ruby process with rbtrace required, which is asked to dump its heap.
+pub struct LiveProcess {
+    pub pid: u32,
+
+    // Where the process writes its dump
+    pub output: PathBuf,
+
+    pub timeout: Duration,
+}
+
+impl LiveProcess {
+    pub fn new(pid: u32) -> Self {
+        LiveProcess {
+            pid,
+            output: std::env::temp_dir().join(format!("reap-{}.json", pid)),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    // Ruby run in the process: the dump is renamed into place once written,
+    // so that its appearance means it's complete.
+    fn script(&self) -> String {
+        let output = self.output.display().to_string();
+        let partial = format!("{}.partial", output);
+        format!(
+            "Thread.new{{require 'objspace';f=open({:?},'w');\
+             ObjectSpace.dump_all(output: f, full: true);f.close;\
+             File.rename({:?},{:?})}}",
+            partial, partial, output
+        )
+    }
+
+    // Has the process dump its heap, returning the path of the dump.
+    pub fn capture(&self) -> Result<PathBuf, SourceError> {
+        if self.output.exists() {
+            std::fs::remove_file(&self.output)?;
+        }
+
+        let status = Command::new("rbtrace")
+            .arg("-p")
+            .arg(self.pid.to_string())
+            .arg("-e")
+            .arg(self.script())
+            .status()
+            .map_err(|e| format!("Could not run rbtrace: {}", e))?;
+        if !status.success() {
+            return Err(format!(
+                "rbtrace failed ({}); is process {} running with rbtrace required?",
+                status, self.pid
+            )
+            .into());
+        }
+
+        let start = Instant::now();
+        while !self.output.exists() {
+            if start.elapsed() > self.timeout {
+                return Err(format!(
+                    "Process {} didn't write {} within {}s",
+                    self.pid,
+                    self.output.display(),
+                    self.timeout.as_secs()
+                )
+                .into());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        Ok(self.output.clone())
+    }
+}
+
+impl HeapSource for LiveProcess {
+    fn describe(&self) -> String {
+        format!("process {}", self.pid)
+    }
+
+    fn load(
+        &mut self,
+        options: &ParseOptions,
+    ) -> Result<(NodeIndex<usize>, ReferenceGraph), SourceError> {
+        let path = self.capture()?;
+        RubyJson::open(&path)?.load(options)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analyze;
+    use petgraph::Graph;
+
+    // A source of some other format, here a fixed chain of objects
+    struct Chain(usize);
+
+    impl HeapSource for Chain {
+        fn describe(&self) -> String {
+            format!("chain of {}", self.0)
+        }
+
+        fn load(
+            &mut self,
+            _: &ParseOptions,
+        ) -> Result<(NodeIndex<usize>, ReferenceGraph), SourceError> {
+            let mut graph: ReferenceGraph = Graph::default();
+            let root = graph.add_node(Object::root());
+            let mut prev = root;
+            for address in 1..=self.0 {
+                let next = graph.add_node(Object {
+                    address,
+                    bytes: 10,
+                    kind: "Node".into(),
+                    class: "Node".into(),
+                    internal_type: "Node".into(),
+                    label: None,
+                    file: None,
+                });
+                graph.add_edge(prev, next, EDGE_WEIGHT);
+                prev = next;
+            }
+            Ok((root, graph))
+        }
+    }
+
+    fn total_bytes(source: &mut dyn HeapSource) -> usize {
+        let (root, graph) = source.load(&ParseOptions::default()).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+        analysis.dominated_totals().bytes
+    }
+
+    #[test]
+    fn test_sources() {
+        let mut json = RubyJson::open(Path::new("test/heap.json")).unwrap();
+        assert_eq!("test/heap.json", json.describe());
+        assert_eq!(3439119, total_bytes(&mut json));
+        assert_eq!(30, total_bytes(&mut Chain(3)));
+
+        let edges = "{\"id\": 1, \"size\": 8, \"type\": \"Node\", \"refs\": [2]}\n\
+                     {\"id\": 2, \"size\": 16, \"type\": \"Node\"}\n";
+        let mut edges = EdgeList::new(std::io::Cursor::new(edges), "edges".to_string());
+        assert_eq!(24, total_bytes(&mut edges));
+    }
+
+    #[test]
+    fn test_live_process_script() {
+        let process = LiveProcess {
+            output: PathBuf::from("/tmp/heap.json"),
+            ..LiveProcess::new(42)
+        };
+        assert_eq!("process 42", process.describe());
+        assert_eq!(
+            "Thread.new{require 'objspace';f=open(\"/tmp/heap.json.partial\",'w');\
+             ObjectSpace.dump_all(output: f, full: true);f.close;\
+             File.rename(\"/tmp/heap.json.partial\",\"/tmp/heap.json\")}",
+            process.script()
+        );
+    }
+}
@@ -0,0 +1,119 @@
+// The dominator tree in speedscope's file format
+// (https://www.speedscope.app/file-format-schema.json), as a sampled profile
+// with one sample per object weighted by its size.
+use crate::analyze::Analysis;
+use serde::Serialize;
+use std::collections::HashMap;
+
+const SCHEMA: &str = "https://www.speedscope.app/file-format-schema.json";
+
+#[derive(Serialize)]
+struct File<'a> {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    shared: Shared,
+    profiles: Vec<Profile<'a>>,
+    name: &'a str,
+    #[serde(rename = "activeProfileIndex")]
+    active_profile_index: usize,
+    exporter: String,
+}
+
+#[derive(Serialize)]
+struct Shared {
+    frames: Vec<Frame>,
+}
+
+#[derive(Serialize)]
+struct Frame {
+    name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Profile<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    name: &'a str,
+    unit: &'static str,
+    start_value: usize,
+    end_value: usize,
+    samples: Vec<Vec<usize>>,
+    weights: Vec<usize>,
+}
+
+// Serializes the dominator tree as a speedscope profile called `name`.
+pub fn to_json(analysis: &Analysis, name: &str) -> serde_json::Result<String> {
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut frame_indices: HashMap<String, usize> = HashMap::new();
+    let mut samples = Vec::new();
+    let mut weights = Vec::new();
+
+    analysis
+        .dominator_stacks(|stack| -> Result<(), ()> {
+            let sample = stack
+                .iter()
+                .map(|obj| {
+                    let name = analysis.frame_name(obj);
+                    *frame_indices.entry(name).or_insert_with_key(|name| {
+                        frames.push(Frame { name: name.clone() });
+                        frames.len() - 1
+                    })
+                })
+                .collect();
+            samples.push(sample);
+            weights.push(stack.last().map_or(0, |obj| obj.bytes));
+            Ok(())
+        })
+        .expect("collecting samples can't fail");
+
+    let file = File {
+        schema: SCHEMA,
+        shared: Shared { frames },
+        profiles: vec![Profile {
+            kind: "sampled",
+            name,
+            unit: "bytes",
+            start_value: 0,
+            end_value: weights.iter().sum(),
+            samples,
+            weights,
+        }],
+        name,
+        active_profile_index: 0,
+        exporter: format!("reap {}", env!("CARGO_PKG_VERSION")),
+    };
+    serde_json::to_string(&file)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use std::io::BufReader;
+
+    #[test]
+    fn test_to_json() {
+        let mut reader = BufReader::new(std::fs::File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, true).unwrap();
+        let analysis = analyze::analyze(root, root, graph, true).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&to_json(&analysis, "heap.json").unwrap()).unwrap();
+        assert_eq!(SCHEMA, json["$schema"]);
+        let frames = json["shared"]["frames"].as_array().unwrap();
+        let profile = &json["profiles"][0];
+        assert_eq!("bytes", profile["unit"]);
+        assert_eq!(
+            profile["samples"].as_array().unwrap().len(),
+            profile["weights"].as_array().unwrap().len()
+        );
+        assert!(profile["samples"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|s| s.as_array().unwrap())
+            .all(|i| (i.as_u64().unwrap() as usize) < frames.len()));
+        assert!(frames.iter().any(|f| f["name"] == "Thread"));
+    }
+}
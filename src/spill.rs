@@ -0,0 +1,715 @@
+// Analysis of dumps larger than memory. The object table, the references,
+// and the working arrays of the dominator computation are kept in temporary
+// files mapped into memory, which the OS can page out, rather than on the
+// heap; only per-kind totals and the largest objects are reported.
+use crate::csr::Csr;
+use crate::dominator;
+use crate::hooks::{Progress, CHECK_INTERVAL};
+use crate::intern::Symbol;
+use crate::object::{GroupBy, KindView, Object, Stats};
+use crate::parse::{
+    check_duplicates, decode, parse_address, Builder, Integrity, Line, Lines, OnDuplicate,
+    ParseError, ParseOptions, References, Skipped, SliceLines, WEAK_REFERENCE_CLASSES,
+};
+use crate::storage::{Allocator, Vector};
+use crate::timing;
+use log::debug;
+use memmap2::{Mmap, MmapMut};
+use petgraph::graph::NodeIndex;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use timed_function::timed;
+
+// Files grow by at least this much at a time
+const MIN_GROWTH: usize = 1 << 16;
+
+// A vector of plain values in a file mapped into memory, which is deleted
+// when the vector is dropped.
+pub struct MmapVec<T> {
+    path: PathBuf,
+    file: File,
+    map: Option<MmapMut>,
+    len: usize,
+    capacity: usize,
+    _values: PhantomData<T>,
+}
+
+impl<T: Copy> MmapVec<T> {
+    fn create(path: PathBuf) -> io::Result<MmapVec<T>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(MmapVec {
+            path,
+            file,
+            map: None,
+            len: 0,
+            capacity: 0,
+            _values: PhantomData,
+        })
+    }
+
+    fn grow(&mut self, capacity: usize) -> io::Result<()> {
+        let grown = (|| {
+            self.map = None;
+            self.file.set_len((capacity * size_of::<T>()) as u64)?;
+            // Safety: the file is private to this vector, which only maps it
+            // once at a time
+            self.map = Some(unsafe { MmapMut::map_mut(&self.file)? });
+            Ok::<_, io::Error>(())
+        })();
+        if let Err(e) = grown {
+            let message = format!("Could not grow {}: {}", self.path.display(), e);
+            return Err(io::Error::new(e.kind(), message));
+        }
+        self.capacity = capacity;
+        Ok(())
+    }
+}
+
+impl<T: Copy> Deref for MmapVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self.map {
+            // Safety: maps are page-aligned, and the first `len` values have
+            // all been written as T
+            Some(ref map) => unsafe { std::slice::from_raw_parts(map.as_ptr().cast(), self.len) },
+            None => &[],
+        }
+    }
+}
+
+impl<T: Copy> DerefMut for MmapVec<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self.map {
+            Some(ref mut map) => unsafe {
+                std::slice::from_raw_parts_mut(map.as_mut_ptr().cast(), self.len)
+            },
+            None => &mut [],
+        }
+    }
+}
+
+impl<T: Copy> Vector<T> for MmapVec<T> {
+    type Error = io::Error;
+
+    fn push(&mut self, value: T) -> io::Result<()> {
+        if self.len == self.capacity {
+            let min = MIN_GROWTH / size_of::<T>().max(1);
+            self.grow((self.capacity * 2).max(min))?;
+        }
+        if let Some(ref mut map) = self.map {
+            // Safety: within capacity, which the map covers
+            unsafe { map.as_mut_ptr().cast::<T>().add(self.len).write(value) };
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let value = *self.last()?;
+        self.len -= 1;
+        Some(value)
+    }
+}
+
+impl<T> Drop for MmapVec<T> {
+    fn drop(&mut self) {
+        self.map = None;
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// A directory for the temporary files of an analysis.
+pub struct SpillDir {
+    dir: PathBuf,
+    files: AtomicUsize,
+}
+
+impl SpillDir {
+    pub fn new(dir: &Path) -> io::Result<SpillDir> {
+        let spill = SpillDir {
+            dir: dir.to_path_buf(),
+            files: AtomicUsize::new(0),
+        };
+        // Check the directory is writable up front
+        spill.create::<u8>()?;
+        Ok(spill)
+    }
+
+    fn create<T: Copy>(&self) -> io::Result<MmapVec<T>> {
+        let n = self.files.fetch_add(1, Ordering::Relaxed);
+        MmapVec::create(
+            self.dir
+                .join(format!("reap-{}-{}.tmp", std::process::id(), n)),
+        )
+    }
+}
+
+impl Allocator for SpillDir {
+    type Error = io::Error;
+    type Vec<T: Copy> = MmapVec<T>;
+
+    fn filled<T: Copy>(&self, len: usize, value: T) -> io::Result<MmapVec<T>> {
+        let mut vec = self.empty()?;
+        if len > 0 {
+            vec.grow(len)?;
+        }
+        for _ in 0..len {
+            vec.push(value)?;
+        }
+        Ok(vec)
+    }
+
+    fn empty<T: Copy>(&self) -> io::Result<MmapVec<T>> {
+        self.create().map_err(|e| {
+            let message = format!("Could not create a file in {}: {}", self.dir.display(), e);
+            io::Error::new(e.kind(), message)
+        })
+    }
+}
+
+// An object, as much of it as the report needs.
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    address: usize,
+    bytes: usize,
+    kind: Symbol,
+
+    // The address of its class, until kinds are resolved
+    class: usize,
+
+    // Where its line starts, to label it if it's reported
+    offset: u64,
+
+    // Whether another object with the same address took its place
+    dropped: bool,
+}
+
+impl Record {
+    fn stats(&self) -> Stats {
+        Stats {
+            count: 1,
+            bytes: self.bytes,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Report {
+    // Per-kind rows, largest first
+    pub live_by_kind: Vec<(Symbol, Stats)>,
+    pub retained_by_kind: Vec<(Symbol, Stats)>,
+    pub unreachable_by_kind: Vec<(Symbol, Stats)>,
+
+    // The objects retaining the most memory, largest first, and the total
+    // retained by each of the others
+    pub largest: Vec<(Object, Stats)>,
+    pub others: Stats,
+}
+
+fn sorted(by_kind: FxHashMap<Symbol, Stats>) -> Vec<(Symbol, Stats)> {
+    let mut rows: Vec<(Symbol, Stats)> = by_kind.into_iter().collect();
+    rows.sort_unstable_by(|(a, x), (b, y)| y.bytes.cmp(&x.bytes).then_with(|| a.cmp(b)));
+    rows
+}
+
+// The object table, with the root first, from the first pass over the dump.
+#[timed]
+fn read_objects(
+    lines: &mut SliceLines,
+    dir: &SpillDir,
+    options: &ParseOptions,
+    view: KindView,
+    group_by: GroupBy,
+    skipped: &mut Skipped,
+    integrity: &mut Integrity,
+) -> Result<MmapVec<Record>, ParseError> {
+    // Labels are only read for the objects reported
+    let decode_options = ParseOptions {
+        class_name_only: true,
+        ..options.clone()
+    };
+    let mut objects: MmapVec<Record> = dir.empty()?;
+    let root = Object::root();
+    objects.push(Record {
+        address: root.address,
+        bytes: root.bytes,
+        kind: root.kind,
+        class: 0,
+        offset: 0,
+        dropped: false,
+    })?;
+    let mut names: FxHashMap<usize, Symbol> = FxHashMap::default();
+
+    let mut scratch = vec![];
+    let (mut count, mut bytes) = (0, 0);
+    lines.each(|offset, line| {
+        count += 1;
+        bytes += line.len() as u64;
+        if count % CHECK_INTERVAL == 0 {
+            options.hooks.check()?;
+            options.hooks.report(Progress::Parsing {
+                lines: count,
+                bytes,
+            });
+        }
+        let parsed = match Builder::decode_object(&decode_options, line, &mut scratch) {
+            Ok(Some(parsed)) => parsed,
+            Ok(None) => {
+                integrity.roots += 1;
+                return Ok(());
+            }
+            Err(err) => {
+                return skipped
+                    .skip(options, offset, err)
+                    .map_err(|err| err.at(count, offset, line))
+            }
+        };
+        integrity.objects += 1;
+        if let Some(name) = parsed.name {
+            options.keep(&mut names, parsed.object.address, name.into());
+        }
+        objects.push(Record {
+            address: parsed.object.address,
+            bytes: parsed.object.bytes,
+            kind: parsed.object.kind,
+            class: parsed.module.unwrap_or(0),
+            offset,
+            dropped: false,
+        })?;
+        Ok(())
+    })?;
+
+    // Until now each record's kind is its internal type
+    let mut kinds: FxHashMap<(Symbol, Symbol), Symbol> = FxHashMap::default();
+    for record in objects.iter_mut() {
+        if let Some(name) = names.get(&record.class) {
+            record.kind = *kinds
+                .entry((*name, record.kind))
+                .or_insert_with(|| group_by.kind(view.kind(*name, record.kind)));
+        }
+    }
+    Ok(objects)
+}
+
+// (address, position) pairs, sorted by address
+type AddressIndex = MmapVec<(usize, usize)>;
+
+// The position of each object in the object table, sorted by address, with
+// one of several objects with the same address chosen by the duplicate
+// policy, and the others marked dropped. Also returns their lines.
+#[timed]
+fn index(
+    dir: &SpillDir,
+    options: &ParseOptions,
+    objects: &mut MmapVec<Record>,
+) -> Result<(AddressIndex, FxHashSet<u64>), ParseError> {
+    let policy = options.on_duplicate.unwrap_or(OnDuplicate::Last);
+    let mut sorted: MmapVec<(usize, usize)> = dir.empty()?;
+    for (i, record) in objects.iter().enumerate() {
+        sorted.push((record.address, i))?;
+    }
+    sorted.sort_unstable();
+
+    let mut index: AddressIndex = dir.empty()?;
+    let mut dropped = vec![];
+    for run in sorted.chunk_by(|(a, _), (b, _)| a == b) {
+        let (kept, others) = match policy {
+            OnDuplicate::First => (run[0], &run[1..]),
+            _ => (run[run.len() - 1], &run[..run.len() - 1]),
+        };
+        index.push(kept)?;
+        dropped.extend(others.iter().map(|(_, i)| *i));
+    }
+    dropped.sort_unstable();
+    let first = dropped.first().map_or(0, |i| objects[*i].address);
+    check_duplicates(policy, dropped.len(), first)?;
+
+    let mut lines = FxHashSet::default();
+    for i in dropped {
+        objects[i].dropped = true;
+        lines.insert(objects[i].offset);
+    }
+    Ok((index, lines))
+}
+
+// The references between objects, by position in the object table, from the
+// second pass over the dump.
+#[timed]
+#[allow(clippy::too_many_arguments)]
+fn read_references(
+    lines: &mut SliceLines,
+    dir: &SpillDir,
+    options: &ParseOptions,
+    objects: &MmapVec<Record>,
+    index: &AddressIndex,
+    duplicates: &FxHashSet<u64>,
+    skipped: &mut Skipped,
+    integrity: &mut Integrity,
+) -> Result<MmapVec<(usize, usize)>, ParseError> {
+    let find = |address: usize| {
+        let k = index.binary_search_by_key(&address, |(a, _)| *a).ok()?;
+        Some(index[k].1)
+    };
+
+    let mut edges: MmapVec<(usize, usize)> = dir.empty()?;
+    let mut scratch = vec![];
+    let (mut count, mut cut) = (0, 0);
+    lines.each(|offset, line| {
+        count += 1;
+        if count % CHECK_INTERVAL == 0 {
+            options.hooks.check()?;
+        }
+        if skipped.contains(offset) || duplicates.contains(&offset) {
+            return Ok(());
+        }
+        let parsed: References = match decode(line, &mut scratch) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                return skipped
+                    .skip(options, offset, err)
+                    .map_err(|err| err.at(count, offset, line))
+            }
+        };
+        if parsed.references.is_empty() {
+            return Ok(());
+        }
+        // Only ROOT lines lack an address, as the first pass checked
+        let address = match parsed.address {
+            Some(address) => parse_address(address).unwrap_or(0),
+            None => 0,
+        };
+        let i = find(address).expect("the first pass read every object");
+        let kind = objects[i].kind.as_str();
+        if WEAK_REFERENCE_CLASSES.contains(&kind)
+            || options.cut_edges_from.iter().any(|p| p.matches(kind))
+        {
+            cut += parsed.references.len();
+            return Ok(());
+        }
+        let mut dangling = 0;
+        for &s in &parsed.references {
+            match parse_address(s).ok().and_then(find) {
+                Some(j) => edges.push((i, j))?,
+                None => dangling += 1,
+            }
+        }
+        integrity.add_references(parsed.references.len(), dangling);
+        Ok(())
+    })?;
+    debug!(
+        "Spilled {} objects & {} references ({} cut)",
+        objects.len(),
+        edges.len(),
+        cut
+    );
+    Ok(edges)
+}
+
+// Adds references from the root to the objects nothing references, as dumps
+// from older tooling lack ROOT lines, returning how many there are.
+fn synthesize_roots(
+    dir: &SpillDir,
+    objects: &MmapVec<Record>,
+    edges: &mut MmapVec<(usize, usize)>,
+) -> io::Result<usize> {
+    let mut referenced: MmapVec<bool> = dir.filled(objects.len(), false)?;
+    referenced[0] = true;
+    for &(_, j) in edges.iter() {
+        referenced[j] = true;
+    }
+    let mut roots = 0;
+    for (i, record) in objects.iter().enumerate() {
+        if !referenced[i] && !record.dropped {
+            edges.push((0, i))?;
+            roots += 1;
+        }
+    }
+    Ok(roots)
+}
+
+// Labels an object the way parsing the dump normally would.
+fn object(dump: &[u8], record: &Record, options: &ParseOptions) -> Result<Object, ParseError> {
+    if record.address == 0 {
+        return Ok(Object::root());
+    }
+    let start = record.offset as usize;
+    let end = dump[start..]
+        .iter()
+        .position(|b| *b == 0x0A)
+        .map_or(dump.len(), |n| start + n);
+    let mut scratch = vec![];
+    let line: Line = decode(&dump[start..end], &mut scratch)?;
+    let parsed = line.parse(options.class_name_only);
+    Ok(Object {
+        address: record.address,
+        bytes: record.bytes,
+        kind: record.kind,
+        // Spilled reports don't filter by class, so the kind stands in
+        class: record.kind,
+        internal_type: parsed
+            .as_ref()
+            .map_or(record.kind, |parsed| parsed.object.internal_type),
+        file: parsed.as_ref().and_then(|parsed| parsed.object.file),
+        label: parsed.and_then(|parsed| parsed.object.label),
+    })
+}
+
+#[timed]
+pub fn analyze(
+    path: &Path,
+    dir: &SpillDir,
+    options: &ParseOptions,
+    view: KindView,
+    group_by: GroupBy,
+    count: usize,
+) -> Result<Report, ParseError> {
+    let file = File::open(path)?;
+    // Safety: the dump mustn't be modified while it's being analyzed, as with
+    // any other reader of it
+    let map = if file.metadata()?.len() > 0 {
+        Some(unsafe { Mmap::map(&file)? })
+    } else {
+        None
+    };
+    let dump: &[u8] = map.as_deref().unwrap_or(&[]);
+    let mut lines = SliceLines(dump);
+
+    let mut skipped = Skipped::default();
+    let mut integrity = Integrity::default();
+    let mut objects = read_objects(
+        &mut lines,
+        dir,
+        options,
+        view,
+        group_by,
+        &mut skipped,
+        &mut integrity,
+    )?;
+    let graph = {
+        let (index, duplicates) = index(dir, options, &mut objects)?;
+        let mut edges = read_references(
+            &mut lines,
+            dir,
+            options,
+            &objects,
+            &index,
+            &duplicates,
+            &mut skipped,
+            &mut integrity,
+        )?;
+        if integrity.roots == 0 {
+            integrity.synthesized_roots = synthesize_roots(dir, &objects, &mut edges)?;
+        }
+        Csr::build(dir, objects.len(), edges.len(), || edges.iter().copied())?
+    };
+    skipped.report();
+    integrity.incomplete_last_line = skipped.truncated();
+    integrity.report();
+    timing::nodes(objects.len());
+
+    options.hooks.check()?;
+    options.hooks.report(Progress::Analyzing {
+        phase: "dominators",
+    });
+    let tree = dominator::dominator_tree(&graph, NodeIndex::new(0), dir)?;
+    drop(graph);
+
+    // Each node's dominator precedes it, so adding each node's subtree to its
+    // dominator's in reverse leaves each subtree complete before it's added
+    options.hooks.check()?;
+    options.hooks.report(Progress::Analyzing {
+        phase: "subtree sizes",
+    });
+    let n = tree.vertex.len();
+    let mut sizes: MmapVec<Stats> = dir.filled(n, Stats::default())?;
+    for w in 0..n {
+        sizes[w] = objects[tree.vertex[w]].stats();
+    }
+    for w in (1..n).rev() {
+        let (d, stats) = (tree.idom[w], sizes[w]);
+        sizes[d] = sizes[d].add(stats);
+    }
+
+    let mut reachable = dir.filled(objects.len(), false)?;
+    let mut live_by_kind: FxHashMap<Symbol, Stats> = FxHashMap::default();
+    let mut retained_by_kind: FxHashMap<Symbol, Stats> = FxHashMap::default();
+    let mut largest = BinaryHeap::new();
+    let mut others = Stats::default();
+    for w in 0..n {
+        let i = tree.vertex[w];
+        let record = &objects[i];
+        reachable[i] = true;
+        let live = live_by_kind.entry(record.kind).or_default();
+        *live = live.add(record.stats());
+        let retained = retained_by_kind.entry(record.kind).or_default();
+        *retained = retained.add(sizes[w]);
+
+        // Keep the largest, breaking ties by address
+        largest.push(Reverse((sizes[w].bytes, Reverse(record.address), w)));
+        if largest.len() > count {
+            let Reverse((_, _, w)) = largest.pop().unwrap();
+            others = others.add(sizes[w]);
+        }
+    }
+
+    let mut unreachable_by_kind: FxHashMap<Symbol, Stats> = FxHashMap::default();
+    for (i, record) in objects.iter().enumerate() {
+        if !reachable[i] && !record.dropped {
+            let stats = unreachable_by_kind.entry(record.kind).or_default();
+            *stats = stats.add(record.stats());
+        }
+    }
+
+    let largest = largest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((_, _, w))| {
+            let object = object(dump, &objects[tree.vertex[w]], options)?;
+            Ok((object, sizes[w]))
+        })
+        .collect::<Result<Vec<_>, ParseError>>()?;
+    Ok(Report {
+        live_by_kind: sorted(live_by_kind),
+        retained_by_kind: sorted(retained_by_kind),
+        unreachable_by_kind: sorted(unreachable_by_kind),
+        largest,
+        others,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analyze;
+    use crate::parse;
+    use rstest::rstest;
+    use std::io::Cursor;
+
+    fn by_kind(rows: Vec<(&Symbol, Stats)>) -> FxHashMap<Symbol, (usize, usize)> {
+        rows.into_iter()
+            .map(|(k, s)| (*k, (s.count, s.bytes)))
+            .collect()
+    }
+
+    fn spilled(rows: &[(Symbol, Stats)]) -> FxHashMap<Symbol, (usize, usize)> {
+        rows.iter().map(|(k, s)| (*k, (s.count, s.bytes))).collect()
+    }
+
+    #[rstest]
+    #[case::whole("whole", false)]
+    #[case::without_roots("without-roots", true)]
+    fn test_matches_analysis(#[case] name: &str, #[case] without_roots: bool) {
+        let dir =
+            std::env::temp_dir().join(format!("reap-spill-test-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut dump = std::fs::read("test/heap.json").unwrap();
+        if without_roots {
+            dump = dump
+                .split_inclusive(|b| *b == 0x0A)
+                .filter(|line| !line.starts_with(br#"{"type":"ROOT""#))
+                .flatten()
+                .copied()
+                .collect();
+        }
+        let path = dir.with_extension("json");
+        std::fs::write(&path, &dump).unwrap();
+        let report = analyze(
+            &path,
+            &SpillDir::new(&dir).unwrap(),
+            &ParseOptions::default(),
+            KindView::Class,
+            GroupBy::Class,
+            5,
+        )
+        .unwrap();
+        // Every file is deleted once it's dropped
+        assert_eq!(0, std::fs::read_dir(&dir).unwrap().count());
+        std::fs::remove_dir(&dir).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let (root, graph) = parse::parse(&mut Cursor::new(&dump), false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+
+        assert_eq!(
+            by_kind(analysis.live_stats_by_kind(usize::MAX).0),
+            spilled(&report.live_by_kind)
+        );
+        assert_eq!(
+            by_kind(analysis.retained_stats_by_kind(usize::MAX).0),
+            spilled(&report.retained_by_kind)
+        );
+        assert_eq!(
+            by_kind(analysis.unreachable_stats_by_kind(usize::MAX).0),
+            spilled(&report.unreachable_by_kind)
+        );
+
+        let (largest, others) = analysis.dominator_subtree_stats(5);
+        let rows: Vec<(String, usize)> = largest
+            .iter()
+            .map(|(o, s)| (o.to_string(), s.bytes))
+            .collect();
+        let spilled_rows: Vec<(String, usize)> = report
+            .largest
+            .iter()
+            .map(|(o, s)| (o.to_string(), s.bytes))
+            .collect();
+        assert_eq!(rows, spilled_rows);
+        assert_eq!(others.bytes, report.others.bytes);
+    }
+
+    #[test]
+    fn test_write_errors() {
+        let dir = std::env::temp_dir().join(format!("reap-spill-test-{}-gone", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let spill_dir = SpillDir::new(&dir).unwrap();
+        // Files can no longer be created, as when the disk fills up
+        std::fs::remove_dir(&dir).unwrap();
+
+        let result = analyze(
+            Path::new("test/heap.json"),
+            &spill_dir,
+            &ParseOptions::default(),
+            KindView::Class,
+            GroupBy::Class,
+            5,
+        );
+        match result {
+            Err(ParseError::IoError(err)) => {
+                assert!(err.to_string().starts_with("Could not create a file in"))
+            }
+            _ => panic!("Expected an I/O error"),
+        }
+    }
+
+    #[test]
+    fn test_mmap_vec() {
+        let dir = SpillDir::new(&std::env::temp_dir()).unwrap();
+        let mut vec: MmapVec<(usize, u32)> = dir.empty().unwrap();
+        assert_eq!(None, vec.pop());
+        for i in 0..100_000 {
+            vec.push((i, i as u32)).unwrap();
+        }
+        vec[7].1 = 0;
+        assert_eq!(100_000, vec.len());
+        assert_eq!((7, 0), vec[7]);
+        assert_eq!(Some((99_999, 99_999)), vec.pop());
+
+        let path = vec.path.clone();
+        assert!(path.exists());
+        drop(vec);
+        assert!(!path.exists());
+    }
+}
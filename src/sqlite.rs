@@ -0,0 +1,150 @@
+// Objects dominated by the root, their references and the dominator tree as
+// an SQLite database, for ad-hoc SQL over the heap, e.g.
+//
+//   SELECT kind, sum(retained_bytes) FROM objects o
+//   JOIN dominators d ON d.address = o.address AND d.dominator = 0
+//   GROUP BY kind;
+//
+// Unreachable objects are left out, since their references aren't kept.
+use crate::analyze::Analysis;
+use rusqlite::{params, Connection};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum SqliteError {
+    IoError(std::io::Error),
+    DatabaseError(rusqlite::Error),
+}
+
+impl fmt::Display for SqliteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqliteError::IoError(err) => write!(f, "Could not replace database: {}", err),
+            SqliteError::DatabaseError(err) => write!(f, "Could not write database: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SqliteError {}
+
+impl From<rusqlite::Error> for SqliteError {
+    fn from(err: rusqlite::Error) -> Self {
+        SqliteError::DatabaseError(err)
+    }
+}
+
+const SCHEMA: &str = "
+CREATE TABLE objects (
+    address INTEGER PRIMARY KEY,
+    kind TEXT NOT NULL,
+    label TEXT,
+    bytes INTEGER NOT NULL,
+    retained_bytes INTEGER NOT NULL,
+    retained_count INTEGER NOT NULL
+);
+CREATE TABLE \"references\" (
+    from_address INTEGER NOT NULL,
+    to_address INTEGER NOT NULL
+);
+CREATE TABLE dominators (
+    address INTEGER PRIMARY KEY,
+    dominator INTEGER NOT NULL
+);
+";
+
+// Created after inserting, which is faster than maintaining them throughout
+const INDEXES: &str = "
+CREATE INDEX objects_kind ON objects (kind);
+CREATE INDEX references_from ON \"references\" (from_address);
+CREATE INDEX references_to ON \"references\" (to_address);
+CREATE INDEX dominators_dominator ON dominators (dominator);
+";
+
+// Addresses fit in SQLite's signed 64-bit integers
+fn int(n: usize) -> i64 {
+    n as i64
+}
+
+// Writes the database to `path`, replacing any existing file, and returns the
+// number of objects written.
+pub fn write(analysis: &Analysis, path: &Path) -> Result<usize, SqliteError> {
+    if path.exists() {
+        std::fs::remove_file(path).map_err(SqliteError::IoError)?;
+    }
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF;")?;
+    conn.execute_batch(SCHEMA)?;
+
+    let tx = conn.transaction()?;
+    let mut count = 0;
+    {
+        let mut insert = tx.prepare("INSERT INTO objects VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+        for (obj, retained) in analysis.objects() {
+            insert.execute(params![
+                int(obj.address),
+                obj.kind.as_str(),
+                obj.label.as_deref(),
+                int(obj.bytes),
+                int(retained.bytes),
+                int(retained.count),
+            ])?;
+            count += 1;
+        }
+
+        let mut insert = tx.prepare("INSERT INTO \"references\" VALUES (?1, ?2)")?;
+        for (from, to) in analysis.references() {
+            insert.execute(params![int(from.address), int(to.address)])?;
+        }
+
+        let mut insert = tx.prepare("INSERT INTO dominators VALUES (?1, ?2)")?;
+        for (dominator, obj) in analysis.dominator_edges() {
+            insert.execute(params![int(obj.address), int(dominator.address)])?;
+        }
+    }
+    tx.commit()?;
+
+    conn.execute_batch(INDEXES)?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use std::fs::File;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_write() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+
+        let path = std::env::temp_dir().join(format!("reap-{}.sqlite", std::process::id()));
+        assert_eq!(15472, write(&analysis, &path).unwrap());
+        // Overwrites the previous database
+        assert_eq!(15472, write(&analysis, &path).unwrap());
+
+        let conn = Connection::open(&path).unwrap();
+        let retained: i64 = conn
+            .query_row(
+                "SELECT retained_bytes FROM objects WHERE address = ?1",
+                [0x7f83df87dc40_i64],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(1053052, retained);
+
+        let dominated: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM dominators d JOIN \"references\" r
+                 ON r.to_address = d.address AND r.from_address = d.dominator",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(dominated > 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
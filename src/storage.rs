@@ -0,0 +1,51 @@
+// Where the large arrays of graph algorithms live: on the heap, or, for
+// dumps larger than memory, in files mapped into it (see spill.rs).
+// Files can fail to grow, e.g. when the disk is full, the heap can't.
+use std::convert::Infallible;
+use std::ops::{Deref, DerefMut};
+
+pub trait Vector<T: Copy>: Deref<Target = [T]> + DerefMut {
+    type Error;
+
+    fn push(&mut self, value: T) -> Result<(), Self::Error>;
+    fn pop(&mut self) -> Option<T>;
+}
+
+impl<T: Copy> Vector<T> for Vec<T> {
+    type Error = Infallible;
+
+    fn push(&mut self, value: T) -> Result<(), Infallible> {
+        Vec::push(self, value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        Vec::pop(self)
+    }
+}
+
+pub trait Allocator {
+    type Error;
+    type Vec<T: Copy>: Vector<T, Error = Self::Error>;
+
+    // A vector of `len` copies of `value`.
+    fn filled<T: Copy>(&self, len: usize, value: T) -> Result<Self::Vec<T>, Self::Error>;
+
+    fn empty<T: Copy>(&self) -> Result<Self::Vec<T>, Self::Error>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Heap;
+
+impl Allocator for Heap {
+    type Error = Infallible;
+    type Vec<T: Copy> = Vec<T>;
+
+    fn filled<T: Copy>(&self, len: usize, value: T) -> Result<Vec<T>, Infallible> {
+        Ok(vec![value; len])
+    }
+
+    fn empty<T: Copy>(&self) -> Result<Vec<T>, Infallible> {
+        Ok(vec![])
+    }
+}
@@ -0,0 +1,212 @@
+// A JSON-friendly overview of an analysis, e.g. for notifying other tools.
+use crate::analyze::{Analysis, Origin};
+use crate::intern::Symbol;
+use crate::object::*;
+use crate::query::Query;
+use crate::threshold::Violation;
+use bytesize::ByteSize;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct KindStats {
+    pub kind: String,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OriginStats {
+    pub origin: String,
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ViolationSummary {
+    pub threshold: String,
+    pub actual: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    // One line describing the results, which is also what chat webhooks
+    // such as Slack's display
+    pub text: String,
+    pub live: Stats,
+    pub unreachable: Stats,
+    pub origins: Vec<OriginStats>,
+    pub live_by_kind: Vec<KindStats>,
+    pub retained_by_kind: Vec<KindStats>,
+    pub thresholds: usize,
+    pub violations: Vec<ViolationSummary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObjectSummary {
+    pub address: String,
+    pub kind: String,
+    pub label: String,
+    pub bytes: usize,
+    pub retained: Stats,
+}
+
+// The `count` objects retaining the most memory among those `query` matches.
+pub fn largest_objects(analysis: &Analysis, count: usize, query: &Query) -> Vec<ObjectSummary> {
+    let (largest, _) = analysis.objects_where(count, |obj, stats| {
+        !obj.is_root() && query.matches(obj, stats)
+    });
+    largest
+        .into_iter()
+        .map(|(obj, retained)| ObjectSummary {
+            address: format!("{:#x}", obj.address),
+            kind: obj.kind.to_string(),
+            label: obj.to_string(),
+            bytes: obj.bytes,
+            retained,
+        })
+        .collect()
+}
+
+fn kind_stats(rows: Vec<(&Symbol, Stats)>) -> Vec<KindStats> {
+    rows.into_iter()
+        .map(|(kind, stats)| KindStats {
+            kind: kind.to_string(),
+            stats,
+        })
+        .collect()
+}
+
+// The shares of live memory of the app, all gems, the stdlib and objects of
+// unknown origin, e.g. "app 40.0%, gems 35.0%, stdlib 25.0%".
+fn origin_split(origins: &[(Origin, Stats)], live: Stats) -> String {
+    let mut shares = [("app", 0), ("gems", 0), ("stdlib", 0), ("unknown", 0)];
+    for (origin, stats) in origins {
+        let share = match origin {
+            Origin::App => 0,
+            Origin::Gem(_) => 1,
+            Origin::Stdlib => 2,
+            Origin::Unknown => 3,
+        };
+        shares[share].1 += stats.bytes;
+    }
+    shares
+        .iter()
+        .filter(|(_, bytes)| *bytes > 0)
+        .map(|(name, bytes)| {
+            format!(
+                "{} {:.1}%",
+                name,
+                100.0 * *bytes as f64 / live.bytes.max(1) as f64
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Summarizes the analysis, with the `count` largest rows of per-kind tables
+// and the result of checking `thresholds` thresholds.
+pub fn summarize(
+    analysis: &Analysis,
+    count: usize,
+    thresholds: usize,
+    violations: &[Violation],
+) -> Summary {
+    let live = analysis.dominated_totals();
+    let (_, unreachable) = analysis.unreachable_stats_by_kind(0);
+
+    let origins = analysis.origin_stats();
+
+    let mut text = format!(
+        "reap: {} live in {} objects",
+        ByteSize(live.bytes as u64),
+        live.count
+    );
+    if origins.iter().any(|(origin, _)| *origin != Origin::Unknown) {
+        text.push_str(&format!(" ({})", origin_split(&origins, live)));
+    }
+    if thresholds > 0 {
+        text.push_str(&format!(
+            "; {} of {} thresholds exceeded",
+            violations.len(),
+            thresholds
+        ));
+        for violation in violations {
+            text.push_str(&format!("\n{}", violation));
+        }
+    }
+
+    Summary {
+        text,
+        live,
+        unreachable,
+        origins: origins
+            .into_iter()
+            .map(|(origin, stats)| OriginStats {
+                origin: origin.to_string(),
+                stats,
+            })
+            .collect(),
+        live_by_kind: kind_stats(analysis.live_stats_by_kind(count).0),
+        retained_by_kind: kind_stats(analysis.retained_stats_by_kind(count).0),
+        thresholds,
+        violations: violations
+            .iter()
+            .map(|v| ViolationSummary {
+                threshold: v.threshold.to_string(),
+                actual: v.actual,
+                message: v.to_string(),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::threshold::{self, Limit, Measure, Threshold};
+    use crate::{analyze, parse};
+    use std::fs::File;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_summarize() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+
+        let thresholds = vec![Threshold {
+            class: None,
+            measure: Measure::Live,
+            limit: Limit::Count(1000),
+        }];
+        let violations = threshold::check(&analysis, &thresholds);
+        let summary = summarize(&analysis, 2, thresholds.len(), &violations);
+
+        // The dump doesn't trace allocations
+        assert_eq!(1, summary.origins.len());
+        assert_eq!("(unknown)", summary.origins[0].origin);
+        assert_eq!(2, summary.live_by_kind.len());
+        assert_eq!("Thread", summary.live_by_kind[0].kind);
+        assert_eq!(1, summary.violations.len());
+        assert!(summary
+            .text
+            .starts_with("reap: 3.4 MB live in 15472 objects; 1 of 1 thresholds exceeded\n"));
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(2099840, json["live_by_kind"][0]["bytes"]);
+    }
+
+    #[test]
+    fn test_largest_objects() {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, false).unwrap();
+        let analysis = analyze::analyze(root, root, graph, false).unwrap();
+
+        let query = "class == \"Thread\"".parse().unwrap();
+        let objects = largest_objects(&analysis, 2, &query);
+        assert_eq!(2, objects.len());
+        assert!(objects.iter().all(|o| o.kind == "Thread"));
+        assert!(objects[0].retained.bytes >= objects[1].retained.bytes);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::analyze::Analysis;
+use crate::object::*;
+use bytesize::ByteSize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measure {
+    // Memory used by the matching objects themselves
+    Live,
+    // Memory retained by the matching objects
+    Retained,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Bytes(usize),
+    Count(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Threshold {
+    // Objects the threshold applies to, or the whole heap if none
+    pub class: Option<ClassPattern>,
+    pub measure: Measure,
+    pub limit: Limit,
+}
+
+#[derive(Debug)]
+pub struct Violation<'a> {
+    pub threshold: &'a Threshold,
+    pub actual: usize,
+}
+
+impl Threshold {
+    fn actual(&self, analysis: &Analysis) -> usize {
+        let stats = match (&self.class, self.measure) {
+            (None, _) => analysis.dominated_totals(),
+            (Some(pattern), Measure::Live) => analysis
+                .live_stats_by_kind_where(usize::MAX, |obj| pattern.matches(&obj.class))
+                .0
+                .iter()
+                .fold(Stats::default(), |mut acc, (_, stats)| acc.add(*stats)),
+            (Some(pattern), Measure::Retained) => {
+                analysis.retained_where(|obj| pattern.matches(&obj.class))
+            }
+        };
+
+        match self.limit {
+            Limit::Bytes(_) => stats.bytes,
+            Limit::Count(_) => stats.count,
+        }
+    }
+
+    fn max(&self) -> usize {
+        match self.limit {
+            Limit::Bytes(max) | Limit::Count(max) => max,
+        }
+    }
+
+    fn format_value(&self, value: usize) -> String {
+        match self.limit {
+            Limit::Bytes(_) => ByteSize(value as u64).to_string(),
+            Limit::Count(_) => format!("{} objects", value),
+        }
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let measure = match self.measure {
+            Measure::Live => "live",
+            Measure::Retained => "retained",
+        };
+        let what = match self.limit {
+            Limit::Bytes(_) => "memory",
+            Limit::Count(_) => "objects",
+        };
+        match self.class {
+            Some(ref pattern) => write!(f, "{} {} of {}", measure, what, pattern),
+            None => write!(f, "{} {}", measure, what),
+        }
+    }
+}
+
+impl fmt::Display for Violation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} exceeds {}",
+            self.threshold,
+            self.threshold.format_value(self.actual),
+            self.threshold.format_value(self.threshold.max()),
+        )
+    }
+}
+
+pub fn check<'a>(analysis: &Analysis, thresholds: &'a [Threshold]) -> Vec<Violation<'a>> {
+    thresholds
+        .iter()
+        .filter_map(|threshold| {
+            let actual = threshold.actual(analysis);
+            if actual > threshold.max() {
+                Some(Violation { threshold, actual })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// Parses counts like `2_000_000`
+pub fn parse_count(s: &str) -> Result<usize, String> {
+    s.replace('_', "")
+        .parse()
+        .map_err(|e| format!("Invalid count {:?}: {}", s, e))
+}
+
+pub fn parse_bytes(s: &str) -> Result<usize, String> {
+    s.replace('_', "")
+        .parse::<ByteSize>()
+        .map(|b| b.as_u64() as usize)
+}
+
+fn split_class(s: &str) -> Result<(ClassPattern, &str), String> {
+    let (class, limit) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("Expected CLASS=LIMIT, got {:?}", s))?;
+    let class = class.parse().map_err(|e| format!("{}", e))?;
+    Ok((class, limit))
+}
+
+// Parses `CLASS=SIZE`, e.g. `String=50MB`
+pub fn parse_class_bytes(measure: Measure, s: &str) -> Result<Threshold, String> {
+    let (class, limit) = split_class(s)?;
+    Ok(Threshold {
+        class: Some(class),
+        measure,
+        limit: Limit::Bytes(parse_bytes(limit)?),
+    })
+}
+
+// Parses `CLASS=COUNT`, e.g. `String=100_000`
+pub fn parse_class_count(s: &str) -> Result<Threshold, String> {
+    let (class, limit) = split_class(s)?;
+    Ok(Threshold {
+        class: Some(class),
+        measure: Measure::Live,
+        limit: Limit::Count(parse_count(limit)?),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{analyze, parse};
+    use rstest::rstest;
+    use std::fs::File;
+    use std::io::BufReader;
+
+    fn analysis() -> Analysis {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, false).unwrap();
+        analyze::analyze(root, root, graph, false).unwrap()
+    }
+
+    #[rstest]
+    #[case::live_objects(None, Measure::Live, Limit::Count(15472), false)]
+    #[case::too_many_live_objects(None, Measure::Live, Limit::Count(15471), true)]
+    #[case::live_strings(Some("String"), Measure::Live, Limit::Bytes(462583), false)]
+    #[case::too_many_live_strings(Some("String"), Measure::Live, Limit::Count(9234), true)]
+    #[case::retained_threads(Some("Thread"), Measure::Retained, Limit::Count(70), false)]
+    #[case::too_much_retained_by_threads(
+        Some("Thread"),
+        Measure::Retained,
+        Limit::Bytes(1_000_000),
+        true
+    )]
+    fn test_check(
+        #[case] class: Option<&str>,
+        #[case] measure: Measure,
+        #[case] limit: Limit,
+        #[case] violated: bool,
+    ) {
+        let thresholds = vec![Threshold {
+            class: class.map(|c| c.parse().unwrap()),
+            measure,
+            limit,
+        }];
+        let violations = check(&analysis(), &thresholds);
+        assert_eq!(violated, !violations.is_empty());
+    }
+
+    #[rstest]
+    #[case("String=50MB", Limit::Bytes(50_000_000))]
+    #[case("Foo::Bar=1_000", Limit::Bytes(1000))]
+    fn test_parse_class_bytes(#[case] input: &str, #[case] limit: Limit) {
+        let threshold = parse_class_bytes(Measure::Retained, input).unwrap();
+        assert_eq!(limit, threshold.limit);
+    }
+
+    #[rstest]
+    #[case("2_000_000", Ok(2_000_000))]
+    #[case("lots", Err(()))]
+    fn test_parse_count(#[case] input: &str, #[case] expected: Result<usize, ()>) {
+        assert_eq!(expected, parse_count(input).map_err(|_| ()));
+    }
+}
@@ -0,0 +1,131 @@
+use serde::Serialize;
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+// A timed function call, recorded by #[timed] in the order calls started.
+#[derive(Debug, Clone, Serialize)]
+pub struct Phase {
+    pub name: &'static str,
+
+    // How many timed calls enclosed this one
+    pub depth: usize,
+
+    #[serde(rename = "seconds", serialize_with = "as_seconds")]
+    pub duration: Duration,
+
+    // Size of the graph the phase worked on, if it reported one
+    pub nodes: Option<usize>,
+}
+
+#[derive(Default)]
+struct Registry {
+    phases: Vec<Phase>,
+    open: Vec<usize>,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry::default());
+}
+
+// Records the duration of its phase when dropped.
+pub struct Guard {
+    index: usize,
+    start: Option<Instant>,
+}
+
+// There's no clock in wasm32-unknown-unknown, where Instant::now panics, so
+// phases are recorded there without durations.
+fn now() -> Option<Instant> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+    } else {
+        Some(Instant::now())
+    }
+}
+
+pub fn start(name: &'static str) -> Guard {
+    REGISTRY.with(|r| {
+        let mut r = r.borrow_mut();
+        let index = r.phases.len();
+        let depth = r.open.len();
+        r.phases.push(Phase {
+            name,
+            depth,
+            duration: Duration::ZERO,
+            nodes: None,
+        });
+        r.open.push(index);
+        Guard {
+            index,
+            start: now(),
+        }
+    })
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let elapsed = self.start.map(|start| start.elapsed());
+        REGISTRY.with(|r| {
+            let mut r = r.borrow_mut();
+            r.open.retain(|i| *i != self.index);
+            let phase = &mut r.phases[self.index];
+            if let Some(elapsed) = elapsed {
+                phase.duration = elapsed;
+                log::info!(
+                    "{}: {}.{:03}s",
+                    phase.name,
+                    elapsed.as_secs(),
+                    elapsed.subsec_millis()
+                );
+            }
+        });
+    }
+}
+
+// Attaches a node count to the innermost phase in progress.
+pub fn nodes(count: usize) {
+    REGISTRY.with(|r| {
+        let mut r = r.borrow_mut();
+        if let Some(&i) = r.open.last() {
+            r.phases[i].nodes = Some(count);
+        }
+    });
+}
+
+// Phases recorded so far on this thread, including any still in progress.
+pub fn phases() -> Vec<Phase> {
+    REGISTRY.with(|r| r.borrow().phases.clone())
+}
+
+pub fn to_json() -> serde_json::Result<String> {
+    serde_json::to_string(&phases())
+}
+
+fn as_seconds<S: serde::Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nested_phases() {
+        {
+            let _outer = start("outer");
+            {
+                let _inner = start("inner");
+                nodes(3);
+            }
+            nodes(5);
+        }
+
+        let phases = phases();
+        let summary: Vec<_> = phases.iter().map(|p| (p.name, p.depth, p.nodes)).collect();
+        assert_eq!(vec![("outer", 0, Some(5)), ("inner", 1, Some(3))], summary);
+        assert!(phases[0].duration >= phases[1].duration);
+        assert!(to_json()
+            .unwrap()
+            .starts_with("[{\"name\":\"outer\",\"depth\":0,"));
+    }
+}
@@ -0,0 +1,481 @@
+// Interactive explorer for an analyzed heap, in the spirit of ncdu.
+use bytesize::ByteSize;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::Frame;
+use reap::analyze::{Analysis, SortKey};
+use reap::intern::Symbol;
+use reap::object::*;
+use std::collections::HashMap;
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Classes,
+    Tree,
+}
+
+struct ClassRow {
+    kind: String,
+    live: Stats,
+    retained: Stats,
+}
+
+struct ObjectRow {
+    address: usize,
+    label: String,
+    bytes: usize,
+    retained: Stats,
+}
+
+pub struct App {
+    analysis: Analysis,
+    total: Stats,
+    view: View,
+    sort: SortKey,
+    classes: Vec<ClassRow>,
+    class_state: TableState,
+
+    // Addresses from the root down to the object whose dominator tree
+    // children are listed
+    path: Vec<usize>,
+    children: Vec<ObjectRow>,
+    tree_state: TableState,
+
+    search: String,
+    searching: bool,
+    quit: bool,
+}
+
+pub fn run(analysis: Analysis) -> io::Result<()> {
+    let mut app = App::new(analysis);
+    let mut terminal = ratatui::init();
+    let result = (|| {
+        while !app.quit {
+            terminal.draw(|frame| app.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    app.handle_key(key);
+                }
+            }
+        }
+        Ok(())
+    })();
+    ratatui::restore();
+    result
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+fn share(stats: Stats, total: Stats) -> f64 {
+    if total.bytes == 0 {
+        0.0
+    } else {
+        100.0 * stats.bytes as f64 / total.bytes as f64
+    }
+}
+
+impl App {
+    pub fn new(analysis: Analysis) -> App {
+        let total = analysis.dominated_totals();
+        let root = analysis.root().address;
+
+        let retained: HashMap<&Symbol, Stats> = analysis
+            .retained_stats_by_kind(usize::MAX)
+            .0
+            .into_iter()
+            .collect();
+        let classes = analysis
+            .live_stats_by_kind(usize::MAX)
+            .0
+            .into_iter()
+            .map(|(kind, live)| ClassRow {
+                kind: kind.to_string(),
+                live,
+                retained: retained.get(kind).copied().unwrap_or_default(),
+            })
+            .collect();
+
+        let mut app = App {
+            analysis,
+            total,
+            view: View::Tree,
+            sort: SortKey::Bytes,
+            classes,
+            class_state: TableState::default(),
+            path: vec![],
+            children: vec![],
+            tree_state: TableState::default(),
+            search: String::new(),
+            searching: false,
+            quit: false,
+        };
+        app.enter(root);
+        app.sort_rows();
+        app
+    }
+
+    // Lists the dominator tree children of the object at `address`
+    fn enter(&mut self, address: usize) {
+        let children = match self.analysis.dominator_children(address, usize::MAX) {
+            Some((children, _)) => children,
+            None => return,
+        };
+        self.children = children
+            .into_iter()
+            .map(|(obj, retained)| ObjectRow {
+                address: obj.address,
+                label: obj.to_string(),
+                bytes: obj.bytes,
+                retained,
+            })
+            .collect();
+        self.path.push(address);
+        self.sort_rows();
+        self.tree_state.select(Some(0));
+    }
+
+    fn leave(&mut self) {
+        if self.path.len() < 2 {
+            return;
+        }
+        let from = self.path.pop();
+        let parent = self.path.pop().expect("path has a parent");
+        self.enter(parent);
+
+        // Keep the object we came from selected
+        let visible = self.visible_children();
+        let i = visible.iter().position(|row| Some(row.address) == from);
+        self.tree_state.select(i.or(Some(0)));
+    }
+
+    fn sort_rows(&mut self) {
+        let sort = self.sort;
+        self.classes.sort_by_key(|row| {
+            std::cmp::Reverse(match sort {
+                SortKey::Bytes => row.live.bytes,
+                SortKey::Count => row.live.count,
+                SortKey::Retained => row.retained.bytes,
+            })
+        });
+        self.children.sort_by_key(|row| {
+            std::cmp::Reverse(match sort {
+                SortKey::Count => row.retained.count,
+                SortKey::Bytes | SortKey::Retained => row.retained.bytes,
+            })
+        });
+    }
+
+    fn visible_classes(&self) -> Vec<&ClassRow> {
+        self.classes
+            .iter()
+            .filter(|row| contains_ignore_case(&row.kind, &self.search))
+            .collect()
+    }
+
+    fn visible_children(&self) -> Vec<&ObjectRow> {
+        self.children
+            .iter()
+            .filter(|row| contains_ignore_case(&row.label, &self.search))
+            .collect()
+    }
+
+    fn visible_len(&self) -> usize {
+        match self.view {
+            View::Classes => self.visible_classes().len(),
+            View::Tree => self.visible_children().len(),
+        }
+    }
+
+    fn state(&mut self) -> &mut TableState {
+        match self.view {
+            View::Classes => &mut self.class_state,
+            View::Tree => &mut self.tree_state,
+        }
+    }
+
+    fn select(&mut self, delta: isize) {
+        let len = self.visible_len();
+        let state = self.state();
+        if len == 0 {
+            state.select(None);
+            return;
+        }
+        let current = state.selected().unwrap_or(0) as isize;
+        state.select(Some((current + delta).clamp(0, len as isize - 1) as usize));
+    }
+
+    fn selected_child(&self) -> Option<&ObjectRow> {
+        let i = self.tree_state.selected()?;
+        self.visible_children().get(i).copied()
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+
+        if self.searching {
+            match key.code {
+                KeyCode::Enter => self.searching = false,
+                KeyCode::Esc => {
+                    self.searching = false;
+                    self.search.clear();
+                }
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                KeyCode::Char(c) => self.search.push(c),
+                _ => return,
+            }
+            self.state().select(Some(0));
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Tab => {
+                self.view = match self.view {
+                    View::Classes => View::Tree,
+                    View::Tree => View::Classes,
+                };
+                if self.state().selected().is_none() {
+                    self.state().select(Some(0));
+                }
+            }
+            KeyCode::Char('/') => self.searching = true,
+            KeyCode::Esc => {
+                self.search.clear();
+                self.state().select(Some(0));
+            }
+            KeyCode::Char('s') => {
+                self.sort = match self.sort {
+                    SortKey::Bytes => SortKey::Count,
+                    SortKey::Count => SortKey::Retained,
+                    SortKey::Retained => SortKey::Bytes,
+                };
+                self.sort_rows();
+            }
+            KeyCode::Down | KeyCode::Char('j') => self.select(1),
+            KeyCode::Up | KeyCode::Char('k') => self.select(-1),
+            KeyCode::PageDown => self.select(20),
+            KeyCode::PageUp => self.select(-20),
+            KeyCode::Home | KeyCode::Char('g') => self.select(isize::MIN / 2),
+            KeyCode::End | KeyCode::Char('G') => self.select(isize::MAX / 2),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') if self.view == View::Tree => {
+                if let Some(row) = self.selected_child() {
+                    if row.retained.count > 1 {
+                        let address = row.address;
+                        self.search.clear();
+                        self.enter(address);
+                    }
+                }
+            }
+            KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') if self.view == View::Tree => {
+                self.search.clear();
+                self.leave();
+            }
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, main, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [table, inspect] =
+            Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+                .areas(main);
+
+        let title = match self.view {
+            View::Classes => "Classes".to_string(),
+            View::Tree => self
+                .path
+                .iter()
+                .filter_map(|a| self.analysis.retained_stats(*a))
+                .map(|(obj, _)| obj.to_string())
+                .collect::<Vec<_>>()
+                .join(" > "),
+        };
+        frame.render_widget(
+            Paragraph::new(format!(
+                "reap: {} ({} objects) | sort: {:?} | {}",
+                ByteSize(self.total.bytes as u64),
+                self.total.count,
+                self.sort,
+                title
+            )),
+            header,
+        );
+
+        match self.view {
+            View::Classes => self.draw_classes(frame, table, inspect),
+            View::Tree => self.draw_tree(frame, table, inspect),
+        }
+
+        let help = if self.searching || !self.search.is_empty() {
+            format!("/{}", self.search)
+        } else {
+            "q quit  tab classes/tree  enter/bksp drill down/up  s sort  / search".to_string()
+        };
+        frame.render_widget(Paragraph::new(help), footer);
+    }
+
+    fn draw_classes(&mut self, frame: &mut Frame, table: Rect, inspect: Rect) {
+        let visible = self.visible_classes();
+        let rows = visible.iter().map(|row| {
+            Row::new(vec![
+                row.kind.clone(),
+                row.live.count.to_string(),
+                ByteSize(row.live.bytes as u64).to_string(),
+                ByteSize(row.retained.bytes as u64).to_string(),
+            ])
+        });
+        let details = match self.class_state.selected().and_then(|i| visible.get(i)) {
+            Some(row) => vec![
+                Line::from(row.kind.clone()),
+                Line::from(""),
+                Line::from(format!("Instances: {}", row.live.count)),
+                Line::from(format!(
+                    "Live: {} ({:.1}%)",
+                    ByteSize(row.live.bytes as u64),
+                    share(row.live, self.total)
+                )),
+                Line::from(format!(
+                    "Retained: {} in {} objects ({:.1}%)",
+                    ByteSize(row.retained.bytes as u64),
+                    row.retained.count,
+                    share(row.retained, self.total)
+                )),
+            ],
+            None => vec![],
+        };
+
+        let widget = Table::new(
+            rows,
+            [
+                Constraint::Min(20),
+                Constraint::Length(10),
+                Constraint::Length(10),
+                Constraint::Length(10),
+            ],
+        )
+        .header(Row::new(vec!["Class", "Count", "Live", "Retained"]).style(bold()))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered());
+        frame.render_stateful_widget(widget, table, &mut self.class_state);
+        render_details(frame, inspect, details);
+    }
+
+    fn draw_tree(&mut self, frame: &mut Frame, table: Rect, inspect: Rect) {
+        let visible = self.visible_children();
+        let rows = visible.iter().map(|row| {
+            Row::new(vec![
+                row.label.clone(),
+                row.retained.count.to_string(),
+                ByteSize(row.retained.bytes as u64).to_string(),
+            ])
+        });
+        let details = match self.tree_state.selected().and_then(|i| visible.get(i)) {
+            Some(row) => vec![
+                Line::from(row.label.clone()),
+                Line::from(""),
+                Line::from(format!("Address: {:#x}", row.address)),
+                Line::from(format!("Self: {}", ByteSize(row.bytes as u64))),
+                Line::from(format!(
+                    "Retained: {} in {} objects ({:.1}%)",
+                    ByteSize(row.retained.bytes as u64),
+                    row.retained.count,
+                    share(row.retained, self.total)
+                )),
+            ],
+            None => vec![],
+        };
+
+        let widget = Table::new(
+            rows,
+            [
+                Constraint::Min(20),
+                Constraint::Length(10),
+                Constraint::Length(10),
+            ],
+        )
+        .header(Row::new(vec!["Object", "Objects", "Retained"]).style(bold()))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .block(Block::bordered());
+        frame.render_stateful_widget(widget, table, &mut self.tree_state);
+        render_details(frame, inspect, details);
+    }
+}
+
+fn bold() -> Style {
+    Style::new().add_modifier(Modifier::BOLD)
+}
+
+fn render_details(frame: &mut Frame, area: Rect, lines: Vec<Line>) {
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title("Inspect")),
+        area,
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reap::{analyze, parse};
+    use std::fs::File;
+    use std::io::BufReader;
+
+    fn app() -> App {
+        let mut reader = BufReader::new(File::open("test/heap.json").unwrap());
+        let (root, graph) = parse::parse(&mut reader, false).unwrap();
+        App::new(analyze::analyze(root, root, graph, false).unwrap())
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    #[test]
+    fn drill_down_and_up() {
+        let mut app = app();
+        let first = app.selected_child().unwrap().address;
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(vec![0, first], app.path);
+
+        press(&mut app, KeyCode::Backspace);
+        assert_eq!(vec![0], app.path);
+        assert_eq!(first, app.selected_child().unwrap().address);
+    }
+
+    #[test]
+    fn search_and_sort_classes() {
+        let mut app = app();
+        press(&mut app, KeyCode::Tab);
+        assert_eq!("Thread", app.visible_classes()[0].kind);
+
+        press(&mut app, KeyCode::Char('s'));
+        assert_eq!("String", app.visible_classes()[0].kind);
+
+        press(&mut app, KeyCode::Char('/'));
+        for c in "thr".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        press(&mut app, KeyCode::Enter);
+        assert!(app
+            .visible_classes()
+            .iter()
+            .all(|row| row.kind.to_lowercase().contains("thr")));
+    }
+}
@@ -0,0 +1,51 @@
+//! Bindings for analyzing a dump in the browser, built with e.g.
+//! `wasm-pack build --target web --no-default-features --features wasm`, so
+//! that a page can analyze a dropped heap.json without uploading it.
+use crate::analyze::{self, Analysis};
+use crate::parse;
+use crate::query::Query;
+use crate::summary;
+use wasm_bindgen::prelude::*;
+
+fn js_error<E: ToString>(e: E) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+#[wasm_bindgen]
+pub struct Heap {
+    analysis: Analysis,
+}
+
+#[wasm_bindgen]
+impl Heap {
+    // Parses and analyzes the contents of a JSON heap dump.
+    #[wasm_bindgen(constructor)]
+    pub fn new(json: &str, class_name_only: bool) -> Result<Heap, JsError> {
+        let options = parse::ParseOptions {
+            class_name_only,
+            ..Default::default()
+        };
+        let (root, graph, _) = parse::parse_bytes(json.as_bytes(), &options).map_err(js_error)?;
+        let analysis = analyze::analyze(root, root, graph, class_name_only).map_err(js_error)?;
+        Ok(Heap { analysis })
+    }
+
+    // A JSON summary, with the `count` largest rows of per-kind tables.
+    pub fn summary(&self, count: usize) -> Result<String, JsError> {
+        let summary = summary::summarize(&self.analysis, count, 0, &[]);
+        serde_json::to_string(&summary).map_err(js_error)
+    }
+
+    // A JSON array of up to `count` objects matching `query`, largest first.
+    pub fn query(&self, query: &str, count: usize) -> Result<String, JsError> {
+        let query: Query = query.parse().map_err(js_error)?;
+        let rows = summary::largest_objects(&self.analysis, count, &query);
+        serde_json::to_string(&rows).map_err(js_error)
+    }
+
+    // The dominator tree in folded stack format, for rendering a flamegraph.
+    pub fn folded(&self) -> Result<String, JsError> {
+        let lines = self.analysis.flamegraph_lines().map_err(js_error)?;
+        Ok(lines.join("\n"))
+    }
+}
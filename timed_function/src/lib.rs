@@ -16,7 +16,8 @@ pub fn timed(_: TokenStream, item: TokenStream) -> TokenStream {
 
 #[proc_macro_attribute]
 #[cfg(feature = "timed")]
-/// Macro for wrapping functions with timing.
+/// Macro for wrapping functions with timing, which is recorded through the
+/// calling crate's `timing` module.
 ///
 /// ~Cargo-culted from https://github.com/Manishearth/rust-adorn/blob/master/src/lib.rs
 pub fn timed(_: TokenStream, item: TokenStream) -> TokenStream {
@@ -54,14 +55,8 @@ pub fn timed(_: TokenStream, item: TokenStream) -> TokenStream {
     quote!(
         #(#attributes),*
         #vis #constness #unsafety #abi fn #funcname #generics (#(#args),*) #output {
-            use std::time::{Duration, Instant};
-
-            let start = Instant::now();
-            let result = { #body };
-            let elapsed = start.elapsed();
-            println!("{}: {}.{}s", #label, elapsed.as_secs(), elapsed.subsec_millis());
-
-            result
+            let _phase = crate::timing::start(#label);
+            #body
         }
     )
     .into()